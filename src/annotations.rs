@@ -0,0 +1,48 @@
+//! `#[key: value]` annotations around inputs, kept as metadata on their values.
+use indexmap::IndexMap;
+
+use crate::Value;
+
+/// The field of a resource or dictionary holding the annotations of its input.
+pub const META_FIELD: &str = "__meta__";
+
+/// Splits the leading and trailing annotations off `input`, like `#[source: item42]`.
+/// An annotation without a value, like `#[draft]`, is `true`.
+pub(crate) fn extract_annotations(mut input: &str) -> (&str, Vec<(String, Value)>) {
+    let mut annotations = Vec::new();
+    loop {
+        input = input.trim();
+        let (rest, annotation) = if let Some(after) = input.strip_prefix("#[") {
+            match after.split_once(']') {
+                Some((annotation, rest)) => (rest, annotation),
+                None => break,
+            }
+        } else if let Some(before) = input.strip_suffix(']') {
+            match before.rsplit_once("#[") {
+                Some((rest, annotation)) => (rest, annotation),
+                None => break,
+            }
+        } else {
+            break;
+        };
+        annotations.push(match annotation.split_once(':') {
+            Some((key, value)) => (key.trim().to_string(), Value::String(value.trim().into())),
+            None => (annotation.trim().to_string(), Value::Bool(true)),
+        });
+        input = rest;
+    }
+    (input, annotations)
+}
+
+/// Adds the `annotations` to a resource or dictionary in its `META_FIELD`.
+/// Other values can't hold them and are returned as is.
+pub(crate) fn attach_annotations(mut value: Value, annotations: Vec<(String, Value)>) -> Value {
+    if annotations.is_empty() {
+        return value;
+    }
+    if let Value::Resource { fields, .. } | Value::Dictionary(fields) = &mut value {
+        let meta: IndexMap<_, _> = annotations.into_iter().collect();
+        fields.insert(META_FIELD.to_string(), Value::Dictionary(meta));
+    }
+    value
+}
@@ -0,0 +1,287 @@
+//! Binary form of a grammar, to ship precompiled grammars instead of `dokedef` sources.
+//!
+//! The compiled bytes start with `MAGIC` and the format `VERSION`, followed by
+//! the bincode encoding of a `CompiledGrammar`, an owned copy of the productions.
+use chumsky::span::SimpleSpan;
+use serde::{Deserialize, Serialize};
+
+use crate::grammar_parser::{Cast, Comparison, Constraint, Str};
+use crate::parser::FieldSpecs;
+use crate::recognizer::{Grammar, OutSpec, Production, Symbol, ValueSpec, WhitespaceMode};
+use crate::DokearleyError;
+
+const MAGIC: &[u8; 4] = b"DOKE";
+/// Bumped whenever the layout of `CompiledGrammar` changes.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CompiledGrammar {
+    productions: Vec<CompiledProduction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompiledProduction {
+    lhs: String,
+    rhs: Vec<CompiledSymbol>,
+    out: CompiledOutSpec,
+    ws: WhitespaceMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum CompiledSymbol {
+    Terminal(String),
+    Placeholder {
+        name: String,
+        typ: String,
+        #[serde(default)]
+        optional: bool,
+    },
+    NonTerminal(String),
+    End,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum CompiledOutSpec {
+    Value(CompiledValueSpec),
+    Resource {
+        typ: String,
+        fields: Vec<(String, CompiledValueSpec)>,
+    },
+    Dict(Vec<(String, CompiledValueSpec)>),
+    Transparent,
+    Tagged,
+    Merged {
+        typ: Option<String>,
+        fields: Vec<(String, CompiledValueSpec)>,
+    },
+    Constrained {
+        out: Box<CompiledOutSpec>,
+        constraints: Vec<(String, Comparison, CompiledValueSpec)>,
+    },
+    Keyword,
+    List(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum CompiledValueSpec {
+    Identifier(String),
+    StringLiteral(String),
+    IntegerLiteral(i64),
+    FloatLiteral(f64),
+    BoolLiteral(bool),
+    Child(String),
+    Children(String),
+    Cast(Cast, String),
+    Index(String, usize),
+}
+
+impl CompiledGrammar {
+    /// The versioned bytes of a grammar.
+    pub fn encode(grammar: &Grammar<'_>) -> Vec<u8> {
+        let compiled = CompiledGrammar {
+            productions: grammar.productions.iter().map(Into::into).collect(),
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        // Serializing owned strings and numbers into a Vec can't fail
+        bytes.extend(bincode::serialize(&compiled).expect("grammar serialization failed"));
+        bytes
+    }
+
+    /// Reads bytes made by `encode`, rejecting other formats and versions.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DokearleyError> {
+        let invalid = |msg: String| DokearleyError::InvalidCompiledGrammar(msg);
+        let payload = bytes
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| invalid("not a compiled dokedef".to_string()))?;
+        let (version, payload) = payload
+            .split_first_chunk::<4>()
+            .ok_or_else(|| invalid("missing format version".to_string()))?;
+        let version = u32::from_le_bytes(*version);
+        if version != VERSION {
+            return Err(invalid(format!(
+                "format version {version}, expected {VERSION}"
+            )));
+        }
+        bincode::deserialize(payload).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The grammar, borrowing its strings from `self`.
+    pub fn grammar(&self) -> Grammar<'_> {
+        Grammar {
+            productions: self.productions.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Spans only matter for highlighting the `dokedef`, which compiled grammars don't keep.
+fn str(text: &str) -> Str<'_> {
+    Str::new(text, SimpleSpan::from(0..0))
+}
+
+/// In declaration order, which is kept by compiled grammars.
+fn compile_fields(fields: &FieldSpecs<'_>) -> Vec<(String, CompiledValueSpec)> {
+    fields
+        .iter()
+        .map(|(name, spec)| (name.to_string(), spec.into()))
+        .collect()
+}
+
+fn load_fields(fields: &[(String, CompiledValueSpec)]) -> FieldSpecs<'_> {
+    fields
+        .iter()
+        .map(|(name, spec)| (name.as_str(), spec.into()))
+        .collect()
+}
+
+impl From<&Production<'_>> for CompiledProduction {
+    fn from(prod: &Production<'_>) -> Self {
+        CompiledProduction {
+            lhs: prod.lhs.to_string(),
+            rhs: prod.rhs.iter().map(Into::into).collect(),
+            out: (&prod.out).into(),
+            ws: prod.ws,
+        }
+    }
+}
+
+impl<'a> From<&'a CompiledProduction> for Production<'a> {
+    fn from(prod: &'a CompiledProduction) -> Self {
+        Production {
+            lhs: &prod.lhs,
+            rhs: prod.rhs.iter().map(Into::into).collect(),
+            out: (&prod.out).into(),
+            ws: prod.ws,
+        }
+    }
+}
+
+impl From<&Symbol<'_>> for CompiledSymbol {
+    fn from(sym: &Symbol<'_>) -> Self {
+        match sym {
+            Symbol::Terminal(t) => CompiledSymbol::Terminal(t.to_string()),
+            Symbol::Placeholder {
+                name,
+                typ,
+                optional,
+            } => CompiledSymbol::Placeholder {
+                name: name.to_string(),
+                typ: typ.to_string(),
+                optional: *optional,
+            },
+            Symbol::NonTerminal(nt) => CompiledSymbol::NonTerminal(nt.to_string()),
+            Symbol::End => CompiledSymbol::End,
+        }
+    }
+}
+
+impl<'a> From<&'a CompiledSymbol> for Symbol<'a> {
+    fn from(sym: &'a CompiledSymbol) -> Self {
+        match sym {
+            CompiledSymbol::Terminal(t) => Symbol::Terminal(t),
+            CompiledSymbol::Placeholder {
+                name,
+                typ,
+                optional,
+            } => Symbol::Placeholder {
+                name,
+                typ,
+                optional: *optional,
+            },
+            CompiledSymbol::NonTerminal(nt) => Symbol::NonTerminal(nt),
+            CompiledSymbol::End => Symbol::End,
+        }
+    }
+}
+
+impl From<&OutSpec<'_>> for CompiledOutSpec {
+    fn from(out: &OutSpec<'_>) -> Self {
+        match out {
+            OutSpec::Value(spec) => CompiledOutSpec::Value(spec.into()),
+            OutSpec::Resource { typ, fields } => CompiledOutSpec::Resource {
+                typ: typ.to_string(),
+                fields: compile_fields(fields),
+            },
+            OutSpec::Dict(fields) => CompiledOutSpec::Dict(compile_fields(fields)),
+            OutSpec::Transparent => CompiledOutSpec::Transparent,
+            OutSpec::Keyword => CompiledOutSpec::Keyword,
+            OutSpec::List(rest) => CompiledOutSpec::List(rest.to_string()),
+            OutSpec::Tagged => CompiledOutSpec::Tagged,
+            OutSpec::Merged { typ, fields } => CompiledOutSpec::Merged {
+                typ: typ.map(str::to_string),
+                fields: compile_fields(fields),
+            },
+            OutSpec::Constrained { out, constraints } => CompiledOutSpec::Constrained {
+                out: Box::new(out.as_ref().into()),
+                constraints: constraints
+                    .iter()
+                    .map(|c| (c.field.text.to_string(), c.op, (&c.value).into()))
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a CompiledOutSpec> for OutSpec<'a> {
+    fn from(out: &'a CompiledOutSpec) -> Self {
+        match out {
+            CompiledOutSpec::Value(spec) => OutSpec::Value(spec.into()),
+            CompiledOutSpec::Resource { typ, fields } => OutSpec::Resource {
+                typ,
+                fields: load_fields(fields),
+            },
+            CompiledOutSpec::Dict(fields) => OutSpec::Dict(load_fields(fields)),
+            CompiledOutSpec::Transparent => OutSpec::Transparent,
+            CompiledOutSpec::Keyword => OutSpec::Keyword,
+            CompiledOutSpec::List(rest) => OutSpec::List(rest),
+            CompiledOutSpec::Tagged => OutSpec::Tagged,
+            CompiledOutSpec::Merged { typ, fields } => OutSpec::Merged {
+                typ: typ.as_deref(),
+                fields: load_fields(fields),
+            },
+            CompiledOutSpec::Constrained { out, constraints } => OutSpec::Constrained {
+                out: Box::new(out.as_ref().into()),
+                constraints: constraints
+                    .iter()
+                    .map(|(field, op, value)| Constraint {
+                        field: str(field),
+                        op: *op,
+                        value: value.into(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl From<&ValueSpec<'_>> for CompiledValueSpec {
+    fn from(spec: &ValueSpec<'_>) -> Self {
+        match spec {
+            ValueSpec::Identifier(s) => CompiledValueSpec::Identifier(s.text.to_string()),
+            ValueSpec::StringLiteral(s) => CompiledValueSpec::StringLiteral(s.text.to_string()),
+            ValueSpec::IntegerLiteral(i) => CompiledValueSpec::IntegerLiteral(*i),
+            ValueSpec::FloatLiteral(f) => CompiledValueSpec::FloatLiteral(*f),
+            ValueSpec::BoolLiteral(b) => CompiledValueSpec::BoolLiteral(*b),
+            ValueSpec::Child(s) => CompiledValueSpec::Child(s.text.to_string()),
+            ValueSpec::Children(s) => CompiledValueSpec::Children(s.text.to_string()),
+            ValueSpec::Cast(cast, s) => CompiledValueSpec::Cast(*cast, s.text.to_string()),
+            ValueSpec::Index(s, i) => CompiledValueSpec::Index(s.text.to_string(), *i),
+        }
+    }
+}
+
+impl<'a> From<&'a CompiledValueSpec> for ValueSpec<'a> {
+    fn from(spec: &'a CompiledValueSpec) -> Self {
+        match spec {
+            CompiledValueSpec::Identifier(s) => ValueSpec::Identifier(str(s)),
+            CompiledValueSpec::StringLiteral(s) => ValueSpec::StringLiteral(str(s)),
+            CompiledValueSpec::IntegerLiteral(i) => ValueSpec::IntegerLiteral(*i),
+            CompiledValueSpec::FloatLiteral(f) => ValueSpec::FloatLiteral(*f),
+            CompiledValueSpec::BoolLiteral(b) => ValueSpec::BoolLiteral(*b),
+            CompiledValueSpec::Child(s) => ValueSpec::Child(str(s)),
+            CompiledValueSpec::Children(s) => ValueSpec::Children(str(s)),
+            CompiledValueSpec::Cast(cast, s) => ValueSpec::Cast(*cast, str(s)),
+            CompiledValueSpec::Index(s, i) => ValueSpec::Index(str(s), *i),
+        }
+    }
+}
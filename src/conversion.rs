@@ -1,6 +1,6 @@
 use crate::{
     grammar_parser::{self, Rule},
-    recognizer::{self},
+    recognizer::{self, tokenize, TokenKind, WhitespaceMode},
 };
 
 // In recognizer or a conversion module
@@ -16,32 +16,59 @@ impl<'gr> From<grammar_parser::Symbol<'gr>> for Vec<recognizer::Symbol<'gr>> {
         match sym {
             Terminal(s) => {
                 let text = s.text;
-                text.char_indices()
-                    .map(|(i, ch)| {
-                        let end = i + ch.len_utf8();
-                        recognizer::Symbol::Terminal(&text[i..end])
-                    })
-                    .collect()
+                // `true` and `false` stay whole, to match the `Bool` token the input has there
+                let mut terminals = Vec::new();
+                for tok in tokenize(text) {
+                    if tok.kind == TokenKind::Bool {
+                        terminals.push(recognizer::Symbol::Terminal(tok.text));
+                        continue;
+                    }
+                    let (start, end) = (tok.span.start, tok.span.end);
+                    terminals.extend(text[start..end].char_indices().map(|(i, ch)| {
+                        let i = start + i;
+                        recognizer::Symbol::Terminal(&text[i..i + ch.len_utf8()])
+                    }));
+                }
+                terminals
             }
-            Placeholder { name, typ } => vec![recognizer::Symbol::Placeholder {
+            Placeholder {
+                name,
+                typ,
+                optional,
+            } => vec![recognizer::Symbol::Placeholder {
                 name: name.text,
                 typ: typ.text,
+                optional,
             }],
             NonTerminal(s) => vec![recognizer::Symbol::NonTerminal(s.text)],
+            Repetition { .. } => unreachable!("grammars read repetitions as their list"),
         }
     }
 }
 
 impl<'gr> From<grammar_parser::Production<'gr>> for recognizer::Production<'gr> {
     fn from(prod: grammar_parser::Production<'gr>) -> Self {
+        let mut rhs: Vec<recognizer::Symbol> = prod
+            .rhs
+            .into_iter()
+            .flat_map(Into::<Vec<recognizer::Symbol>>::into)
+            .collect();
+        if prod.ws == WhitespaceMode::Collapse {
+            // A single whitespace terminal already matches a whole run of whitespace
+            let is_ws = |s: &recognizer::Symbol| {
+                matches!(s, recognizer::Symbol::Terminal(t) if t.chars().all(char::is_whitespace))
+            };
+            rhs.dedup_by(|a, b| is_ws(a) && is_ws(b));
+        }
+        // A pattern ending with `$` must end the input
+        if let Some(last @ recognizer::Symbol::Terminal("$")) = rhs.last_mut() {
+            *last = recognizer::Symbol::End;
+        }
         recognizer::Production {
             lhs: prod.lhs.text,
-            rhs: prod
-                .rhs
-                .into_iter()
-                .flat_map(Into::<Vec<recognizer::Symbol>>::into)
-                .collect(),
+            rhs,
             out: prod.out,
+            ws: prod.ws,
         }
     }
 }
@@ -0,0 +1,153 @@
+//! Every problem of a `dokedef` at once, with where it is, for `Dokearley::validate`.
+use std::ops::Range;
+
+use crate::recognizer::{Grammar, Production, Symbol};
+use crate::{regex_types, DokearleyError};
+
+/// How bad a `Diagnostic` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// The grammar is rejected when building a parser
+    Error,
+    /// The grammar builds, but this is likely a mistake
+    Warning,
+}
+
+/// A problem of a `dokedef`, e.g. for an editor's problem panel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The byte range of the `dokedef` it is about, when it can be told
+    pub span: Option<Range<usize>>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: impl ToString, span: Option<Range<usize>>) -> Self {
+        Self {
+            severity,
+            message: message.to_string(),
+            span,
+        }
+    }
+
+    pub(crate) fn error(message: impl ToString, span: Option<Range<usize>>) -> Self {
+        Self::new(Severity::Error, message, span)
+    }
+
+    fn warning(message: impl ToString, span: Option<Range<usize>>) -> Self {
+        Self::new(Severity::Warning, message, span)
+    }
+}
+
+/// Where `text` is in `source`, as grammar names are slices of their `dokedef`.
+/// `None` for text from elsewhere, like the names of hand-built grammars.
+fn span_of(source: &str, text: &str) -> Option<Range<usize>> {
+    let start = (text.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    let end = start + text.len();
+    (!text.is_empty() && end <= source.len()).then_some(start..end)
+}
+
+/// The warning for a number written in a pattern, see `Grammar::find_shadowed_numbers`.
+pub(crate) fn shadowed_number_warning(prod: &Production, number: &str) -> String {
+    format!(
+        "{} can never match : {number} is read as a number, use a placeholder like {{n:Int}}",
+        prod.pattern_text()
+    )
+}
+
+/// The problems of `grammar`, parsed from `source`, in the order they are in it.
+/// Errors come with the `DokearleyError` building a parser fails with, the first one.
+/// `scanner_types` are the custom types placeholders may use.
+pub(crate) fn grammar_diagnostics(
+    source: &str,
+    grammar: &Grammar,
+    scanner_types: &[String],
+) -> Vec<(Diagnostic, Option<DokearleyError>)> {
+    let span = |text: &str| span_of(source, text);
+    let error = |error: DokearleyError, span| (Diagnostic::error(&error, span), Some(error));
+    let mut diagnostics = Vec::new();
+    if let Some(cycle) = grammar.find_nullable_cycle() {
+        let names = cycle.iter().map(|s| s.to_string()).collect();
+        diagnostics.push(error(DokearleyError::InfiniteNullableLoop(names), span(cycle[0])));
+    }
+    for sym in grammar.productions.iter().flat_map(|p| &p.rhs) {
+        if let Symbol::Placeholder { typ, .. } = sym {
+            let checked = match regex_types::is_regex_type(typ) {
+                true => regex_types::check_regex_type(typ),
+                false => Ok(()),
+            };
+            if let Err(message) = checked {
+                let diagnostic = Diagnostic::error(&message, span(typ));
+                diagnostics.push((diagnostic, Some(DokearleyError::InvalidDokedef(message))));
+            }
+        }
+    }
+    if let Some(prod) = grammar.find_empty_terminal() {
+        let problem = DokearleyError::EmptyTerminal(prod.pattern_text());
+        diagnostics.push(error(problem, span(prod.lhs)));
+    }
+    if let Some((prod, first, second)) = grammar.find_adjacent_numbers() {
+        let problem = DokearleyError::AdjacentPlaceholders {
+            rule: prod.pattern_text(),
+            first: first.to_string(),
+            second: second.to_string(),
+        };
+        diagnostics.push(error(problem, span(prod.lhs)));
+    }
+    for (prod, alias) in grammar.undefined_aliases() {
+        let problem = DokearleyError::UndefinedAlias {
+            rule: prod.pattern_text(),
+            alias: alias.to_string(),
+        };
+        diagnostics.push(error(problem, span(alias)));
+    }
+    for (prod, constraint, reason) in grammar.invalid_constraints() {
+        let problem = DokearleyError::InvalidConstraint {
+            rule: prod.pattern_text(),
+            constraint: constraint.to_string(),
+            reason,
+        };
+        diagnostics.push(error(problem, span(constraint.field.text)));
+    }
+    for (name, referencing) in grammar.undefined_nonterminals() {
+        let problem = DokearleyError::UndefinedSymbol {
+            name: name.to_string(),
+            referenced_in: referencing.iter().map(|p| p.pattern_text()).collect(),
+        };
+        diagnostics.push(error(problem, span(name)));
+    }
+    for typ in grammar.unknown_types(scanner_types) {
+        let problem = DokearleyError::UnknownType {
+            typ: typ.to_string(),
+            suggestion: grammar.suggest_type(typ).map(str::to_string),
+        };
+        diagnostics.push(error(problem, span(typ)));
+    }
+
+    for (prod, number) in grammar.find_shadowed_numbers() {
+        let warning = shadowed_number_warning(prod, &number);
+        diagnostics.push((Diagnostic::warning(warning, span(prod.lhs)), None));
+    }
+    if let Some(start) = grammar.productions.first().map(|p| p.lhs) {
+        for name in grammar.unreachable_nonterminals(start) {
+            let warning = format!("The rule {name} is never used from {start}, the first rule");
+            diagnostics.push((Diagnostic::warning(warning, span(name)), None));
+        }
+    }
+    for name in grammar.builtin_shadowing_rules() {
+        let warning = format!(
+            "The rule {name} is shadowed by the builtin type, {{x:{name}}} reads the builtin"
+        );
+        diagnostics.push((Diagnostic::warning(warning, span(name)), None));
+    }
+    for (prod, field) in grammar.duplicate_fields() {
+        let warning = format!(
+            "The output of {} sets the field {field} twice, the last one is kept",
+            prod.pattern_text()
+        );
+        diagnostics.push((Diagnostic::warning(warning, span(field)), None));
+    }
+    diagnostics.sort_by_key(|(d, _)| d.span.as_ref().map_or(usize::MAX, |span| span.start));
+    diagnostics
+}
@@ -1,6 +1,6 @@
 use std::ops::Range;
 
-use crate::grammar_parser::{Pattern, Rule, RuleRhs, Str, Symbol, ValueSpec};
+use crate::grammar_parser::{Alternative, Pattern, Rule, RuleRhs, Str, Symbol, ValueSpec};
 
 /// What kind of token this is for highlighting
 #[derive(Debug, Clone, Copy)]
@@ -16,7 +16,8 @@ pub enum HighlightKind {
     IntegerLiteral,
     FloatLiteral,
     Identifier,
-    ChildName
+    ChildName,
+    Comment,
 }
 
 /// A token with a span in the original input
@@ -35,9 +36,38 @@ fn span_token<'a>(s: &Str<'a>, kind: HighlightKind) -> HighlightToken<'a> {
     }
 }
 
+/// The `# comment` spans of a `dokedef`, skipping the `#` of quoted patterns and strings
+fn comment_spans(input: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut in_quotes = false;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            '#' if !in_quotes => {
+                let end = input[i..].find('\n').map_or(input.len(), |n| i + n);
+                spans.push(i..end);
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
 /// Produce highlight tokens for the entire input & rules
-pub fn highlight_tokens<'a>(_input: &'a str, rules: &[Rule<'a>]) -> Vec<HighlightToken<'a>> {
-    let mut tokens = Vec::new();
+pub fn highlight_tokens<'a>(input: &'a str, rules: &[Rule<'a>]) -> Vec<HighlightToken<'a>> {
+    let mut tokens: Vec<_> = comment_spans(input)
+        .into_iter()
+        .map(|span| HighlightToken {
+            text: &input[span.clone()],
+            span,
+            kind: HighlightKind::Comment,
+        })
+        .collect();
 
     for rule in rules {
         // LHS
@@ -51,9 +81,12 @@ pub fn highlight_tokens<'a>(_input: &'a str, rules: &[Rule<'a>]) -> Vec<Highligh
                         Symbol::Terminal(t) => {
                             tokens.push(span_token(t, HighlightKind::Terminal));
                         }
-                        Symbol::Placeholder { name, typ } => {
-                            // {name:Type}
-                            tokens.push(span_token(name, HighlightKind::PlaceholderName));
+                        Symbol::Placeholder { name, typ, .. }
+                        | Symbol::Repetition { name, typ, .. } => {
+                            // {name:Type}, the name of `{:Type}` isn't written
+                            if name.span.start < name.span.end {
+                                tokens.push(span_token(name, HighlightKind::PlaceholderName));
+                            }
                             tokens.push(span_token(typ, HighlightKind::PlaceholderType));
                         }
                         Symbol::NonTerminal(nt) => {
@@ -62,122 +95,151 @@ pub fn highlight_tokens<'a>(_input: &'a str, rules: &[Rule<'a>]) -> Vec<Highligh
                     }
                 }
             }
-            Pattern::Disjunction(symbols) => {
-                // disjunction is a list of single NonTerminals (as you build them)
-                // highlight each nonterminal
-                for sym in symbols {
-                    match sym {
-                        Symbol::NonTerminal(nt) => {
+            Pattern::Disjunction(alternatives) => {
+                // disjunction is a list of NonTerminals and quoted patterns
+                for alternative in alternatives {
+                    let symbols = match alternative {
+                        Alternative::NonTerminal(nt) => {
                             tokens.push(span_token(nt, HighlightKind::NonTerminal));
+                            continue;
                         }
-                        // In case you later allow other kinds in disjunction, handle them too:
-                        Symbol::Terminal(t) => {
-                            tokens.push(span_token(t, HighlightKind::Terminal));
+                        Alternative::Quoted {
+                            symbols, output, ..
+                        } => {
+                            if let Some((_, rhs, _)) = output {
+                                push_rhs_tokens(rhs, &mut tokens);
+                            }
+                            symbols
                         }
-                        Symbol::Placeholder { name, typ } => {
-                            tokens.push(span_token(name, HighlightKind::PlaceholderName));
-                            tokens.push(span_token(typ, HighlightKind::PlaceholderType));
+                    };
+                    for sym in symbols {
+                        match sym {
+                            Symbol::NonTerminal(nt) => {
+                                tokens.push(span_token(nt, HighlightKind::NonTerminal));
+                            }
+                            Symbol::Terminal(t) => {
+                                tokens.push(span_token(t, HighlightKind::Terminal));
+                            }
+                            Symbol::Placeholder { name, typ, .. }
+                            | Symbol::Repetition { name, typ, .. } => {
+                                if name.span.start < name.span.end {
+                                    tokens.push(span_token(name, HighlightKind::PlaceholderName));
+                                }
+                                tokens.push(span_token(typ, HighlightKind::PlaceholderType));
+                            }
                         }
                     }
                 }
             }
+            Pattern::Keywords { keywords, field } => {
+                for keyword in keywords {
+                    tokens.push(span_token(keyword, HighlightKind::Terminal));
+                }
+                tokens.push(span_token(field, HighlightKind::FieldName));
+            }
         }
 
         // RHS
         if let Some(rhs) = &rule.rhs {
-            match rhs {
-                RuleRhs::Type(name) => {
-                    tokens.push(span_token(name, HighlightKind::RHS));
-                }
-                RuleRhs::TypeWithFields { name, fields } => {
-                    tokens.push(span_token(name, HighlightKind::RHS));
-                    for (field_name, field_val) in fields {
-                        tokens.push(span_token(field_name, HighlightKind::FieldName));
-                        match field_val {
-                            ValueSpec::Identifier(s) => {
-                                                        tokens.push(span_token(s, HighlightKind::Identifier));
-                                                    }
-                            ValueSpec::StringLiteral(s) => {
-                                                        // Emit quotes + content
-                                                        let span = s.span.clone();
-                                                        tokens.push(HighlightToken {
-                                                            text: "\"",
-                                                            span: (span.start - 1)..span.start,
-                                                            kind: HighlightKind::StringLiteral,
-                                                        });
-                                                        tokens.push(span_token(s, HighlightKind::StringLiteral));
-                                                        tokens.push(HighlightToken {
-                                                            text: "\"",
-                                                            span: span.end..(span.end + 1),
-                                                            kind: HighlightKind::StringLiteral,
-                                                        });
-                                                    }
-                            ValueSpec::IntegerLiteral(_) => {
-                                                        // spans not yet carried — TODO
-                                                    }
-                            ValueSpec::FloatLiteral(_) => {
-                                                        // spans not yet carried — TODO
-                                                    }
-                            ValueSpec::BoolLiteral(_) => {
-                                                        // no spans for bool yet
-                                                    }
-                            ValueSpec::Child(s) => {
-                                tokens.push(span_token(s, HighlightKind::ChildName))
-                            },
-                            ValueSpec::Children(s) => {
-                                tokens.push(span_token(s, HighlightKind::ChildName))
-                            },
-                        }
+            push_rhs_tokens(rhs, &mut tokens);
+        }
+    }
+
+    // the shared output of a rule group is in each of its rules
+    let mut seen = std::collections::HashSet::new();
+    tokens.retain(|t| seen.insert(t.span.clone()));
+    tokens
+}
+
+/// Tokens of a rule's output, or of a disjunction alternative's own one
+fn push_rhs_tokens<'a>(rhs: &RuleRhs<'a>, tokens: &mut Vec<HighlightToken<'a>>) {
+    match rhs {
+        RuleRhs::Type(name) => {
+            tokens.push(span_token(name, HighlightKind::RHS));
+        }
+        RuleRhs::TypeWithFields { name, fields } => {
+            tokens.push(span_token(name, HighlightKind::RHS));
+            for (field_name, field_val) in fields {
+                tokens.push(span_token(field_name, HighlightKind::FieldName));
+                match field_val {
+                    ValueSpec::Identifier(s) => {
+                        tokens.push(span_token(s, HighlightKind::Identifier));
+                    }
+                    ValueSpec::StringLiteral(s) => {
+                        // Emit quotes + content
+                        let span = s.span;
+                        tokens.push(HighlightToken {
+                            text: "\"",
+                            span: (span.start - 1)..span.start,
+                            kind: HighlightKind::StringLiteral,
+                        });
+                        tokens.push(span_token(s, HighlightKind::StringLiteral));
+                        tokens.push(HighlightToken {
+                            text: "\"",
+                            span: span.end..(span.end + 1),
+                            kind: HighlightKind::StringLiteral,
+                        });
+                    }
+                    ValueSpec::IntegerLiteral(_) => {
+                        // spans not yet carried — TODO
+                    }
+                    ValueSpec::FloatLiteral(_) => {
+                        // spans not yet carried — TODO
+                    }
+                    ValueSpec::BoolLiteral(_) => {
+                        // no spans for bool yet
+                    }
+                    ValueSpec::Child(s) => tokens.push(span_token(s, HighlightKind::ChildName)),
+                    ValueSpec::Children(s) => tokens.push(span_token(s, HighlightKind::ChildName)),
+                    ValueSpec::Cast(_, s) | ValueSpec::Index(s, _) => {
+                        tokens.push(span_token(s, HighlightKind::Identifier))
                     }
                 }
-                RuleRhs::Transparent => {
-                    // Transparent has no explicit RHS text to highlight.
-                    // We already highlighted the pattern (which for transparent rules
-                    // is a single nonterminal), so nothing more to do here.
-                }
-                RuleRhs::Dictionary(fields) => {
-                    for (field_name, field_val) in fields {
-                        tokens.push(span_token(field_name, HighlightKind::FieldName));
-                        match field_val {
-                            ValueSpec::Identifier(s) => {
-                                                        tokens.push(span_token(s, HighlightKind::Identifier));
-                                                    }
-                            ValueSpec::StringLiteral(s) => {
-                                                        // Emit quotes + content
-                                                        let span = s.span.clone();
-                                                        tokens.push(HighlightToken {
-                                                            text: "\"",
-                                                            span: (span.start - 1)..span.start,
-                                                            kind: HighlightKind::StringLiteral,
-                                                        });
-                                                        tokens.push(span_token(s, HighlightKind::StringLiteral));
-                                                        tokens.push(HighlightToken {
-                                                            text: "\"",
-                                                            span: span.end..(span.end + 1),
-                                                            kind: HighlightKind::StringLiteral,
-                                                        });
-                                                    }
-                            ValueSpec::IntegerLiteral(_) => {
-                                                        // spans not yet carried — TODO
-                                                    }
-                            ValueSpec::FloatLiteral(_) => {
-                                                        // spans not yet carried — TODO
-                                                    }
-                            ValueSpec::BoolLiteral(_) => {
-                                                        // no spans for bool yet
-                                                    }
-                            ValueSpec::Child(s) => {
-                                tokens.push(span_token(s, HighlightKind::ChildName))
-                            },
-                            ValueSpec::Children(s) => {
-                                tokens.push(span_token(s, HighlightKind::ChildName))
-                            },
-                        }
+            }
+        }
+        RuleRhs::Transparent | RuleRhs::Tagged => {
+            // Transparent has no explicit RHS text to highlight.
+            // We already highlighted the pattern (which for transparent rules
+            // is a single nonterminal), so nothing more to do here.
+        }
+        RuleRhs::Dictionary(fields) => {
+            for (field_name, field_val) in fields {
+                tokens.push(span_token(field_name, HighlightKind::FieldName));
+                match field_val {
+                    ValueSpec::Identifier(s) => {
+                        tokens.push(span_token(s, HighlightKind::Identifier));
+                    }
+                    ValueSpec::StringLiteral(s) => {
+                        // Emit quotes + content
+                        let span = s.span;
+                        tokens.push(HighlightToken {
+                            text: "\"",
+                            span: (span.start - 1)..span.start,
+                            kind: HighlightKind::StringLiteral,
+                        });
+                        tokens.push(span_token(s, HighlightKind::StringLiteral));
+                        tokens.push(HighlightToken {
+                            text: "\"",
+                            span: span.end..(span.end + 1),
+                            kind: HighlightKind::StringLiteral,
+                        });
+                    }
+                    ValueSpec::IntegerLiteral(_) => {
+                        // spans not yet carried — TODO
+                    }
+                    ValueSpec::FloatLiteral(_) => {
+                        // spans not yet carried — TODO
+                    }
+                    ValueSpec::BoolLiteral(_) => {
+                        // no spans for bool yet
+                    }
+                    ValueSpec::Child(s) => tokens.push(span_token(s, HighlightKind::ChildName)),
+                    ValueSpec::Children(s) => tokens.push(span_token(s, HighlightKind::ChildName)),
+                    ValueSpec::Cast(_, s) | ValueSpec::Index(s, _) => {
+                        tokens.push(span_token(s, HighlightKind::Identifier))
                     }
                 }
             }
         }
     }
-
-    tokens
 }
@@ -9,9 +9,10 @@ use chumsky::{
     prelude::*,
     text::{inline_whitespace, newline},
 };
-use std::{collections::HashMap, hash::Hash};
+use std::hash::Hash;
 
 use crate::parser::OutSpec;
+pub use crate::recognizer::WhitespaceMode;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Str<'gr> {
@@ -28,7 +29,7 @@ impl<'gr> std::ops::Deref for Str<'gr> {
 
 impl<'gr> AsRef<str> for Str<'gr> {
     fn as_ref(&self) -> &str {
-        &self.text
+        self.text
     }
 }
 
@@ -42,6 +43,35 @@ impl<'gr> Str<'gr> {
     pub fn new(text: &'gr str, span: SimpleSpan) -> Self {
         Self { text, span }
     }
+
+    /// The text with the escapes of string literals decoded, see `unescape`.
+    pub fn unescaped(&self) -> std::borrow::Cow<'gr, str> {
+        unescape(self.text)
+    }
+}
+
+/// `text` with the `\"`, `\\`, `\n` and `\t` escapes of string literals decoded,
+/// in `dokedef` files and inputs alike. Other backslashes are kept as they are.
+pub fn unescape(text: &str) -> std::borrow::Cow<'_, str> {
+    if !text.contains('\\') {
+        return text.into();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let decoded = match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('"' | '\\'))) => next,
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('t')) => '\t',
+            _ => {
+                out.push(c);
+                continue;
+            }
+        };
+        out.push(decoded);
+        chars.next();
+    }
+    out.into()
 }
 
 impl<'gr> PartialEq<str> for Str<'gr> {
@@ -59,15 +89,43 @@ impl<'gr> PartialEq<&str> for Str<'gr> {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Symbol<'gr> {
     Terminal(Str<'gr>),
-    Placeholder { name: Str<'gr>, typ: Str<'gr> },
+    /// `{name:Type}`, or `{name:Type?}` when `optional`, which the input may leave out
+    Placeholder {
+        name: Str<'gr>,
+        typ: Str<'gr>,
+        optional: bool,
+    },
+    /// `{items:Item*}`, `{items:Item+}` or `{items:Item}*(,)`, read as a placeholder of the list
+    /// nonterminal the `Grammar` adds for it
+    Repetition {
+        /// The repetition as written, which names its nonterminals
+        source: Str<'gr>,
+        name: Str<'gr>,
+        typ: Str<'gr>,
+        /// The text between the parentheses of `*(,)`, if written
+        separator: Option<Str<'gr>>,
+        /// `+`, not matching zero items
+        at_least_one: bool,
+    },
     NonTerminal(Str<'gr>),
 }
 
+impl<'gr> Symbol<'gr> {
+    /// The name of a placeholder or repetition, naming the field of its value.
+    pub fn placeholder_name(&self) -> Option<Str<'gr>> {
+        match self {
+            Symbol::Placeholder { name, .. } | Symbol::Repetition { name, .. } => Some(*name),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Production<'gr> {
     pub lhs: Str<'gr>,
     pub rhs: Vec<Symbol<'gr>>,
     pub out: OutSpec<'gr>,
+    pub ws: WhitespaceMode,
 }
 
 impl<'gr> From<Option<RuleRhs<'gr>>> for OutSpec<'gr> {
@@ -76,31 +134,22 @@ impl<'gr> From<Option<RuleRhs<'gr>>> for OutSpec<'gr> {
             Some(value) => match value {
                 RuleRhs::Type(typ) => OutSpec::Resource {
                     typ: *typ,
-                    fields: HashMap::new(),
+                    fields: Vec::new(),
                 },
                 RuleRhs::TypeWithFields {
                     name: typ,
                     fields: rule_fields,
-                } => {
-                    let mut hash: HashMap<&'gr str, ValueSpec<'gr>> = HashMap::new();
-                    rule_fields.iter().for_each(|(k, v)| {
-                        hash.insert(&k, *v);
-                    });
-                    OutSpec::Resource {
-                        typ: *typ,
-                        fields: hash,
-                    }
-                }
+                } => OutSpec::Resource {
+                    typ: *typ,
+                    fields: rule_fields.iter().map(|(k, v)| (k.text, *v)).collect(),
+                },
                 RuleRhs::Transparent => OutSpec::Transparent,
+                RuleRhs::Tagged => OutSpec::Tagged,
                 RuleRhs::Dictionary(items) => {
-                    let mut hash: HashMap<&'gr str, ValueSpec<'gr>> = HashMap::new();
-                    items.iter().for_each(|(k, v)| {
-                        hash.insert(&k, *v);
-                    });
-                    OutSpec::Dict(hash)
+                    OutSpec::Dict(items.iter().map(|(k, v)| (k.text, *v)).collect())
                 }
             },
-            None => Self::Dict(HashMap::new()),
+            None => Self::Dict(Vec::new()),
         }
     }
 }
@@ -118,7 +167,123 @@ pub enum ValueSpec<'gr> {
     FloatLiteral(f64),
     BoolLiteral(bool),
     Child(Str<'gr>),
-    Children(Str<'gr>)
+    Children(Str<'gr>),
+    /// A placeholder converted to another type, like `int(amount)`
+    Cast(Cast, Str<'gr>),
+    /// An element of a repeated placeholder, like `c[0]` for `{c:Int}{3}`
+    Index(Str<'gr>, usize),
+}
+
+impl<'gr> ValueSpec<'gr> {
+    /// Whether two specs give the same value, wherever they were written in the `dokedef`.
+    pub fn same_spec(&self, other: &ValueSpec<'_>) -> bool {
+        use ValueSpec::*;
+        match (self, other) {
+            (Identifier(a), Identifier(b))
+            | (StringLiteral(a), StringLiteral(b))
+            | (Child(a), Child(b))
+            | (Children(a), Children(b)) => a.text == b.text,
+            (IntegerLiteral(a), IntegerLiteral(b)) => a == b,
+            (FloatLiteral(a), FloatLiteral(b)) => a == b,
+            (BoolLiteral(a), BoolLiteral(b)) => a == b,
+            (Cast(ca, a), Cast(cb, b)) => ca == cb && a.text == b.text,
+            (Index(a, i), Index(b, j)) => a.text == b.text && i == j,
+            _ => false,
+        }
+    }
+}
+
+/// A field value as written in a `dokedef`, like `"fire"`, `int(amount)` or `<* Effect`.
+impl std::fmt::Display for ValueSpec<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueSpec::Identifier(s) => write!(f, "{}", s),
+            ValueSpec::StringLiteral(s) => write!(f, "\"{}\"", s),
+            ValueSpec::IntegerLiteral(i) => write!(f, "{}", i),
+            ValueSpec::FloatLiteral(x) => write!(f, "{:?}", x),
+            ValueSpec::BoolLiteral(b) => write!(f, "{}", b),
+            ValueSpec::Child(s) => write!(f, "< {}", s),
+            ValueSpec::Children(s) => write!(f, "<* {}", s),
+            ValueSpec::Cast(cast, s) => write!(f, "{}({})", cast, s),
+            ValueSpec::Index(s, i) => write!(f, "{}[{}]", s, i),
+        }
+    }
+}
+
+/// How a `where` clause compares a field to a literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "compile", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    /// Whether the comparison holds when the field is `ordering` to the literal.
+    pub fn holds(self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Comparison::Less => ordering == Less,
+            Comparison::LessOrEqual => ordering != Greater,
+            Comparison::Greater => ordering == Greater,
+            Comparison::GreaterOrEqual => ordering != Less,
+            Comparison::Equal => ordering == Equal,
+            Comparison::NotEqual => ordering != Equal,
+        }
+    }
+}
+
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Comparison::Less => write!(f, "<"),
+            Comparison::LessOrEqual => write!(f, "<="),
+            Comparison::Greater => write!(f, ">"),
+            Comparison::GreaterOrEqual => write!(f, ">="),
+            Comparison::Equal => write!(f, "=="),
+            Comparison::NotEqual => write!(f, "!="),
+        }
+    }
+}
+
+/// `amount > 0` in the `where` clause of a rule, checked on the value the rule builds.
+#[derive(Debug, Clone, Copy)]
+pub struct Constraint<'gr> {
+    pub field: Str<'gr>,
+    pub op: Comparison,
+    /// A string or number literal
+    pub value: ValueSpec<'gr>,
+}
+
+impl std::fmt::Display for Constraint<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.field, self.op, self.value)
+    }
+}
+
+/// Target type of a cast in an output spec field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "compile", derive(serde::Serialize, serde::Deserialize))]
+pub enum Cast {
+    Int,
+    Float,
+    String,
+    Bool,
+}
+
+impl std::fmt::Display for Cast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cast::Int => write!(f, "int"),
+            Cast::Float => write!(f, "float"),
+            Cast::String => write!(f, "str"),
+            Cast::Bool => write!(f, "bool"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -130,6 +295,8 @@ pub enum RuleRhs<'gr> {
     },
     Dictionary(Vec<(Str<'gr>, ValueSpec<'gr>)>),
     Transparent,
+    /// A disjunction ending with `@tagged`, noting which alternative matched
+    Tagged,
 }
 
 #[derive(Debug, Clone)]
@@ -137,11 +304,54 @@ pub struct Rule<'gr> {
     pub lhs: Str<'gr>,
     pub pattern: Pattern<'gr>,
     pub rhs: Option<RuleRhs<'gr>>,
+    pub ws: WhitespaceMode,
+    /// The `->` or `=>` written before `rhs`, if any
+    pub arrow: Option<Str<'gr>>,
+    /// The `where` clause after `rhs`
+    pub constraints: Vec<Constraint<'gr>>,
 }
+
+impl<'gr> Rule<'gr> {
+    /// The arrows written in the rule, before its output and those of its alternatives
+    pub fn arrows(&self) -> impl Iterator<Item = Str<'gr>> + '_ {
+        let alternatives = match &self.pattern {
+            Pattern::Disjunction(alternatives) => alternatives.as_slice(),
+            _ => &[],
+        };
+        let own = alternatives.iter().filter_map(|a| match a {
+            Alternative::Quoted {
+                output: Some((arrow, ..)),
+                ..
+            } => Some(*arrow),
+            _ => None,
+        });
+        self.arrow.into_iter().chain(own)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Pattern<'gr> {
     Normal(Vec<Symbol<'gr>>),
-    Disjunction(Vec<Symbol<'gr>>),
+    Disjunction(Vec<Alternative<'gr>>),
+    /// `"self" | "ally" @keyword(kind)`, literal alternatives whose matched text goes in the `field`
+    Keywords {
+        keywords: Vec<Str<'gr>>,
+        field: Str<'gr>,
+    },
+}
+
+/// One of the `|` separated alternatives of a disjunction
+#[derive(Debug, Clone)]
+pub enum Alternative<'gr> {
+    NonTerminal(Str<'gr>),
+    /// `"yes"`, a pattern given its own production, named after its source text
+    Quoted {
+        source: Str<'gr>,
+        symbols: Vec<Symbol<'gr>>,
+        /// The alternative's own output, like `"fast" -> { fast: true }`, in which case
+        /// it is part of the source
+        output: Option<Output<'gr>>,
+    },
 }
 
 impl<'gr> From<&Vec<Rule<'gr>>> for Grammar<'gr> {
@@ -152,27 +362,340 @@ impl<'gr> From<&Vec<Rule<'gr>>> for Grammar<'gr> {
                 Pattern::Normal(symbols) => productions.push(Production {
                     lhs: rule.lhs,
                     rhs: symbols.clone(),
-                    out: OutSpec::from(rule.rhs.clone()),
+                    out: pattern_out(symbols, &rule.rhs).constrained(&rule.constraints),
+                    ws: rule.ws,
                 }),
-                Pattern::Disjunction(symbols) => {
-                    productions.extend(symbols.iter().map(|nt| Production {
-                        lhs: rule.lhs,
-                        rhs: vec![*nt],
-                        out: OutSpec::Transparent,
+                Pattern::Disjunction(alternatives) => {
+                    let out = match &rule.rhs {
+                        Some(RuleRhs::Tagged) => OutSpec::Tagged,
+                        Some(RuleRhs::Type(typ)) => OutSpec::Merged {
+                            typ: Some(typ.text),
+                            fields: Vec::new(),
+                        },
+                        Some(RuleRhs::TypeWithFields { name, fields }) => OutSpec::Merged {
+                            typ: Some(name.text),
+                            fields: fields.iter().map(|(k, v)| (k.text, *v)).collect(),
+                        },
+                        Some(RuleRhs::Dictionary(fields)) => OutSpec::Merged {
+                            typ: None,
+                            fields: fields.iter().map(|(k, v)| (k.text, *v)).collect(),
+                        },
+                        _ => OutSpec::Transparent,
+                    }
+                    .constrained(&rule.constraints);
+                    for alternative in alternatives {
+                        let nt = match alternative {
+                            Alternative::NonTerminal(nt) => *nt,
+                            Alternative::Quoted {
+                                source,
+                                symbols,
+                                output,
+                            } => {
+                                // The same quoted pattern in several disjunctions is one production
+                                if !productions.iter().any(|p| p.lhs.text == source.text) {
+                                    productions.push(match output {
+                                        Some((_, rhs, constraints)) => Production {
+                                            lhs: *source,
+                                            rhs: symbols.clone(),
+                                            out: pattern_out(symbols, &Some(rhs.clone()))
+                                                .constrained(constraints),
+                                            ws: rule.ws,
+                                        },
+                                        None => quoted_production(*source, symbols, rule.ws),
+                                    });
+                                }
+                                *source
+                            }
+                        };
+                        productions.push(Production {
+                            lhs: rule.lhs,
+                            rhs: vec![Symbol::NonTerminal(nt)],
+                            out: out.clone(),
+                            ws: rule.ws,
+                        })
+                    }
+                }
+                Pattern::Keywords { keywords, field } => {
+                    // One production per keyword, with the keyword as a fixed field
+                    productions.extend(keywords.iter().map(|keyword| {
+                        let mut out = OutSpec::from(rule.rhs.clone());
+                        if let OutSpec::Resource { fields, .. } | OutSpec::Dict(fields) = &mut out {
+                            fields.push((field.text, ValueSpec::StringLiteral(*keyword)));
+                        }
+                        Production {
+                            lhs: rule.lhs,
+                            rhs: vec![Symbol::Terminal(*keyword)],
+                            out: out.constrained(&rule.constraints),
+                            ws: rule.ws,
+                        }
                     }))
                 }
             }
         }
+        // repetitions read the list nonterminal they add, after the productions of the rules
+        let mut internal = Vec::new();
+        for p in &mut productions {
+            for sym in &mut p.rhs {
+                if let Symbol::Repetition {
+                    source,
+                    name,
+                    typ,
+                    separator,
+                    at_least_one,
+                } = *sym
+                {
+                    let repeat = (separator, at_least_one);
+                    let list = repetition_productions(source, typ, repeat, p.ws, &mut internal);
+                    *sym = Symbol::Placeholder {
+                        name,
+                        typ: list,
+                        optional: false,
+                    };
+                }
+            }
+        }
+        // `{~}` refers to a run of whitespace, matched by one collapsed whitespace terminal
+        let ws_run = productions
+            .iter()
+            .chain(&internal)
+            .flat_map(|p| &p.rhs)
+            .find_map(|sym| match sym {
+                Symbol::NonTerminal(nt) if nt.text == WS_RUN => Some(*nt),
+                _ => None,
+            });
+        if let Some(lhs) = ws_run {
+            productions.push(Production {
+                lhs,
+                rhs: vec![Symbol::Terminal(Str::new(" ", lhs.span))],
+                out: OutSpec::Dict(Vec::new()),
+                ws: WhitespaceMode::Collapse,
+            });
+        }
+        productions.extend(internal);
         Self { productions }
     }
 }
 
+/// The productions of the repetition `source` of `typ`, like `{items:Item}*(,)`, unless the
+/// same repetition already added them: `list -> ε | item rest` and `rest -> ε | sep item rest`.
+/// `list` is named `source`, and `rest` the same text without its last character, slices of the
+/// `dokedef` that no rule can be named. Returns `list`. The separator matches with any whitespace
+/// around it. Without one, like in `{items:Item*}`, items are separated by whitespace or a comma.
+/// `{items:Item+}` has no `list -> ε`.
+fn repetition_productions<'gr>(
+    source: Str<'gr>,
+    typ: Str<'gr>,
+    (separator, at_least_one): (Option<Str<'gr>>, bool),
+    ws: WhitespaceMode,
+    productions: &mut Vec<Production<'gr>>,
+) -> Str<'gr> {
+    let list = source;
+    if productions.iter().any(|p| p.lhs.text == list.text) {
+        return list;
+    }
+    let last = list.text.chars().next_back().map_or(0, char::len_utf8);
+    let end = list.text.len() - last;
+    let rest = Str::new(
+        &list.text[..end],
+        (list.span.start..list.span.end - last).into(),
+    );
+    // the items are the values of the list, not fields
+    let item = Symbol::Placeholder {
+        name: Str::new(ANONYMOUS, typ.span),
+        typ,
+        optional: false,
+    };
+    let list_production = |lhs, rhs| Production {
+        lhs,
+        rhs,
+        out: OutSpec::List(rest.text),
+        ws,
+    };
+    if !at_least_one {
+        productions.push(list_production(list, vec![]));
+    }
+    productions.extend([
+        list_production(list, vec![item, Symbol::NonTerminal(rest)]),
+        list_production(rest, vec![]),
+    ]);
+    let sep_span = separator.map_or(typ.span, |s| s.span);
+    let ws_run = Symbol::NonTerminal(Str::new(WS_RUN, sep_span));
+    let around = |text| {
+        let text = Symbol::Terminal(Str::new(text, sep_span));
+        vec![
+            vec![text],
+            vec![ws_run, text],
+            vec![text, ws_run],
+            vec![ws_run, text, ws_run],
+        ]
+    };
+    let separators = match separator.map(|s| s.text.trim()) {
+        Some("") => vec![vec![ws_run]],
+        Some(text) => around(text),
+        None => [vec![vec![ws_run]], around(",")].concat(),
+    };
+    // `rest -> sep item rest`, for each way to write the separator
+    productions.extend(separators.into_iter().map(|mut rhs| {
+        rhs.extend([item, Symbol::NonTerminal(rest)]);
+        list_production(rest, rhs)
+    }));
+    list
+}
+
+/// Whether `name` is a nonterminal the grammar adds for its repetitions, like `{items:Item*}`,
+/// or for `{~}`. Users can't define them, so they aren't listed with the rules of the `dokedef`.
+pub fn is_internal(name: &str) -> bool {
+    name.starts_with('{') || name == WS_RUN
+}
+
+/// The output of a pattern's production, as its `rhs` says
+fn pattern_out<'gr>(symbols: &[Symbol<'gr>], rhs: &Option<RuleRhs<'gr>>) -> OutSpec<'gr> {
+    let keyword = rhs.is_none()
+        && !symbols.is_empty()
+        && symbols.iter().all(|s| matches!(s, Symbol::Terminal(_)));
+    // `-> amount`, naming one of the rule's placeholders, yields its value
+    let placeholder_output = match rhs {
+        Some(RuleRhs::Type(out)) => symbols
+            .iter()
+            .filter_map(Symbol::placeholder_name)
+            .any(|name| name.text == out.text)
+            .then_some(*out),
+        _ => None,
+    };
+    if keyword {
+        OutSpec::Keyword
+    } else if let Some(name) = placeholder_output {
+        OutSpec::Value(ValueSpec::Identifier(name))
+    } else {
+        OutSpec::from(rhs.clone())
+    }
+}
+
+/// The production of a quoted disjunction alternative: its matched text if it's only
+/// literal text, else a dictionary of its placeholders.
+fn quoted_production<'gr>(
+    source: Str<'gr>,
+    symbols: &[Symbol<'gr>],
+    ws: WhitespaceMode,
+) -> Production<'gr> {
+    let out = if symbols.iter().all(|s| matches!(s, Symbol::Terminal(_))) {
+        let inner = source.text.trim_matches('"');
+        let start = source.span.start + 1;
+        OutSpec::Value(ValueSpec::StringLiteral(Str::new(
+            inner,
+            SimpleSpan::from(start..start + inner.len()),
+        )))
+    } else {
+        OutSpec::Dict(Vec::new())
+    };
+    Production {
+        lhs: source,
+        rhs: symbols.to_vec(),
+        out,
+        ws,
+    }
+}
+
+/// The name of placeholders written `{_:Int}` or `{:Int}`, which match without adding a field.
+pub const ANONYMOUS: &str = "_";
+
+/// The nonterminal written `{~}` in patterns, matching one or more whitespace characters.
+pub const WS_RUN: &str = "~";
+
+/// `@alias Amount = Int`, another name for a builtin type within a `dokedef`
+#[derive(Debug, Clone, Copy)]
+pub struct Alias<'gr> {
+    pub name: Str<'gr>,
+    pub target: Str<'gr>,
+}
+
+/// `@const CRIT_MULT = 2`, a literal that output fields can use by name
+#[derive(Debug, Clone, Copy)]
+pub struct Const<'gr> {
+    pub name: Str<'gr>,
+    pub value: ValueSpec<'gr>,
+}
+
+/// `@default_output dict`, what the rules of a `dokedef` written without an output give
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultOutput {
+    /// A dictionary of the rule's placeholders, even for rules of plain text
+    Dict,
+    /// A resource typed by the rule's LHS, like `-> Lhs`
+    Resource,
+    /// The value of the rule's only placeholder, like `-> name`
+    Transparent,
+}
+
+/// A line starting with `@` before the rules of a `dokedef`
+#[derive(Debug, Clone, Copy)]
+pub enum Directive<'gr> {
+    Alias(Alias<'gr>),
+    Const(Const<'gr>),
+    DefaultOutput(Str<'gr>, DefaultOutput),
+}
+
+/// Chumsky Parser for a whole `dokedef` : its `@alias`, `@const` and `@default_output`
+/// directives, then its rules
+pub fn dokedef<'gr>(
+) -> impl Parser<'gr, &'gr str, (Vec<Directive<'gr>>, Vec<Rule<'gr>>), extra::Err<Rich<'gr, char>>>
+{
+    let directive = choice((
+        alias_directive().map(Directive::Alias),
+        const_directive().map(Directive::Const),
+        default_output_directive(),
+    ));
+    let skipped = choice((comment(), text::whitespace().at_least(1))).repeated();
+    directive
+        .padded_by(skipped)
+        .repeated()
+        .collect()
+        .then(rules())
+}
+
+/// A `# comment`, up to the end of its line
+fn comment<'gr>() -> impl Parser<'gr, &'gr str, (), extra::Err<Rich<'gr, char>>> + Clone {
+    just('#').then(none_of('\n').repeated()).ignored()
+}
+
+fn const_directive<'gr>() -> impl Parser<'gr, &'gr str, Const<'gr>, extra::Err<Rich<'gr, char>>> {
+    just("@const")
+        .ignore_then(ident().padded_by(inline_whitespace()))
+        .then_ignore(just('='))
+        .then(choice((string_literal(), number_literal())).padded_by(inline_whitespace()))
+        .map(|(name, value)| Const { name, value })
+        .labelled("constant")
+}
+
+fn default_output_directive<'gr>(
+) -> impl Parser<'gr, &'gr str, Directive<'gr>, extra::Err<Rich<'gr, char>>> {
+    let mode = choice((
+        just("dict").to(DefaultOutput::Dict),
+        just("resource").to(DefaultOutput::Resource),
+        just("transparent").to(DefaultOutput::Transparent),
+    ));
+    just("@default_output")
+        .map_with(|s, extra| Str::new(s, extra.span()))
+        .then(mode.padded_by(inline_whitespace()))
+        .map(|(directive, mode)| Directive::DefaultOutput(directive, mode))
+        .labelled("default output")
+}
+
+fn alias_directive<'gr>() -> impl Parser<'gr, &'gr str, Alias<'gr>, extra::Err<Rich<'gr, char>>> {
+    just("@alias")
+        .ignore_then(ident().padded_by(inline_whitespace()))
+        .then_ignore(just('='))
+        .then(ident().padded_by(inline_whitespace()))
+        .map(|(name, target)| Alias { name, target })
+        .labelled("alias")
+}
+
 /// Chumsky Parser for a Vec of Rules, applying defaults for optional RHS (You can expect RHS to be Some)
 pub fn rules<'gr>() -> impl Parser<'gr, &'gr str, Vec<Rule<'gr>>, extra::Err<Rich<'gr, char>>> {
     rules_raw().map_with(|r, _extra| {
         r.clone().iter_mut().for_each(|r| {
-            if let None = r.rhs {
-                r.rhs = Some(RuleRhs::Type(r.lhs.clone()))
+            if r.rhs.is_none() {
+                r.rhs = Some(RuleRhs::Type(r.lhs))
             }
         });
         r
@@ -180,67 +703,472 @@ pub fn rules<'gr>() -> impl Parser<'gr, &'gr str, Vec<Rule<'gr>>, extra::Err<Ric
 }
 
 pub fn rules_raw<'gr>() -> impl Parser<'gr, &'gr str, Vec<Rule<'gr>>, extra::Err<Rich<'gr, char>>> {
-    choice((normal_rule(), transparent_rule()))
+    // the end of a line, after a rule or on its own, with an optional comment
+    let line_end = comment()
+        .or_not()
+        .then(newline())
+        .padded_by(inline_whitespace());
+    let separator = choice((
+        line_end
+            .clone()
+            .repeated()
+            .then(just(';').padded_by(inline_whitespace()))
+            .then(line_end.clone().repeated())
+            .ignored(),
+        line_end.repeated().at_least(1),
+    ));
+    let rule = choice((keyword_rule(), normal_rule(), transparent_rule())).map(|r| vec![r]);
+    choice((rule_group(), rule))
         .padded_by(inline_whitespace())
-        .separated_by(
-            just(';')
-                .padded()
-                .ignored()
-                .or(newline().repeated().at_least(1)),
-        )
+        .separated_by(separator)
         .allow_trailing()
         .allow_leading()
-        .collect()
+        .collect::<Vec<_>>()
+        .map(|groups| groups.concat())
+        // a comment on the last line, without a newline after it
+        .then_ignore(comment().padded_by(inline_whitespace()).or_not())
+}
+
+/// Patterns sharing an output, each with its own extra fields, expanded into one rule each:
+///
+/// ```text
+/// Target: {
+///     "self" { kind: "self" }
+///     "an ally" { kind: "ally" }
+/// } -> Target { category: "targeting" }
+/// ```
+///
+/// A field of a pattern replaces the shared field of the same name.
+fn rule_group<'gr>() -> impl Parser<'gr, &'gr str, Vec<Rule<'gr>>, extra::Err<Rich<'gr, char>>> {
+    let own_fields = just('{')
+        .padded()
+        .ignore_then(fields_parser())
+        .padded()
+        .then_ignore(just('}'));
+    let entry = pattern_in_quotes()
+        .then(ws_annotation().padded_by(inline_whitespace()).or_not())
+        .then(own_fields.or_not())
+        .padded();
+    ident()
+        .then_ignore(just(':').padded())
+        .then(
+            entry
+                .repeated()
+                .at_least(1)
+                .collect::<Vec<_>>()
+                .delimited_by(just('{'), just('}')),
+        )
+        .then(output())
+        .try_map(|((lhs, entries), (arrow, rhs, constraints)), span| {
+            let expand = |pattern: &[Symbol<'gr>], own: Option<Vec<(Str<'gr>, ValueSpec<'gr>)>>| {
+                // `-> amount` gives the value of the entry's `amount` placeholder, not a resource
+                let names_placeholder = |name: &Str<'gr>| {
+                    pattern
+                        .iter()
+                        .any(|s| s.placeholder_name().is_some_and(|n| n.text == name.text))
+                };
+                let merge = |shared: &[(Str<'gr>, ValueSpec<'gr>)]| {
+                    let own = own.clone().unwrap_or_default();
+                    let mut fields: Vec<_> = shared
+                        .iter()
+                        .filter(|(k, _)| !own.iter().any(|(o, _)| o.text == k.text))
+                        .copied()
+                        .collect();
+                    fields.extend(own);
+                    fields
+                };
+                match &rhs {
+                    RuleRhs::Type(name) if own.is_none() => Ok(RuleRhs::Type(*name)),
+                    RuleRhs::Type(name) if !names_placeholder(name) => {
+                        Ok(RuleRhs::TypeWithFields {
+                            name: *name,
+                            fields: merge(&[]),
+                        })
+                    }
+                    RuleRhs::TypeWithFields { name, fields } => Ok(RuleRhs::TypeWithFields {
+                        name: *name,
+                        fields: merge(fields),
+                    }),
+                    RuleRhs::Dictionary(fields) => Ok(RuleRhs::Dictionary(merge(fields))),
+                    other if own.is_none() => Ok(other.clone()),
+                    _ => Err(Rich::custom(
+                        span,
+                        "fields of a rule group entry need a resource or dictionary output",
+                    )),
+                }
+            };
+            entries
+                .into_iter()
+                .map(|((pattern, ws), own)| {
+                    let rhs = expand(&pattern, own)?;
+                    Ok(Rule {
+                        lhs,
+                        pattern: Pattern::Normal(pattern),
+                        rhs: Some(rhs),
+                        ws: ws.unwrap_or_default(),
+                        arrow: Some(arrow),
+                        constraints: constraints.clone(),
+                    })
+                })
+                .collect()
+        })
+        .labelled("rule group")
 }
 
 fn transparent_rule<'gr>() -> impl Parser<'gr, &'gr str, Rule<'gr>, extra::Err<Rich<'gr, char>>> {
     ident()
         .then_ignore(just(':').padded())
-        .then(ident().separated_by(just('|').padded()).collect::<Vec<_>>())
+        .then(
+            alternative()
+                .separated_by(just('|').padded())
+                .collect::<Vec<_>>(),
+        )
+        .then(
+            choice((
+                just("@tagged").padded_by(inline_whitespace()).to((
+                    None,
+                    RuleRhs::Tagged,
+                    Vec::new(),
+                )),
+                // `-> { category: "elemental" }`, fields added to each alternative's value
+                output().map(|(arrow, rhs, constraints)| (Some(arrow), rhs, constraints)),
+            ))
+            .or_not(),
+        )
         .padded_by(inline_whitespace())
-        .map_with(|(lhs, pattern), _extra| Rule {
-            lhs,
-            pattern: Pattern::Disjunction(
-                pattern.iter().map(|x| Symbol::NonTerminal(*x)).collect(),
-            ),
-            rhs: Some(RuleRhs::Transparent),
+        .map_with(|((lhs, mut pattern), mut out), _extra| {
+            // an output after the last alternative only is the disjunction's
+            let own_outputs = pattern
+                .iter()
+                .filter_map(|a| match a {
+                    Alternative::Quoted { output, .. } => output.as_ref(),
+                    _ => None,
+                })
+                .count();
+            if let (None, 1, Some(Alternative::Quoted { source, output, .. })) =
+                (&out, own_outputs, pattern.last_mut())
+            {
+                if let Some((arrow, rhs, constraints)) = output.take() {
+                    let text = source.text[..arrow.span.start - source.span.start].trim_end();
+                    let start = source.span.start;
+                    *source = Str::new(text, SimpleSpan::from(start..start + text.len()));
+                    out = Some((Some(arrow), rhs, constraints));
+                }
+            }
+            let (arrow, rhs, constraints) = out.unwrap_or((None, RuleRhs::Transparent, Vec::new()));
+            Rule {
+                lhs,
+                pattern: Pattern::Disjunction(pattern),
+                rhs: Some(rhs),
+                ws: WhitespaceMode::Exact,
+                arrow,
+                constraints,
+            }
         })
         .labelled("rule")
 }
 
+/// A nonterminal or a quoted pattern, in which a `|` is plain text: `"a|b" | Other`.
+/// A quoted pattern can have its own output, `"fast" -> { fast: true }`.
+fn alternative<'gr>() -> impl Parser<'gr, &'gr str, Alternative<'gr>, extra::Err<Rich<'gr, char>>> {
+    let quoted = pattern_in_quotes().then(output().or_not());
+    let quoted = quoted.map_with(|(symbols, output), extra| {
+        let source: &str = extra.slice();
+        let source = source.trim_end();
+        let start = extra.span().start;
+        Alternative::Quoted {
+            source: Str::new(source, SimpleSpan::from(start..start + source.len())),
+            symbols,
+            output,
+        }
+    });
+    choice((ident().map(Alternative::NonTerminal), quoted))
+}
+
+/// `Target: "self" | "ally" @keyword(kind) -> Target`, where the matched keyword is the `kind` field.
+fn keyword_rule<'gr>() -> impl Parser<'gr, &'gr str, Rule<'gr>, extra::Err<Rich<'gr, char>>> {
+    let keyword = none_of('"')
+        .repeated()
+        .at_least(1)
+        .to_slice()
+        .map_with(|s, extra| Str::new(s, extra.span()))
+        .delimited_by(just('"'), just('"'));
+    ident()
+        .then_ignore(just(':').padded())
+        .then(keyword.separated_by(just('|').padded()).at_least(1).collect())
+        .then_ignore(just("@keyword").padded_by(inline_whitespace()))
+        .then(ident().padded().delimited_by(just('('), just(')')))
+        .then(output().or_not())
+        .padded_by(inline_whitespace())
+        .map_with(|(((lhs, keywords), field), out), _extra| {
+            let (arrow, rhs, constraints) = split_output(out);
+            Rule {
+                lhs,
+                pattern: Pattern::Keywords { keywords, field },
+                rhs,
+                ws: WhitespaceMode::Exact,
+                arrow,
+                constraints,
+            }
+        })
+        .labelled("keyword rule")
+}
+
 fn normal_rule<'gr>() -> impl Parser<'gr, &'gr str, Rule<'gr>, extra::Err<Rich<'gr, char>>> {
     ident()
         .then_ignore(just(':').padded())
-        .then(pattern_in_quotes().padded())
+        // not past the end of the line, for rules without an output
+        .then(pattern_in_quotes().padded_by(inline_whitespace()))
+        // else it's the first alternative of a disjunction
+        .then_ignore(just('|').padded().not())
         .padded_by(inline_whitespace())
-        .then(
-            choice((just("=>"), just("->")))
-                .padded()
-                .ignore_then(out_spec_parser())
-                .or_not(),
-        )
-        .map_with(|((lhs, pattern), opt_rhs), _extra| Rule {
-            lhs,
-            pattern: Pattern::Normal(pattern),
-            rhs: opt_rhs,
+        .then(ws_annotation().padded_by(inline_whitespace()).or_not())
+        .then(output().or_not())
+        // else it's the first alternative of a disjunction, with its own output
+        .then_ignore(just('|').padded().not())
+        .map_with(|(((lhs, pattern), ws), out), _extra| {
+            let (arrow, rhs, constraints) = split_output(out);
+            Rule {
+                lhs,
+                pattern: Pattern::Normal(pattern),
+                rhs,
+                ws: ws.unwrap_or_default(),
+                arrow,
+                constraints,
+            }
         })
         .labelled("rule")
 }
 
+/// What follows a rule's pattern: `-> Buff { amount: n } where n > 0`
+pub type Output<'gr> = (Str<'gr>, RuleRhs<'gr>, Vec<Constraint<'gr>>);
+
+fn output<'gr>() -> impl Parser<'gr, &'gr str, Output<'gr>, extra::Err<Rich<'gr, char>>> {
+    arrow()
+        .padded()
+        .then(out_spec_parser())
+        .then(where_clause().or_not())
+        .map(|((arrow, rhs), constraints)| (arrow, rhs, constraints.unwrap_or_default()))
+}
+
+impl<'gr> Rule<'gr> {
+    /// Gives an output to this rule if it was written without one, following `default`.
+    /// A `Transparent` default only applies to rules with a single placeholder.
+    pub fn apply_default_output(&mut self, default: DefaultOutput) {
+        let Pattern::Normal(symbols) = &self.pattern else {
+            return;
+        };
+        if self.rhs.is_some() {
+            return;
+        }
+        self.rhs = match default {
+            DefaultOutput::Dict => Some(RuleRhs::Dictionary(Vec::new())),
+            DefaultOutput::Resource => Some(RuleRhs::Type(self.lhs)),
+            DefaultOutput::Transparent => {
+                let mut names = symbols.iter().filter_map(Symbol::placeholder_name);
+                match (names.next(), names.next()) {
+                    (Some(name), None) => Some(RuleRhs::Type(name)),
+                    _ => None,
+                }
+            }
+        };
+    }
+}
+
+/// The arrow, output and constraints of a rule, for the fields of `Rule`
+fn split_output(
+    out: Option<Output<'_>>,
+) -> (Option<Str<'_>>, Option<RuleRhs<'_>>, Vec<Constraint<'_>>) {
+    match out {
+        Some((arrow, rhs, constraints)) => (Some(arrow), Some(rhs), constraints),
+        None => (None, None, Vec::new()),
+    }
+}
+
+/// `where amount > 0 and kind != "none"`, comparing fields of the output to literals
+fn where_clause<'gr>(
+) -> impl Parser<'gr, &'gr str, Vec<Constraint<'gr>>, extra::Err<Rich<'gr, char>>> {
+    let comparison = choice((
+        just(">=").to(Comparison::GreaterOrEqual),
+        just("<=").to(Comparison::LessOrEqual),
+        just("==").to(Comparison::Equal),
+        just("!=").to(Comparison::NotEqual),
+        just('>').to(Comparison::Greater),
+        just('<').to(Comparison::Less),
+    ));
+    let constraint = ident()
+        .then(comparison.padded_by(inline_whitespace()))
+        .then(choice((string_literal(), number_literal())))
+        .map(|((field, op), value)| Constraint { field, op, value });
+    just("where")
+        .padded_by(inline_whitespace())
+        .ignore_then(
+            constraint
+                .separated_by(just("and").padded_by(inline_whitespace()))
+                .at_least(1)
+                .collect(),
+        )
+        .labelled("where clause")
+}
+
+/// `->` or `=>`, between a rule's pattern and its output
+fn arrow<'gr>() -> impl Parser<'gr, &'gr str, Str<'gr>, extra::Err<Rich<'gr, char>>> {
+    choice((just("=>"), just("->"))).map_with(|s, extra| Str::new(s, extra.span()))
+}
+
+/// `@ws:exact` or `@ws:collapse`, placed between a rule's pattern and its arrow.
+fn ws_annotation<'gr>() -> impl Parser<'gr, &'gr str, WhitespaceMode, extra::Err<Rich<'gr, char>>>
+{
+    just("@ws:")
+        .ignore_then(choice((
+            just("exact").to(WhitespaceMode::Exact),
+            just("collapse").to(WhitespaceMode::Collapse),
+        )))
+        .labelled("whitespace annotation")
+}
+
 fn ident<'gr>() -> impl Parser<'gr, &'gr str, Str<'gr>, extra::Err<Rich<'gr, char>>> {
     text::ident().map_with(|s, extra| Str::new(s, extra.span()))
 }
 
+/// `{~}`, one or more whitespace characters
+fn ws_run<'gr>() -> impl Parser<'gr, &'gr str, Symbol<'gr>, extra::Err<Rich<'gr, char>>> {
+    just(WS_RUN)
+        .map_with(|s, extra| Symbol::NonTerminal(Str::new(s, extra.span())))
+        .delimited_by(just('{'), just('}'))
+        .labelled("whitespace run")
+}
+
+/// A placeholder, optional when written `{t:Target?}`.
 fn placeholder<'gr>() -> impl Parser<'gr, &'gr str, Symbol<'gr>, extra::Err<Rich<'gr, char>>> {
+    placeholder_parts()
+        .filter(|(_, suffix)| matches!(suffix, None | Some('?')))
+        .map(|(sym, suffix)| match sym {
+            Symbol::Placeholder { name, typ, .. } => Symbol::Placeholder {
+                name,
+                typ,
+                optional: suffix.is_some(),
+            },
+            other => other,
+        })
+        .labelled("placeholder")
+}
+
+/// A placeholder, with the `*`, `+` or `?` of `{items:Item*}`, `{items:Item+}` and `{t:Target?}`
+/// if it has one.
+fn placeholder_parts<'gr>(
+) -> impl Parser<'gr, &'gr str, (Symbol<'gr>, Option<char>), extra::Err<Rich<'gr, char>>> {
+    // `{:Int}` is anonymous, like `{_:Int}`
+    let name = ident()
+        .or_not()
+        .map_with(|name, extra| name.unwrap_or(Str::new(ANONYMOUS, extra.span())));
     just('{')
-        .ignore_then(ident().padded())
+        .ignore_then(name.padded())
         .then_ignore(just(':').padded())
-        .then(ident().padded())
+        .then(choice((sub_grammar_type(), regex_type(), exclusion_type(), ident())).padded())
+        .then(one_of("*+?").or_not())
         .then_ignore(just('}'))
-        .map(|(name, typ)| Symbol::Placeholder { name, typ })
+        .map(|((name, typ), repeat)| {
+            let placeholder = Symbol::Placeholder {
+                name,
+                typ,
+                optional: false,
+            };
+            (placeholder, repeat)
+        })
         .labelled("placeholder")
 }
 
+/// A placeholder, or `{c:Int}{3}` for exactly three of them separated by whitespace,
+/// the same name repeated so that their values are collected in an array.
+fn repeated_placeholder<'gr>(
+) -> impl Parser<'gr, &'gr str, Vec<Symbol<'gr>>, extra::Err<Rich<'gr, char>>> {
+    let count = text::int(10)
+        .padded()
+        .delimited_by(just('{'), just('}'))
+        .try_map(|n: &str, span| match n.parse::<usize>() {
+            Ok(n) if n > 0 => Ok((n, span)),
+            _ => Err(Rich::custom(span, "a placeholder repeats at least once")),
+        });
+    placeholder()
+        .then(count.or_not())
+        .map(|(sym, count)| match count {
+            None => vec![sym],
+            Some((n, span)) => {
+                let separator = Symbol::NonTerminal(Str::new(WS_RUN, span));
+                let mut symbols = vec![sym];
+                for _ in 1..n {
+                    symbols.extend([separator, sym]);
+                }
+                symbols
+            }
+        })
+}
+
+/// `{items:Item}*(,)`, a placeholder read any number of times with the separator between them,
+/// or `{items:Item*}` and `{items:Item+}` with the default separator.
+fn repetition<'gr>() -> impl Parser<'gr, &'gr str, Symbol<'gr>, extra::Err<Rich<'gr, char>>> {
+    let separated = placeholder_parts()
+        .filter(|(_, suffix)| suffix.is_none())
+        .then_ignore(just("*("))
+        .then(
+            none_of(')')
+                .repeated()
+                .at_least(1)
+                .to_slice()
+                .map_with(|s, extra| Str::new(s, extra.span())),
+        )
+        .then_ignore(just(')'))
+        .map(|((item, _), separator)| (item, Some(separator), false));
+    let suffixed = placeholder_parts().try_map(|(item, repeat), span| match repeat {
+        Some(repeat) if repeat != '?' => Ok((item, None, repeat == '+')),
+        _ => Err(Rich::custom(span, "a placeholder that doesn't repeat")),
+    });
+    choice((separated, suffixed))
+        .map_with(|(item, separator, at_least_one), extra| {
+            let Symbol::Placeholder { name, typ, .. } = item else {
+                unreachable!("a repetition repeats a placeholder")
+            };
+            Symbol::Repetition {
+                source: Str::new(extra.slice(), extra.span()),
+                name,
+                typ,
+                separator,
+                at_least_one,
+            }
+        })
+        .labelled("repetition")
+}
+
+/// `@Name`, a placeholder type parsed by the sub-grammar registered as `Name`
+fn sub_grammar_type<'gr>() -> impl Parser<'gr, &'gr str, Str<'gr>, extra::Err<Rich<'gr, char>>> {
+    just('@')
+        .then(text::ident())
+        .to_slice()
+        .map_with(|s, extra| Str::new(s, extra.span()))
+}
+
+/// `/regex/`, a placeholder type matching the input with a regular expression.
+/// `\/` stands for a `/` inside it.
+fn regex_type<'gr>() -> impl Parser<'gr, &'gr str, Str<'gr>, extra::Err<Rich<'gr, char>>> {
+    let escaped_slash = just("\\/").ignored();
+    just('/')
+        .then(escaped_slash.or(none_of('/').ignored()).repeated().at_least(1))
+        .then(just('/'))
+        .to_slice()
+        .map_with(|s, extra| Str::new(s, extra.span()))
+        .labelled("regex")
+}
+
+/// `!word`, a placeholder type matching one token anywhere the input doesn't read `word`.
+fn exclusion_type<'gr>() -> impl Parser<'gr, &'gr str, Str<'gr>, extra::Err<Rich<'gr, char>>> {
+    just('!')
+        .then(none_of("} \t\r\n").repeated().at_least(1))
+        .to_slice()
+        .map_with(|s, extra| Str::new(s, extra.span()))
+        .labelled("exclusion")
+}
+
 fn terminal_text<'gr>() -> impl Parser<'gr, &'gr str, Symbol<'gr>, extra::Err<Rich<'gr, char>>> {
     any()
         .filter(|c: &char| *c != '{' && *c != '"')
@@ -255,18 +1183,31 @@ fn pattern_in_quotes<'gr>(
 ) -> impl Parser<'gr, &'gr str, Vec<Symbol<'gr>>, extra::Err<Rich<'gr, char>>> {
     just('"')
         .ignore_then(
-            choice((placeholder(), terminal_text()))
-                .repeated()
-                .collect(),
+            choice((
+                ws_run().map(|sym| vec![sym]),
+                repetition().map(|sym| vec![sym]),
+                repeated_placeholder(),
+                terminal_text().map(|sym| vec![sym]),
+            ))
+            .repeated()
+            .collect::<Vec<_>>()
+            .map(|symbols| symbols.concat()),
         )
-        .then_ignore(just('"').padded())
+        .then_ignore(just('"'))
         .labelled("pattern in quotes")
 }
 
 fn string_literal<'gr>() -> impl Parser<'gr, &'gr str, ValueSpec<'gr>, extra::Err<Rich<'gr, char>>>
 {
+    // escapes are kept here, see `Str::unescaped`
+    let escape = just('\\').then(any()).ignored();
     just('"')
-        .ignore_then(any().filter(|c| *c != '"').repeated().to_slice())
+        .ignore_then(
+            escape
+                .or(any().filter(|c| *c != '"').ignored())
+                .repeated()
+                .to_slice(),
+        )
         .then_ignore(just('"'))
         .map_with(|s, extra| ValueSpec::StringLiteral(Str::new(s, extra.span())))
         .labelled("string literal")
@@ -277,11 +1218,49 @@ fn number_literal<'gr>() -> impl Parser<'gr, &'gr str, ValueSpec<'gr>, extra::Er
     numbers::number_literal().labelled("number literal")
 }
 
+fn cast<'gr>() -> impl Parser<'gr, &'gr str, ValueSpec<'gr>, extra::Err<Rich<'gr, char>>> {
+    choice((
+        just("int").to(Cast::Int),
+        just("float").to(Cast::Float),
+        just("string").to(Cast::String),
+        just("str").to(Cast::String),
+        just("bool").to(Cast::Bool),
+    ))
+    .then(
+        ident()
+            .padded()
+            .delimited_by(just('(').padded_by(inline_whitespace()), just(')')),
+    )
+    .map(|(cast, name)| ValueSpec::Cast(cast, name))
+    .labelled("cast")
+}
+
+/// `c[0]`, an element of a repeated placeholder
+fn index<'gr>() -> impl Parser<'gr, &'gr str, ValueSpec<'gr>, extra::Err<Rich<'gr, char>>> {
+    let position = text::int(10)
+        .padded()
+        .delimited_by(just('['), just(']'))
+        .try_map(|i: &str, span| {
+            i.parse::<usize>()
+                .map_err(|_| Rich::custom(span, "index too large"))
+        });
+    ident()
+        .then(position)
+        .map(|(name, i)| ValueSpec::Index(name, i))
+        .labelled("index")
+}
+
 fn field_value<'gr>() -> impl Parser<'gr, &'gr str, ValueSpec<'gr>, extra::Err<Rich<'gr, char>>> {
     choice((
         string_literal(),
         number_literal(),
-        ident().map(ValueSpec::Identifier),
+        cast(),
+        index(),
+        ident().map(|name| match name.text {
+            "true" => ValueSpec::BoolLiteral(true),
+            "false" => ValueSpec::BoolLiteral(false),
+            _ => ValueSpec::Identifier(name),
+        }),
     ))
 }
 
@@ -353,9 +1332,7 @@ fn dict_out_spec<'gr>() -> impl Parser<'gr, &'gr str, RuleRhs<'gr>, extra::Err<R
         .ignore_then(fields_parser())
         .padded()
         .then_ignore(just('}'))
-        .map_with(|opt_fields, _span| match opt_fields {
-            fields => RuleRhs::Dictionary(fields),
-        })
+        .map_with(|fields, _span| RuleRhs::Dictionary(fields))
         .labelled("output specification")
 }
 
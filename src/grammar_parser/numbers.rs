@@ -57,7 +57,8 @@ pub(crate) fn number_literal<'gr>(
             .map_err(|e| Rich::custom(span, format!("Invalid float: {}", e))),
 
         NumLit::DecInt(digits, sign) => {
-            let mut val = i64::from_str_radix(digits, 10)
+            let mut val = digits
+                .parse::<i64>()
                 .map_err(|e| Rich::custom(span, format!("Invalid decimal int: {}", e)))?;
             if sign == Some('-') {
                 val = -val;
@@ -219,6 +220,8 @@ mod tests {
     }
 
     #[test]
+    // `3.14` is a float literal to parse, not an approximation of pi
+    #[allow(clippy::approx_constant)]
     fn test_float_literals() {
         let cases = [
             ("1.5", 1.5),
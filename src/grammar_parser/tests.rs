@@ -2,6 +2,7 @@ use super::*;
 use chumsky::prelude::*;
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use super::*;
 
@@ -12,7 +13,7 @@ mod tests {
         }
     }
 
-    fn unwrap_disjunction<'gr>(pat: &'gr Pattern<'gr>) -> &'gr Vec<Symbol<'gr>> {
+    fn unwrap_disjunction<'gr>(pat: &'gr Pattern<'gr>) -> &'gr Vec<Alternative<'gr>> {
         match pat {
             Pattern::Disjunction(v) => v,
             _ => panic!("Expected Disjunction pattern"),
@@ -56,7 +57,7 @@ mod tests {
         let pattern = unwrap_normal(&rule.pattern);
         assert_eq!(pattern.len(), 1);
 
-        if let Symbol::Placeholder { name, typ } = &pattern[0] {
+        if let Symbol::Placeholder { name, typ, .. } = &pattern[0] {
             assert_eq!(*name, "action");
             assert_eq!(*typ, "String");
         } else {
@@ -74,7 +75,7 @@ mod tests {
         let pattern = unwrap_normal(&rule.pattern);
         assert_eq!(pattern.len(), 1);
 
-        if let Symbol::Placeholder { name, typ } = &pattern[0] {
+        if let Symbol::Placeholder { name, typ, .. } = &pattern[0] {
             assert_eq!(*name, "action");
             assert_eq!(*typ, "String");
         } else {
@@ -92,7 +93,7 @@ mod tests {
         let pattern = unwrap_normal(&rule.pattern);
         assert_eq!(pattern.len(), 3);
 
-        if let Symbol::Placeholder { name, typ } = &pattern[0] {
+        if let Symbol::Placeholder { name, typ, .. } = &pattern[0] {
             assert_eq!(*name, "verb");
             assert_eq!(*typ, "String");
         } else {
@@ -105,7 +106,7 @@ mod tests {
             panic!("Expected space terminal");
         }
 
-        if let Symbol::Placeholder { name, typ } = &pattern[2] {
+        if let Symbol::Placeholder { name, typ, .. } = &pattern[2] {
             assert_eq!(*name, "object");
             assert_eq!(*typ, "String");
         } else {
@@ -154,6 +155,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cast_field_values() {
+        let input = r#"Effect : "{a:String} {b:Int}" => { x: int(a), y: float( b ), integer: b }"#;
+        let result = rules().parse(input);
+
+        assert!(!result.has_errors());
+        let rule = &result.output().unwrap()[0];
+        if let Some(RuleRhs::Dictionary(fields)) = &rule.rhs {
+            assert!(matches!(fields[0].1, ValueSpec::Cast(Cast::Int, name) if name == "a"));
+            assert!(matches!(fields[1].1, ValueSpec::Cast(Cast::Float, name) if name == "b"));
+            assert!(matches!(fields[2].1, ValueSpec::Identifier(name) if name == "b"));
+        } else {
+            panic!("Expected Some(Dictionary)");
+        }
+    }
+
+    #[test]
+    fn test_escaped_string_literal() {
+        let input = r#"Talk : "talk" -> Msg { text: "he said \"hi\" \\o/" }"#;
+        let result = rules().parse(input);
+
+        assert!(!result.has_errors());
+        let rule = &result.output().unwrap()[0];
+        if let Some(RuleRhs::TypeWithFields { fields, .. }) = &rule.rhs {
+            match fields[0].1 {
+                ValueSpec::StringLiteral(s) => {
+                    assert_eq!(s.text, r#"he said \"hi\" \\o/"#);
+                    assert_eq!(s.unescaped(), r#"he said "hi" \o/"#);
+                }
+                other => panic!("Expected a string literal, got {:?}", other),
+            }
+        } else {
+            panic!("Expected Some(TypeWithFields)");
+        }
+    }
+
     #[test]
     fn test_implicit_output_type() {
         let input = r#"Something : "pattern with {place:Holders}""#;
@@ -191,6 +228,17 @@ Greeting : "Hi" => Message
         assert!(pattern.is_empty());
     }
 
+    #[test]
+    fn test_whitespace_annotation() {
+        let input = r#"
+Greeting : "Hello  there" @ws:collapse => Message
+Greeting : "Hi" => Message
+"#;
+        let result = rules().parse(input).unwrap();
+        assert_eq!(result[0].ws, WhitespaceMode::Collapse);
+        assert_eq!(result[1].ws, WhitespaceMode::Exact);
+    }
+
     #[test]
     fn test_disjunction_rule() {
         let input = r#"Foo : Bar | Baz | Bez"#;
@@ -199,12 +247,76 @@ Greeting : "Hi" => Message
 
         let alts = unwrap_disjunction(&rule.pattern);
         assert_eq!(alts.len(), 3);
-        for sym in alts {
-            if let Symbol::NonTerminal(nt) = sym {
+        for alt in alts {
+            if let Alternative::NonTerminal(nt) = alt {
                 assert!(["Bar", "Baz", "Bez"].contains(&nt.as_ref()));
             } else {
                 panic!("Expected nonterminal in disjunction");
             }
         }
     }
+
+    #[test]
+    fn test_keyword_rule() {
+        let input = r#"Target : "self" | "an ally" @keyword(kind) -> Target"#;
+        let result = rules().parse(input).unwrap();
+        match &result[0].pattern {
+            Pattern::Keywords { keywords, field } => {
+                let keywords: Vec<&str> = keywords.iter().map(|k| k.text).collect();
+                assert_eq!(keywords, ["self", "an ally"]);
+                assert_eq!(*field, "kind");
+            }
+            _ => panic!("Expected Keywords pattern"),
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "# effects\nEffect : \"hit #{n:Int}\" -> Hit { tag: \"#x\" } # a hit\n\n# end";
+        let result = rules().parse(input).unwrap();
+        assert_eq!(result.len(), 1);
+        let symbols = unwrap_normal(&result[0].pattern);
+        assert!(matches!(symbols[0], Symbol::Terminal(t) if t.text == "hit #"));
+        match &result[0].rhs {
+            Some(RuleRhs::TypeWithFields { fields, .. }) => {
+                assert!(matches!(fields[0].1, ValueSpec::StringLiteral(s) if s.text == "#x"));
+            }
+            other => panic!("Expected fields, got {other:?}"),
+        }
+
+        let comments: Vec<_> = highlighter::highlight_tokens(input, &result)
+            .into_iter()
+            .filter(|t| matches!(t.kind, highlighter::HighlightKind::Comment))
+            .map(|t| t.text)
+            .collect();
+        assert_eq!(comments, ["# effects", "# a hit", "# end"]);
+    }
+
+    #[test]
+    fn test_optional_placeholder() {
+        let input = r#"Command : "attack {target:Target?}" -> Attack"#;
+        let result = rules().parse(input).unwrap();
+        let symbols = unwrap_normal(&result[0].pattern);
+        assert_eq!(symbols.len(), 2);
+        assert!(matches!(symbols[0], Symbol::Terminal(t) if t.text == "attack "));
+        match symbols[1] {
+            Symbol::Placeholder {
+                name,
+                typ,
+                optional,
+            } => {
+                assert_eq!(name, "target");
+                assert_eq!(typ.text, "Target");
+                assert!(optional);
+            }
+            _ => panic!("Expected placeholder symbol"),
+        }
+
+        let types: Vec<_> = highlighter::highlight_tokens(input, &result)
+            .into_iter()
+            .filter(|t| matches!(t.kind, highlighter::HighlightKind::PlaceholderType))
+            .map(|t| t.text)
+            .collect();
+        assert_eq!(types, ["Target"]);
+    }
 }
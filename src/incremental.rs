@@ -0,0 +1,47 @@
+//! Inputs recognized again after small edits, see `Dokearley::parse_incremental`.
+use std::ops::Range;
+
+use crate::recognizer::{tokenize, Chart};
+use crate::{Dokearley, DokearleyError, Value};
+
+/// An input kept recognized as `start` while it is edited, like a line in a text editor.
+/// `edit` only recomputes the chart from the first position the edit can change.
+pub struct IncrementalParse<'gr, 'inp> {
+    parser: &'gr Dokearley<'gr>,
+    chart: Chart<'gr, 'inp>,
+    start: &'inp str,
+}
+
+impl<'gr, 'inp> IncrementalParse<'gr, 'inp> {
+    pub(crate) fn new(parser: &'gr Dokearley<'gr>, input: &'inp str, start: &'inp str) -> Self {
+        let input = parser.strip_trailing_punctuation(input);
+        let mut chart = parser.chart(tokenize(input), start);
+        chart.recognize_with_nullable(start, &parser.cache.nullable);
+        Self {
+            parser,
+            chart,
+            start,
+        }
+    }
+
+    /// Recognizes `edited` again, the input after replacing `byte_range` of the previous one.
+    pub fn edit(&mut self, byte_range: Range<usize>, edited: &'inp str) {
+        let edited = self.parser.strip_trailing_punctuation(edited);
+        let nullable = &self.parser.cache.nullable;
+        self.chart.edit(byte_range, edited, nullable);
+    }
+
+    /// Whether the current input is read as `start`.
+    pub fn accepted(&self) -> bool {
+        self.chart.accepted(self.start)
+    }
+
+    /// The value of the current input, like `Dokearley::parse` would give it.
+    pub fn value(&self) -> Result<Value, DokearleyError> {
+        let first_sets = &self.parser.cache.first_sets;
+        self.chart
+            .try_accept_with_first_sets(self.start, first_sets)?;
+        let tree = self.chart.build_parse_tree()?;
+        Ok(self.parser.public_value(self.parser.tree_value(&tree)?))
+    }
+}
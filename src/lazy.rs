@@ -0,0 +1,156 @@
+//! Values computed on demand from a parse tree, see `Dokearley::parse_lazy`.
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::rc::Rc;
+
+use self_cell::self_cell;
+
+use crate::grammar_parser::ANONYMOUS;
+use crate::parser::{is_absent, OutSpec, ParseTree};
+use crate::recognizer::{tokenize, Symbol};
+use crate::{Dokearley, DokearleyError, Value};
+
+/// A parse tree whose grammar and input both outlive `'a`.
+type Tree<'a> = ParseTree<'a, 'a>;
+
+self_cell!(
+    /// The parse tree of an input with the prepared input it reads,
+    /// which may be a normalized copy of the one given to `parse_lazy`.
+    struct PreparedTree<'inp> {
+        owner: Cow<'inp, str>,
+
+        #[covariant]
+        dependent: Tree,
+    }
+);
+
+impl std::fmt::Debug for PreparedTree<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.borrow_dependent().fmt(f)
+    }
+}
+
+/// A parsed input whose value is only computed for the parts that are read.
+/// `get` goes down to a field through the parse tree, as long as the field is a placeholder:
+/// other fields, like fixed ones, are read from the computed value of their resource.
+#[derive(Debug, Clone)]
+pub struct LazyValue<'gr, 'inp> {
+    parser: &'gr Dokearley<'gr>,
+    root: Rc<PreparedTree<'inp>>,
+    /// The child indices leading to this value's node
+    path: Vec<usize>,
+    /// Fields to read from the computed value of the node
+    fields: Vec<String>,
+    /// How many tree nodes had their value computed, shared by the lazy values of a parse
+    materialized: Rc<Cell<usize>>,
+}
+
+impl<'gr, 'inp> LazyValue<'gr, 'inp> {
+    /// Parses `input`, already prepared by `Dokearley::prepare_input`, as `start`.
+    pub(crate) fn new(
+        parser: &'gr Dokearley<'gr>,
+        input: Cow<'inp, str>,
+        start: &'inp str,
+    ) -> Result<Self, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let root = PreparedTree::try_new(input, |input| {
+            parser.bounded_tree(tokenize(input), start, Some(input), None)
+        })?;
+        Ok(Self {
+            parser,
+            root: Rc::new(root),
+            path: Vec::new(),
+            fields: Vec::new(),
+            materialized: Rc::new(Cell::new(0)),
+        })
+    }
+
+    /// The field `name` of this resource or dictionary, still not computed.
+    pub fn get(&self, name: &str) -> Self {
+        let mut field = self.clone();
+        if !field.fields.is_empty() {
+            field.fields.push(name.to_string());
+            return field;
+        }
+        // transparent rules hold their only child's value
+        while let ParseTree::Node { rule, .. } = field.node() {
+            if !matches!(rule.out, OutSpec::Transparent) {
+                break;
+            }
+            field.path.push(0);
+        }
+        match placeholder_child(field.node(), name) {
+            Some(i) => field.path.push(i),
+            None => field.fields.push(name.to_string()),
+        }
+        field
+    }
+
+    /// The value, `None` for a field the value doesn't have.
+    pub fn value(&self) -> Result<Option<Value>, DokearleyError> {
+        let node = self.node();
+        self.materialized
+            .set(self.materialized.get() + node_count(node));
+        let value = self.parser.tree_value(node)?;
+        let fields: Vec<&str> = self.fields.iter().map(String::as_str).collect();
+        Ok(value
+            .into_field(&fields)
+            .map(|value| self.parser.public_value(value)))
+    }
+
+    /// The value if it is an integer.
+    pub fn as_i64(&self) -> Result<Option<i64>, DokearleyError> {
+        Ok(match self.value()? {
+            Some(Value::Integer(i)) => Some(i),
+            _ => None,
+        })
+    }
+
+    /// How many parse tree nodes had their value computed so far, by all the lazy values
+    /// of this parse, to check that reading a field left the rest of the input alone.
+    pub fn materialized_nodes(&self) -> usize {
+        self.materialized.get()
+    }
+
+    fn node(&self) -> &ParseTree<'_, '_> {
+        let root = self.root.borrow_dependent();
+        self.path.iter().fold(root, |tree, &i| match tree {
+            ParseTree::Node { children, .. } => &children[i],
+            _ => unreachable!("paths only go through nodes"),
+        })
+    }
+}
+
+/// The child of `tree` holding the field `name`, when it is a placeholder that is the whole
+/// value of the field: used once, and not shadowed by a fixed field of the output.
+fn placeholder_child(tree: &ParseTree<'_, '_>, name: &str) -> Option<usize> {
+    let ParseTree::Node { rule, children } = tree else {
+        return None;
+    };
+    let (OutSpec::Resource { fields, .. } | OutSpec::Dict(fields)) = &rule.out else {
+        return None;
+    };
+    if name == ANONYMOUS || fields.iter().any(|(field, _)| *field == name) {
+        return None;
+    }
+    let mut placeholders = rule
+        .rhs
+        .iter()
+        .enumerate()
+        .filter(|(_, sym)| matches!(sym, Symbol::Placeholder { name: n, .. } if *n == name));
+    match (placeholders.next(), placeholders.next()) {
+        // a left out optional placeholder has no value of its own
+        (Some((i, _)), None) if !is_absent(&children[i]) => Some(i),
+        _ => None,
+    }
+}
+
+/// The number of nodes and leaves in `tree`.
+fn node_count(tree: &ParseTree<'_, '_>) -> usize {
+    match tree {
+        ParseTree::Node { children, .. } => 1 + children.iter().map(node_count).sum::<usize>(),
+        _ => 1,
+    }
+}
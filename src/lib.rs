@@ -26,28 +26,171 @@
 //! ```
 //! 
 use crate::{
-    grammar_parser::rules,
-    recognizer::{Chart, Grammar},
+    grammar_parser::{dokedef, Alias, Const, Directive, ValueSpec},
+    parse_cache::ParseCache,
+    scanners::Scanners,
+    parser::ParseTree,
+    recognizer::{Chart, KeywordIndex, Token},
 };
 use chumsky::Parser;
 use thiserror::Error;
+use unicode_segmentation::UnicodeSegmentation;
+mod annotations;
+#[cfg(feature = "compile")]
+mod compiled;
 mod conversion;
+mod diagnostics;
+mod incremental;
+mod lazy;
+#[doc(hidden)]
+pub mod macros;
 /// `dokedef` parser for the grammars, including highlighting utilities.
 pub mod grammar_parser;
 
+mod parse_cache;
 mod parser;
 mod recognizer;
+mod regex_types;
+mod scanners;
+#[cfg(feature = "serde")]
+mod serialize;
+mod token_stream;
 mod try_accept;
 
+pub use recognizer::TokenKind;
+/// The grammar a parser runs, for building one in code rather than from a `dokedef`.
+pub use recognizer::{Grammar, Production, Symbol};
+pub use parser::OutSpec;
+pub use annotations::META_FIELD;
+pub use diagnostics::{Diagnostic, Severity};
+pub use incremental::IncrementalParse;
+pub use indexmap::IndexMap;
+pub use lazy::LazyValue;
+pub use token_stream::{tokenize_reader, OwnedToken, TokenizeError};
+#[cfg(feature = "serde")]
+pub use serialize::{ResourceTagging, TaggedValue};
+
 #[cfg(test)]
 mod mock_values;
 
 #[derive(Debug, Clone)]
 pub struct Dokearley<'gr> {
     grammar: Grammar<'gr>,
+    cache: GrammarCache<'gr>,
+    parse_cache: ParseCache,
+    options: Options,
+    sub_grammars: SubGrammars<'gr>,
+    scanners: Scanners,
+}
+
+/// Grammars registered with `Dokearley::with_sub_grammar`, by name.
+pub(crate) type SubGrammars<'gr> = HashMap<String, Dokearley<'gr>>;
+
+/// Settings for building a `Dokearley`, see `Dokearley::from_dokedef_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// How many parsed values to keep for repeated `(input, start)` pairs,
+    /// like the same effect text on many cards. 0, the default, disables the cache.
+    pub parse_cache_capacity: usize,
+    /// Report `ParseError::pos` in grapheme clusters instead of tokens,
+    /// so that an emoji counts as one position in user-facing errors.
+    pub grapheme_positions: bool,
+    /// Characters stripped from the end of inputs before parsing, like `".!"`
+    /// so that `heal for 7.` reads as `heal for 7`. Empty, the default, keeps inputs as is.
+    pub trailing_punctuation: String,
+    /// The arrows rules may use before their output. Both `->` and `=>` by default.
+    pub allowed_arrows: AllowedArrows,
+    /// Read `#[key: value]` annotations at the start and end of inputs, like
+    /// `heal for 7 #[source:item42]`, into the `__meta__` dictionary of the parsed value. Off by default.
+    pub annotations: bool,
+    /// Custom placeholder types, like `Dice` for `{roll:Dice}`, read by the scanners
+    /// given to `Dokearley::register_scanner`. Until then, they match nothing.
+    pub scanner_types: Vec<String>,
+    /// Read inputs in Unicode NFC, so that an `é` typed as `e` and a combining accent still
+    /// matches the `é` of a terminal. The `dokedef` must then be in NFC too. Off by default.
+    #[cfg(feature = "unicode-normalization")]
+    pub nfc_normalization: bool,
+    /// Match terminals ignoring case and accents, so that `HÉAL` reads as `heal`, e.g. for a
+    /// search box. Placeholders and their values are untouched. Off by default.
+    #[cfg(feature = "unicode-normalization")]
+    pub fold_terminals: bool,
+    /// Collapse dictionaries with a single key to their value, see
+    /// `Value::unwrap_single_field_dicts`, for minimalist JSON targets. Off by default.
+    pub unwrap_single_field_dicts: bool,
+    /// Only start the rules whose first terminal matches the first token of the input,
+    /// for grammars with hundreds of keyword commands. Values are the same, but errors on
+    /// the first token then expect fewer terminals. Ignored with `fold_terminals`. Off by default.
+    pub keyword_dispatch: bool,
+    /// How many derivations `Dokearley::parse_all` reads at most, so that very ambiguous
+    /// grammars don't explode. 0, the default, stands for `DEFAULT_MAX_DERIVATIONS`.
+    pub max_derivations: usize,
+}
+
+/// The number of derivations `Dokearley::parse_all` reads when `Options::max_derivations` is 0.
+pub const DEFAULT_MAX_DERIVATIONS: usize = 64;
+
+/// Which arrows a `dokedef` may use, for teams settling on a single one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllowedArrows {
+    /// `->` and `=>`
+    #[default]
+    Both,
+    /// Only `->`
+    Thin,
+    /// Only `=>`
+    Fat,
+}
+
+impl AllowedArrows {
+    fn allows(self, arrow: &str) -> bool {
+        match self {
+            AllowedArrows::Both => true,
+            AllowedArrows::Thin => arrow == "->",
+            AllowedArrows::Fat => arrow == "=>",
+        }
+    }
+}
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Analyses that only depend on the shape of the grammar,
+/// computed once instead of on every parse.
+#[derive(Debug, Clone)]
+struct GrammarCache<'gr> {
+    nullable: HashSet<&'gr str>,
+    first_sets: HashMap<&'gr str, HashSet<Symbol<'gr>>>,
+    keyword_index: KeywordIndex<'gr>,
+    /// How many times the caches were computed, to check reloads reuse them.
+    builds: usize,
 }
 
-use std::collections::HashMap;
+impl<'gr> GrammarCache<'gr> {
+    fn new(grammar: &Grammar<'gr>) -> Self {
+        Self {
+            nullable: grammar.compute_nullable(),
+            first_sets: grammar.compute_first_sets(),
+            keyword_index: KeywordIndex::new(grammar),
+            builds: 1,
+        }
+    }
+
+    /// Updates the caches of `old` for `grammar`, keeping the nullability and FIRST sets of
+    /// the nonterminals the change can't affect, see `Grammar::changed_nonterminals`.
+    fn update(&mut self, old: &Grammar<'gr>, grammar: &Grammar<'gr>) {
+        let changed = grammar.changed_nonterminals(old);
+        let mut nullable = std::mem::take(&mut self.nullable);
+        nullable.retain(|nt| !changed.contains(nt));
+        let mut first_sets = std::mem::take(&mut self.first_sets);
+        first_sets.retain(|nt, _| !changed.contains(nt));
+        self.nullable = grammar.compute_nullable_from(nullable);
+        self.first_sets = grammar.compute_first_sets_from(first_sets);
+        self.keyword_index = KeywordIndex::new(grammar);
+        self.builds += 1;
+    }
+}
 
 /// The output value type of any grammar,
 /// compatible with most games engines.
@@ -64,25 +207,150 @@ pub enum Value {
     String(String),
     /// true or false.
     Bool(bool),
+    /// No value, the output `-> t` of a rule whose optional placeholder `t` was left out.
+    Null,
     /// Represents some user data type with a type and some fields
     /// to be built by a factory.
-    /// The fields are implemented as a HashMap<String, Value>
+    /// The fields are implemented as an IndexMap<String, Value>, in declaration order
     Resource {
         /// The type of this resource
         typ: String,
         /// The fields of this resource
-        fields: HashMap<String, Value>,
+        fields: IndexMap<String, Value>,
     },
     /// An array, implmented as a Vec
     Array(Vec<Value>),
-    /// A dictionary, implemented as a HashMap<String, Value>
-    Dictionary(HashMap<String, Value>),
+    /// A dictionary, implemented as an IndexMap<String, Value>, in declaration order
+    Dictionary(IndexMap<String, Value>),
      /// A value that will come from the first child matching the given non-terminal.
     Child(String),
     /// A value that will collect all children matching the given non-terminal into a vec.
     Children(String),
 }
 
+impl Value {
+    /// The fields of a resource or dictionary as `(key, value)` pairs, sorted by key.
+    /// Other values have no entries.
+    pub fn into_entries(self) -> Vec<(String, Value)> {
+        let mut entries: Vec<(String, Value)> = match self {
+            Value::Resource { fields, .. } | Value::Dictionary(fields) => {
+                fields.into_iter().collect()
+            }
+            _ => Vec::new(),
+        };
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+
+    /// For engines without maps : an `Array` of `{ "key": .., "value": .. }` dictionaries,
+    /// one per entry of `into_entries`.
+    pub fn into_entry_array(self) -> Value {
+        Value::Array(
+            self.into_entries()
+                .into_iter()
+                .map(|(key, value)| {
+                    Value::Dictionary(IndexMap::from([
+                        ("key".to_string(), Value::String(key)),
+                        ("value".to_string(), value),
+                    ]))
+                })
+                .collect(),
+        )
+    }
+
+    /// Flattens nested resources, dictionaries and arrays into one map keyed by path,
+    /// like `{"effect.amount": 7, "target.kind": "self"}`, e.g. for diffing or storage.
+    /// Array items are keyed `effects[0]`, and each resource's type is a `__type__` entry,
+    /// like `effect.__type__: "Heal"`.
+    pub fn flatten(&self) -> HashMap<String, Value> {
+        let mut flat = HashMap::new();
+        self.flatten_into(String::new(), &mut flat);
+        flat
+    }
+
+    fn flatten_into(&self, path: String, flat: &mut HashMap<String, Value>) {
+        let key = |name: &str| match path.as_str() {
+            "" => name.to_string(),
+            _ => format!("{path}.{name}"),
+        };
+        match self {
+            Value::Resource { typ, fields } => {
+                flat.insert(key("__type__"), Value::String(typ.clone()));
+                for (k, v) in fields {
+                    v.flatten_into(key(k), flat);
+                }
+            }
+            Value::Dictionary(fields) => {
+                for (k, v) in fields {
+                    v.flatten_into(key(k), flat);
+                }
+            }
+            Value::Array(values) => {
+                for (i, v) in values.iter().enumerate() {
+                    v.flatten_into(format!("{path}[{i}]"), flat);
+                }
+            }
+            leaf => {
+                flat.insert(path, leaf.clone());
+            }
+        }
+    }
+
+    /// This value with each dictionary of exactly one key replaced by that key's value,
+    /// nested ones too, like `{"amount": 7}` becoming `7`. Resources keep their fields.
+    pub fn unwrap_single_field_dicts(self) -> Value {
+        let unwrap_all = |fields: IndexMap<String, Value>| {
+            fields
+                .into_iter()
+                .map(|(k, v)| (k, v.unwrap_single_field_dicts()))
+                .collect()
+        };
+        match self {
+            Value::Dictionary(fields) if fields.len() == 1 => {
+                let (_, value) = fields.into_iter().next().unwrap();
+                value.unwrap_single_field_dicts()
+            }
+            Value::Dictionary(fields) => Value::Dictionary(unwrap_all(fields)),
+            Value::Resource { typ, fields } => Value::Resource {
+                typ,
+                fields: unwrap_all(fields),
+            },
+            Value::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(Value::unwrap_single_field_dicts)
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// Like `==`, but floats are equal when they differ by at most `epsilon`,
+    /// e.g. to compare re-parsed or computed floats in tests.
+    pub fn approx_eq(&self, other: &Value, epsilon: f64) -> bool {
+        let fields_eq = |a: &IndexMap<String, Value>, b: &IndexMap<String, Value>| {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, v)| b.get(k).is_some_and(|w| v.approx_eq(w, epsilon)))
+        };
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a == b || (a - b).abs() <= epsilon,
+            (
+                Value::Resource { typ, fields },
+                Value::Resource {
+                    typ: other_typ,
+                    fields: other_fields,
+                },
+            ) => typ == other_typ && fields_eq(fields, other_fields),
+            (Value::Dictionary(a), Value::Dictionary(b)) => fields_eq(a, b),
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(v, w)| v.approx_eq(w, epsilon))
+            }
+            _ => self == other,
+        }
+    }
+}
+
 impl<'gr, 'inp> From<crate::parser::Value<'gr, 'inp>> for Value {
     fn from(v: crate::parser::Value<'gr, 'inp>) -> Self {
         match v {
@@ -97,6 +365,7 @@ impl<'gr, 'inp> From<crate::parser::Value<'gr, 'inp>> for Value {
                             .collect(),
                     },
             parser::Value::Bool(b) => Value::Bool(b),
+            parser::Value::Null => Value::Null,
             parser::Value::Dictionary(fields) => Value::Dictionary({
                         fields
                             .into_iter()
@@ -104,11 +373,330 @@ impl<'gr, 'inp> From<crate::parser::Value<'gr, 'inp>> for Value {
                             .collect()
                     }),
             parser::Value::Child(c) => Value::Child(c.to_string()),
+            parser::Value::Array(values) => {
+                Value::Array(values.into_iter().map(Value::from).collect())
+            }
             parser::Value::Children(c) => Value::Children(c.to_string()),
+            parser::Value::Parsed(v) => v,
+        }
+    }
+}
+
+/// The text of a float in `Display` and JSON: the shortest one reading back as the same float,
+/// always with a `.` or an exponent, like `7.0` or `1e21`, so that floats never look like integers.
+pub(crate) fn format_float(f: f64) -> String {
+    match f.is_finite() {
+        true => ryu::Buffer::new().format_finite(f).to_string(),
+        false => f.to_string(),
+    }
+}
+
+/// JSON-like text, like `Heal { amount: 7, mult: 1.5 }` for a resource, with fields in
+/// declaration order. Floats are written by `format_float`, like in JSON.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = |f: &mut std::fmt::Formatter<'_>, fields: &IndexMap<String, Value>| {
+            let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{k}: {v}")).collect();
+            match fields.is_empty() {
+                true => write!(f, "{{}}"),
+                false => write!(f, "{{ {} }}", fields.join(", ")),
+            }
+        };
+        match self {
+            Value::Integer(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{}", format_float(*x)),
+            Value::String(s) => write!(f, "{s:?}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Null => write!(f, "null"),
+            Value::Resource { typ, fields: fs } => {
+                write!(f, "{typ} ")?;
+                fields(f, fs)
+            }
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Value::Dictionary(fs) => fields(f, fs),
+            Value::Child(nt) => write!(f, "< {nt}"),
+            Value::Children(nt) => write!(f, "<* {nt}"),
+        }
+    }
+}
+
+impl Value {
+    /// JSON text for this value, without needing `serde`: resources are objects with their type
+    /// in `"$type"`, then the other keys sorted by name, so a snapshot doesn't depend on the order
+    /// the grammar declares fields in.
+    /// Strings escape control and non-ASCII characters, and floats that aren't finite are `null`.
+    pub fn to_json_string(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) {
+        let object = |out: &mut String, typ: Option<&str>, fields: &IndexMap<String, Value>| {
+            out.push('{');
+            if let Some(typ) = typ {
+                write_json_string(out, "$type");
+                out.push(':');
+                write_json_string(out, typ);
+            }
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort_unstable_by_key(|(k, _)| *k);
+            for (i, (k, v)) in fields.into_iter().enumerate() {
+                if i > 0 || typ.is_some() {
+                    out.push(',');
+                }
+                write_json_string(out, k);
+                out.push(':');
+                v.write_json(out);
+            }
+            out.push('}');
+        };
+        match self {
+            Value::Integer(i) => out.push_str(&i.to_string()),
+            Value::Float(x) if x.is_finite() => out.push_str(&format_float(*x)),
+            Value::Float(_) | Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(&b.to_string()),
+            Value::String(s) | Value::Child(s) | Value::Children(s) => write_json_string(out, s),
+            Value::Array(values) => {
+                out.push('[');
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    v.write_json(out);
+                }
+                out.push(']');
+            }
+            Value::Resource { typ, fields } => object(out, Some(typ), fields),
+            Value::Dictionary(fields) => object(out, None, fields),
+        }
+    }
+}
+
+impl Value {
+    /// The Godot type of this value: `Integer` is `int`, `Float` is `float`, `Bool` is `bool`,
+    /// `Null` is `Nil`, `String`, `Child` and `Children` are `String`, `Array` is `Array` and
+    /// `Dictionary` is `Dictionary`. A resource is its own type, like `Heal`, for a custom
+    /// `Resource` class.
+    pub fn godot_type(&self) -> &str {
+        match self {
+            Value::Integer(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Null => "Nil",
+            Value::String(_) | Value::Child(_) | Value::Children(_) => "String",
+            Value::Array(_) => "Array",
+            Value::Dictionary(_) => "Dictionary",
+            Value::Resource { typ, .. } => typ,
+        }
+    }
+
+    /// A description of a resource for an engine-side loader, naming its type then each field
+    /// with its `godot_type` and JSON value, in declaration order like the exported properties:
+    ///
+    /// ```text
+    /// [resource type="Heal"]
+    /// amount: int = 7
+    /// ```
+    ///
+    /// Other values are a single `int = 7` line.
+    pub fn to_godot_resource_spec(&self) -> String {
+        let Value::Resource { typ, fields } = self else {
+            return format!("{} = {}\n", self.godot_type(), self.to_json_string());
+        };
+        let mut spec = String::from("[resource type=");
+        write_json_string(&mut spec, typ);
+        spec.push_str("]\n");
+        for (k, v) in fields {
+            spec.push_str(&format!("{k}: {} = {}\n", v.godot_type(), v.to_json_string()));
+        }
+        spec
+    }
+}
+
+/// `s` as a quoted JSON string, with `\u` escapes (surrogate pairs past the BMP)
+/// for control and non-ASCII characters.
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' '..='~' => out.push(c),
+            _ => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{unit:04x}"));
+                }
+            }
+        }
+    }
+    out.push('"');
+}
+
+/// A production that could continue a partial input, see `Dokearley::continuations`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProductionView {
+    /// The non-terminal this production builds
+    pub lhs: String,
+    /// The rendered pattern, like `heal for <amount:Int>`
+    pub pattern: String,
+    /// How many symbols of the pattern the input already matched
+    pub matched: usize,
+}
+
+/// A token an input was read as, see `Dokearley::parse_with_tokens`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenView {
+    pub kind: TokenKind,
+    /// The token text as written, string literals keeping their quotes
+    pub text: String,
+    /// Byte range of the token in the input
+    pub span: Range<usize>,
+}
+
+/// A node of the concrete syntax tree of an input, see `Dokearley::concrete_tree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConcreteNode {
+    /// A token of the input, with its text as written (string literals keep their quotes)
+    Token {
+        kind: TokenKind,
+        text: String,
+        span: Range<usize>,
+    },
+    /// A rule that matched, or a placeholder read over several tokens like a `Duration`,
+    /// with `lhs` its type. The children are in input order.
+    Node {
+        lhs: String,
+        children: Vec<ConcreteNode>,
+    },
+}
+
+impl ConcreteNode {
+    fn from_tree(tree: &ParseTree<'_, '_>, input: &str) -> Self {
+        let token = |tok: &Token<'_>| ConcreteNode::Token {
+            kind: tok.kind.clone(),
+            text: input[tok.span.start..tok.span.end].to_string(),
+            span: tok.span.start..tok.span.end,
+        };
+        let ParseTree::Node { rule, children } = tree else {
+            unreachable!("the root of a parse tree is a node")
+        };
+        let mut nodes = Vec::new();
+        for (child, sym) in children.iter().zip(&rule.rhs) {
+            let tokens = match child {
+                ParseTree::Node { .. } => {
+                    nodes.push(Self::from_tree(child, input));
+                    continue;
+                }
+                ParseTree::Token(tok) => std::slice::from_ref(tok),
+                ParseTree::Tokens(tokens)
+                | ParseTree::RegexMatch(tokens)
+                | ParseTree::SubInput { tokens, .. }
+                | ParseTree::Scanned { tokens, .. } => tokens,
+            };
+            match sym {
+                Symbol::Placeholder { typ, .. } if tokens.len() > 1 => {
+                    nodes.push(ConcreteNode::Node {
+                        lhs: typ.to_string(),
+                        children: tokens.iter().map(token).collect(),
+                    })
+                }
+                // the end of the input has no text
+                Symbol::End => {}
+                _ => nodes.extend(tokens.iter().map(token)),
+            }
+        }
+        ConcreteNode::Node {
+            lhs: rule.lhs.to_string(),
+            children: nodes,
+        }
+    }
+
+    /// The input text this node covers, all its tokens as written.
+    pub fn text(&self) -> String {
+        match self {
+            ConcreteNode::Token { text, .. } => text.clone(),
+            ConcreteNode::Node { children, .. } => children.iter().map(|c| c.text()).collect(),
+        }
+    }
+}
+
+/// The byte span `(start, end)` of the input each placeholder read, by path,
+/// see `Dokearley::parse_with_placeholder_spans`.
+pub type PlaceholderSpans = HashMap<String, (usize, usize)>;
+
+/// The bytes of the input read by `tree`, `None` if it read nothing.
+fn tree_span(tree: &ParseTree<'_, '_>) -> Option<(usize, usize)> {
+    let tokens = match tree {
+        ParseTree::Token(tok) => std::slice::from_ref(tok),
+        ParseTree::Tokens(tokens)
+        | ParseTree::RegexMatch(tokens)
+        | ParseTree::SubInput { tokens, .. }
+        | ParseTree::Scanned { tokens, .. } => tokens,
+        ParseTree::Node { children, .. } => {
+            let mut spans = children.iter().filter_map(tree_span);
+            let first = spans.next()?;
+            let last = spans.next_back().unwrap_or(first);
+            return Some((first.0, last.1));
+        }
+    };
+    // the end of the input has no text
+    let mut tokens = tokens.iter().filter(|tok| tok.kind != TokenKind::Eof);
+    let first = tokens.next()?;
+    let last = tokens.next_back().unwrap_or(first);
+    Some((first.span.start, last.span.end))
+}
+
+/// Adds the spans of the placeholders in `tree` to `spans`, keyed by `prefix` then their name.
+fn placeholder_spans(
+    tree: &ParseTree<'_, '_>,
+    prefix: &str,
+    spans: &mut PlaceholderSpans,
+) {
+    let ParseTree::Node { rule, children } = tree else {
+        return;
+    };
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (sym, child) in rule.rhs.iter().zip(children) {
+        match sym {
+            Symbol::Placeholder { name, .. } if *name != grammar_parser::ANONYMOUS => {
+                let repeats = rule.rhs.iter().filter(|s| {
+                    matches!(s, Symbol::Placeholder { name: n, .. } if n == name)
+                });
+                let mut key = format!("{prefix}{name}");
+                if repeats.count() > 1 {
+                    let index = seen.entry(name).or_default();
+                    key = format!("{key}[{index}]");
+                    *index += 1;
+                }
+                if let Some(span) = tree_span(child) {
+                    spans.insert(key.clone(), span);
+                }
+                placeholder_spans(child, &format!("{key}."), spans);
+            }
+            // rules without a name of their own, like disjunctions, keep the path
+            Symbol::NonTerminal(_) => placeholder_spans(child, prefix, spans),
+            _ => {}
         }
     }
 }
 
+/// Which parts of an input some rule could read, see `Dokearley::coverage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    /// Byte ranges read by a completed rule, sorted and merged
+    pub covered: Vec<Range<usize>>,
+    /// The byte ranges in between, which no rule could read
+    pub uncovered: Vec<Range<usize>>,
+}
+
 /// Errors for parsing grammar files or the input
 #[derive(Debug, Error)]
 pub enum DokearleyError {
@@ -119,43 +707,457 @@ pub enum DokearleyError {
     #[error("Error while parsing input : {0}")]
     ParseError(#[from] try_accept::ParseError),
     /// This error would be a bug in dokearley, where it can't get a derivation for an accepted grammar.
-    #[error("Could not build parse tree, this is a bug in Dokearley!!")]
-    DokearleyBuildParseTreeError,
+    /// It carries the input, start symbol and chart to report it.
+    #[error("{0}")]
+    DokearleyBuildParseTreeError(#[from] parser::BuildParseTreeError),
     /// Parsing the grammar worked, but it is rejected due to being dubious, 
     /// i.e. having an infinite loop of nullable symbols that would blow up the earley parser.
-    #[error("There is an infinite loop of nullable symbols in the provided grammar")]
-    InfiniteNullableLoop,
+    /// It carries the cycle, as nonterminal names starting and ending with the same one.
+    #[error("There is an infinite loop of nullable symbols in the provided grammar : {}", .0.join(" -> "))]
+    InfiniteNullableLoop(Vec<String>),
+    /// A placeholder type is neither a builtin nor a rule of the grammar,
+    /// with the closest known type when there is one.
+    #[error(
+        "Unknown placeholder type \"{typ}\"{}",
+        suggestion.as_ref().map(|s| format!(", did you mean \"{s}\"?")).unwrap_or_default()
+    )]
+    UnknownType {
+        typ: String,
+        suggestion: Option<String>,
+    },
+    /// Two number placeholders follow each other without a separator, like `{a:Int}{b:Int}`.
+    /// Adjacent digits are read as a single number, so the rule could never match.
+    #[error("The placeholders {first}{second} in {rule} can't be told apart, they need a separator")]
+    AdjacentPlaceholders {
+        rule: String,
+        first: String,
+        second: String,
+    },
+    /// A nonterminal has no productions, like `Ice` in `Element: Fire | Ice` without an `Ice`
+    /// rule, with the rules referencing it. Unknown placeholder types are `UnknownType`.
+    #[error("The symbol {name} has no rule, it is referenced in : {}", referenced_in.join(", "))]
+    UndefinedSymbol {
+        name: String,
+        referenced_in: Vec<String>,
+    },
+    /// An output field reads a name that is neither a placeholder of its rule, one of the rules
+    /// it contains, nor a `@const`, like `nope` in `-> X { y: nope }`.
+    #[error("The output of {rule} reads \"{alias}\", which is not a placeholder of the rule")]
+    UndefinedAlias { rule: String, alias: String },
+    /// A `where` clause could never hold, like `z > 1` on an output without a `z` field,
+    /// or `n > "s"` when `n` is an `Int`.
+    #[error("The where clause {constraint} of {rule} can never hold : {reason}")]
+    InvalidConstraint {
+        rule: String,
+        constraint: String,
+        reason: String,
+    },
+    /// A rule has an empty terminal, which could never match. `dokedef` patterns can't
+    /// write one, but a corrupted compiled grammar could hold one.
+    #[error("The rule {0} has an empty terminal, which can never match")]
+    EmptyTerminal(String),
+    /// The bytes given to `Dokearley::from_compiled` aren't a compiled grammar of this version
+    #[cfg(feature = "compile")]
+    #[error("Invalid compiled grammar : {0}")]
+    InvalidCompiledGrammar(String),
+    /// An `@alias` directive doesn't name a builtin, or its name is already taken.
+    #[error("Invalid alias {0}")]
+    InvalidAlias(String),
+    /// Several `dokedef` fragments define the same rule pattern with different outputs.
+    #[error("Conflicting outputs for the rule(s) : {}", .0.join(", "))]
+    ConflictingRules(Vec<String>),
+    /// A value doesn't have the shape a macro like `value_resource!` expects.
+    #[error("Unexpected value : {0}")]
+    UnexpectedValue(String),
+    /// The value of a rule doesn't satisfy its `where` clause, like `amount > 0`.
+    #[error("Validation failed : {0}")]
+    ValidationFailed(String),
+    /// `Dokearley::parse_bounded` gave up after adding this many recognizer items.
+    #[error("Parsing gave up after {0} steps")]
+    StepBudgetExceeded(usize),
+    /// The input was accepted, but its value couldn't be built, e.g. a failed cast.
+    #[error("Error while computing the value : {0}")]
+    ValueError(#[from] parser::ValueError),
+    /// `Dokearley::parse_any` was given no start to try.
+    #[error("parse_any needs at least one start")]
+    NoStart,
+}
+
+/// An enum with one variant per start, holding the value parsed from it,
+/// as declared by `starts!`, for `Dokearley::parse_matched`.
+pub trait Starts: Sized {
+    /// The starts to try, in order, named like the variants.
+    const STARTS: &'static [&'static str];
+    /// The variant of `start`, one of `STARTS`, holding `value`.
+    fn from_match(start: &str, value: Value) -> Self;
 }
 
 /// A parser that recognizes and parses a custom grammar, defined in a `dokedef` file.
 impl<'gr> Dokearley<'gr> {
     /// Builds a parser from a `dokedef` grammar string
     pub fn from_dokedef(grammar_string: &'gr str) -> Result<Self, DokearleyError> {
-        Ok(Self {
-            grammar: {
-                let rules = rules::<'gr>().parse(grammar_string);
-                if rules.has_errors() {
-                    Err(DokearleyError::InvalidDokedef({
-                        let errors = rules.errors();
-                        let mut error_string = "".to_string();
-                        for e in errors {
-                            error_string += &("\n".to_string() + &e.to_string());
-                        }
-                        error_string
-                    }))?
-                } else {
-                    let rules = rules.output();
-                    if let Some(rules) = rules {
-                        let grammar: Grammar<'gr> = rules.into();
-                        if grammar.has_infinite_loop() {
-                            Err(DokearleyError::InfiniteNullableLoop)?
+        Self::from_dokedef_with_options(grammar_string, Options::default())
+    }
+
+    /// Builds a parser from a `dokedef` grammar string, with non-default `Options`
+    pub fn from_dokedef_with_options(
+        grammar_string: &'gr str,
+        options: Options,
+    ) -> Result<Self, DokearleyError> {
+        let grammar = Self::grammar_from_dokedef(grammar_string, &options)?;
+        Ok(Self::new(grammar, options))
+    }
+
+    /// Builds a parser from a grammar built in code, checked like a `dokedef` grammar.
+    /// Its terminals each match a single token, a character or a number, where the
+    /// `dokedef` splits literal text for you.
+    pub fn from_grammar(grammar: Grammar<'gr>) -> Result<Self, DokearleyError> {
+        let options = Options::default();
+        // no `dokedef` to tell where the problems are
+        Self::check(&grammar, "", &options.scanner_types)?;
+        Ok(Self::new(grammar, options))
+    }
+
+    /// Registers `other` for `{name:@Name}` placeholders: the text they match is parsed
+    /// by `other`, starting from its `Name` non-terminal, and its value is used as is.
+    ///
+    /// The sub-grammar is owned by this parser, and borrows its `dokedef` for as long.
+    /// Sub-grammars can have their own sub-grammars, but not refer back to this one.
+    pub fn with_sub_grammar(mut self, name: &str, other: Dokearley<'gr>) -> Self {
+        self.sub_grammars.insert(name.to_string(), other);
+        self.parse_cache.clear();
+        self
+    }
+
+    /// Registers `scanner` to read the placeholders of `typ`, a type declared in
+    /// `Options::scanner_types`, for domain-specific tokens like `2d6` dice.
+    /// From where a placeholder starts, it returns how many bytes of the input it read,
+    /// with their value, or `None` if `typ` doesn't start there.
+    /// It must read whole tokens : `2d6`, but not `2d` of `2d6`.
+    pub fn register_scanner(
+        &mut self,
+        typ: &str,
+        scanner: impl Fn(&str) -> Option<(usize, Value)> + Send + Sync + 'static,
+    ) {
+        self.scanners.insert(typ, Arc::new(scanner));
+        self.parse_cache.clear();
+    }
+
+    fn new(grammar: Grammar<'gr>, options: Options) -> Self {
+        Self {
+            cache: GrammarCache::new(&grammar),
+            parse_cache: ParseCache::new(options.parse_cache_capacity),
+            grammar,
+            options,
+            sub_grammars: HashMap::new(),
+            scanners: Scanners::default(),
+        }
+    }
+
+    /// Replaces the grammar with a new `dokedef`, for live-editing tools.
+    /// The cached grammar analyses are kept if only output specs changed, and otherwise
+    /// only recomputed for the nonterminals whose rules changed and those using them.
+    /// On error, the previous grammar stays in place.
+    pub fn reload(&mut self, grammar_string: &'gr str) -> Result<(), DokearleyError> {
+        let grammar = Self::grammar_from_dokedef(grammar_string, &self.options)?;
+        if !grammar.structural_eq(&self.grammar) {
+            self.cache.update(&self.grammar, &grammar);
+        }
+        self.grammar = grammar;
+        self.parse_cache.clear();
+        Ok(())
+    }
+
+    /// Builds a parser from several `dokedef` fragments, like a base game and its mods.
+    /// Their rules are concatenated, and the combined grammar is validated once.
+    /// Rules repeated identically in several fragments are only kept once,
+    /// but the same LHS and pattern with different outputs is a conflict.
+    pub fn from_dokedefs(fragments: &[&'gr str]) -> Result<Self, DokearleyError> {
+        Self::from_dokedefs_with_options(fragments, Options::default())
+    }
+
+    /// Builds a parser from several `dokedef` fragments, like `from_dokedefs`,
+    /// with non-default `Options` applying to every fragment.
+    pub fn from_dokedefs_with_options(
+        fragments: &[&'gr str],
+        options: Options,
+    ) -> Result<Self, DokearleyError> {
+        let mut grammar = Grammar {
+            productions: Vec::new(),
+        };
+        let mut conflicts = Vec::new();
+        for (i, fragment) in fragments.iter().enumerate() {
+            let fragment = Self::parse_dokedef(fragment, &options).map_err(|e| match e {
+                DokearleyError::InvalidDokedef(msg) => {
+                    DokearleyError::InvalidDokedef(format!("in fragment {} : {}", i, msg))
+                }
+                e => e,
+            })?;
+            for prod in fragment.productions {
+                let existing = grammar
+                    .productions
+                    .iter()
+                    .find(|p| p.lhs == prod.lhs && p.rhs == prod.rhs);
+                match existing {
+                    Some(p) if p.out.same_output(&prod.out) => {}
+                    Some(p) => conflicts.push(p.pattern_text()),
+                    None => grammar.productions.push(prod),
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            Err(DokearleyError::ConflictingRules(conflicts))?
+        }
+        // the rules come from several fragments, so the errors keep the order of the checks
+        Self::check(&grammar, "", &options.scanner_types)?;
+        Ok(Self::new(grammar, options))
+    }
+
+    fn grammar_from_dokedef(
+        grammar_string: &'gr str,
+        options: &Options,
+    ) -> Result<Grammar<'gr>, DokearleyError> {
+        #[cfg(feature = "unicode-normalization")]
+        if options.nfc_normalization && !unicode_normalization::is_nfc(grammar_string) {
+            Err(DokearleyError::InvalidDokedef(
+                "the dokedef isn't in Unicode NFC, its terminals can't match normalized inputs".into(),
+            ))?
+        }
+        let grammar = Self::parse_dokedef(grammar_string, options)?;
+        Self::check(&grammar, grammar_string, &options.scanner_types)?;
+        Ok(grammar)
+    }
+
+    /// Every problem of a `dokedef`, errors and warnings, with where they are, e.g. for a
+    /// linter or an editor's problem panel. Unlike `from_dokedef`, it doesn't stop at the
+    /// first error, but a `dokedef` that doesn't parse only gives its syntax errors.
+    pub fn validate(grammar_string: &str) -> Vec<Diagnostic> {
+        match Dokearley::parse_dokedef(grammar_string, &Options::default()) {
+            Ok(grammar) => diagnostics::grammar_diagnostics(grammar_string, &grammar, &[])
+                .into_iter()
+                .map(|(diagnostic, _)| diagnostic)
+                .collect(),
+            Err(DokearleyError::InvalidDokedef(message)) => {
+                let syntax_errors: Vec<_> = dokedef()
+                    .parse(grammar_string)
+                    .errors()
+                    .map(|e| Diagnostic::error(e, Some(e.span().into_range())))
+                    .collect();
+                match syntax_errors.is_empty() {
+                    // a directive or arrow the grammar can't have
+                    true => vec![Diagnostic::error(message, None)],
+                    false => syntax_errors,
+                }
+            }
+            Err(e) => vec![Diagnostic::error(e, None)],
+        }
+    }
+
+    /// Rejects grammars that parsed fine but can't be used, with the first error
+    /// `validate` reports, in the order of `source`.
+    /// `scanner_types` are the custom types placeholders may use.
+    fn check(
+        grammar: &Grammar<'gr>,
+        source: &str,
+        scanner_types: &[String],
+    ) -> Result<(), DokearleyError> {
+        let diagnostics = diagnostics::grammar_diagnostics(source, grammar, scanner_types);
+        match diagnostics.into_iter().find_map(|(_, error)| error) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces `@alias` names in placeholder types by the builtins they stand for.
+    fn apply_aliases(
+        grammar: &mut Grammar<'gr>,
+        aliases: &[Alias<'gr>],
+    ) -> Result<(), DokearleyError> {
+        for alias in aliases {
+            let invalid = |reason: &str| {
+                Err(DokearleyError::InvalidAlias(format!(
+                    "{} = {} : {}",
+                    alias.name.text, alias.target.text, reason
+                )))
+            };
+            if !recognizer::is_builtin_type(alias.target.text) {
+                invalid("only builtin types can be aliased")?
+            }
+            if recognizer::is_builtin_type(alias.name.text) {
+                invalid("a builtin type can't be redefined")?
+            }
+            if grammar.productions.iter().any(|p| p.lhs == alias.name.text) {
+                invalid("a rule already has this name")?
+            }
+        }
+        for prod in &mut grammar.productions {
+            for sym in &mut prod.rhs {
+                if let Symbol::Placeholder { typ, .. } = sym {
+                    if let Some(alias) = aliases.iter().find(|a| a.name.text == *typ) {
+                        *typ = alias.target.text;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the field values naming a `@const` by its value, in the rules
+    /// without a placeholder of that name: placeholders come first.
+    fn apply_consts(
+        grammar: &mut Grammar<'gr>,
+        consts: &[Const<'gr>],
+    ) -> Result<(), DokearleyError> {
+        for (i, constant) in consts.iter().enumerate() {
+            if consts[..i].iter().any(|c| c.name.text == constant.name.text) {
+                Err(DokearleyError::InvalidDokedef(format!(
+                    "the constant {} is declared twice",
+                    constant.name
+                )))?
+            }
+        }
+        for prod in &mut grammar.productions {
+            let has_placeholder = |name: &str| {
+                prod.rhs
+                    .iter()
+                    .any(|sym| matches!(sym, Symbol::Placeholder { name: n, .. } if *n == name))
+            };
+            let replace = |spec: &mut ValueSpec<'gr>| {
+                if let ValueSpec::Identifier(name) = spec {
+                    let constant = consts.iter().find(|c| c.name.text == name.text);
+                    if let Some(constant) = constant.filter(|_| !has_placeholder(name.text)) {
+                        *spec = constant.value;
+                    }
+                }
+            };
+            for_each_spec(&mut prod.out, replace);
+        }
+        Ok(())
+    }
+
+    fn parse_dokedef(
+        grammar_string: &'gr str,
+        options: &Options,
+    ) -> Result<Grammar<'gr>, DokearleyError> {
+        let rules = dokedef::<'gr>().parse(grammar_string);
+        if rules.has_errors() {
+            Err(DokearleyError::InvalidDokedef({
+                let errors = rules.errors();
+                let mut error_string = "".to_string();
+                for e in errors {
+                    error_string += &("\n".to_string() + &e.to_string());
+                }
+                error_string
+            }))?
+        } else {
+            let rules = rules.output();
+            if let Some((directives, rules)) = rules {
+                let disallowed = rules
+                    .iter()
+                    .flat_map(|rule| rule.arrows())
+                    .find(|arrow| !options.allowed_arrows.allows(arrow.text));
+                if let Some(arrow) = disallowed {
+                    Err(DokearleyError::InvalidDokedef(format!(
+                        "the {} arrow at {} is not allowed by Options::allowed_arrows",
+                        arrow.text, arrow.span
+                    )))?
+                }
+                let (mut aliases, mut consts) = (Vec::new(), Vec::new());
+                let mut default_output = None;
+                for directive in directives {
+                    match directive {
+                        Directive::Alias(alias) => aliases.push(*alias),
+                        Directive::Const(constant) => consts.push(*constant),
+                        Directive::DefaultOutput(directive, _) if default_output.is_some() => {
+                            Err(DokearleyError::InvalidDokedef(format!(
+                                "the default output is set twice, again at {}",
+                                directive.span
+                            )))?
                         }
-                        grammar
-                    } else {
-                        Err(DokearleyError::InvalidDokedef("??".to_string()))?
+                        Directive::DefaultOutput(_, mode) => default_output = Some(*mode),
                     }
                 }
-            },
+                let mut grammar: Grammar<'gr> = match default_output {
+                    Some(mode) => {
+                        let mut rules = rules.clone();
+                        rules.iter_mut().for_each(|rule| rule.apply_default_output(mode));
+                        (&rules).into()
+                    }
+                    None => rules.into(),
+                };
+                Self::apply_aliases(&mut grammar, &aliases)?;
+                Self::apply_consts(&mut grammar, &consts)?;
+                Ok(grammar)
+            } else {
+                Err(DokearleyError::InvalidDokedef("??".to_string()))?
+            }
+        }
+    }
+}
+
+/// Calls `f` on each value spec of an output, like the fields of a resource.
+fn for_each_spec<'gr>(out: &mut parser::OutSpec<'gr>, mut f: impl FnMut(&mut ValueSpec<'gr>)) {
+    use parser::OutSpec::*;
+    match out {
+        Value(spec) => f(spec),
+        Resource { fields, .. } | Dict(fields) | Merged { fields, .. } => {
+            fields.iter_mut().for_each(|(_, spec)| f(spec))
+        }
+        Constrained { out, .. } => for_each_spec(out, f),
+        Transparent | Tagged | Keyword | List(_) => {}
+    }
+}
+
+/// Owned `dokedef` fragments, for when the sources don't outlive the loading code
+/// (e.g. mod files read at startup). They are checked when built, and
+/// `parser` borrows them into a `Dokearley`.
+#[derive(Debug, Clone)]
+pub struct OwnedDokearley {
+    sources: Vec<String>,
+    /// Set when loaded with `Dokearley::from_compiled`, instead of `sources`.
+    #[cfg(feature = "compile")]
+    compiled: Option<compiled::CompiledGrammar>,
+}
+
+impl OwnedDokearley {
+    /// Owned counterpart of `Dokearley::from_dokedefs`.
+    pub fn from_dokedefs(fragments: Vec<String>) -> Result<Self, DokearleyError> {
+        let owned = Self {
+            sources: fragments,
+            #[cfg(feature = "compile")]
+            compiled: None,
+        };
+        owned.parser()?;
+        Ok(owned)
+    }
+
+    /// Builds a parser borrowing the fragments.
+    pub fn parser(&self) -> Result<Dokearley<'_>, DokearleyError> {
+        #[cfg(feature = "compile")]
+        if let Some(compiled) = &self.compiled {
+            return Ok(Dokearley::new(compiled.grammar(), Options::default()));
+        }
+        let fragments: Vec<&str> = self.sources.iter().map(String::as_str).collect();
+        Dokearley::from_dokedefs(&fragments)
+    }
+}
+
+#[cfg(feature = "compile")]
+impl<'gr> Dokearley<'gr> {
+    /// Serializes the grammar to a compact, versioned binary format,
+    /// to ship a precompiled grammar instead of parsing its `dokedef` at startup.
+    /// Options and sub-grammars aren't included.
+    pub fn compile(&self) -> Vec<u8> {
+        compiled::CompiledGrammar::encode(&self.grammar)
+    }
+
+    /// Loads a grammar made by `compile`, with the default options.
+    pub fn from_compiled(bytes: &[u8]) -> Result<OwnedDokearley, DokearleyError> {
+        let compiled = compiled::CompiledGrammar::decode(bytes)?;
+        Dokearley::check(&compiled.grammar(), "", &[])?;
+        Ok(OwnedDokearley {
+            sources: Vec::new(),
+            compiled: Some(compiled),
         })
     }
 }
@@ -163,6 +1165,7 @@ impl<'gr> Dokearley<'gr> {
 impl<'gr> Dokearley<'gr> {
     /// Parses an input into a `Value`with the parser's grammar, starting from a non-terminal `start`.
     /// The `start` specifies what we are trying to parse.
+    /// With `Options::parse_cache_capacity` set, repeated inputs return a clone of the cached value.
     pub fn parse<'inp>(
         &'gr self,
         input: &'inp str,
@@ -171,41 +1174,708 @@ impl<'gr> Dokearley<'gr> {
     where
         'gr: 'inp,
     {
-        let tokens = recognizer::tokenize(input);
-        let mut chart = Chart::new(&self.grammar, tokens, start);
-        chart.recognize(start);
-        chart.try_accept(start)?;
-        let tree = chart
-            .build_parse_tree()
-            .ok_or(DokearleyError::DokearleyBuildParseTreeError)?;
-        Ok(tree.compute_value().into())
+        if self.options.annotations {
+            let (input, annotations) = annotations::extract_annotations(input);
+            let value = self.parse_unannotated(input, start)?;
+            return Ok(annotations::attach_annotations(value, annotations));
+        }
+        self.parse_unannotated(input, start)
     }
-}
 
-#[cfg(test)]
-mod item_effects_tests {
-    use super::*;
-    use std::collections::HashMap;
+    /// `parse`, for an input without annotations.
+    fn parse_unannotated<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> Result<Value, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        if let Some(value) = self.parse_cache.get(input, start) {
+            return Ok(value);
+        }
+        let tokens = recognizer::tokenize(input);
+        let value = self.parse_token_list(tokens, start, Some(input))?;
+        self.parse_cache.insert(input, start, &value);
+        Ok(value)
+    }
 
-    fn make_engine() -> Dokearley<'static> {
-        let grammar = r#"
-ItemEffect: "deal {amount:Int} damage" -> Damage
-ItemEffect: "heal for {amount:Int}" -> Heal
-ItemEffect: "apply {status:String}" -> ApplyStatus
-ItemEffect: "remove {status:String}" -> RemoveStatus
-ItemEffect: "increase {stat:String} by {amount:Int}" -> Buff 
-ItemEffect: "decrease {stat:String} by {amount:Int}" -> Debuff 
+    /// Parses tokens read with `tokenize_reader`, like `parse` does with a string.
+    /// Error positions are in tokens, even with `Options::grapheme_positions`.
+    pub fn parse_tokens(
+        &'gr self,
+        tokens: &[OwnedToken],
+        start: &str,
+    ) -> Result<Value, DokearleyError> {
+        let tokens = tokens.iter().map(OwnedToken::as_token).collect();
+        self.parse_token_list(tokens, start, None)
+    }
 
-ItemEffect: "to {target : Target} : {effect : ItemEffect}" -> TargetedEffect
+    /// Whether `a` and `b` parse as `start` to equal values, e.g. to deduplicate card effects
+    /// written differently. Fields compare regardless of their order.
+    pub fn parse_eq<'inp>(
+        &'gr self,
+        a: &'inp str,
+        b: &'inp str,
+        start: &'inp str,
+    ) -> Result<bool, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        Ok(self.parse(a, start)? == self.parse(b, start)?)
+    }
 
-Target: "self" -> Target { kind: "self" }
-Target: "an ally" -> Target { kind: "ally" }
-Target: "an enemy" -> Target { kind: "enemy" }
-Target: "all allies" -> Target { kind: "allies" }
-Target: "all enemies" -> Target { kind: "enemies" }
-"#;
+    /// Parses `input` as `start`, leaving its value to be computed on demand: `LazyValue::get`
+    /// walks down to a field without computing the others. Annotations aren't read.
+    pub fn parse_lazy<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> Result<LazyValue<'gr, 'inp>, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        LazyValue::new(self, self.prepare_input(input), start)
+    }
 
-        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    /// Recognizes `input` as `start`, keeping its chart so that `IncrementalParse::edit`
+    /// only recomputes what follows an edit, e.g. to revalidate a line on each keystroke.
+    /// Annotations aren't read and the input isn't normalized, see `Options`.
+    pub fn parse_incremental<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> IncrementalParse<'gr, 'inp>
+    where
+        'gr: 'inp,
+    {
+        IncrementalParse::new(self, input, start)
+    }
+
+    /// Parses each line of `text` as `start`, for content files with one entry per line.
+    /// Lines are trimmed and blank ones skipped; results come with their line number, from 1.
+    pub fn parse_lines<'inp>(
+        &'gr self,
+        text: &'inp str,
+        start: &'inp str,
+    ) -> Vec<(usize, Result<Value, DokearleyError>)>
+    where
+        'gr: 'inp,
+    {
+        text.lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(number, line)| (number, self.parse(line, start)))
+            .collect()
+    }
+
+    /// Parses `input` as `start` and returns only the field at `field_path`,
+    /// like `["effect", "amount"]`, walking nested resources and dictionaries. Only that field is converted to a `Value`,
+    /// for hot paths that need one number out of a big resource.
+    /// `None` if the input isn't accepted or has no such field.
+    pub fn parse_scalar<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+        field_path: &[&str],
+    ) -> Option<Value>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let tokens = recognizer::tokenize(input);
+        self.with_value(tokens, start, Some(input), |value| value.into_field(field_path))
+            .ok()
+            .flatten()
+            .map(|value| self.public_value(value))
+    }
+
+    /// Recognizes `tokens` as `start` and builds their value.
+    /// The `input` they come from, if known, gives grapheme positions to errors.
+    fn parse_token_list<'inp>(
+        &'gr self,
+        tokens: Vec<Token<'inp>>,
+        start: &'inp str,
+        input: Option<&str>,
+    ) -> Result<Value, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        self.with_value(tokens, start, input, |value| self.public_value(value))
+    }
+
+    /// A computed value as the public API gives it, following `Options::unwrap_single_field_dicts`.
+    pub(crate) fn public_value(&self, value: impl Into<Value>) -> Value {
+        let value = value.into();
+        match self.options.unwrap_single_field_dicts {
+            true => value.unwrap_single_field_dicts(),
+            false => value,
+        }
+    }
+
+    /// Like `parse_token_list`, giving the value still borrowing the grammar and input to `f`.
+    fn with_value<'inp, R>(
+        &'gr self,
+        tokens: Vec<Token<'inp>>,
+        start: &'inp str,
+        input: Option<&str>,
+        f: impl FnOnce(parser::Value<'gr, '_>) -> R,
+    ) -> Result<R, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        self.with_tree(tokens, start, input, |tree| Ok(f(self.tree_value(tree)?)))
+    }
+
+    /// The value of a parse tree, still borrowing the grammar and input.
+    pub(crate) fn tree_value<'t>(
+        &'gr self,
+        tree: &ParseTree<'gr, 't>,
+    ) -> Result<parser::Value<'gr, 't>, DokearleyError>
+    where
+        'gr: 't,
+    {
+        tree.compute_value_with(&self.sub_grammars).map_err(|e| match e {
+            parser::ValueError::ValidationFailed(msg) => DokearleyError::ValidationFailed(msg),
+            e => e.into(),
+        })
+    }
+
+    /// Recognizes `tokens` as `start` and gives their parse tree to `f`.
+    fn with_tree<'inp, R>(
+        &'gr self,
+        tokens: Vec<Token<'inp>>,
+        start: &'inp str,
+        input: Option<&str>,
+        f: impl FnOnce(&ParseTree<'gr, '_>) -> Result<R, DokearleyError>,
+    ) -> Result<R, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        self.with_bounded_tree(tokens, start, input, None, f)
+    }
+
+    /// `with_tree`, giving up once recognition added more than `max_steps` items.
+    fn with_bounded_tree<'inp, R>(
+        &'gr self,
+        tokens: Vec<Token<'inp>>,
+        start: &'inp str,
+        input: Option<&str>,
+        max_steps: Option<usize>,
+        f: impl FnOnce(&ParseTree<'gr, '_>) -> Result<R, DokearleyError>,
+    ) -> Result<R, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        f(&self.bounded_tree(tokens, start, input, max_steps)?)
+    }
+
+    /// The parse tree of `tokens` as `start`, see `with_bounded_tree`.
+    fn bounded_tree<'inp>(
+        &'gr self,
+        tokens: Vec<Token<'inp>>,
+        start: &'inp str,
+        input: Option<&str>,
+        max_steps: Option<usize>,
+    ) -> Result<ParseTree<'gr, 'inp>, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let mut chart = self.chart(tokens, start);
+        chart.max_steps = max_steps;
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        if let Some(max_steps) = max_steps.filter(|_| chart.exceeded_steps()) {
+            Err(DokearleyError::StepBudgetExceeded(max_steps))?
+        }
+        chart
+            .try_accept_with_first_sets(start, &self.cache.first_sets)
+            .map_err(|mut err| {
+                if let Some(input) = input.filter(|_| self.options.grapheme_positions) {
+                    err.pos = input[..err.byte_pos].graphemes(true).count();
+                }
+                err
+            })?;
+        Ok(chart.build_parse_tree()?)
+    }
+
+    /// Parses `input` as `start` into its concrete syntax tree: the rules that matched
+    /// and every token they read, for source-to-source transformations.
+    pub fn concrete_tree<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> Result<ConcreteNode, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let tokens = recognizer::tokenize(input);
+        self.with_tree(tokens, start, Some(input), |tree| {
+            Ok(ConcreteNode::from_tree(tree, input))
+        })
+    }
+
+    /// Parses `input` as `start`, giving up with `DokearleyError::StepBudgetExceeded`
+    /// once the recognizer added more than `max_steps` items, to bound the cost of
+    /// user-supplied grammars and inputs. The parse cache isn't used.
+    pub fn parse_bounded<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+        max_steps: usize,
+    ) -> Result<Value, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let tokens = recognizer::tokenize(input);
+        self.with_bounded_tree(tokens, start, Some(input), Some(max_steps), |tree| {
+            Ok(self.public_value(self.tree_value(tree)?))
+        })
+    }
+
+    /// Parses `input` as `start`, with the byte span of the input each placeholder read,
+    /// for tooltips. Placeholders of nested rules are keyed by their path, like `effect.amount`,
+    /// and repeated ones by their index, like `c[1]`. Placeholders that read nothing have no span.
+    pub fn parse_with_placeholder_spans<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> Result<(Value, PlaceholderSpans), DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let tokens = recognizer::tokenize(input);
+        self.with_tree(tokens, start, Some(input), |tree| {
+            let mut spans = HashMap::new();
+            placeholder_spans(tree, "", &mut spans);
+            Ok((self.public_value(self.tree_value(tree)?), spans))
+        })
+    }
+
+    /// Parses `input` as `start`, with the tokens it was read as, for logging and replay.
+    /// They cover the whole input, without the trailing punctuation `Options` strips:
+    /// their texts put together give it back.
+    pub fn parse_with_tokens<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> Result<(Value, Vec<TokenView>), DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let tokens = recognizer::tokenize(input);
+        let views = tokens
+            .iter()
+            .filter(|tok| tok.kind != TokenKind::Eof)
+            .map(|tok| TokenView {
+                kind: tok.kind.clone(),
+                text: input[tok.span.start..tok.span.end].to_string(),
+                span: tok.span.start..tok.span.end,
+            })
+            .collect();
+        let value = self.parse_token_list(tokens, start, Some(input))?;
+        Ok((value, views))
+    }
+
+    /// Parses `input` with the first of `starts` that accepts it, returning that start with the value,
+    /// so callers can dispatch on it. If none accepts it, the error is the one that read the furthest.
+    pub fn parse_any(
+        &'gr self,
+        input: &str,
+        starts: &[&'static str],
+    ) -> Result<(&'static str, Value), DokearleyError> {
+        let mut furthest: Option<DokearleyError> = None;
+        for &start in starts {
+            match self.parse(input, start) {
+                Ok(value) => return Ok((start, value)),
+                Err(DokearleyError::ParseError(err)) => {
+                    let further = match &furthest {
+                        Some(DokearleyError::ParseError(best)) => err.byte_pos > best.byte_pos,
+                        _ => true,
+                    };
+                    if further {
+                        furthest = Some(DokearleyError::ParseError(err));
+                    }
+                }
+                // The input was accepted, its value is wrong
+                Err(err) => return Err(err),
+            }
+        }
+        Err(furthest.unwrap_or(DokearleyError::NoStart))
+    }
+
+    /// `parse_any` with the starts of an enum declared by `starts!`, returning
+    /// the variant of the start that matched, so callers `match` on it instead of a string.
+    pub fn parse_matched<M: Starts>(&'gr self, input: &str) -> Result<M, DokearleyError> {
+        let (start, value) = self.parse_any(input, M::STARTS)?;
+        Ok(M::from_match(start, value))
+    }
+
+    /// The input without the `Options::trailing_punctuation` characters at its end.
+    fn strip_trailing_punctuation<'inp>(&self, input: &'inp str) -> &'inp str {
+        let punctuation = &self.options.trailing_punctuation;
+        input.trim_end_matches(|c| punctuation.contains(c))
+    }
+
+    /// `input` as it is parsed: without its trailing punctuation,
+    /// and in Unicode NFC with `Options::nfc_normalization`.
+    fn prepare_input<'inp>(&self, input: &'inp str) -> Cow<'inp, str> {
+        let input = self.strip_trailing_punctuation(input);
+        #[cfg(feature = "unicode-normalization")]
+        if self.options.nfc_normalization && !unicode_normalization::is_nfc(input) {
+            use unicode_normalization::UnicodeNormalization;
+            return Cow::Owned(input.nfc().collect());
+        }
+        Cow::Borrowed(input)
+    }
+
+    /// A chart over `tokens` using this parser's sub-grammars, scanners and terminal matching.
+    fn chart<'inp>(&'gr self, tokens: Vec<Token<'inp>>, start: &'inp str) -> Chart<'gr, 'inp> {
+        let mut chart = Chart::new(&self.grammar, tokens, start);
+        chart.sub_grammars = Some(&self.sub_grammars);
+        chart.scanners = Some(&self.scanners);
+        chart.keyword_index = self
+            .options
+            .keyword_dispatch
+            .then_some(&self.cache.keyword_index);
+        #[cfg(feature = "unicode-normalization")]
+        {
+            chart.fold_terminals = self.options.fold_terminals;
+            if chart.fold_terminals {
+                chart.keyword_index = None;
+            }
+        }
+        chart
+    }
+
+    /// Whether `input` can be parsed as `start`, without building its value.
+    pub(crate) fn accepts(&'gr self, input: &str, start: &str) -> bool {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let tokens = recognizer::tokenize(input);
+        let mut chart = self.chart(tokens, start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        chart.accepted(start)
+    }
+
+    /// Where parsing `input` as `start` first becomes ambiguous, for grammar development:
+    /// the earliest `(pos, start, lhs)` where two rules of `lhs` match the same tokens,
+    /// positions being in tokens. `None` if no two rules overlap like that.
+    pub fn first_ambiguity(
+        &'gr self,
+        input: &str,
+        start: &str,
+    ) -> Option<(usize, usize, &'gr str)> {
+        let input = self.prepare_input(input);
+        let tokens = recognizer::tokenize(input.as_ref());
+        let mut chart = self.chart(tokens, start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        chart.first_ambiguity()
+    }
+
+    /// The `start` productions that read the whole `input`, by production id, to see why
+    /// an ambiguous input is accepted by several rules. Empty if `input` isn't accepted.
+    pub fn accepting_items(
+        &'gr self,
+        input: &str,
+        start: &str,
+    ) -> Vec<(usize, &'gr Production<'gr>)> {
+        let input = self.prepare_input(input);
+        let tokens = recognizer::tokenize(input.as_ref());
+        let mut chart = self.chart(tokens, start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        chart.accepting_items(start)
+    }
+
+    /// Parses `input` as `start` into the value of each of its derivations, to see every reading
+    /// of an ambiguous input. Equal values are kept once, and at most `Options::max_derivations`
+    /// derivations are read. The parse cache isn't used. Derivations whose value can't be
+    /// computed, like one failing a `where` clause, are skipped, unless all of them are.
+    pub fn parse_all<'inp>(
+        &'gr self,
+        input: &'inp str,
+        start: &'inp str,
+    ) -> Result<Vec<Value>, DokearleyError>
+    where
+        'gr: 'inp,
+    {
+        let input = self.prepare_input(input);
+        let input = input.as_ref();
+        let mut chart = self.chart(recognizer::tokenize(input), start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        chart.try_accept_with_first_sets(start, &self.cache.first_sets)?;
+        let limit = match self.options.max_derivations {
+            0 => DEFAULT_MAX_DERIVATIONS,
+            limit => limit,
+        };
+        let mut values: Vec<Value> = Vec::new();
+        let mut first_error = None;
+        for tree in chart.build_parse_trees(limit) {
+            match self.tree_value(&tree) {
+                Ok(value) => {
+                    let value = self.public_value(value);
+                    if !values.contains(&value) {
+                        values.push(value);
+                    }
+                }
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        match (values.is_empty(), first_error) {
+            (true, Some(err)) => Err(err),
+            _ => Ok(values),
+        }
+    }
+
+    /// Whether `input` has several derivations as `start`, e.g. to warn that a rule just added
+    /// made an existing phrase ambiguous. Takes time proportional to the chart, not to the number
+    /// of derivations.
+    pub fn is_ambiguous(&'gr self, input: &str, start: &str) -> bool {
+        let input = self.prepare_input(input);
+        let tokens = recognizer::tokenize(input.as_ref());
+        let mut chart = self.chart(tokens, start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        chart.is_ambiguous(start)
+    }
+
+    /// Whether the grammar looks deterministic (LL(1)-like), a hint for performance:
+    /// such grammars never need the Earley parser to track several derivations.
+    pub fn is_deterministic_hint(&self) -> bool {
+        self.grammar.is_deterministic_hint()
+    }
+
+    /// Indices of the productions that can match the empty input, to spot unintended empty matches.
+    /// They index the productions in the order of `signatures`.
+    pub fn nullable_productions(&self) -> Vec<usize> {
+        let productions = &self.grammar.productions;
+        let mut nullable = self.grammar.nullable_productions();
+        nullable.retain(|&i| !grammar_parser::is_internal(productions[i].lhs));
+        nullable
+    }
+
+    /// A readable signature of each production, like `ItemEffect := "heal for " <amount:Int> -> Heal`,
+    /// e.g. for autocompletion docs.
+    pub fn signatures(&self) -> Vec<String> {
+        self.grammar
+            .productions
+            .iter()
+            .filter(|p| !grammar_parser::is_internal(p.lhs))
+            .map(|p| p.signature())
+            .collect()
+    }
+
+    /// Problems that don't make the grammar invalid but are likely mistakes, like a literal
+    /// `"level 12"`: `12` is read as a number, so the rule can never match.
+    pub fn warnings(&self) -> Vec<String> {
+        self.grammar
+            .find_shadowed_numbers()
+            .into_iter()
+            .map(|(prod, number)| diagnostics::shadowed_number_warning(prod, &number))
+            .collect()
+    }
+
+    /// Each nonterminal, with the nonterminals and placeholder types (builtins included)
+    /// its rules reference, for grammar explorers.
+    pub fn dependency_graph(&self) -> HashMap<&'gr str, HashSet<&'gr str>> {
+        self.grammar.dependency_graph()
+    }
+
+    /// Every resource type that parsing `start` could produce, nested ones included,
+    /// e.g. to register only the resource factories an engine needs.
+    pub fn reachable_output_types(&self, start: &str) -> HashSet<String> {
+        self.grammar
+            .output_types_from(start)
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The types of all placeholders in the grammar, like `Int` or `Target`,
+    /// e.g. to document the kinds of values it produces.
+    pub fn placeholder_types(&self) -> HashSet<&'gr str> {
+        self.grammar.placeholder_types()
+    }
+
+    /// The fields of `typ` resources, in the order the grammar declares them.
+    pub fn field_order(&self, typ: &str) -> Vec<&'gr str> {
+        self.grammar.field_order(typ)
+    }
+
+    /// The fields of a resource or dictionary, in the order to write them out:
+    /// a resource's fields as its grammar declares them, then any others by name.
+    /// Values without fields have none.
+    pub fn ordered_fields<'v>(&self, value: &'v Value) -> Vec<(&'v str, &'v Value)> {
+        let (order, fields) = match value {
+            Value::Resource { typ, fields } => (self.field_order(typ), fields),
+            Value::Dictionary(fields) => (Vec::new(), fields),
+            _ => return Vec::new(),
+        };
+        let mut ordered: Vec<_> = fields.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let rank = |k: &str| order.iter().position(|o| *o == k).unwrap_or(order.len());
+        ordered.sort_by_key(|(k, _)| (rank(k), *k));
+        ordered
+    }
+
+    /// Like `Value::into_entries`, but in the order of `ordered_fields`, for writers that
+    /// need the grammar's field order with owned values.
+    pub fn into_ordered_entries(&self, value: Value) -> Vec<(String, Value)> {
+        let order: Vec<String> = self
+            .ordered_fields(&value)
+            .into_iter()
+            .map(|(k, _)| k.to_string())
+            .collect();
+        let mut entries = value.into_entries();
+        entries.sort_by_key(|(k, _)| order.iter().position(|o| o == k));
+        entries
+    }
+
+    /// Lists the productions that could continue or complete a `partial` input parsed as `start`,
+    /// at the furthest position the input could be read to.
+    pub fn continuations(&'gr self, partial: &str, start: &str) -> Vec<ProductionView> {
+        let partial = self.prepare_input(partial);
+        let tokens = recognizer::tokenize(&partial);
+        let mut chart = self.chart(tokens, start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+        chart
+            .continuations()
+            .into_iter()
+            .map(|(prod_id, dot)| {
+                let prod = &self.grammar.productions[prod_id];
+                ProductionView {
+                    lhs: prod.lhs.to_string(),
+                    pattern: prod.rhs_text(),
+                    matched: dot,
+                }
+            })
+            .collect()
+    }
+
+    /// For debugging inputs that don't parse: the byte ranges of `input` read by some completed rule
+    /// while parsing it as `start`, and the gaps between them. Gaps show where the parse went wrong,
+    /// even when it fails.
+    pub fn coverage(&'gr self, input: &str, start: &str) -> Coverage {
+        let input = self.prepare_input(input);
+        let tokens = recognizer::tokenize(&input);
+        let mut chart = self.chart(tokens, start);
+        chart.recognize_with_nullable(start, &self.cache.nullable);
+
+        let mut covered: Vec<Range<usize>> = Vec::new();
+        for (first, end) in chart.completed_ranges() {
+            let bytes = chart.tokens[first].span.start..chart.tokens[end - 1].span.end;
+            match covered.last_mut() {
+                Some(last) if bytes.start <= last.end => last.end = last.end.max(bytes.end),
+                _ => covered.push(bytes),
+            }
+        }
+        let mut uncovered = Vec::new();
+        let mut pos = 0;
+        for range in covered.iter().chain([&(input.len()..input.len())]) {
+            if range.start > pos {
+                uncovered.push(pos..range.start);
+            }
+            pos = pos.max(range.end);
+        }
+        Coverage { covered, uncovered }
+    }
+}
+
+#[cfg(test)]
+mod item_effects_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "deal {amount:Int} damage" -> Damage
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "apply {status:String}" -> ApplyStatus
+ItemEffect: "remove {status:String}" -> RemoveStatus
+ItemEffect: "increase {stat:String} by {amount:Int}" -> Buff 
+ItemEffect: "decrease {stat:String} by {amount:Int}" -> Debuff 
+
+ItemEffect: "to {target : Target} : {effect : ItemEffect}" -> TargetedEffect
+
+Target: "self" -> Target { kind: "self" }
+Target: "an ally" -> Target { kind: "ally" }
+Target: "an enemy" -> Target { kind: "enemy" }
+Target: "all allies" -> Target { kind: "allies" }
+Target: "all enemies" -> Target { kind: "enemies" }
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn signatures() {
+        let signatures = make_engine().signatures();
+        for expected in [
+            r#"ItemEffect := "heal for " <amount:Int> -> Heal"#,
+            r#"ItemEffect := "to " <target:Target> " : " <effect:ItemEffect> -> TargetedEffect"#,
+            r#"Target := "an ally" -> Target { kind: "ally" }"#,
+        ] {
+            assert!(signatures.iter().any(|s| s == expected), "{expected} in {signatures:?}");
+        }
+    }
+
+    #[test]
+    fn placeholder_types() {
+        let types = make_engine().placeholder_types();
+        assert_eq!(types, HashSet::from(["Int", "String", "Target", "ItemEffect"]));
+    }
+
+    #[test]
+    fn flatten_targeted_effect() {
+        let value = make_engine().parse("to self : heal for 7", "ItemEffect").unwrap();
+        let expected: HashMap<String, Value> = [
+            ("__type__", Value::String("TargetedEffect".into())),
+            ("target.__type__", Value::String("Target".into())),
+            ("target.kind", Value::String("self".into())),
+            ("effect.__type__", Value::String("Heal".into())),
+            ("effect.amount", Value::Integer(7)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        assert_eq!(value.flatten(), expected);
+        // Array items are indexed
+        let array = Value::Dictionary(IndexMap::from([("c".into(), Value::Array(vec![value]))]));
+        assert_eq!(array.flatten()["c[0].effect.amount"], Value::Integer(7));
+    }
+
+    #[test]
+    fn reachable_output_types() {
+        let engine = make_engine();
+        let types = engine.reachable_output_types("ItemEffect");
+        for expected in ["Heal", "Damage", "TargetedEffect", "Target", "Buff"] {
+            assert!(types.contains(expected), "{expected} in {types:?}");
+        }
+        assert_eq!(engine.reachable_output_types("Target"), HashSet::from(["Target".into()]));
+        assert!(engine.reachable_output_types("Unknown").is_empty());
+    }
+
+    #[test]
+    fn dependency_graph() {
+        let engine = make_engine();
+        let graph = engine.dependency_graph();
+        assert_eq!(graph.len(), 2);
+        // `TargetedEffect` references itself
+        assert_eq!(
+            graph["ItemEffect"],
+            HashSet::from(["Int", "String", "Target", "ItemEffect"])
+        );
+        assert!(graph["Target"].is_empty());
     }
 
     #[test]
@@ -221,7 +1891,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Target".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("kind".into(), Value::String("self".into()));
                             m
                         }
@@ -232,7 +1902,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Heal".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("amount".into(), Value::Integer(7));
                             m
                         }
@@ -257,7 +1927,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Target".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("kind".into(), Value::String("enemy".into()));
                             m
                         }
@@ -268,7 +1938,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Damage".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("amount".into(), Value::Integer(7));
                             m
                         }
@@ -293,7 +1963,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Target".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("kind".into(), Value::String("allies".into()));
                             m
                         }
@@ -304,7 +1974,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Buff".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("stat".into(), Value::String("strength".into()));
                             m.insert("amount".into(), Value::Integer(5));
                             m
@@ -325,7 +1995,7 @@ Target: "all enemies" -> Target { kind: "enemies" }
             Value::Resource {
                 typ: "RemoveStatus".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("status".into(), Value::String("poison".into()));
                     m
                 }
@@ -337,9 +2007,12 @@ Target: "all enemies" -> Target { kind: "enemies" }
 #[cfg(test)]
 mod emoji_effects_tests {
     use super::*;
-    use std::collections::HashMap;
 
     fn make_engine() -> Dokearley<'static> {
+        make_engine_with(Options::default())
+    }
+
+    fn make_engine_with(options: Options) -> Dokearley<'static> {
         // Grammar that directly uses emojis as tokens
         let grammar = r#"
 ItemEffect: "🔥 {amount:Int}" -> FireDamage
@@ -358,7 +2031,7 @@ Target: "👨‍👩‍👦" -> Target { kind: "allies" }
 Target: "👥" -> Target { kind: "enemies" }
 "#;
 
-        Dokearley::from_dokedef(grammar).expect("invalid emoji grammar")
+        Dokearley::from_dokedef_with_options(grammar, options).expect("invalid emoji grammar")
     }
 
     #[test]
@@ -373,7 +2046,7 @@ Target: "👥" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "Target".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("kind".into(), Value::String("enemy".into()));
                             m
                         }
@@ -384,7 +2057,7 @@ Target: "👥" -> Target { kind: "enemies" }
                     Value::Resource {
                         typ: "FireDamage".into(),
                         fields: {
-                            let mut m = HashMap::new();
+                            let mut m = IndexMap::new();
                             m.insert("amount".into(), Value::Integer(10));
                             m
                         }
@@ -404,13 +2077,13 @@ Target: "👥" -> Target { kind: "enemies" }
             Value::Resource {
                 typ: "TargetedEffect".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert(
                         "target".into(),
                         Value::Resource {
                             typ: "Target".into(),
                             fields: {
-                                let mut m = HashMap::new();
+                                let mut m = IndexMap::new();
                                 m.insert("kind".into(), Value::String("self".into()));
                                 m
                             },
@@ -421,7 +2094,7 @@ Target: "👥" -> Target { kind: "enemies" }
                         Value::Resource {
                             typ: "Heal".into(),
                             fields: {
-                                let mut m = HashMap::new();
+                                let mut m = IndexMap::new();
                                 m.insert("amount".into(), Value::Integer(7));
                                 m
                             },
@@ -442,7 +2115,7 @@ Target: "👥" -> Target { kind: "enemies" }
             Value::Resource {
                 typ: "ApplyStatus".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("status".into(), Value::String("death".into()));
                     m
                 }
@@ -459,7 +2132,7 @@ Target: "👥" -> Target { kind: "enemies" }
             Value::Resource {
                 typ: "Buff".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("stat".into(), Value::String("attack".into()));
                     m.insert("amount".into(), Value::Integer(5));
                     m
@@ -467,12 +2140,32 @@ Target: "👥" -> Target { kind: "enemies" }
             }
         );
     }
+
+    #[test]
+    fn error_position_in_graphemes() {
+        let input = "👨‍👩‍👦 🔥 x";
+        let engine = make_engine_with(Options {
+            grapheme_positions: true,
+            ..Options::default()
+        });
+        match engine.parse(input, "ItemEffect") {
+            Err(DokearleyError::ParseError(err)) => {
+                // the family emoji is a single grapheme, but five chars
+                assert_eq!(err.pos, 4);
+                assert_eq!(&input[err.byte_pos..], "x");
+            }
+            other => panic!("unexpected parse output: {:?}", other),
+        }
+        match make_engine().parse(input, "ItemEffect") {
+            Err(DokearleyError::ParseError(err)) => assert_eq!(err.pos, 8),
+            other => panic!("unexpected parse output: {:?}", other),
+        }
+    }
 }
 
 #[cfg(test)]
 mod transparent_rules_tests {
     use super::*;
-    use std::collections::HashMap;
 
     fn make_engine() -> Dokearley<'static> {
         // Transparent rules: Effect can be either DamageEffect or HealEffect
@@ -496,7 +2189,7 @@ HealEffect   : "heal for {amount:Int}"    -> Heal
             Value::Resource {
                 typ: "Damage".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("amount".into(), Value::Integer(10));
                     m
                 }
@@ -513,7 +2206,7 @@ HealEffect   : "heal for {amount:Int}"    -> Heal
             Value::Resource {
                 typ: "Heal".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("amount".into(), Value::Integer(7));
                     m
                 }
@@ -525,7 +2218,6 @@ HealEffect   : "heal for {amount:Int}"    -> Heal
 #[cfg(test)]
 mod disjunction_rules_tests {
     use super::*;
-    use std::collections::HashMap;
 
     fn make_engine() -> Dokearley<'static> {
         // Transparent rules: Effect can be either DamageEffect or HealEffect
@@ -548,7 +2240,7 @@ HealEffect   : "heal for {amount:Int}"    -> Heal
             Value::Resource {
                 typ: "Damage".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("amount".into(), Value::Integer(10));
                     m
                 }
@@ -565,23 +2257,86 @@ HealEffect   : "heal for {amount:Int}"    -> Heal
             Value::Resource {
                 typ: "Heal".into(),
                 fields: {
-                    let mut m = HashMap::new();
+                    let mut m = IndexMap::new();
                     m.insert("amount".into(), Value::Integer(7));
                     m
                 }
             }
         );
     }
+
+    #[test]
+    fn tagged_disjunction_names_the_branch() {
+        let grammar = r#"
+Effect : DamageEffect | HealEffect @tagged
+
+DamageEffect : "deal {amount:Int} damage" -> Damage
+HealEffect   : "heal for {amount:Int}"    -> Heal
+"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let result = engine.parse("heal for 7", "Effect").unwrap();
+        assert_eq!(
+            result,
+            Value::Dictionary({
+                let mut m = IndexMap::new();
+                m.insert("variant".into(), Value::String("HealEffect".into()));
+                m.insert(
+                    "value".into(),
+                    Value::Resource {
+                        typ: "Heal".into(),
+                        fields: IndexMap::from([("amount".into(), Value::Integer(7))]),
+                    },
+                );
+                m
+            })
+        );
+    }
 }
 
 #[cfg(test)]
 mod dictionary_outspecs_tests {
     use super::*;
-    use std::collections::HashMap;
 
-    fn make_engine() -> Dokearley<'static> {
-        // Grammar where RHS directly produces dictionaries
-        let grammar = r#"
+    #[test]
+    fn dictionary_into_entry_array() {
+        let engine = make_engine();
+        let result = engine.parse(r#"status "poisoned""#, "Effect").unwrap();
+        let entry = |key: &str, value: &str| {
+            Value::Dictionary(IndexMap::from([
+                ("key".to_string(), Value::String(key.into())),
+                ("value".to_string(), Value::String(value.into())),
+            ]))
+        };
+        assert_eq!(
+            result.into_entry_array(),
+            Value::Array(vec![
+                entry("kind", "status"),
+                entry("status", "poisoned"),
+                entry("value", "poisoned"),
+            ])
+        );
+    }
+
+    #[test]
+    fn escaped_quotes_in_field_literal() {
+        let grammar = r#"Talk: "talk" -> Msg { text: "he said \"hi\"" }"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let result = engine.parse("talk", "Talk").unwrap();
+        assert_eq!(
+            result,
+            Value::Resource {
+                typ: "Msg".into(),
+                fields: IndexMap::from([(
+                    "text".into(),
+                    Value::String(r#"he said "hi""#.into())
+                )]),
+            }
+        );
+    }
+
+    fn make_engine() -> Dokearley<'static> {
+        // Grammar where RHS directly produces dictionaries
+        let grammar = r#"
 Effect: "gain {amount:Int} gold" -> { kind: "gain_gold"}
 Effect: "lose {amount:Int} health" -> { kind: "lose_health"}
 Effect: "status {status:String}" -> { kind: "status", value: status}
@@ -597,7 +2352,7 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
         assert_eq!(
             result,
             Value::Dictionary({
-                let mut m = HashMap::new();
+                let mut m = IndexMap::new();
                 m.insert("kind".into(), Value::String("gain_gold".into()));
                 m.insert("amount".into(), Value::Integer(5));
                 m
@@ -612,7 +2367,7 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
         assert_eq!(
             result,
             Value::Dictionary({
-                let mut m = HashMap::new();
+                let mut m = IndexMap::new();
                 m.insert("kind".into(), Value::String("lose_health".into()));
                 m.insert("amount".into(), Value::Integer(3));
                 m
@@ -627,7 +2382,7 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
         assert_eq!(
             result,
             Value::Dictionary({
-                let mut m = HashMap::new();
+                let mut m = IndexMap::new();
                 m.insert("value".into(), Value::String("burned".into()));
                 m.insert("kind".into(), Value::String("status".into()));
                 m.insert("status".into(), Value::String("burned".into()));
@@ -642,7 +2397,6 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
 #[cfg(test)]
 mod children_outspecs_tests {
     use super::*;
-    use std::collections::HashMap;
 
     fn make_engine() -> Dokearley<'static> {
         // Grammar where RHS directly produces dictionaries
@@ -662,7 +2416,7 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
         assert_eq!(
             result,
             Value::Dictionary({
-                let mut m = HashMap::new();
+                let mut m = IndexMap::new();
                 m.insert("amount".into(), Value::Integer(20));
                 m.insert("kind".into(), Value::String("gain_gold".into()));
                 m.insert("children".into(), Value::Children("Effect".to_string()));
@@ -678,7 +2432,7 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
         assert_eq!(
             result,
             Value::Dictionary({
-                let mut m = HashMap::new();
+                let mut m = IndexMap::new();
                 m.insert("amount".into(), Value::Integer(20));
                 m.insert("kind".into(), Value::String("lose_health".into()));
                 m.insert("child".into(), Value::Child("Effect".to_string()));
@@ -687,3 +2441,3888 @@ Effect: "status {status:String}" -> { kind: "status", value: status}
         );
     }
 }
+
+#[cfg(test)]
+mod reload_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+ItemEffect: "deal {amount:Int} damage" -> Damage
+ItemEffect: "heal for {amount:Int}" -> Heal
+"#;
+
+    #[test]
+    fn reload_unchanged_grammar_keeps_caches() {
+        let mut engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert_eq!(engine.cache.builds, 1);
+        engine.reload(GRAMMAR).unwrap();
+        assert_eq!(engine.cache.builds, 1);
+        assert!(engine.parse("heal for 7", "ItemEffect").is_ok());
+    }
+
+    #[test]
+    fn reload_output_change_keeps_caches() {
+        let mut engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        engine
+            .reload(
+                r#"
+ItemEffect: "deal {amount:Int} damage" -> Hurt
+ItemEffect: "heal for {amount:Int}" -> Heal
+"#,
+            )
+            .unwrap();
+        assert_eq!(engine.cache.builds, 1);
+        match engine.parse("deal 3 damage", "ItemEffect").unwrap() {
+            Value::Resource { typ, .. } => assert_eq!(typ, "Hurt"),
+            v => panic!("unexpected parse output: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn reload_changed_pattern_recomputes_caches() {
+        let mut engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        engine
+            .reload(
+                r#"
+ItemEffect: "deal {amount:Int} damage" -> Damage
+ItemEffect: "restore {amount:Int}" -> Heal
+"#,
+            )
+            .unwrap();
+        assert_eq!(engine.cache.builds, 2);
+        assert!(engine.parse("restore 7", "ItemEffect").is_ok());
+        assert!(engine.parse("heal for 7", "ItemEffect").is_err());
+    }
+
+    #[test]
+    fn failed_reload_keeps_previous_grammar() {
+        let mut engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert!(engine.reload(r#"ItemEffect: "unclosed"#).is_err());
+        assert!(engine.parse("heal for 7", "ItemEffect").is_ok());
+    }
+
+    #[test]
+    fn reload_recomputes_only_changed_nonterminals() {
+        let before = r#"
+Effect: "hit {t:Target}" -> Hit
+Target: "self" -> Me
+Duration: "{n:Int} turns" -> Turns
+"#;
+        let after = r#"
+Effect: "hit {t:Target}" -> Hit
+Target: "me" -> Me
+Duration: "{n:Int} turns" -> Turns
+"#;
+        let mut engine = Dokearley::from_dokedef(before).unwrap();
+        let old = engine.grammar.clone();
+        engine.reload(after).unwrap();
+        let changed = engine.grammar.changed_nonterminals(&old);
+        assert_eq!(changed, HashSet::from(["Target", "Effect"]));
+        assert_eq!(engine.cache.nullable, engine.grammar.compute_nullable());
+        assert_eq!(engine.cache.first_sets, engine.grammar.compute_first_sets());
+        assert!(engine.parse("hit me", "Effect").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod fragments_tests {
+    use super::*;
+
+    const BASE: &str = r#"
+ItemEffect: "deal {amount:Int} damage" -> Damage
+ItemEffect: "heal for {amount:Int}" -> Heal
+"#;
+
+    const MOD: &str = r#"
+ItemEffect: "poison for {turns:Int} turns" -> Poison
+"#;
+
+    #[test]
+    fn combined_fragments_parse_both() {
+        let engine = Dokearley::from_dokedefs(&[BASE, MOD]).unwrap();
+        assert!(engine.parse("heal for 7", "ItemEffect").is_ok());
+        match engine.parse("poison for 3 turns", "ItemEffect").unwrap() {
+            Value::Resource { typ, fields } => {
+                assert_eq!(typ, "Poison");
+                assert_eq!(fields.get("turns"), Some(&Value::Integer(3)));
+            }
+            v => panic!("unexpected parse output: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn identical_rules_are_merged() {
+        let engine = Dokearley::from_dokedefs(&[BASE, BASE]).unwrap();
+        assert_eq!(engine.grammar.productions.len(), 2);
+    }
+
+    #[test]
+    fn conflicting_rules_are_reported() {
+        let other = r#"ItemEffect: "heal for {amount:Int}" -> Regen"#;
+        match Dokearley::from_dokedefs(&[BASE, other]) {
+            Err(DokearleyError::ConflictingRules(rules)) => assert_eq!(rules.len(), 1),
+            r => panic!("expected a conflict, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn owned_fragments() {
+        let owned =
+            OwnedDokearley::from_dokedefs(vec![BASE.to_string(), MOD.to_string()]).unwrap();
+        let engine = owned.parser().unwrap();
+        assert!(engine.parse("poison for 3 turns", "ItemEffect").is_ok());
+    }
+
+    #[test]
+    fn fragments_with_options() {
+        let dice = r#"ItemEffect: "roll {dice:Dice}" -> Roll"#;
+        assert!(matches!(
+            Dokearley::from_dokedefs(&[BASE, dice]),
+            Err(DokearleyError::UnknownType { .. })
+        ));
+        let options = Options {
+            scanner_types: vec!["Dice".into()],
+            ..Options::default()
+        };
+        let engine = Dokearley::from_dokedefs_with_options(&[BASE, dice], options).unwrap();
+        assert!(engine.parse("heal for 7", "ItemEffect").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod whitespace_mode_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "heal for {amount:Int}" @ws:collapse -> Heal
+Effect: "deal {amount:Int} damage" -> Damage
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn collapsed_rule_accepts_extra_spaces() {
+        let engine = make_engine();
+        let result = engine.parse("heal   for \t 7", "Effect").unwrap();
+        assert_eq!(
+            result,
+            Value::Resource {
+                typ: "Heal".into(),
+                fields: {
+                    let mut m = IndexMap::new();
+                    m.insert("amount".into(), Value::Integer(7));
+                    m
+                }
+            }
+        );
+        assert!(engine.parse("heal for 7", "Effect").is_ok());
+        assert!(engine.parse("healfor 7", "Effect").is_err());
+    }
+
+    #[test]
+    fn exact_rule_requires_exact_spacing() {
+        let engine = make_engine();
+        assert!(engine.parse("deal 3 damage", "Effect").is_ok());
+        assert!(engine.parse("deal  3 damage", "Effect").is_err());
+    }
+}
+
+#[cfg(test)]
+mod typed_dict_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "deal {amount:String} from {who:String}" -> { damage: int(amount), name: str(who) }
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn dict_values_are_coerced() {
+        let engine = make_engine();
+        let result = engine.parse("deal \"12\" from \"bob\"", "Effect").unwrap();
+        assert_eq!(
+            result,
+            Value::Dictionary({
+                let mut m = IndexMap::new();
+                m.insert("amount".into(), Value::String("12".into()));
+                m.insert("who".into(), Value::String("bob".into()));
+                m.insert("damage".into(), Value::Integer(12));
+                m.insert("name".into(), Value::String("bob".into()));
+                m
+            })
+        );
+    }
+
+    #[test]
+    fn int_placeholder_as_float() {
+        let grammar = r#"Effect: "heal for {amount:Int}" -> Heal { amount: float(amount) }"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        assert_eq!(
+            engine.parse("heal for 7", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Heal".into(),
+                fields: IndexMap::from([("amount".into(), Value::Float(7.0))]),
+            }
+        );
+    }
+
+    #[test]
+    fn failed_coercion_is_an_error() {
+        let engine = make_engine();
+        let result = engine.parse("deal \"lots\" from \"bob\"", "Effect");
+        assert!(matches!(result, Err(DokearleyError::ValueError(_))));
+    }
+
+    #[test]
+    fn floats_out_of_int_range_are_not_cast() {
+        let grammar = r#"Effect: "deal {amount:Float}" -> { damage: int(amount) }"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let damage = |input| match engine.parse(input, "Effect") {
+            Ok(Value::Dictionary(fields)) => Ok(fields["damage"].clone()),
+            Ok(other) => panic!("expected a dictionary, got {:?}", other),
+            Err(e) => Err(e),
+        };
+        assert_eq!(damage("deal 12.0").unwrap(), Value::Integer(12));
+        let result = damage("deal 100000000000000000000.0");
+        assert!(matches!(result, Err(DokearleyError::ValueError(_))));
+    }
+}
+
+#[cfg(test)]
+mod unsigned_numbers_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "push at {speed:UFloat}" -> Push
+Effect: "wait {turns:UInt}" -> Wait
+Effect: "shift by {offset:Float}" -> Shift
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn unsigned_float_accepts_positive() {
+        let engine = make_engine();
+        let result = engine.parse("push at 3.0", "Effect").unwrap();
+        assert_eq!(
+            result,
+            Value::Resource {
+                typ: "Push".into(),
+                fields: {
+                    let mut m = IndexMap::new();
+                    m.insert("speed".into(), Value::Float(3.0));
+                    m
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn unsigned_float_rejects_negative() {
+        let engine = make_engine();
+        match engine.parse("push at -3.0", "Effect") {
+            Err(DokearleyError::ParseError(err)) => {
+                assert_eq!(err.found.as_deref(), Some("-3.0"));
+                assert!(err.expected.contains(&"<speed:UFloat>".to_string()));
+            }
+            other => panic!("unexpected parse output: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unsigned_int_rejects_negative() {
+        let engine = make_engine();
+        assert!(engine.parse("wait 2", "Effect").is_ok());
+        assert!(engine.parse("wait -2", "Effect").is_err());
+    }
+
+    #[test]
+    fn signed_float_accepts_negative() {
+        let engine = make_engine();
+        let result = engine.parse("shift by -1.5", "Effect").unwrap();
+        assert_eq!(
+            result,
+            Value::Resource {
+                typ: "Shift".into(),
+                fields: {
+                    let mut m = IndexMap::new();
+                    m.insert("offset".into(), Value::Float(-1.5));
+                    m
+                }
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod duration_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "wait {d:Duration}" -> Wait
+Effect: "heal {amount:Int}%" -> HealPercent
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    fn wait(value: i64, unit: &str) -> Value {
+        Value::Resource {
+            typ: "Wait".into(),
+            fields: IndexMap::from([(
+                "d".into(),
+                Value::Resource {
+                    typ: "Duration".into(),
+                    fields: IndexMap::from([
+                        ("value".into(), Value::Integer(value)),
+                        ("unit".into(), Value::String(unit.into())),
+                    ]),
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn parse_wait_seconds() {
+        let engine = make_engine();
+        assert_eq!(engine.parse("wait 5s", "Effect").unwrap(), wait(5, "s"));
+    }
+
+    #[test]
+    fn parse_wait_milliseconds() {
+        let engine = make_engine();
+        assert_eq!(engine.parse("wait 200ms", "Effect").unwrap(), wait(200, "ms"));
+    }
+
+    #[test]
+    fn unknown_unit_is_rejected() {
+        let engine = make_engine();
+        assert!(engine.parse("wait 5h", "Effect").is_err());
+        assert!(engine.parse("wait 5min", "Effect").is_err());
+    }
+
+    #[test]
+    fn int_followed_by_unit_terminal() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("heal 10%", "Effect").unwrap(),
+            Value::Resource {
+                typ: "HealPercent".into(),
+                fields: IndexMap::from([("amount".into(), Value::Integer(10))]),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod percent_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "heal {ratio:Percent}" -> HealRatio
+Effect: "{a:Int} % {b:Int}" -> Modulo
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    fn ratio(engine: &Dokearley, input: &str) -> Option<Value> {
+        match engine.parse(input, "Effect") {
+            Ok(Value::Resource { typ, mut fields }) if typ == "HealRatio" => fields.shift_remove("ratio"),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn percent_is_a_fraction() {
+        let engine = make_engine();
+        assert_eq!(ratio(&engine, "heal 50%"), Some(Value::Float(0.5)));
+        assert_eq!(ratio(&engine, "heal 12.5%"), Some(Value::Float(0.125)));
+        assert_eq!(ratio(&engine, "heal 50"), None);
+        assert_eq!(ratio(&engine, "heal 50 %"), None);
+    }
+
+    #[test]
+    fn percent_sign_elsewhere_is_text() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("7 % 3", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Modulo".into(),
+                fields: IndexMap::from([
+                    ("a".into(), Value::Integer(7)),
+                    ("b".into(), Value::Integer(3)),
+                ]),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod end_anchor_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Line: "{c:Command} please" -> Polite
+Line: "{c:Command}" -> Bare
+Command: "quit$" -> Quit
+Command: "stop" -> Stop
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn anchored_rule_matches_at_end() {
+        let engine = make_engine();
+        assert!(engine.parse("quit", "Line").is_ok());
+        assert!(engine.parse("quit", "Command").is_ok());
+    }
+
+    #[test]
+    fn anchored_rule_rejects_trailing_input() {
+        let engine = make_engine();
+        assert!(engine.parse("stop please", "Line").is_ok());
+        match engine.parse("quit please", "Line") {
+            Err(DokearleyError::ParseError(err)) => {
+                assert!(err.expected.contains(&"<EOF>".to_string()))
+            }
+            other => panic!("unexpected parse output: {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod continuations_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "deal {amount:Int} damage" -> Damage
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "to {target : Target} : {effect : ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+Target: "an ally" -> Target { kind: "ally" }
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn continuations_after_target() {
+        let engine = make_engine();
+        let views = engine.continuations("to self : ", "ItemEffect");
+        let patterns: Vec<&str> = views.iter().map(|v| v.pattern.as_str()).collect();
+        assert!(patterns.contains(&"deal <amount:Int> damage"));
+        assert!(patterns.contains(&"heal for <amount:Int>"));
+        assert!(views.iter().all(|v| v.lhs == "ItemEffect"));
+        // The targeted effect itself is waiting for its effect
+        assert!(views
+            .iter()
+            .any(|v| v.pattern.starts_with("to ") && v.matched == 7));
+    }
+
+    #[test]
+    fn continuations_inside_a_pattern() {
+        let engine = make_engine();
+        let views = engine.continuations("heal f", "ItemEffect");
+        assert_eq!(
+            views,
+            vec![ProductionView {
+                lhs: "ItemEffect".to_string(),
+                pattern: "heal for <amount:Int>".to_string(),
+                matched: 6,
+            }]
+        );
+    }
+}
+
+#[cfg(test)]
+mod parse_cache_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+ItemEffect: "deal {amount:Int} damage" -> Damage
+ItemEffect: "heal for {amount:Int}" -> Heal
+"#;
+
+    fn make_engine(capacity: usize) -> Dokearley<'static> {
+        let options = Options {
+            parse_cache_capacity: capacity,
+            ..Options::default()
+        };
+        Dokearley::from_dokedef_with_options(GRAMMAR, options).expect("invalid grammar")
+    }
+
+    #[test]
+    fn repeated_parses_hit_the_cache() {
+        let engine = make_engine(8);
+        let first = engine.parse("heal for 7", "ItemEffect").unwrap();
+        let second = engine.parse("heal for 7", "ItemEffect").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(engine.parse_cache.misses(), 1);
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let engine = make_engine(2);
+        engine.parse("heal for 1", "ItemEffect").unwrap();
+        engine.parse("heal for 2", "ItemEffect").unwrap();
+        engine.parse("heal for 1", "ItemEffect").unwrap();
+        engine.parse("heal for 3", "ItemEffect").unwrap();
+        assert_eq!(engine.parse_cache.misses(), 3);
+        // "heal for 2" was the least recently used
+        engine.parse("heal for 1", "ItemEffect").unwrap();
+        engine.parse("heal for 2", "ItemEffect").unwrap();
+        assert_eq!(engine.parse_cache.misses(), 4);
+    }
+
+    #[test]
+    fn inserting_again_marks_as_recently_used() {
+        let cache = parse_cache::ParseCache::new(2);
+        cache.insert("a", "S", &Value::Integer(1));
+        cache.insert("b", "S", &Value::Integer(2));
+        cache.insert("a", "S", &Value::Integer(3));
+        cache.insert("c", "S", &Value::Integer(4));
+        assert_eq!(cache.get("a", "S"), Some(Value::Integer(3)));
+        assert_eq!(cache.get("b", "S"), None);
+        assert_eq!(cache.get("c", "S"), Some(Value::Integer(4)));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        engine.parse("heal for 7", "ItemEffect").unwrap();
+        engine.parse("heal for 7", "ItemEffect").unwrap();
+        assert_eq!(engine.parse_cache.misses(), 0);
+    }
+
+    #[test]
+    fn reload_clears_the_cache() {
+        let mut engine = make_engine(8);
+        engine.parse("heal for 7", "ItemEffect").unwrap();
+        engine
+            .reload(r#"ItemEffect: "heal for {amount:Int}" -> Regen"#)
+            .unwrap();
+        match engine.parse("heal for 7", "ItemEffect").unwrap() {
+            Value::Resource { typ, .. } => assert_eq!(typ, "Regen"),
+            v => panic!("unexpected parse output: {:?}", v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod sub_grammar_tests {
+    use super::*;
+
+    const MATH: &str = r#"
+Expr: "{a:Int} + {b:Int}" -> Add
+Expr: "{a:Int} * {b:Int}" -> Mul
+"#;
+
+    const EFFECTS: &str = r#"
+Effect: "deal {amount:@Expr} damage" -> Damage
+Effect: "heal for {amount:Int}" -> Heal
+"#;
+
+    fn make_engine() -> Dokearley<'static> {
+        let math = Dokearley::from_dokedef(MATH).expect("invalid math grammar");
+        Dokearley::from_dokedef(EFFECTS)
+            .expect("invalid grammar")
+            .with_sub_grammar("Expr", math)
+    }
+
+    #[test]
+    fn effect_embeds_math_expression() {
+        let engine = make_engine();
+        let result = engine.parse("deal 2 * 3 damage", "Effect").unwrap();
+        assert_eq!(
+            result,
+            Value::Resource {
+                typ: "Damage".into(),
+                fields: IndexMap::from([(
+                    "amount".into(),
+                    Value::Resource {
+                        typ: "Mul".into(),
+                        fields: IndexMap::from([
+                            ("a".into(), Value::Integer(2)),
+                            ("b".into(), Value::Integer(3)),
+                        ]),
+                    }
+                )]),
+            }
+        );
+    }
+
+    #[test]
+    fn input_rejected_by_sub_grammar() {
+        let engine = make_engine();
+        assert!(engine.parse("deal 2 - 3 damage", "Effect").is_err());
+    }
+
+    #[test]
+    fn unregistered_sub_grammar_never_matches() {
+        let engine = Dokearley::from_dokedef(EFFECTS).unwrap();
+        assert!(engine.parse("deal 2 * 3 damage", "Effect").is_err());
+        assert!(engine.parse("heal for 3", "Effect").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod nullable_loop_tests {
+    use super::*;
+
+    #[test]
+    fn nullable_cycle_is_reported() {
+        let grammar = r#"
+A : B
+B : A
+B : "" -> Nothing
+"#;
+        match Dokearley::from_dokedef(grammar) {
+            Err(err @ DokearleyError::InfiniteNullableLoop(_)) => {
+                let message = err.to_string();
+                assert!(message.ends_with("A -> B -> A"), "{}", message);
+            }
+            r => panic!("expected a nullable loop, got {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod trailing_punctuation_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "deal {amount:Int} damage" -> Damage
+"#;
+
+    #[test]
+    fn trailing_punctuation_is_stripped() {
+        let options = Options {
+            trailing_punctuation: ".!".into(),
+            ..Options::default()
+        };
+        let engine = Dokearley::from_dokedef_with_options(GRAMMAR, options).unwrap();
+        let expected = engine.parse("heal for 7", "ItemEffect").unwrap();
+        assert_eq!(engine.parse("heal for 7.", "ItemEffect").unwrap(), expected);
+        assert_eq!(
+            engine.parse("deal 3 damage!", "ItemEffect").unwrap(),
+            engine.parse("deal 3 damage", "ItemEffect").unwrap()
+        );
+    }
+
+    #[test]
+    fn punctuation_is_kept_by_default() {
+        let engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert!(engine.parse("deal 3 damage!", "ItemEffect").is_err());
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod compiled_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: DamageEffect | HealEffect
+DamageEffect: "deal {amount:Int} damage" -> Damage { element: "fire" } where amount > 0
+HealEffect: "heal   for {amount:String}" @ws:collapse -> { heal: int(amount) }
+Effect: "{target:Target} : {effect:Effect}" -> Targeted
+Target: "self" -> Target { kind: "self" }
+Effect: "stop$" -> Stop
+"#;
+
+    #[test]
+    fn compiled_grammar_round_trip() {
+        let engine = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let owned = Dokearley::from_compiled(&engine.compile()).unwrap();
+        let loaded = owned.parser().unwrap();
+        for input in ["deal 3 damage", "heal for \"12\"", "self : heal  for \"4\"", "stop"] {
+            assert_eq!(
+                loaded.parse(input, "Effect").unwrap(),
+                engine.parse(input, "Effect").unwrap(),
+                "{}",
+                input
+            );
+        }
+        assert!(loaded.parse("stop now", "Effect").is_err());
+        assert!(matches!(
+            loaded.parse("deal -3 damage", "Effect"),
+            Err(DokearleyError::ValidationFailed(_))
+        ));
+        assert_eq!(loaded.compile(), engine.compile());
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        assert!(matches!(
+            Dokearley::from_compiled(b"not a grammar"),
+            Err(DokearleyError::InvalidCompiledGrammar(_))
+        ));
+        let mut bytes = Dokearley::from_dokedef(GRAMMAR).unwrap().compile();
+        bytes[4] += 1;
+        assert!(matches!(
+            Dokearley::from_compiled(&bytes),
+            Err(DokearleyError::InvalidCompiledGrammar(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod unknown_type_tests {
+    use super::*;
+
+    fn unknown_type(grammar: &str) -> (String, Option<String>) {
+        match Dokearley::from_dokedef(grammar) {
+            Err(DokearleyError::UnknownType { typ, suggestion }) => (typ, suggestion),
+            r => panic!("expected an unknown type, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn misspelled_builtin_is_suggested() {
+        let grammar = r#"Effect: "deal {x:Integer} damage" -> Damage"#;
+        assert_eq!(
+            unknown_type(grammar),
+            ("Integer".to_string(), Some("Int".to_string()))
+        );
+        let err = Dokearley::from_dokedef(grammar).err().unwrap();
+        assert!(err.to_string().ends_with(r#"did you mean "Int"?"#), "{}", err);
+    }
+
+    #[test]
+    fn misspelled_rule_is_suggested() {
+        let grammar = r#"
+Effect: "to {t:Traget} : heal" -> Heal
+Target: "self" -> Target { kind: "self" }
+"#;
+        assert_eq!(
+            unknown_type(grammar),
+            ("Traget".to_string(), Some("Target".to_string()))
+        );
+        let (_, suggestion) = unknown_type(r#"Effect: "use {x:Wand}" -> Use"#);
+        assert_eq!(suggestion, None);
+    }
+
+    #[test]
+    fn builtins_are_case_insensitive() {
+        assert!(Dokearley::from_dokedef(r#"Effect: "deal {x:int} damage" -> Damage"#).is_ok());
+        assert!(Dokearley::from_dokedef(r#"Effect: "wait {d:DURATION}" -> Wait"#).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod keyword_capture_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Target: "self" | "ally" | "enemy" @keyword(kind) -> Target
+Element: "fire"|"ice" @keyword(name) -> { power: 2 }
+Effect: "hit {target:Target} with {element:Element}" -> Hit
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn each_keyword_is_captured() {
+        let engine = make_engine();
+        for keyword in ["self", "ally", "enemy"] {
+            assert_eq!(
+                engine.parse(keyword, "Target").unwrap(),
+                Value::Resource {
+                    typ: "Target".into(),
+                    fields: IndexMap::from([("kind".into(), Value::String(keyword.into()))]),
+                }
+            );
+        }
+        assert!(engine.parse("boss", "Target").is_err());
+    }
+
+    #[test]
+    fn keyword_in_dictionary_and_nested() {
+        let engine = make_engine();
+        let element = Value::Dictionary(IndexMap::from([
+            ("power".into(), Value::Integer(2)),
+            ("name".into(), Value::String("ice".into())),
+        ]));
+        assert_eq!(engine.parse("ice", "Element").unwrap(), element);
+        match engine.parse("hit ally with ice", "Effect").unwrap() {
+            Value::Resource { fields, .. } => assert_eq!(fields["element"], element),
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod approx_eq_tests {
+    use super::*;
+
+    fn push(speed: f64) -> Value {
+        Value::Resource {
+            typ: "Push".into(),
+            fields: IndexMap::from([
+                ("speed".into(), Value::Float(speed)),
+                ("steps".into(), Value::Array(vec![Value::Float(speed * 2.0)])),
+            ]),
+        }
+    }
+
+    #[test]
+    fn nearly_equal_float_resources() {
+        let engine = Dokearley::from_dokedef(r#"Effect: "push at {speed:Float}" -> Push"#).unwrap();
+        let parsed = engine.parse("push at 0.3", "Effect").unwrap();
+        let computed = Value::Resource {
+            typ: "Push".into(),
+            fields: IndexMap::from([("speed".into(), Value::Float(0.1 + 0.2))]),
+        };
+        assert_ne!(parsed, computed);
+        assert!(parsed.approx_eq(&computed, 1e-9));
+
+        assert!(push(0.1 + 0.2).approx_eq(&push(0.3), 1e-9));
+        assert!(!push(0.3).approx_eq(&push(0.31), 1e-9));
+        assert!(push(0.3).approx_eq(&push(0.31), 0.1));
+    }
+
+    #[test]
+    fn other_variants_compare_exactly() {
+        assert!(!Value::Integer(1).approx_eq(&Value::Integer(2), 10.0));
+        assert!(!Value::Integer(1).approx_eq(&Value::Float(1.0), 10.0));
+        let dict = |v: f64| Value::Dictionary(IndexMap::from([("x".into(), Value::Float(v))]));
+        assert!(!dict(1.0).approx_eq(&Value::Dictionary(IndexMap::new()), 10.0));
+        assert!(dict(1.0).approx_eq(&dict(1.5), 1.0));
+    }
+}
+
+#[cfg(test)]
+mod ws_run_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "heal{~}for{~}{amount:Int}" -> Heal
+Effect: "deal {amount:Int} damage" -> Damage
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn ws_run_matches_several_spaces() {
+        let engine = make_engine();
+        let heal = Value::Resource {
+            typ: "Heal".into(),
+            fields: IndexMap::from([("amount".into(), Value::Integer(7))]),
+        };
+        assert_eq!(engine.parse("heal   for   7", "Effect").unwrap(), heal);
+        assert_eq!(engine.parse("heal for\t7", "Effect").unwrap(), heal);
+    }
+
+    #[test]
+    fn ws_run_needs_whitespace() {
+        let engine = make_engine();
+        assert!(engine.parse("healfor 7", "Effect").is_err());
+        // Other rules keep counting their spaces
+        assert!(engine.parse("deal  3 damage", "Effect").is_err());
+    }
+}
+
+#[cfg(test)]
+mod deterministic_hint_tests {
+    use super::*;
+
+    fn hint(grammar: &str) -> bool {
+        Dokearley::from_dokedef(grammar)
+            .expect("invalid grammar")
+            .is_deterministic_hint()
+    }
+
+    #[test]
+    fn ll1_grammar_is_deterministic() {
+        assert!(hint(
+            r#"
+Effect: "deal {amount:Int} damage" -> Damage
+Effect: "heal {amount:Int}" -> Heal
+Effect: "to {target:Target} : {effect:Effect}" -> Targeted
+Target: "self" -> Target { kind: "self" }
+Target: "an ally" -> Target { kind: "ally" }
+"#
+        ));
+    }
+
+    #[test]
+    fn shared_prefix_is_not_deterministic() {
+        // Both start with `h`, only the rest of the input tells them apart
+        assert!(!hint(
+            r#"
+Effect: "heal {amount:Int}" -> Heal
+Effect: "hit {amount:Int}" -> Hit
+"#
+        ));
+        // Ambiguous: the same input has two derivations
+        assert!(!hint(
+            r#"
+Effect: Fire | Burn
+Fire: "burn" -> Fire
+Burn: "burn" -> Burn
+"#
+        ));
+    }
+
+    #[test]
+    fn overlapping_builtins_are_not_deterministic() {
+        assert!(!hint(
+            r#"
+Wait: "{turns:Int}" -> Turns
+Wait: "{d:Duration}" -> Time
+"#
+        ));
+        assert!(hint(
+            r#"
+Value: "{i:Int}" -> Int
+Value: "{s:String}" -> Str
+"#
+        ));
+    }
+
+    #[test]
+    fn nullable_followed_by_its_first_is_not_deterministic() {
+        // After `Opt` comes `x`, which `Opt` could also start with
+        assert!(!hint(
+            r#"
+Line: "{o:Opt}x" -> Line
+Opt: "x" -> Some
+Opt: "" -> Nothing
+"#
+        ));
+        assert!(hint(
+            r#"
+Line: "{o:Opt}y" -> Line
+Opt: "x" -> Some
+Opt: "" -> Nothing
+"#
+        ));
+    }
+}
+
+#[cfg(test)]
+mod regex_placeholder_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "ticket {code:/[A-Z]{3}-\d+/}" -> Ticket
+Effect: "path {p:/[a-z]+(\/[a-z]+)*/} now" -> Path
+"#;
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_captures_text() {
+        let engine = Dokearley::from_dokedef(GRAMMAR).expect("invalid grammar");
+        assert_eq!(
+            engine.parse("ticket ABC-42", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Ticket".into(),
+                fields: IndexMap::from([("code".into(), Value::String("ABC-42".into()))]),
+            }
+        );
+        assert_eq!(
+            engine.parse("path a/bc/d now", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Path".into(),
+                fields: IndexMap::from([("p".into(), Value::String("a/bc/d".into()))]),
+            }
+        );
+        assert!(engine.parse("ticket AB-42", "Effect").is_err());
+        // `42` is a single token, the match can't stop inside it
+        assert!(engine.parse("ticket ABC-42x", "Effect").is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn invalid_regex_is_rejected() {
+        let grammar = r#"Effect: "ticket {code:/[A-Z/}" -> Ticket"#;
+        assert!(matches!(
+            Dokearley::from_dokedef(grammar),
+            Err(DokearleyError::InvalidDokedef(_))
+        ));
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn regex_needs_the_feature() {
+        match Dokearley::from_dokedef(GRAMMAR) {
+            Err(DokearleyError::InvalidDokedef(msg)) => assert!(msg.contains("`regex` feature")),
+            r => panic!("expected an error, got {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod coverage_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "to {target : Target} : {effect : ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn gap_where_the_colon_is_missing() {
+        let engine = make_engine();
+        let input = "to self heal for 7";
+        let coverage = engine.coverage(input, "ItemEffect");
+        // `self` is read as a Target, nothing reads past it without the `:`
+        assert_eq!(coverage.covered, vec![3..7]);
+        assert_eq!(coverage.uncovered, vec![0..3, 7..input.len()]);
+        assert_eq!(&input[coverage.uncovered[1].clone()], " heal for 7");
+    }
+
+    #[test]
+    fn parsed_input_is_fully_covered() {
+        let engine = make_engine();
+        let input = "to self : heal for 7";
+        let coverage = engine.coverage(input, "ItemEffect");
+        assert_eq!(coverage.covered, vec![0..input.len()]);
+        assert!(coverage.uncovered.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod alias_tests {
+    use super::*;
+
+    #[test]
+    fn alias_behaves_like_builtin() {
+        let grammar = r#"
+@alias Amount = Int
+@alias Text = String
+
+Effect: "heal for {n:Amount}" -> Heal
+Effect: "say {t:Text}" -> Say
+"#;
+        let aliased = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let plain = Dokearley::from_dokedef(
+            r#"
+Effect: "heal for {n:Int}" -> Heal
+Effect: "say {t:String}" -> Say
+"#,
+        )
+        .unwrap();
+        for input in ["heal for 7", "say \"hi\""] {
+            assert_eq!(
+                aliased.parse(input, "Effect").unwrap(),
+                plain.parse(input, "Effect").unwrap()
+            );
+        }
+        assert!(aliased.parse("heal for \"7\"", "Effect").is_err());
+    }
+
+    #[test]
+    fn alias_colliding_with_a_rule_is_rejected() {
+        let grammar = r#"
+@alias Target = String
+Effect: "hit {t:Target}" -> Hit
+Target: "self" -> Target
+"#;
+        assert!(matches!(
+            Dokearley::from_dokedef(grammar),
+            Err(DokearleyError::InvalidAlias(_))
+        ));
+        let grammar = r#"
+@alias Amount = Target
+Effect: "hit {n:Amount}" -> Hit
+"#;
+        assert!(matches!(
+            Dokearley::from_dokedef(grammar),
+            Err(DokearleyError::InvalidAlias(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod parse_any_tests {
+    use super::*;
+
+    const STARTS: [&str; 2] = ["ItemEffect", "Target"];
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "deal {amount:Int} damage" -> Damage
+Target: "self" -> Target { kind: "self" }
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    fn describe(engine: &Dokearley<'static>, input: &str) -> String {
+        match engine.parse_any(input, &STARTS) {
+            Ok(("ItemEffect", Value::Resource { typ, .. })) => format!("effect {}", typ),
+            Ok(("Target", _)) => "target".to_string(),
+            Ok((start, _)) => panic!("unexpected start {}", start),
+            Err(err) => format!("error {}", err),
+        }
+    }
+
+    #[test]
+    fn dispatch_on_matched_start() {
+        let engine = make_engine();
+        assert_eq!(describe(&engine, "heal for 7"), "effect Heal");
+        assert_eq!(describe(&engine, "self"), "target");
+    }
+
+    #[test]
+    fn furthest_error_is_reported() {
+        let engine = make_engine();
+        match engine.parse_any("deal 3 dmg", &STARTS) {
+            // `ItemEffect` reads up to the `m` of `dmg`, `Target` fails right away
+            Err(DokearleyError::ParseError(err)) => assert_eq!(err.byte_pos, 8),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_start_is_an_error() {
+        let engine = make_engine();
+        assert!(matches!(engine.parse_any("self", &[]), Err(DokearleyError::NoStart)));
+    }
+
+    starts! {
+        enum Matched { ItemEffect, Target }
+    }
+
+    #[test]
+    fn dispatch_on_generated_enum() {
+        let engine = make_engine();
+        assert_eq!(Matched::STARTS, STARTS);
+        match engine.parse_matched::<Matched>("heal for 7").unwrap() {
+            Matched::ItemEffect(Value::Resource { typ, .. }) => assert_eq!(typ, "Heal"),
+            other => panic!("unexpected match: {:?}", other),
+        }
+        assert!(matches!(engine.parse_matched("self"), Ok(Matched::Target(_))));
+    }
+}
+
+#[cfg(test)]
+mod token_stream_tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    const INPUT: &str = "to self : deal 12 damage and say \"héllo 🔥 there\"";
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "deal {amount:Int} damage and say {text:String}" -> Say
+ItemEffect: "to {target:Target} : {effect:ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+"#;
+
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    /// Hands out one byte per read, splitting every multi-byte character.
+    struct OneByte<'a>(&'a [u8]);
+
+    impl Read for OneByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn owned(input: &str) -> Vec<OwnedToken> {
+        recognizer::tokenize(input)
+            .into_iter()
+            .map(|t| OwnedToken {
+                kind: t.kind,
+                text: t.text.to_string(),
+                span: t.span.start..t.span.end,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_and_parse_from_cursor() {
+        let engine = make_engine();
+        let tokens = tokenize_reader(Cursor::new(INPUT)).unwrap();
+        assert_eq!(tokens, owned(INPUT));
+        assert_eq!(
+            engine.parse_tokens(&tokens, "ItemEffect").unwrap(),
+            engine.parse(INPUT, "ItemEffect").unwrap()
+        );
+    }
+
+    #[test]
+    fn characters_split_between_reads() {
+        let tokens = tokenize_reader(OneByte(INPUT.as_bytes())).unwrap();
+        assert_eq!(tokens, owned(INPUT));
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported() {
+        let bytes = [b'a', b' ', 0xff, b'b'];
+        assert!(matches!(
+            tokenize_reader(Cursor::new(bytes)),
+            Err(TokenizeError::InvalidUtf8(2))
+        ));
+        // A character cut by the end of the stream
+        let bytes = [b'a', 0xf0, 0x9f];
+        assert!(matches!(
+            tokenize_reader(Cursor::new(bytes)),
+            Err(TokenizeError::InvalidUtf8(1))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod field_order_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Move: "move {c:Int} {a:Int}" -> Move { d: 4, b: c }
+Shape: "triangle" -> Shape { c: 1, a: 2, b: 3 }
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    fn keys<'v>(engine: &Dokearley, value: &'v Value) -> Vec<&'v str> {
+        engine.ordered_fields(value).into_iter().map(|(k, _)| k).collect()
+    }
+
+    #[test]
+    fn fixed_fields_keep_declared_order() {
+        let engine = make_engine();
+        assert_eq!(engine.field_order("Shape"), ["c", "a", "b"]);
+        let shape = engine.parse("triangle", "Shape").unwrap();
+        assert_eq!(keys(&engine, &shape), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn placeholders_then_fixed_fields() {
+        let engine = make_engine();
+        assert_eq!(engine.field_order("Move"), ["c", "a", "d", "b"]);
+        let value = engine.parse("move 1 2", "Move").unwrap();
+        assert_eq!(keys(&engine, &value), ["c", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn owned_entries_keep_declared_order() {
+        let engine = make_engine();
+        let value = engine.parse("move 1 2", "Move").unwrap();
+        let entries = engine.into_ordered_entries(value);
+        let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["c", "a", "d", "b"]);
+        assert_eq!(entries[3].1, Value::Integer(1));
+    }
+
+    #[test]
+    fn parsed_values_keep_declared_order() {
+        let value = make_engine().parse("move 1 2", "Move").unwrap();
+        let Value::Resource { fields, .. } = &value else {
+            panic!("expected a resource, got {value:?}");
+        };
+        let keys: Vec<&str> = fields.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["c", "a", "d", "b"]);
+        assert_eq!(value.to_string(), "Move { c: 1, a: 2, d: 4, b: 1 }");
+        // JSON snapshots sort the keys instead
+        assert_eq!(value.to_json_string(), r#"{"$type":"Move","a":2,"b":1,"c":1,"d":4}"#);
+    }
+
+    #[test]
+    fn undeclared_fields_come_last_by_name() {
+        let engine = make_engine();
+        let value = Value::Resource {
+            typ: "Shape".into(),
+            fields: IndexMap::from([
+                ("z".into(), Value::Integer(0)),
+                ("b".into(), Value::Integer(3)),
+                ("y".into(), Value::Integer(0)),
+                ("c".into(), Value::Integer(1)),
+            ]),
+        };
+        assert_eq!(keys(&engine, &value), ["c", "b", "y", "z"]);
+        assert!(engine.ordered_fields(&Value::Integer(1)).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod parse_lines_tests {
+    use super::*;
+
+    #[test]
+    fn errors_come_with_their_line() {
+        let grammar = r#"
+Effect: "heal for {amount:Int}" -> Heal
+"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let text = "heal for 3\n\n  heal for nothing \nheal for 5\n";
+        let results = engine.parse_lines(text, "Effect");
+
+        let lines: Vec<usize> = results.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 3, 4]);
+        assert!(results[0].1.is_ok());
+        assert!(matches!(results[1].1, Err(DokearleyError::ParseError(_))));
+        assert_eq!(
+            results[2].1.as_ref().unwrap(),
+            &Value::Resource {
+                typ: "Heal".into(),
+                fields: IndexMap::from([("amount".into(), Value::Integer(5))]),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod exclusion_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Text: "{c:!then}{rest:Text}" -> { }
+Text: "{c:!then}" -> { }
+Line: "{before:Text}then {after:Text}" -> Line
+Letter: "{c:!then}" -> Letter
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn consumes_tokens_until_then() {
+        let engine = make_engine();
+        assert!(engine.accepts("blah blah then stop", "Line"));
+        // `Text` can't read past `then`
+        assert!(!engine.accepts("blah blah then stop", "Text"));
+        assert!(engine.accepts("blah blah", "Text"));
+        assert!(engine.accepts("the", "Text"));
+    }
+
+    #[test]
+    fn matches_a_single_token() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("x", "Letter").unwrap(),
+            Value::Resource {
+                typ: "Letter".into(),
+                fields: IndexMap::from([("c".into(), Value::String("x".into()))]),
+            }
+        );
+        assert!(engine.parse("t", "Letter").is_ok());
+        assert!(engine.parse("xy", "Letter").is_err());
+        assert!(engine.parse("", "Letter").is_err());
+    }
+}
+
+#[cfg(test)]
+mod value_macros_tests {
+    use super::*;
+    use crate::{dok_value, value_resource};
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "heal for {amount:Int}" -> Heal
+Effect: "deal {amount:Int} to {target:String}" -> Damage
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn dok_value_builds_values() {
+        assert_eq!(dok_value!(7), Value::Integer(7));
+        assert_eq!(dok_value!((-3)), Value::Integer(-3));
+        assert_eq!(
+            dok_value!({ Heal: {} }),
+            Value::Resource {
+                typ: "Heal".into(),
+                fields: IndexMap::new(),
+            }
+        );
+        assert_eq!(
+            dok_value!({ kind: "self", tags: ["a", 0.5, true] }),
+            Value::Dictionary(IndexMap::from([
+                ("kind".into(), Value::String("self".into())),
+                (
+                    "tags".into(),
+                    Value::Array(vec![
+                        Value::String("a".into()),
+                        Value::Float(0.5),
+                        Value::Bool(true)
+                    ])
+                ),
+            ]))
+        );
+        // only a single uppercase key holding a map is a resource
+        assert_eq!(
+            dok_value!({ Heal: 7 }),
+            Value::Dictionary(IndexMap::from([("Heal".into(), Value::Integer(7))]))
+        );
+        let heal = make_engine().parse("heal for 7", "Effect").unwrap();
+        assert_eq!(heal, dok_value!({ Heal: { amount: 7 } }));
+    }
+
+    #[test]
+    fn binds_the_fields_of_a_resource() {
+        let engine = make_engine();
+        let heal = engine.parse("heal for 7", "Effect").unwrap();
+        let amount = value_resource!(heal, "Heal", { amount } => match amount {
+            Value::Integer(i) => *i,
+            _ => 0,
+        });
+        assert_eq!(amount.unwrap(), 7);
+
+        let damage = engine.parse(r#"deal 3 to "boss""#, "Effect").unwrap();
+        let both = value_resource!(damage, "Damage", { amount, target } => {
+            (amount.clone(), target.clone())
+        });
+        assert_eq!(
+            both.unwrap(),
+            (Value::Integer(3), Value::String("boss".into()))
+        );
+    }
+
+    #[test]
+    fn other_shapes_are_errors() {
+        let engine = make_engine();
+        let damage = engine.parse(r#"deal 3 to "boss""#, "Effect").unwrap();
+        let err = value_resource!(damage, "Heal", { amount } => amount.clone()).unwrap_err();
+        assert!(matches!(err, DokearleyError::UnexpectedValue(_)));
+        assert_eq!(
+            err.to_string(),
+            "Unexpected value : expected a Heal resource, found a Damage resource"
+        );
+
+        let err = value_resource!(damage, "Damage", { power } => power.clone()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unexpected value : the Damage resource has no field power"
+        );
+        assert!(value_resource!(Value::Integer(3), "Heal", {} => ()).is_err());
+    }
+
+    #[test]
+    fn dok_value_matches_nested_parses() {
+        let grammar = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "to {target : Target} : {effect : ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        assert_eq!(
+            engine.parse("to self : heal for 7", "ItemEffect").unwrap(),
+            dok_value!({ TargetedEffect: {
+                target: { Target: { kind: "self" } },
+                effect: { Heal: { amount: 7 } },
+            } })
+        );
+    }
+}
+
+#[cfg(test)]
+mod allowed_arrows_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "deal {amount:Int} damage" => Damage
+"#;
+
+    fn with_arrows(allowed_arrows: AllowedArrows) -> Result<Dokearley<'static>, DokearleyError> {
+        let options = Options {
+            allowed_arrows,
+            ..Options::default()
+        };
+        Dokearley::from_dokedef_with_options(GRAMMAR, options)
+    }
+
+    #[test]
+    fn both_arrows_by_default() {
+        assert!(Dokearley::from_dokedef(GRAMMAR).is_ok());
+        assert!(with_arrows(AllowedArrows::Both).is_ok());
+    }
+
+    #[test]
+    fn disallowed_arrow_is_rejected() {
+        match with_arrows(AllowedArrows::Thin) {
+            Err(DokearleyError::InvalidDokedef(msg)) => assert!(msg.contains("=>"), "{msg}"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(with_arrows(AllowedArrows::Fat).is_err());
+        let thin_only = "ItemEffect: \"heal for {amount:Int}\" -> Heal";
+        let options = Options {
+            allowed_arrows: AllowedArrows::Thin,
+            ..Options::default()
+        };
+        assert!(Dokearley::from_dokedef_with_options(thin_only, options).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod merged_disjunction_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Fire: "fire {power:Int}" -> Fire
+Ice: "ice {power:Int}" -> { slow: 2 }
+Element: Fire | Ice -> { category: "elemental" }
+Spell: Fire | Ice -> Spell { school: "magic", level: power }
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn every_alternative_gains_the_fields() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("fire 3", "Element").unwrap(),
+            Value::Resource {
+                typ: "Fire".into(),
+                fields: IndexMap::from([
+                    ("power".into(), Value::Integer(3)),
+                    ("category".into(), Value::String("elemental".into())),
+                ]),
+            }
+        );
+        assert_eq!(
+            engine.parse("ice 1", "Element").unwrap(),
+            Value::Dictionary(IndexMap::from([
+                ("power".into(), Value::Integer(1)),
+                ("slow".into(), Value::Integer(2)),
+                ("category".into(), Value::String("elemental".into())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn a_typed_output_renames_the_alternative() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("ice 4", "Spell").unwrap(),
+            Value::Resource {
+                typ: "Spell".into(),
+                fields: IndexMap::from([
+                    ("power".into(), Value::Integer(4)),
+                    ("slow".into(), Value::Integer(2)),
+                    ("school".into(), Value::String("magic".into())),
+                    ("level".into(), Value::Integer(4)),
+                ]),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod adjacent_placeholders_tests {
+    use super::*;
+
+    #[test]
+    fn adjacent_numbers_are_rejected() {
+        let grammar = r#"Pair: "{a:Int}{b:Int}" -> Pair"#;
+        match Dokearley::from_dokedef(grammar) {
+            Err(DokearleyError::AdjacentPlaceholders { first, second, .. }) => {
+                assert_eq!(first, "<a:Int>");
+                assert_eq!(second, "<b:Int>");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(Dokearley::from_dokedef(r#"Wait: "{n:Float}{d:Duration}" -> Wait"#).is_err());
+    }
+
+    #[test]
+    fn separated_or_distinct_placeholders_are_fine() {
+        let grammar = r#"
+Pair: "{a:Int} {b:Int}" -> Pair
+Range: "{a:Int}-{b:Int}" -> Range
+Labeled: "{n:Int}{label:String}" -> Labeled
+"#;
+        let engine = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        assert!(engine.accepts("1 2", "Pair"));
+        assert!(engine.accepts("1-2", "Range"));
+        assert!(engine.accepts(r#"3"hp""#, "Labeled"));
+    }
+}
+
+#[cfg(test)]
+mod where_clause_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "decrease {stat:String} by {amount:Int}" -> Debuff where amount > 0
+ItemEffect: "set {stat:String} to {value:Float}" -> Set where value <= 1.5 and stat != "hp"
+ItemEffect: "to {target:Target} : {effect:ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn failed_constraint_is_an_error() {
+        let engine = make_engine();
+        assert!(engine.parse(r#"decrease "hp" by 5"#, "ItemEffect").is_ok());
+        match engine.parse(r#"decrease "hp" by -5"#, "ItemEffect") {
+            Err(DokearleyError::ValidationFailed(msg)) => {
+                assert_eq!(msg, "amount > 0, but amount is -5")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // Nested rules are checked too
+        assert!(matches!(
+            engine.parse(r#"to self : decrease "hp" by 0"#, "ItemEffect"),
+            Err(DokearleyError::ValidationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn all_constraints_must_hold() {
+        let engine = make_engine();
+        assert!(engine.parse(r#"set "speed" to 1.5"#, "ItemEffect").is_ok());
+        assert!(engine.parse(r#"set "speed" to 2.5"#, "ItemEffect").is_err());
+        assert!(engine.parse(r#"set "hp" to 0.5"#, "ItemEffect").is_err());
+    }
+
+    #[test]
+    fn signature_shows_the_clause() {
+        let signatures = make_engine().signatures();
+        assert!(signatures.contains(
+            &r#"ItemEffect := "decrease " <stat:String> " by " <amount:Int> -> Debuff where amount > 0"#
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn constraints_that_never_hold_are_rejected() {
+        let unknown = r#"Effect: "hit {n:Int}" -> X { y: n } where z > 1"#;
+        match Dokearley::from_dokedef(unknown) {
+            Err(DokearleyError::InvalidConstraint { constraint, reason, .. }) => {
+                assert_eq!(constraint, "z > 1");
+                assert_eq!(reason, "the output has no field z");
+            }
+            other => panic!("expected an invalid constraint, got {:?}", other.err()),
+        }
+        let mistyped = r#"Effect: "hit {n:Int}" -> X where n > "s""#;
+        match Dokearley::from_dokedef(mistyped) {
+            Err(DokearleyError::InvalidConstraint { reason, .. }) => {
+                assert_eq!(reason, "n is a number, not a string")
+            }
+            other => panic!("expected an invalid constraint, got {:?}", other.err()),
+        }
+        // fixed fields and casts can be constrained too
+        let fixed = r#"Effect: "hit {n:String}" -> X { y: int(n) } where y > 1"#;
+        assert!(Dokearley::from_dokedef(fixed).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod quoted_alternatives_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Reply : "yes" | "no" | CustomReply
+CustomReply : "maybe {days:Int} days" -> Maybe
+Answer : "a|b" | "{n:Int} times" | Reply @tagged
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn quoted_alternatives_yield_their_text() {
+        let engine = make_engine();
+        assert_eq!(engine.parse("yes", "Reply").unwrap(), Value::String("yes".into()));
+        assert_eq!(engine.parse("no", "Reply").unwrap(), Value::String("no".into()));
+        assert_eq!(
+            engine.parse("maybe 3 days", "Reply").unwrap(),
+            Value::Resource {
+                typ: "Maybe".into(),
+                fields: IndexMap::from([("days".into(), Value::Integer(3))]),
+            }
+        );
+        assert!(engine.parse("perhaps", "Reply").is_err());
+    }
+
+    #[test]
+    fn quoted_alternatives_can_hold_bars_and_placeholders() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("a|b", "Answer").unwrap(),
+            Value::Dictionary(IndexMap::from([
+                ("variant".into(), Value::String("a|b".into())),
+                ("value".into(), Value::String("a|b".into())),
+            ]))
+        );
+        assert_eq!(
+            engine.parse("2 times", "Answer").unwrap(),
+            Value::Dictionary(IndexMap::from([
+                ("variant".into(), Value::String("{n:Int} times".into())),
+                (
+                    "value".into(),
+                    Value::Dictionary(IndexMap::from([("n".into(), Value::Integer(2))]))
+                ),
+            ]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod parse_scalar_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "to {target:Target} : {effect:ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn extracts_a_nested_field() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse_scalar("to self : heal for 12", "ItemEffect", &["effect", "amount"]),
+            Some(Value::Integer(12))
+        );
+        assert_eq!(
+            engine.parse_scalar("to self : heal for 12", "ItemEffect", &["target", "kind"]),
+            Some(Value::String("self".into()))
+        );
+        assert_eq!(
+            engine.parse_scalar("heal for 3", "ItemEffect", &[]),
+            engine.parse("heal for 3", "ItemEffect").ok()
+        );
+    }
+
+    #[test]
+    fn missing_fields_and_rejected_inputs_are_none() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse_scalar("to self : heal for 12", "ItemEffect", &["effect", "stat"]),
+            None
+        );
+        assert_eq!(
+            engine.parse_scalar("to self : heal for 12", "ItemEffect", &["effect", "amount", "x"]),
+            None
+        );
+        assert_eq!(engine.parse_scalar("heal for many", "ItemEffect", &["amount"]), None);
+    }
+}
+
+#[cfg(test)]
+mod shadowed_numbers_tests {
+    use super::*;
+
+    #[test]
+    fn single_digit_literals_match() {
+        let engine = Dokearley::from_dokedef(r#"Level: "level 3" -> Three"#).unwrap();
+        assert!(engine.warnings().is_empty());
+        assert!(engine.parse("level 3", "Level").is_ok());
+        // `3d6`: a digit next to a letter is still its own token
+        let dice = Dokearley::from_dokedef(r#"Dice: "3d6" -> Dice"#).unwrap();
+        assert!(dice.warnings().is_empty());
+        assert!(dice.parse("3d6", "Dice").is_ok());
+    }
+
+    #[test]
+    fn number_literals_are_flagged() {
+        let engine = Dokearley::from_dokedef(
+            r#"
+Level: "level 12" -> Twelve
+Version: "v1.5" -> Version
+Debt: "owe -3" -> Debt
+Range: "{a:Int}-3" -> Range
+"#,
+        )
+        .unwrap();
+        let warnings = engine.warnings();
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+        assert!(warnings[0].contains("12 is read as a number"));
+        assert!(warnings[1].contains("1.5 is read as a number"));
+        assert!(warnings[2].contains("-3 is read as a number"));
+        // They really never match
+        assert!(engine.parse("level 12", "Level").is_err());
+        assert!(engine.parse("v1.5", "Version").is_err());
+        assert!(engine.parse("owe -3", "Debt").is_err());
+        // After a placeholder, `-` is an operator and `3` a single digit
+        assert!(engine.parse("5-3", "Range").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod concrete_tree_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+ItemEffect: "heal for {amount:Int}" -> Heal
+ItemEffect: "wait {time:Duration}" -> Wait
+ItemEffect: "say {text:String}" -> Say
+ItemEffect: "to {target:Target} : {effect:ItemEffect}" -> TargetedEffect
+Target: "self" -> Target { kind: "self" }
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    fn leaves(node: &ConcreteNode, out: &mut Vec<String>) {
+        match node {
+            ConcreteNode::Token { text, .. } => out.push(text.clone()),
+            ConcreteNode::Node { children, .. } => {
+                children.iter().for_each(|c| leaves(c, out));
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_rebuild_the_input() {
+        let engine = make_engine();
+        for input in ["to self : heal for 12", r#"to self : say "hi there""#, "wait 200ms"] {
+            let tree = engine.concrete_tree(input, "ItemEffect").unwrap();
+            let mut texts = Vec::new();
+            leaves(&tree, &mut texts);
+            assert_eq!(texts.concat(), input);
+            assert_eq!(tree.text(), input);
+        }
+    }
+
+    #[test]
+    fn nodes_keep_rules_and_spans() {
+        let tree = make_engine().concrete_tree("to self : heal for 12", "ItemEffect").unwrap();
+        let ConcreteNode::Node { lhs, children } = &tree else {
+            panic!("expected a node, got {tree:?}")
+        };
+        assert_eq!(lhs, "ItemEffect");
+        let rules: Vec<_> = children
+            .iter()
+            .filter_map(|c| match c {
+                ConcreteNode::Node { lhs, .. } => Some(lhs.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(rules, ["Target", "ItemEffect"]);
+        assert_eq!(
+            children.last(),
+            Some(&ConcreteNode::Node {
+                lhs: "ItemEffect".into(),
+                children: "heal for "
+                    .char_indices()
+                    .map(|(i, c)| ConcreteNode::Token {
+                        kind: TokenKind::Char,
+                        text: c.to_string(),
+                        span: 10 + i..11 + i,
+                    })
+                    .chain([ConcreteNode::Token {
+                        kind: TokenKind::Int,
+                        text: "12".into(),
+                        span: 19..21,
+                    }])
+                    .collect(),
+            })
+        );
+    }
+
+    #[test]
+    fn builtins_over_several_tokens_are_grouped() {
+        let tree = make_engine().concrete_tree("wait 200ms", "ItemEffect").unwrap();
+        let ConcreteNode::Node { children, .. } = &tree else {
+            panic!("expected a node, got {tree:?}")
+        };
+        match children.last() {
+            Some(ConcreteNode::Node { lhs, children }) => {
+                assert_eq!(lhs, "Duration");
+                assert_eq!(children.len(), 3);
+            }
+            other => panic!("expected the duration's tokens, got {other:?}"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serialize_tests {
+    use super::*;
+    use serde_json::json;
+
+    fn heal() -> Value {
+        Dokearley::from_dokedef(r#"Effect: "heal for {amount:Int}" -> Heal"#)
+            .unwrap()
+            .parse("heal for 7", "Effect")
+            .unwrap()
+    }
+
+    #[test]
+    fn internally_tagged() {
+        let expected = json!({ "$type": "Heal", "amount": 7 });
+        let value = heal();
+        assert_eq!(serde_json::to_value(value.tagged(ResourceTagging::Internal)).unwrap(), expected);
+        // the default shape
+        assert_eq!(serde_json::to_value(heal()).unwrap(), expected);
+    }
+
+    #[test]
+    fn adjacently_tagged() {
+        assert_eq!(
+            serde_json::to_value(heal().tagged(ResourceTagging::Adjacent)).unwrap(),
+            json!({ "$type": "Heal", "fields": { "amount": 7 } })
+        );
+    }
+
+    #[test]
+    fn nested_resources_use_the_same_tagging() {
+        let value = Value::Dictionary(IndexMap::from([
+            ("effects".to_string(), Value::Array(vec![heal()])),
+            ("label".to_string(), Value::String("twice".into())),
+        ]));
+        assert_eq!(
+            serde_json::to_value(value.tagged(ResourceTagging::Adjacent)).unwrap(),
+            json!({
+                "effects": [{ "$type": "Heal", "fields": { "amount": 7 } }],
+                "label": "twice",
+            })
+        );
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let value = Value::Dictionary(IndexMap::from([
+            ("effects".to_string(), Value::Array(vec![heal()])),
+            ("ratio".to_string(), Value::Float(2.0)),
+            ("count".to_string(), Value::Integer(2)),
+            ("negative".to_string(), Value::Integer(-3)),
+            ("on".to_string(), Value::Bool(true)),
+            ("label".to_string(), Value::String("twice".into())),
+        ]));
+        let json = serde_json::to_value(&value).unwrap();
+        let back: Value = serde_json::from_value(json).unwrap();
+        assert_eq!(back, value);
+        let back: Value = serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap();
+        assert_eq!(back, value);
+    }
+
+    #[test]
+    fn objects_without_a_string_type_are_dictionaries() {
+        let value: Value = serde_json::from_value(json!({ "$type": 3 })).unwrap();
+        assert_eq!(
+            value,
+            Value::Dictionary(IndexMap::from([("$type".to_string(), Value::Integer(3))]))
+        );
+        assert_eq!(serde_json::from_value::<Value>(json!(null)).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn type_keys_round_trip() {
+        let parser = Dokearley::from_dokedef(
+            r#"
+            Element: "fire" -> { type: "fire", power: 3 }
+            Effect: "cast {element:Element}" -> Cast { type: "spell" }
+            "#,
+        )
+        .unwrap();
+        let dictionary = parser.parse("fire", "Element").unwrap();
+        let json = serde_json::to_value(&dictionary).unwrap();
+        assert_eq!(json, json!({ "type": "fire", "power": 3 }));
+        assert_eq!(serde_json::from_value::<Value>(json).unwrap(), dictionary);
+
+        let resource = parser.parse("cast fire", "Effect").unwrap();
+        let json = serde_json::to_value(&resource).unwrap();
+        assert_eq!(
+            json,
+            json!({ "$type": "Cast", "type": "spell", "element": { "type": "fire", "power": 3 } })
+        );
+        assert_eq!(serde_json::from_value::<Value>(json).unwrap(), resource);
+    }
+}
+
+#[cfg(test)]
+mod annotations_tests {
+    use super::*;
+
+    fn make_engine(annotations: bool) -> Dokearley<'static> {
+        let options = Options {
+            annotations,
+            ..Default::default()
+        };
+        Dokearley::from_dokedef_with_options(
+            r#"Effect: "heal for {amount:Int}" -> Heal"#,
+            options,
+        )
+        .expect("invalid grammar")
+    }
+
+    #[test]
+    fn trailing_annotation_becomes_meta() {
+        let value = make_engine(true).parse("heal for 7 #[source:item42]", "Effect").unwrap();
+        assert_eq!(
+            value,
+            Value::Resource {
+                typ: "Heal".into(),
+                fields: IndexMap::from([
+                    ("amount".into(), Value::Integer(7)),
+                    (
+                        META_FIELD.into(),
+                        Value::Dictionary(IndexMap::from([(
+                            "source".into(),
+                            Value::String("item42".into())
+                        )]))
+                    ),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn leading_and_flag_annotations() {
+        let value = make_engine(true)
+            .parse("#[draft] #[author: kim] heal for 7", "Effect")
+            .unwrap();
+        let Value::Resource { fields, .. } = value else {
+            panic!("expected a resource, got {value:?}")
+        };
+        assert_eq!(
+            fields[META_FIELD],
+            Value::Dictionary(IndexMap::from([
+                ("draft".into(), Value::Bool(true)),
+                ("author".into(), Value::String("kim".into())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn no_annotations_no_meta() {
+        let value = make_engine(true).parse("heal for 7", "Effect").unwrap();
+        assert!(matches!(value, Value::Resource { fields, .. } if !fields.contains_key(META_FIELD)));
+        // Off by default, where annotations are part of the input
+        assert!(make_engine(false).parse("heal for 7 #[source:item42]", "Effect").is_err());
+    }
+}
+
+#[cfg(test)]
+mod repeat_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Color: "rgb {c:Int}{3}" -> Color
+Color: "gray {g:Int}" -> Color { c: g }
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn exactly_n_values_in_an_array() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("rgb 255 128 0", "Color").unwrap(),
+            Value::Resource {
+                typ: "Color".into(),
+                fields: IndexMap::from([(
+                    "c".into(),
+                    Value::Array(vec![
+                        Value::Integer(255),
+                        Value::Integer(128),
+                        Value::Integer(0)
+                    ])
+                )]),
+            }
+        );
+        assert!(engine.parse("rgb 255 128", "Color").is_err());
+        assert!(engine.parse("rgb 255 128 0 7", "Color").is_err());
+        // a single placeholder stays a single value
+        assert!(matches!(
+            engine.parse("gray 12", "Color").unwrap(),
+            Value::Resource { fields, .. } if fields["c"] == Value::Integer(12)
+        ));
+    }
+
+    #[test]
+    fn zero_repeats_are_rejected() {
+        assert!(matches!(
+            Dokearley::from_dokedef(r#"Color: "rgb {c:Int}{0}" -> Color"#),
+            Err(DokearleyError::InvalidDokedef(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod empty_terminal_tests {
+    use super::*;
+    use crate::recognizer::{OutSpec, Production, WhitespaceMode};
+
+    #[test]
+    fn patterns_never_hold_empty_terminals() {
+        for dokedef in [
+            r#"A: "" -> A"#,
+            r#"A: "{x:Int}{~}{y:Int}" -> A"#,
+            r#"A: "{c:Int}{2}" -> A"#,
+            r#"A: "x" | "" | B; B: "b""#,
+        ] {
+            let grammar = Dokearley::parse_dokedef(dokedef, &Options::default()).unwrap();
+            assert!(grammar.find_empty_terminal().is_none(), "{dokedef}");
+        }
+    }
+
+    #[test]
+    fn empty_terminals_are_rejected() {
+        let grammar = Grammar {
+            productions: vec![Production {
+                lhs: "S",
+                rhs: vec![Symbol::Terminal("a"), Symbol::Terminal("")],
+                out: OutSpec::Dict(Vec::new()),
+                ws: WhitespaceMode::Exact,
+            }],
+        };
+        match Dokearley::check(&grammar, "", &[]) {
+            Err(DokearleyError::EmptyTerminal(rule)) => assert_eq!(rule, r#"S : "a""#),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod anonymous_placeholder_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Wait: "wait {:Int} turns" -> Wait
+Wait: "wait {_:Int} or {_:Int} turns" -> Wait
+Move: "move {:Int} to {x:Int}" -> Move
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn anonymous_placeholders_add_no_field() {
+        let engine = make_engine();
+        let wait = Value::Resource {
+            typ: "Wait".into(),
+            fields: IndexMap::new(),
+        };
+        assert_eq!(engine.parse("wait 3 turns", "Wait").unwrap(), wait);
+        assert_eq!(engine.parse("wait 3 or 4 turns", "Wait").unwrap(), wait);
+        assert_eq!(
+            engine.parse("move 1 to 5", "Move").unwrap(),
+            Value::Resource {
+                typ: "Move".into(),
+                fields: IndexMap::from([("x".into(), Value::Integer(5))]),
+            }
+        );
+    }
+
+    #[test]
+    fn anonymous_placeholders_still_match() {
+        assert!(make_engine().parse("wait turns", "Wait").is_err());
+    }
+}
+
+#[cfg(test)]
+mod chain_children_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Inventory: "carry{items:Items}" -> Inventory { items <* Items }
+Items: " {item:Item}{rest:Items}" -> {}
+Items: "" -> {}
+Item: "sword" | "shield" | "bow"
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    fn inventory(items: &[&str]) -> Value {
+        let items = items.iter().map(|&item| Value::String(item.into()));
+        Value::Resource {
+            typ: "Inventory".into(),
+            fields: IndexMap::from([("items".into(), Value::Array(items.collect()))]),
+        }
+    }
+
+    #[test]
+    fn recursive_chain_is_a_flat_array() {
+        let engine = make_engine();
+        assert_eq!(
+            engine.parse("carry sword shield bow", "Inventory").unwrap(),
+            inventory(&["sword", "shield", "bow"])
+        );
+        assert_eq!(engine.parse("carry", "Inventory").unwrap(), inventory(&[]));
+    }
+
+    #[test]
+    fn children_outside_the_rule_stay_markers() {
+        let engine =
+            Dokearley::from_dokedef(r#"Action: "do" -> Action { components <* Effect }"#).unwrap();
+        assert_eq!(
+            engine.parse("do", "Action").unwrap(),
+            Value::Resource {
+                typ: "Action".into(),
+                fields: IndexMap::from([("components".into(), Value::Children("Effect".into()))]),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod placeholder_spans_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Effect: "heal for {amount:Int}" -> Heal
+Effect: "wait {d:Duration}" -> Wait
+Effect: "rgb {c:Int}{3}" -> Color
+Spell: "cast {name:String}: {effect:Effect}" -> Spell
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn span_covers_the_placeholder() {
+        let input = "heal for 7";
+        let (value, spans) = make_engine()
+            .parse_with_placeholder_spans(input, "Effect")
+            .unwrap();
+        assert_eq!(value, make_engine().parse(input, "Effect").unwrap());
+        assert_eq!(spans, HashMap::from([("amount".to_string(), (9, 10))]));
+        assert_eq!(&input[9..10], "7");
+    }
+
+    #[test]
+    fn nested_and_repeated_placeholders() {
+        let engine = make_engine();
+        let input = r#"cast "regen": wait 5s"#;
+        let (_, spans) = engine.parse_with_placeholder_spans(input, "Spell").unwrap();
+        let text = |key: &str| &input[spans[key].0..spans[key].1];
+        assert_eq!(text("name"), r#""regen""#);
+        assert_eq!(text("effect"), "wait 5s");
+        assert_eq!(text("effect.d"), "5s");
+
+        let input = "rgb 255 128 0";
+        let (_, spans) = engine.parse_with_placeholder_spans(input, "Effect").unwrap();
+        let text = |key: &str| &input[spans[key].0..spans[key].1];
+        assert_eq!((text("c[0]"), text("c[1]"), text("c[2]")), ("255", "128", "0"));
+    }
+}
+
+#[cfg(test)]
+mod scanner_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"Effect: "roll {dice:Dice} for damage" -> Roll"#;
+
+    /// Reads `2d6` as two six-sided dice
+    fn dice(text: &str) -> Option<(usize, Value)> {
+        let count_len = text.find(|c: char| !c.is_ascii_digit())?;
+        let rest = text[count_len..].strip_prefix('d')?;
+        let sides_len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let count = text[..count_len].parse().ok()?;
+        let sides = rest[..sides_len].parse().ok()?;
+        let fields = IndexMap::from([
+            ("count".into(), Value::Integer(count)),
+            ("sides".into(), Value::Integer(sides)),
+        ]);
+        let value = Value::Resource {
+            typ: "Dice".into(),
+            fields,
+        };
+        Some((count_len + 1 + sides_len, value))
+    }
+
+    fn make_engine() -> Dokearley<'static> {
+        let options = Options {
+            scanner_types: vec!["Dice".into()],
+            ..Default::default()
+        };
+        Dokearley::from_dokedef_with_options(GRAMMAR, options).expect("invalid grammar")
+    }
+
+    #[test]
+    fn registered_scanner_reads_its_type() {
+        let mut engine = make_engine();
+        assert!(engine.parse("roll 2d6 for damage", "Effect").is_err());
+        engine.register_scanner("Dice", dice);
+        let value = engine.parse("roll 2d6 for damage", "Effect").unwrap();
+        let roll = Value::Resource {
+            typ: "Roll".into(),
+            fields: IndexMap::from([("dice".into(), dice("2d6").unwrap().1)]),
+        };
+        assert_eq!(value, roll);
+        assert!(engine.parse("roll 2x6 for damage", "Effect").is_err());
+        assert!(engine.parse("roll 2d for damage", "Effect").is_err());
+    }
+
+    #[test]
+    fn continuations_read_scanned_types() {
+        let mut engine = make_engine();
+        engine.register_scanner("Dice", dice);
+        // the dice are read, and `for` after them
+        let views = engine.continuations("roll 2d6 for", "Effect");
+        assert_eq!(views.len(), 1);
+        assert_eq!(views[0].matched, 10);
+    }
+
+    #[test]
+    fn scanner_types_must_be_declared() {
+        assert!(matches!(
+            Dokearley::from_dokedef(GRAMMAR),
+            Err(DokearleyError::UnknownType { .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod step_budget_tests {
+    use super::*;
+
+    // every split of the input is a parse
+    const AMBIGUOUS: &str = r#"
+S: "{a:S}{b:S}" -> Pair
+S: "x" -> X
+"#;
+
+    #[test]
+    fn ambiguous_grammar_hits_the_budget() {
+        let engine = Dokearley::from_dokedef(AMBIGUOUS).unwrap();
+        let input = "x".repeat(40);
+        assert!(matches!(
+            engine.parse_bounded(&input, "S", 500),
+            Err(DokearleyError::StepBudgetExceeded(500))
+        ));
+        assert_eq!(
+            engine.parse_bounded(&input, "S", 1_000_000).unwrap(),
+            engine.parse(&input, "S").unwrap()
+        );
+    }
+
+    #[test]
+    fn budget_keeps_parse_errors() {
+        let engine = Dokearley::from_dokedef(AMBIGUOUS).unwrap();
+        assert!(matches!(
+            engine.parse_bounded("xyx", "S", 1_000),
+            Err(DokearleyError::ParseError(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod keyword_rules_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        let grammar = r#"
+Reply : "yes" | "no" | Later
+Later : "not now"
+Say : "say {reply:Reply}" -> Say
+Empty : "nothing" -> {}
+"#;
+        Dokearley::from_dokedef(grammar).expect("invalid grammar")
+    }
+
+    #[test]
+    fn literal_rules_yield_their_text() {
+        let engine = make_engine();
+        assert_eq!(engine.parse("yes", "Reply").unwrap(), Value::String("yes".into()));
+        assert_eq!(
+            engine.parse("not now", "Reply").unwrap(),
+            Value::String("not now".into())
+        );
+        assert_eq!(
+            engine.parse("say not now", "Say").unwrap(),
+            Value::Resource {
+                typ: "Say".into(),
+                fields: IndexMap::from([("reply".into(), Value::String("not now".into()))]),
+            }
+        );
+    }
+
+    #[test]
+    fn explicit_outputs_are_kept() {
+        assert_eq!(
+            make_engine().parse("nothing", "Empty").unwrap(),
+            Value::Dictionary(IndexMap::new())
+        );
+    }
+}
+
+#[cfg(all(test, feature = "unicode-normalization"))]
+mod nfc_normalization_tests {
+    use super::*;
+
+    const GRAMMAR: &str = "Effect: \"caf\u{e9} for {amount:Int}\" -> Coffee";
+
+    fn engine(nfc_normalization: bool) -> Result<Dokearley<'static>, DokearleyError> {
+        let options = Options {
+            nfc_normalization,
+            ..Options::default()
+        };
+        Dokearley::from_dokedef_with_options(GRAMMAR, options)
+    }
+
+    #[test]
+    fn decomposed_input_matches_composed_terminal() {
+        // `e` followed by a combining acute accent
+        let input = "cafe\u{301} for 3";
+        let value = engine(true).unwrap().parse(input, "Effect").unwrap();
+        assert_eq!(
+            value,
+            Value::Resource {
+                typ: "Coffee".into(),
+                fields: IndexMap::from([("amount".into(), Value::Integer(3))]),
+            }
+        );
+        assert!(engine(false).unwrap().parse(input, "Effect").is_err());
+    }
+
+    #[test]
+    fn lazy_values_read_the_normalized_input() {
+        let parser = engine(true).unwrap();
+        let lazy = parser.parse_lazy("cafe\u{301} for 3", "Effect").unwrap();
+        assert_eq!(lazy.get("amount").as_i64().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn decomposed_dokedef_is_rejected() {
+        let options = Options {
+            nfc_normalization: true,
+            ..Options::default()
+        };
+        let result = Dokearley::from_dokedef_with_options("Effect: \"cafe\u{301}\" -> Coffee", options);
+        assert!(matches!(result, Err(DokearleyError::InvalidDokedef(_))));
+    }
+}
+
+#[cfg(test)]
+mod repetition_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+List: "[{items:Item}*(,)]" -> items
+Item: "{n:Int}" -> n
+"#;
+
+    fn parse(input: &str) -> Result<Value, DokearleyError> {
+        Dokearley::from_dokedef(GRAMMAR).unwrap().parse(input, "List")
+    }
+
+    #[test]
+    fn separated_items_are_an_array() {
+        assert_eq!(
+            parse("[1, 2, 3]").unwrap(),
+            Value::Array(vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)])
+        );
+        assert_eq!(
+            parse("[4,5]").unwrap(),
+            Value::Array(vec![Value::Integer(4), Value::Integer(5)])
+        );
+    }
+
+    #[test]
+    fn empty_and_single_lists() {
+        assert_eq!(parse("[]").unwrap(), Value::Array(vec![]));
+        assert_eq!(parse("[7]").unwrap(), Value::Array(vec![Value::Integer(7)]));
+    }
+
+    #[test]
+    fn trailing_separator_is_rejected() {
+        assert!(parse("[1, 2,]").is_err());
+    }
+
+    const INVENTORY: &str = r#"
+Command: "give {items:Item+}" -> Give
+Command: "drop[{items:Item*}]" -> Drop
+Item: "sword" -> Sword
+Item: "shield" -> Shield
+Item: "{n:Int} gold" -> Gold
+"#;
+
+    fn items(input: &str) -> Result<Value, DokearleyError> {
+        let parser = Dokearley::from_dokedef(INVENTORY).unwrap();
+        match parser.parse(input, "Command")? {
+            Value::Resource { mut fields, .. } => Ok(fields.shift_remove("items").unwrap()),
+            other => panic!("expected a resource, got {other:?}"),
+        }
+    }
+
+    fn item(typ: &str) -> Value {
+        Value::Resource {
+            typ: typ.into(),
+            fields: IndexMap::new(),
+        }
+    }
+
+    #[test]
+    fn suffixed_repetitions_collect_items_in_order() {
+        assert_eq!(
+            items("give sword shield").unwrap(),
+            Value::Array(vec![item("Sword"), item("Shield")])
+        );
+        let gold = Value::Resource {
+            typ: "Gold".into(),
+            fields: [("n".to_string(), Value::Integer(3))].into(),
+        };
+        assert_eq!(
+            items("give shield, 3 gold, sword").unwrap(),
+            Value::Array(vec![item("Shield"), gold, item("Sword")])
+        );
+        assert_eq!(
+            items("drop[sword,shield]").unwrap(),
+            Value::Array(vec![item("Sword"), item("Shield")])
+        );
+    }
+
+    #[test]
+    fn star_matches_no_items_and_plus_does_not() {
+        assert_eq!(items("drop[]").unwrap(), Value::Array(vec![]));
+        assert!(items("give ").is_err());
+        assert!(items("give").is_err());
+    }
+
+    #[test]
+    fn repetition_helpers_stay_hidden() {
+        let parser = Dokearley::from_dokedef(INVENTORY).unwrap();
+        assert_eq!(parser.placeholder_types(), HashSet::from(["Item", "Int"]));
+        let graph = parser.dependency_graph();
+        assert_eq!(graph.keys().collect::<HashSet<_>>(), HashSet::from([&"Command", &"Item"]));
+        assert_eq!(graph["Command"], HashSet::from(["Item"]));
+        let signatures = parser.signatures();
+        assert_eq!(signatures.len(), 5);
+        assert!(signatures.contains(&r#"Command := "give " {items:Item+} -> Give"#.to_string()));
+        assert!(parser.nullable_productions().is_empty());
+        match parser.parse("give sword 3", "Command") {
+            Err(DokearleyError::ParseError(err)) => {
+                assert!(!err.items.is_empty());
+                assert!(err.items.iter().all(|item| !item.contains('{')));
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod number_placeholder_tests {
+    use super::*;
+
+    fn value(input: &str) -> Value {
+        let parser = Dokearley::from_dokedef(r#"Effect: "scale by {v:Number}" -> Scale"#).unwrap();
+        match parser.parse(input, "Effect").unwrap() {
+            Value::Resource { mut fields, .. } => fields.shift_remove("v").unwrap(),
+            other => panic!("expected a resource, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn integer_stays_integer() {
+        assert_eq!(value("scale by 3"), Value::Integer(3));
+        assert_eq!(value("scale by -2"), Value::Integer(-2));
+    }
+
+    #[test]
+    fn float_stays_float() {
+        assert_eq!(value("scale by 3.0"), Value::Float(3.0));
+    }
+}
+
+#[cfg(test)]
+mod from_grammar_tests {
+    use super::*;
+    use crate::grammar_parser::WhitespaceMode;
+
+    #[test]
+    fn grammar_built_in_code_parses() {
+        let grammar = Grammar {
+            productions: vec![Production {
+                lhs: "Effect",
+                rhs: vec![
+                    Symbol::Terminal("x"),
+                    Symbol::Terminal(" "),
+                    Symbol::Placeholder {
+                        name: "amount",
+                        typ: "Int",
+                        optional: false,
+                    },
+                ],
+                out: OutSpec::Resource {
+                    typ: "Heal",
+                    fields: Vec::new(),
+                },
+                ws: WhitespaceMode::Exact,
+            }],
+        };
+        let parser = Dokearley::from_grammar(grammar).unwrap();
+        assert_eq!(
+            parser.parse("x 7", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Heal".into(),
+                fields: IndexMap::from([("amount".into(), Value::Integer(7))]),
+            }
+        );
+    }
+
+    #[test]
+    fn nullable_loop_is_rejected() {
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "A",
+                    rhs: vec![Symbol::NonTerminal("A")],
+                    out: OutSpec::Transparent,
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![],
+                    out: OutSpec::Dict(Vec::new()),
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+        assert!(matches!(
+            Dokearley::from_grammar(grammar),
+            Err(DokearleyError::InfiniteNullableLoop(_))
+        ));
+    }
+}
+
+#[cfg(all(test, feature = "unicode-normalization"))]
+mod fold_terminals_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"Effect: "heal for {amount:Int}" -> Heal"#;
+
+    fn engine(fold_terminals: bool) -> Dokearley<'static> {
+        let options = Options {
+            fold_terminals,
+            ..Options::default()
+        };
+        Dokearley::from_dokedef_with_options(GRAMMAR, options).unwrap()
+    }
+
+    #[test]
+    fn case_and_accents_are_ignored() {
+        assert_eq!(
+            engine(true).parse("HÉAL for 7", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Heal".into(),
+                fields: IndexMap::from([("amount".into(), Value::Integer(7))]),
+            }
+        );
+        assert!(engine(false).parse("HÉAL for 7", "Effect").is_err());
+    }
+
+    #[test]
+    fn accented_terminals_match_plain_input() {
+        let options = Options {
+            fold_terminals: true,
+            ..Options::default()
+        };
+        let parser =
+            Dokearley::from_dokedef_with_options(r#"Drink: "café" -> Coffee"#, options).unwrap();
+        assert!(parser.parse("Cafe", "Drink").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod indexed_fields_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Rgb: "rgb {c:Int}{3}" -> Color { r: c[0], g: c[1], b: c[2] }
+Gray: "gray {c:Int}" -> Color { r: c[0] }
+Bad: "pair {c:Int}{2}" -> Pair { third: c[2] }
+"#;
+
+    #[test]
+    fn indices_pick_repeated_values() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let Value::Resource { typ, fields } = parser.parse("rgb 255 128 0", "Rgb").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Color");
+        assert_eq!(fields["r"], Value::Integer(255));
+        assert_eq!(fields["g"], Value::Integer(128));
+        assert_eq!(fields["b"], Value::Integer(0));
+    }
+
+    #[test]
+    fn index_out_of_bounds_is_an_error() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        for (input, start) in [("pair 1 2", "Bad"), ("gray 3", "Gray")] {
+            assert!(matches!(
+                parser.parse(input, start),
+                Err(DokearleyError::ValueError(parser::ValueError::InvalidIndex { .. }))
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod parse_eq_tests {
+    use super::*;
+
+    fn make_engine() -> Dokearley<'static> {
+        Dokearley::from_dokedef(r#"Effect: "heal for {amount:Int}" -> Heal"#).unwrap()
+    }
+
+    #[test]
+    fn same_values_are_equal() {
+        let engine = make_engine();
+        assert!(engine.parse_eq("heal for 7", "heal for 07", "Effect").unwrap());
+        assert!(!engine.parse_eq("heal for 7", "heal for 8", "Effect").unwrap());
+    }
+
+    #[test]
+    fn parse_errors_are_returned() {
+        let engine = make_engine();
+        assert!(engine.parse_eq("heal for 7", "hurt for 7", "Effect").is_err());
+    }
+}
+
+#[cfg(test)]
+mod lazy_value_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "{effect:Heal} to {target:Target}" -> TargetedEffect { source: "spell" }
+Heal: "heal for {amount:Int}" -> Heal
+Target: "all {kind:Kind} within {range:Int} tiles of {origin:Origin}" -> Target
+Kind: "allies" | "enemies"
+Origin: "the {what:Anchor}" -> Origin
+Anchor: "caster" | "target"
+"#;
+    const INPUT: &str = "heal for 7 to all allies within 3 tiles of the caster";
+
+    #[test]
+    fn reading_a_field_leaves_the_rest_alone() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let lazy = parser.parse_lazy(INPUT, "Effect").unwrap();
+        assert_eq!(lazy.get("effect").get("amount").as_i64().unwrap(), Some(7));
+        // only the `7` token
+        assert_eq!(lazy.materialized_nodes(), 1);
+
+        assert_eq!(lazy.value().unwrap(), Some(parser.parse(INPUT, "Effect").unwrap()));
+        assert!(lazy.materialized_nodes() > 10);
+    }
+
+    #[test]
+    fn other_fields_are_read_from_their_value() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let lazy = parser.parse_lazy(INPUT, "Effect").unwrap();
+        assert_eq!(
+            lazy.get("source").value().unwrap(),
+            Some(Value::String("spell".into()))
+        );
+        assert_eq!(
+            lazy.get("target").get("origin").get("what").value().unwrap(),
+            Some(Value::String("caster".into()))
+        );
+        assert_eq!(lazy.get("missing").value().unwrap(), None);
+    }
+}
+
+#[cfg(test)]
+mod const_tests {
+    use super::*;
+
+    #[test]
+    fn const_fills_an_output_field() {
+        let grammar = r#"
+@const CRIT_MULT = 2
+@alias Amount = Int
+@const NAME = "crit"
+
+Effect: "crit for {n:Amount}" -> Damage { amount: n, mult: CRIT_MULT, name: NAME }
+"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let Value::Resource { typ, fields } = parser.parse("crit for 7", "Effect").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Damage");
+        assert_eq!(fields["amount"], Value::Integer(7));
+        assert_eq!(fields["mult"], Value::Integer(2));
+        assert_eq!(fields["name"], Value::String("crit".into()));
+    }
+
+    #[test]
+    fn placeholder_shadows_a_const() {
+        let grammar = r#"
+@const mult = 2
+Effect: "hit times {mult:Int}" -> Damage { mult: mult }
+Effect: "hit" -> Damage { mult: mult }
+"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let mult = |input| match parser.parse(input, "Effect").unwrap() {
+            Value::Resource { fields, .. } => fields["mult"].clone(),
+            other => panic!("expected a resource, got {other:?}"),
+        };
+        assert_eq!(mult("hit times 5"), Value::Integer(5));
+        assert_eq!(mult("hit"), Value::Integer(2));
+    }
+
+    #[test]
+    fn duplicate_const_is_rejected() {
+        let grammar = "@const A = 1\n@const A = 2\nEffect: \"hit\" -> Damage { a: A }\n";
+        assert!(matches!(
+            Dokearley::from_dokedef(grammar),
+            Err(DokearleyError::InvalidDokedef(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod nested_resource_tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_typed_as_rules_nest_under_their_names() {
+        let grammar = r#"
+Attack: "{weapon:Weapon} against {target:Target}" -> Attack
+Weapon: "sword of {power:Int}" -> Weapon
+Target: "the {name:String}" -> Target
+"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let value = parser.parse("sword of 3 against the \"goblin\"", "Attack").unwrap();
+        let Value::Resource { typ, fields } = value else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Attack");
+        assert_eq!(fields.len(), 2);
+        assert_eq!(
+            fields["weapon"],
+            Value::Resource {
+                typ: "Weapon".into(),
+                fields: [("power".to_string(), Value::Integer(3))].into(),
+            }
+        );
+        assert_eq!(
+            fields["target"],
+            Value::Resource {
+                typ: "Target".into(),
+                fields: [("name".to_string(), Value::String("goblin".into()))].into(),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod unwrap_single_field_dicts_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "gain {amount:Int} gold" -> { amount: amount }
+Effect: "pay {cost:Cost}" -> Pay
+Cost: "{gold:Int} gold" -> { gold: gold }
+"#;
+
+    #[test]
+    fn one_key_dict_unwraps_under_the_option() {
+        let options = Options {
+            unwrap_single_field_dicts: true,
+            ..Default::default()
+        };
+        let parser = Dokearley::from_dokedef_with_options(GRAMMAR, options).unwrap();
+        assert_eq!(parser.parse("gain 7 gold", "Effect").unwrap(), Value::Integer(7));
+        assert_eq!(
+            parser.parse("pay 3 gold", "Effect").unwrap(),
+            Value::Resource {
+                typ: "Pay".into(),
+                fields: [("cost".to_string(), Value::Integer(3))].into(),
+            }
+        );
+    }
+
+    #[test]
+    fn every_entry_point_unwraps() {
+        let options = Options {
+            unwrap_single_field_dicts: true,
+            ..Default::default()
+        };
+        let parser = Dokearley::from_dokedef_with_options(GRAMMAR, options).unwrap();
+        let seven = Value::Integer(7);
+        assert_eq!(parser.parse_bounded("gain 7 gold", "Effect", 1000).unwrap(), seven);
+        let (value, _) = parser.parse_with_placeholder_spans("gain 7 gold", "Effect").unwrap();
+        assert_eq!(value, seven);
+        let three = Some(Value::Integer(3));
+        assert_eq!(parser.parse_scalar("pay 3 gold", "Effect", &["cost"]), three);
+        let lazy = parser.parse_lazy("pay 3 gold", "Effect").unwrap();
+        assert_eq!(lazy.get("cost").value().unwrap(), three);
+        let incremental = parser.parse_incremental("gain 7 gold", "Effect");
+        assert_eq!(incremental.value().unwrap(), seven);
+        assert_eq!(parser.parse_all("gain 7 gold", "Effect").unwrap(), [seven]);
+    }
+
+    #[test]
+    fn one_key_dict_stays_without_the_option() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert_eq!(
+            parser.parse("gain 7 gold", "Effect").unwrap(),
+            Value::Dictionary([("amount".to_string(), Value::Integer(7))].into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod keyword_dispatch_tests {
+    use super::*;
+
+    /// 300 commands like `bcmdb {n:Int}`, their keywords starting with all the letters
+    fn commands() -> String {
+        (0..300)
+            .map(|i| {
+                let letter = |n: usize| (b'a' + n as u8) as char;
+                let (first, last) = (letter(i % 26), letter(i / 26));
+                format!("Command: \"{first}cmd{last} {{n:Int}}\" -> Cmd{i}\n")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keyword_dispatch_seeds_fewer_items() {
+        let grammar = commands();
+        let seeded = |keyword_dispatch| {
+            let options = Options {
+                keyword_dispatch,
+                ..Default::default()
+            };
+            let parser = Dokearley::from_dokedef_with_options(&grammar, options).unwrap();
+            let mut chart = parser.chart(recognizer::tokenize("bcmdb 5"), "Command");
+            chart.recognize_with_nullable("Command", &parser.cache.nullable);
+            let value = parser.parse("bcmdb 5", "Command").unwrap();
+            (chart.sets[0].len(), value)
+        };
+        let (all, plain) = seeded(false);
+        let (dispatched, value) = seeded(true);
+        assert_eq!(all, 300);
+        assert_eq!(dispatched, 12);
+        assert_eq!(value, plain);
+    }
+
+    #[test]
+    fn rules_not_starting_with_a_terminal_are_still_seeded() {
+        let grammar = r#"
+Command: "heal {n:Int}" -> Heal
+Command: "{n:Int} damage" -> Damage
+Command: "{t:Target}" -> Aim
+Target: "me" -> Me
+"#;
+        let options = Options {
+            keyword_dispatch: true,
+            ..Default::default()
+        };
+        let parser = Dokearley::from_dokedef_with_options(grammar, options).unwrap();
+        assert!(parser.parse("heal 3", "Command").is_ok());
+        assert!(parser.parse("3 damage", "Command").is_ok());
+        assert!(parser.parse("me", "Command").is_ok());
+        assert!(parser.parse("hurt 3", "Command").is_err());
+    }
+}
+
+#[cfg(test)]
+mod float_format_tests {
+    use super::*;
+
+    #[test]
+    fn integral_floats_keep_their_point() {
+        assert_eq!(Value::Float(7.0).to_string(), "7.0");
+        assert_eq!(Value::Float(0.1).to_string(), "0.1");
+        assert_eq!(Value::Float(-2.5e21).to_string(), "-2.5e21");
+        assert_eq!(Value::Integer(7).to_string(), "7");
+    }
+
+    #[test]
+    fn display_renders_values_like_literals() {
+        let value = Value::Resource {
+            typ: "Heal".into(),
+            fields: [
+                ("mult".to_string(), Value::Float(2.0)),
+                ("amount".to_string(), Value::Integer(7)),
+                ("tags".to_string(), Value::Array(vec![Value::String("a".into())])),
+            ]
+            .into(),
+        };
+        assert_eq!(value.to_string(), r#"Heal { mult: 2.0, amount: 7, tags: ["a"] }"#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn float_field_renders_the_same_in_display_and_json() {
+        let parser =
+            Dokearley::from_dokedef(r#"Effect: "crit" -> Damage { mult: 7.0 }"#).unwrap();
+        let value = parser.parse("crit", "Effect").unwrap();
+        let Value::Resource { fields, .. } = &value else {
+            panic!("expected a resource");
+        };
+        let mult = &fields["mult"];
+        assert_eq!(mult.to_string(), "7.0");
+        assert_eq!(serde_json::to_string(mult).unwrap(), mult.to_string());
+        assert!(value.to_string().contains("mult: 7.0"));
+        assert!(serde_json::to_string(&value).unwrap().contains(r#""mult":7.0"#));
+    }
+}
+
+#[cfg(test)]
+mod parse_all_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "{a:Effect} and {b:Effect}" -> Both
+Effect: "hit" -> Hit
+Effect: "heal" -> Heal
+"#;
+
+    #[test]
+    fn every_derivation_has_its_value() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let values = parser.parse_all("hit and heal and hit", "Effect").unwrap();
+        assert_eq!(values.len(), 2);
+        let firsts: Vec<_> = values
+            .iter()
+            .map(|v| match v {
+                Value::Resource { fields, .. } => fields["a"].to_string(),
+                other => panic!("expected a resource, got {other:?}"),
+            })
+            .collect();
+        assert!(firsts.contains(&"Hit {}".to_string()));
+        assert!(firsts.contains(&"Both { a: Hit {}, b: Heal {} }".to_string()));
+        assert_eq!(parser.parse_all("hit", "Effect").unwrap().len(), 1);
+        assert!(parser.parse_all("hit and", "Effect").is_err());
+    }
+
+    #[test]
+    fn equal_values_are_kept_once() {
+        let grammar = "Effect: Fire | Burn\nFire: \"fire\" -> Fire\nBurn: \"fire\" -> Fire";
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        assert!(parser.is_ambiguous("fire", "Effect"));
+        assert_eq!(parser.parse_all("fire", "Effect").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn derivations_are_capped() {
+        let options = Options {
+            max_derivations: 3,
+            ..Options::default()
+        };
+        let parser = Dokearley::from_dokedef_with_options(GRAMMAR, options).unwrap();
+        // 14 ways to group five effects
+        let input = "hit and hit and hit and hit and hit";
+        assert_eq!(parser.parse_all(input, "Effect").unwrap().len(), 3);
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert_eq!(parser.parse_all(input, "Effect").unwrap().len(), 14);
+    }
+
+    #[test]
+    fn failing_derivations_are_skipped() {
+        let grammar = r#"
+Effect: "gain {n:Int}" -> Big where n > 5
+Effect: "gain {n:Int}" -> Small
+Effect: "lose {n:Int}" -> Loss where n > 5
+Effect: "lose {n:Int}" -> Drop where n > 9
+"#;
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        let values = parser.parse_all("gain 3", "Effect").unwrap();
+        let values: Vec<String> = values.iter().map(Value::to_string).collect();
+        assert_eq!(values, ["Small { n: 3 }"]);
+        assert_eq!(parser.parse_all("gain 7", "Effect").unwrap().len(), 2);
+        assert!(matches!(
+            parser.parse_all("lose 1", "Effect"),
+            Err(DokearleyError::ValidationFailed(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod is_ambiguous_tests {
+    use super::*;
+
+    #[test]
+    fn chained_binary_rule_is_ambiguous() {
+        let grammar = r#"
+Expr: "{a:Expr}+{b:Expr}" -> Add
+Expr: "{n:Int}" -> Num
+"#;
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        assert!(!parser.is_ambiguous("1", "Expr"));
+        assert!(!parser.is_ambiguous("1+2", "Expr"));
+        assert!(parser.is_ambiguous("1+2+3", "Expr"));
+    }
+
+    #[test]
+    fn left_recursion_is_not_ambiguous() {
+        let grammar = r#"
+Expr: "{a:Expr}+{n:Int}" -> Add
+Expr: "{n:Int}" -> Num
+"#;
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        assert!(!parser.is_ambiguous("1+2+3+4+5+6+7+8", "Expr"));
+    }
+
+    #[test]
+    fn added_rule_makes_a_phrase_ambiguous() {
+        let before = r#"
+Effect: "heal {n:Int}" -> Heal
+"#;
+        let after = r#"
+Effect: "heal {n:Int}" -> Heal
+Effect: "heal {t:Target}" -> HealTarget
+Target: "{n:Int}" -> Slot
+"#;
+        let before = Dokearley::from_dokedef(before).unwrap();
+        let after = Dokearley::from_dokedef(after).unwrap();
+        assert!(!before.is_ambiguous("heal 3", "Effect"));
+        assert!(after.is_ambiguous("heal 3", "Effect"));
+        assert!(!after.is_ambiguous("heal", "Effect"));
+    }
+}
+
+#[cfg(test)]
+mod rule_group_tests {
+    use super::*;
+
+    #[test]
+    fn grouped_patterns_share_their_output() {
+        let grammar = r#"
+Target: {
+    "self" { kind: "self" }
+    "an ally" { kind: "ally" }
+    "an enemy" { kind: "enemy", category: "hostile" }
+} -> Target { category: "targeting" }
+"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let target = |input| match parser.parse(input, "Target").unwrap() {
+            Value::Resource { typ, fields } => {
+                (typ, fields["kind"].clone(), fields["category"].clone())
+            }
+            other => panic!("expected a resource, got {other:?}"),
+        };
+        let string = |s: &str| Value::String(s.into());
+        assert_eq!(target("self"), ("Target".into(), string("self"), string("targeting")));
+        assert_eq!(target("an ally"), ("Target".into(), string("ally"), string("targeting")));
+        assert_eq!(target("an enemy"), ("Target".into(), string("enemy"), string("hostile")));
+    }
+
+    #[test]
+    fn group_entries_can_have_placeholders() {
+        let grammar = "Effect: {\n  \"heal {n:Int}\"\n  \"regen {n:Int}\" { turns: 3 }\n} -> Heal\n";
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let Value::Resource { typ, fields } = parser.parse("regen 3", "Effect").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Heal");
+        assert_eq!(fields["n"], Value::Integer(3));
+        assert_eq!(fields["turns"], Value::Integer(3));
+        assert!(parser.parse("heal 3", "Effect").is_ok());
+    }
+
+    #[test]
+    fn entry_fields_need_fields_in_the_output() {
+        let grammar = "Effect: {\n  \"heal {n:Int}\"\n  \"regen {n:Int}\" { turns: 3 }\n} -> n\n";
+        assert!(Dokearley::from_dokedef(grammar).is_err());
+        let grammar = "Effect: {\n  \"heal {n:Int}\"\n  \"regen {n:Int}\"\n} -> n\n";
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        assert_eq!(parser.parse("regen 3", "Effect").unwrap(), Value::Integer(3));
+    }
+}
+
+#[cfg(test)]
+mod parse_with_tokens_tests {
+    use super::*;
+
+    #[test]
+    fn tokens_rebuild_the_input() {
+        let grammar = r#"
+Effect: "say {text:String} {n:Int} times" -> Say
+"#;
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        let input = r#"say "hi there" 12 times"#;
+        let (value, tokens) = parser.parse_with_tokens(input, "Effect").unwrap();
+        assert_eq!(value, parser.parse(input, "Effect").unwrap());
+        // `say `, the string, ` `, the number, ` times`
+        assert_eq!(tokens.len(), 4 + 1 + 1 + 1 + 6);
+        assert_eq!(tokens.iter().map(|t| t.text.as_str()).collect::<String>(), input);
+        assert_eq!(tokens[4].kind, TokenKind::StringLit);
+        assert_eq!(tokens[4].span, 4..14);
+        assert_eq!(tokens[6].text, "12");
+    }
+}
+
+#[cfg(test)]
+mod signed_int_tests {
+    use super::*;
+
+    fn amount(input: &str) -> Result<Value, DokearleyError> {
+        let parser = Dokearley::from_dokedef(r#"Effect: "adjust {amount:SignedInt}" -> Adjust"#)
+            .expect("invalid grammar");
+        match parser.parse(input, "Effect")? {
+            Value::Resource { mut fields, .. } => Ok(fields.shift_remove("amount").unwrap()),
+            other => panic!("expected a resource, got {other:?}"),
+        }
+    }
+
+    fn signed(magnitude: i64, negative: bool) -> Value {
+        Value::Dictionary(IndexMap::from([
+            ("magnitude".to_string(), Value::Integer(magnitude)),
+            ("negative".to_string(), Value::Bool(negative)),
+        ]))
+    }
+
+    #[test]
+    fn sign_and_magnitude_are_separate_fields() {
+        assert_eq!(amount("adjust -5").unwrap(), signed(5, true));
+        assert_eq!(amount("adjust +5").unwrap(), signed(5, false));
+        assert_eq!(amount("adjust 5").unwrap(), signed(5, false));
+    }
+
+    #[test]
+    fn signed_ints_reject_floats() {
+        assert!(amount("adjust -5.5").is_err());
+    }
+}
+
+#[cfg(test)]
+mod balanced_tests {
+    use super::*;
+
+    fn expr(input: &str) -> Result<Value, DokearleyError> {
+        let parser = Dokearley::from_dokedef(r#"Effect: "cast {expr:Balanced}" -> Cast"#)
+            .expect("invalid grammar");
+        match parser.parse(input, "Effect")? {
+            Value::Resource { mut fields, .. } => Ok(fields.shift_remove("expr").unwrap()),
+            other => panic!("expected a resource, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn inner_text_is_captured() {
+        assert_eq!(expr("cast (fireball 3)").unwrap(), Value::String("fireball 3".into()));
+        assert_eq!(expr("cast ()").unwrap(), Value::String("".into()));
+    }
+
+    #[test]
+    fn nested_parentheses_are_kept() {
+        assert_eq!(
+            expr("cast (twice (fireball 3))").unwrap(),
+            Value::String("twice (fireball 3)".into())
+        );
+        assert_eq!(expr(r#"cast (say ")")"#).unwrap(), Value::String(r#"say ")""#.into()));
+    }
+
+    #[test]
+    fn unbalanced_parentheses_are_rejected() {
+        assert!(expr("cast (a (b)").is_err());
+        assert!(expr("cast (a) b)").is_err());
+        assert!(expr("cast fireball").is_err());
+    }
+}
+
+#[cfg(test)]
+mod optional_placeholder_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Command: "attack {target:Target?}" -> Attack
+Command: "wait {turns:Int?} turns" -> Wait
+Target: "the goblin" -> Goblin
+"#;
+
+    fn command(input: &str) -> Result<Value, DokearleyError> {
+        Dokearley::from_dokedef(GRAMMAR).unwrap().parse(input, "Command")
+    }
+
+    #[test]
+    fn present_optional_placeholders_are_fields() {
+        let Value::Resource { typ, fields } = command("attack the goblin").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Attack");
+        assert!(matches!(&fields["target"], Value::Resource { typ, .. } if typ == "Goblin"));
+    }
+
+    #[test]
+    fn absent_optional_placeholders_are_left_out() {
+        let Value::Resource { fields, .. } = command("attack").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert!(fields.is_empty());
+        assert!(command("attack a goblin").is_err());
+    }
+
+    #[test]
+    fn optional_placeholders_fold_the_whitespace_before_them() {
+        let turns = |input| match command(input).unwrap() {
+            Value::Resource { fields, .. } => fields.get("turns").cloned(),
+            other => panic!("expected a resource, got {other:?}"),
+        };
+        assert_eq!(turns("wait 3 turns"), Some(Value::Integer(3)));
+        assert_eq!(turns("wait turns"), None);
+    }
+
+    #[test]
+    fn optional_placeholders_keep_their_type() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let types = parser.placeholder_types();
+        assert_eq!(types, HashSet::from(["Target", "Int"]));
+        let signatures = parser.signatures();
+        assert!(signatures.contains(&"Command := \"attack \" <target:Target?> -> Attack".into()));
+    }
+
+    #[test]
+    fn absent_optional_placeholders_have_no_lazy_value() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let attack = parser.parse_lazy("attack", "Command").unwrap();
+        assert_eq!(attack.get("target").value().unwrap(), None);
+        let (_, spans) = parser
+            .parse_with_placeholder_spans("wait turns", "Command")
+            .unwrap();
+        assert!(spans.is_empty());
+    }
+
+    #[test]
+    fn outputs_reading_absent_placeholders() {
+        let grammar = r#"
+Command: "attack {t:Target?}" -> Hit { target: t, power: 2 }
+Command: "aim {t:Target?}" -> t
+Target: "the goblin" -> Goblin
+"#;
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        let hit = parser.parse("attack", "Command").unwrap();
+        assert_eq!(
+            hit,
+            Value::Resource {
+                typ: "Hit".into(),
+                fields: IndexMap::from([("power".into(), Value::Integer(2))]),
+            }
+        );
+        assert_eq!(parser.parse("aim", "Command").unwrap(), Value::Null);
+        assert_eq!(
+            parser.parse("aim the goblin", "Command").unwrap().godot_type(),
+            "Goblin"
+        );
+    }
+}
+
+#[cfg(test)]
+mod bool_placeholder_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Command: "set godmode {on:Bool}" -> GodMode
+Command: "face true north" -> North
+"#;
+
+    #[test]
+    fn bool_placeholder_reads_true_and_false() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        for (input, on) in [("set godmode true", true), ("set godmode false", false)] {
+            assert_eq!(
+                parser.parse(input, "Command").unwrap(),
+                Value::Resource {
+                    typ: "GodMode".into(),
+                    fields: [("on".to_string(), Value::Bool(on))].into(),
+                }
+            );
+        }
+        assert!(parser.parse("set godmode truest", "Command").is_err());
+        assert!(parser.parse("set godmode yes", "Command").is_err());
+    }
+
+    #[test]
+    fn true_in_terminal_text_still_matches() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert_eq!(
+            parser.parse("face true north", "Command").unwrap(),
+            Value::Resource {
+                typ: "North".into(),
+                fields: IndexMap::new(),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use super::*;
+
+    fn find<'d>(diagnostics: &'d [Diagnostic], text: &str) -> &'d Diagnostic {
+        diagnostics
+            .iter()
+            .find(|d| d.message.contains(text))
+            .unwrap_or_else(|| panic!("no diagnostic about {text} in {diagnostics:?}"))
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let grammar = r#"
+Effect: "deal {n:Int} damage to {t:Targt}" -> Damage { amount: nope }
+Effect: Heal | Shield
+Heal: "heal {n:Int}" -> Heal { n: n, n: 1 }
+Int: "one" -> One
+Unused: "never" -> Unused
+"#;
+        let diagnostics = Dokearley::validate(grammar);
+        let span_text = |d: &Diagnostic| &grammar[d.span.clone().expect("no span")];
+
+        let alias = find(&diagnostics, "\"nope\"");
+        assert_eq!((alias.severity, span_text(alias)), (Severity::Error, "nope"));
+        let symbol = find(&diagnostics, "symbol Shield");
+        assert_eq!((symbol.severity, span_text(symbol)), (Severity::Error, "Shield"));
+        let typ = find(&diagnostics, "\"Targt\"");
+        assert_eq!((typ.severity, span_text(typ)), (Severity::Error, "Targt"));
+
+        let unreachable = find(&diagnostics, "rule Unused");
+        assert_eq!(unreachable.severity, Severity::Warning);
+        assert_eq!(span_text(unreachable), "Unused");
+        let shadowing = find(&diagnostics, "shadowed by the builtin");
+        assert_eq!((shadowing.severity, span_text(shadowing)), (Severity::Warning, "Int"));
+        let duplicate = find(&diagnostics, "field n twice");
+        assert_eq!(duplicate.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn nullable_cycles_are_errors() {
+        let diagnostics = Dokearley::validate("A: B\nB: A\nA: \"\"\n");
+        let cycle = find(&diagnostics, "infinite loop");
+        assert_eq!(cycle.severity, Severity::Error);
+    }
+
+    #[test]
+    fn syntax_errors_have_spans() {
+        let grammar = "Effect: \"heal\" -> Heal\nEffect \"oops\n";
+        let diagnostics = Dokearley::validate(grammar);
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error && d.span.is_some()));
+    }
+
+    #[test]
+    fn diagnostics_are_in_source_order() {
+        let grammar = "Effect: \"use {t:Targt}\" -> Use\nEffect: \"{a:Int}{b:Int}\" -> Pair\n";
+        let diagnostics = Dokearley::validate(grammar);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].message.contains("Targt"));
+        assert!(diagnostics[1].message.contains("can't be told apart"));
+        // building a parser fails with the first of them
+        match Dokearley::from_dokedef(grammar) {
+            Err(error @ DokearleyError::UnknownType { .. }) => {
+                assert_eq!(error.to_string(), diagnostics[0].message)
+            }
+            other => panic!("expected an unknown type, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn valid_grammars_have_no_diagnostics() {
+        let grammar = "Effect: \"heal {n:Int}\" -> Heal\nEffect: \"wait\" -> Wait\n";
+        assert_eq!(Dokearley::validate(grammar), Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod string_escape_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "name {name:String}" -> Named
+Effect: "motto" -> Motto { text: "say \"hi\"\n\tto \\ all" }
+"#;
+
+    fn name(input: &str) -> Result<Value, DokearleyError> {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        match parser.parse(input, "Effect")? {
+            Value::Resource { mut fields, .. } => Ok(fields.shift_remove("name").unwrap()),
+            other => panic!("expected a resource, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn input_escapes_are_decoded() {
+        assert_eq!(
+            name(r#"name "the \"reaper\"""#).unwrap(),
+            Value::String(r#"the "reaper""#.into())
+        );
+        assert_eq!(
+            name(r#"name "a\\b\nc\td""#).unwrap(),
+            Value::String("a\\b\nc\td".into())
+        );
+    }
+
+    #[test]
+    fn dokedef_escapes_are_decoded() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let Value::Resource { fields, .. } = parser.parse("motto", "Effect").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(fields["text"], Value::String("say \"hi\"\n\tto \\ all".into()));
+    }
+
+    #[test]
+    fn trailing_backslash_is_an_error() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        for input in [r#"name "reaper\"#, r#"name "reaper\""#, r#"name "reaper"#] {
+            assert!(matches!(
+                parser.parse(input, "Effect"),
+                Err(DokearleyError::ParseError(_))
+            ));
+            assert!(parser.parse_with_tokens(input, "Effect").is_err());
+            assert!(parser.concrete_tree(input, "Effect").is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod default_output_tests {
+    use super::*;
+
+    const RULES: &str = "Effect: \"heal {n:Int}\"\nEffect: \"stun\"\n";
+
+    fn effects(directive: &str) -> (Value, Value) {
+        let grammar = format!("{directive}\n{RULES}");
+        let parser = Dokearley::from_dokedef(&grammar).expect("invalid grammar");
+        (
+            parser.parse("heal 3", "Effect").unwrap(),
+            parser.parse("stun", "Effect").unwrap(),
+        )
+    }
+
+    #[test]
+    fn dict_default_gives_dictionaries() {
+        let (heal, stun) = effects("@default_output dict");
+        assert_eq!(
+            heal,
+            Value::Dictionary(IndexMap::from([("n".to_string(), Value::Integer(3))]))
+        );
+        assert_eq!(stun, Value::Dictionary(IndexMap::new()));
+    }
+
+    #[test]
+    fn resource_default_is_typed_by_the_lhs() {
+        let (heal, stun) = effects("@default_output resource");
+        assert_eq!(
+            heal,
+            Value::Resource {
+                typ: "Effect".into(),
+                fields: IndexMap::from([("n".to_string(), Value::Integer(3))]),
+            }
+        );
+        assert!(matches!(stun, Value::Resource { typ, .. } if typ == "Effect"));
+    }
+
+    #[test]
+    fn transparent_default_gives_the_only_placeholder() {
+        let (heal, stun) = effects("@default_output transparent");
+        assert_eq!(heal, Value::Integer(3));
+        assert_eq!(stun, Value::String("stun".into()));
+    }
+
+    #[test]
+    fn written_outputs_are_kept() {
+        let grammar = "@default_output dict\nEffect: \"heal {n:Int}\" -> Heal\n";
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let value = parser.parse("heal 3", "Effect").unwrap();
+        assert!(matches!(value, Value::Resource { typ, .. } if typ == "Heal"));
+    }
+
+    #[test]
+    fn default_output_can_only_be_set_once() {
+        let grammar = "@default_output dict\n@default_output resource\nEffect: \"stun\"\n";
+        assert!(matches!(
+            Dokearley::from_dokedef(grammar),
+            Err(DokearleyError::InvalidDokedef(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod parse_error_location_tests {
+    use super::*;
+
+    fn error(input: &str) -> try_accept::ParseError {
+        let parser = Dokearley::from_dokedef(r#"Effect: "heal{~}for{~}{n:Int}" -> Heal"#).unwrap();
+        match parser.parse(input, "Effect") {
+            Err(DokearleyError::ParseError(err)) => err,
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn error_has_line_and_column() {
+        let err = error("heal\nfor  x");
+        assert_eq!((err.line, err.column), (2, 6));
+        assert_eq!(&*err.source_line, "for  x");
+        let message = err.to_string();
+        assert!(message.starts_with("Parse error at 2:6: around \"x\"\nfor  x\n     ^\n"));
+    }
+
+    #[test]
+    fn error_at_the_end_points_past_the_last_character() {
+        let err = error("heal\nfor ");
+        assert_eq!(err.found, None);
+        assert_eq!((err.line, err.column), (2, 5));
+        assert!(err.to_string().contains("for \n    ^\n"));
+    }
+}
+
+#[cfg(test)]
+mod undefined_alias_tests {
+    use super::*;
+
+    #[test]
+    fn undefined_alias_is_rejected() {
+        let grammar = r#"Effect: "hit {n:Int}" -> X { y: nope }"#;
+        match Dokearley::from_dokedef(grammar) {
+            Err(DokearleyError::UndefinedAlias { alias, .. }) => assert_eq!(alias, "nope"),
+            other => panic!("expected an undefined alias, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn child_placeholders_and_consts_are_defined() {
+        let grammar = r#"
+@const MULT = 2
+Effect: "hit {t:Target}" -> Damage { who: name, mult: MULT, n: int(n) }
+Target: "{name:String} {n:Int}" -> Target
+"#;
+        assert!(Dokearley::from_dokedef(grammar).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod to_json_string_tests {
+    use super::*;
+
+    #[test]
+    fn resources_have_their_type_then_sorted_fields() {
+        let value = Value::Resource {
+            typ: "Heal".into(),
+            fields: IndexMap::from([
+                ("mult".to_string(), Value::Float(1.5)),
+                ("amount".to_string(), Value::Integer(7)),
+                (
+                    "tags".to_string(),
+                    Value::Array(vec![Value::Bool(true), Value::Dictionary(IndexMap::new())]),
+                ),
+            ]),
+        };
+        assert_eq!(
+            value.to_json_string(),
+            r#"{"$type":"Heal","amount":7,"mult":1.5,"tags":[true,{}]}"#
+        );
+    }
+
+    #[test]
+    fn field_order_does_not_change_the_snapshot() {
+        let snapshot = |fields: &str| {
+            let grammar = format!(r#"Effect: "heal {{amount:Int}}" -> Heal {{ {fields} }}"#);
+            let engine = Dokearley::from_dokedef(&grammar).unwrap();
+            engine.parse("heal 7", "Effect").unwrap().to_json_string()
+        };
+        let mult_first = snapshot(r#"mult: 2, kind: "self""#);
+        let kind_first = snapshot(r#"kind: "self", mult: 2"#);
+        assert_eq!(mult_first, kind_first);
+        assert_eq!(mult_first, r#"{"$type":"Heal","amount":7,"kind":"self","mult":2}"#);
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let value = Value::String("a\"b\\c\nd\u{1}é😀".into());
+        assert_eq!(value.to_json_string(), r#""a\"b\\c\nd\u0001\u00e9\ud83d\ude00""#);
+        assert_eq!(Value::Float(f64::NAN).to_json_string(), "null");
+        assert_eq!(Value::Float(7.0).to_json_string(), "7.0");
+    }
+}
+
+#[cfg(test)]
+mod comment_tests {
+    use super::*;
+
+    #[test]
+    fn comments_are_ignored() {
+        let grammar = r#"# A small grammar
+@const MULT = 2 # a constant
+
+# effects
+Effect: "hit #{n:Int}" -> Hit { n: n, mult: MULT } # a comment after a rule
+Effect: "heal" -> Heal; # after a separator
+Effect: Buff # a disjunction
+Buff: "buff" -> Buff
+# no newline at the end"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let Value::Resource { typ, fields } = parser.parse("hit #3", "Effect").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Hit");
+        assert_eq!(fields["n"], Value::Integer(3));
+        assert_eq!(fields["mult"], Value::Integer(2));
+        assert!(parser.parse("buff", "Effect").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod alternative_output_tests {
+    use super::*;
+
+    #[test]
+    fn alternatives_have_their_own_outputs() {
+        let grammar = r#"Mode : "fast" -> { fast: true } | "slow" -> { fast: false }"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let fast = |input| match parser.parse(input, "Mode").unwrap() {
+            Value::Dictionary(fields) => fields,
+            other => panic!("expected a dictionary, got {other:?}"),
+        };
+        assert_eq!(fast("fast"), IndexMap::from([("fast".to_string(), Value::Bool(true))]));
+        assert_eq!(fast("slow"), IndexMap::from([("fast".to_string(), Value::Bool(false))]));
+    }
+
+    #[test]
+    fn an_output_after_the_last_alternative_only_is_the_disjunctions() {
+        let grammar = r#"Mode : "fast" | "slow" -> Mode { kind: "speed" }"#;
+        let parser = Dokearley::from_dokedef(grammar).expect("invalid grammar");
+        let Value::Resource { typ, fields } = parser.parse("fast", "Mode").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(typ, "Mode");
+        assert_eq!(fields["value"], Value::String("fast".into()));
+        assert_eq!(fields["kind"], Value::String("speed".into()));
+    }
+}
+
+#[cfg(test)]
+mod undefined_symbol_tests {
+    use super::*;
+
+    #[test]
+    fn undefined_alternative_is_rejected() {
+        let grammar = "Element: Fire | Ice\nFire: \"fire\" -> Fire\nCold: Ice | \"snow\"";
+        match Dokearley::from_dokedef(grammar) {
+            Err(DokearleyError::UndefinedSymbol {
+                name,
+                referenced_in,
+            }) => {
+                assert_eq!(name, "Ice");
+                assert_eq!(referenced_in.len(), 2);
+            }
+            other => panic!("expected an undefined symbol, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn undefined_placeholder_type_is_an_unknown_type() {
+        let grammar = r#"Effect: "hit {t:Target}" -> Hit"#;
+        assert!(matches!(
+            Dokearley::from_dokedef(grammar),
+            Err(DokearleyError::UnknownType { typ, .. }) if typ == "Target"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod godot_resource_spec_tests {
+    use super::*;
+
+    #[test]
+    fn resource_spec_names_the_type_and_typed_fields() {
+        let parser = Dokearley::from_dokedef(r#"Effect: "heal {amount:Int}" -> Heal"#).unwrap();
+        let heal = parser.parse("heal 7", "Effect").unwrap();
+        assert_eq!(heal.to_godot_resource_spec(), "[resource type=\"Heal\"]\namount: int = 7\n");
+        assert_eq!(Value::Float(1.5).to_godot_resource_spec(), "float = 1.5\n");
+    }
+
+    #[test]
+    fn resource_spec_keeps_declaration_order() {
+        let grammar = r#"Effect: "heal {mult:Float} {amount:Int}" -> Heal { kind: "self" }"#;
+        let parser = Dokearley::from_dokedef(grammar).unwrap();
+        let heal = parser.parse("heal 1.5 7", "Effect").unwrap();
+        assert_eq!(
+            heal.to_godot_resource_spec(),
+            "[resource type=\"Heal\"]\n\
+             mult: float = 1.5\n\
+             amount: int = 7\n\
+             kind: String = \"self\"\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod positive_sign_tests {
+    use super::*;
+
+    #[test]
+    fn plus_signed_number_binds_to_int() {
+        let parser = Dokearley::from_dokedef(r#"Effect: "gain {amount:Int} gold" -> Gain"#).unwrap();
+        let Value::Resource { fields, .. } = parser.parse("gain +5 gold", "Effect").unwrap() else {
+            panic!("expected a resource");
+        };
+        assert_eq!(fields["amount"], Value::Integer(5));
+    }
+}
+
+#[cfg(test)]
+mod incremental_parse_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "deal {x:Int} damage" -> Damage
+Effect: "wait {d:Duration}" -> Wait
+Effect: "{a:Effect} then {b:Effect}" -> Then
+"#;
+
+    #[test]
+    fn edits_give_the_value_of_a_full_parse() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let mut parse = parser.parse_incremental("deal 1 damage then wait 5s", "Effect");
+        assert!(parse.accepted());
+
+        let edited = "deal 12 damage then wait 5s";
+        parse.edit(5..6, edited);
+        assert_eq!(parse.value().unwrap(), parser.parse(edited, "Effect").unwrap());
+
+        parse.edit(7..14, "deal 12 then wait 5s");
+        assert!(!parse.accepted());
+        assert!(matches!(parse.value(), Err(DokearleyError::ParseError(_))));
+    }
+}
+
+#[cfg(test)]
+mod accepting_items_tests {
+    use super::*;
+
+    const GRAMMAR: &str = r#"
+Effect: "heal for {amount:Int}" -> Heal
+Effect: "heal for {amount:Int}" -> Regen
+Effect: "wait {d:Duration}" -> Wait
+"#;
+
+    #[test]
+    fn ambiguous_input_lists_both_rules() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        let items = parser.accepting_items("heal for 7", "Effect");
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().all(|(_, prod)| prod.lhs == "Effect"));
+        assert!(items[0].0 < items[1].0);
+    }
+
+    #[test]
+    fn rejected_input_has_none() {
+        let parser = Dokearley::from_dokedef(GRAMMAR).unwrap();
+        assert!(parser.accepting_items("heal for", "Effect").is_empty());
+        assert_eq!(parser.accepting_items("wait 3s", "Effect").len(), 1);
+    }
+}
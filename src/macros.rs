@@ -0,0 +1,184 @@
+//! Macros to destructure parsed values without nested `if let`.
+use crate::{DokearleyError, Value};
+
+/// Matches a `Value::Resource` of a given type and binds some of its fields,
+/// as `&Value`, for an expression using them:
+///
+/// ```
+/// # use dokearley::{value_resource, Dokearley, Value};
+/// let parser = Dokearley::from_dokedef(r#"Effect: "heal for {amount:Int}" -> Heal"#).unwrap();
+/// let value = parser.parse("heal for 7", "Effect").unwrap();
+/// let amount = value_resource!(value, "Heal", { amount } => amount.clone());
+/// assert_eq!(amount.unwrap(), Value::Integer(7));
+/// ```
+///
+/// Gives `Err(DokearleyError::UnexpectedValue)` when the value is another type
+/// of resource, not a resource, or lacks one of the fields.
+#[macro_export]
+macro_rules! value_resource {
+    ($value:expr, $typ:expr, { $($field:ident),* $(,)? } => $body:expr) => {
+        match &$value {
+            $crate::Value::Resource { typ, fields } if typ == $typ => {
+                match ($(fields.get(stringify!($field)),)*) {
+                    ($(Some($field),)*) => Ok($body),
+                    #[allow(unreachable_patterns)]
+                    _ => Err($crate::macros::missing_field(
+                        $typ,
+                        fields,
+                        &[$(stringify!($field)),*],
+                    )),
+                }
+            }
+            other => Err($crate::macros::unexpected_value($typ, other)),
+        }
+    };
+}
+
+/// Declares an enum with one variant per start, holding the value parsed from it,
+/// for `Dokearley::parse_matched`:
+///
+/// ```
+/// # use dokearley::{starts, Dokearley};
+/// starts! {
+///     enum Matched { ItemEffect, Target }
+/// }
+/// let parser = Dokearley::from_dokedef("ItemEffect: \"heal\" -> Heal\nTarget: \"self\" -> Me")
+///     .unwrap();
+/// match parser.parse_matched::<Matched>("self").unwrap() {
+///     Matched::ItemEffect(effect) => println!("effect {effect}"),
+///     Matched::Target(target) => println!("target {target}"),
+/// }
+/// ```
+///
+/// The variants are tried in order, and there is at least one.
+#[macro_export]
+macro_rules! starts {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($start:ident),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        $vis enum $name {
+            $($start($crate::Value)),+
+        }
+
+        impl $crate::Starts for $name {
+            const STARTS: &'static [&'static str] = &[$(stringify!($start)),+];
+
+            fn from_match(start: &str, value: $crate::Value) -> Self {
+                match start {
+                    $(stringify!($start) => $name::$start(value),)+
+                    other => unreachable!("{other} is not a start of {}", stringify!($name)),
+                }
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+pub fn unexpected_value(typ: &str, value: &Value) -> DokearleyError {
+    let found = match value {
+        Value::Resource { typ, .. } => format!("a {typ} resource"),
+        other => format!("{other:?}"),
+    };
+    DokearleyError::UnexpectedValue(format!("expected a {typ} resource, found {found}"))
+}
+
+#[doc(hidden)]
+pub fn missing_field(
+    typ: &str,
+    fields: &crate::IndexMap<String, Value>,
+    names: &[&str],
+) -> DokearleyError {
+    let missing = names.iter().find(|name| !fields.contains_key(**name));
+    DokearleyError::UnexpectedValue(format!(
+        "the {typ} resource has no field {}",
+        missing.unwrap_or(&"")
+    ))
+}
+
+/// Builds a `Value` from a JSON-like literal, for tests:
+///
+/// ```
+/// # #[cfg(feature = "test-utils")] {
+/// # use dokearley::{dok_value, Value};
+/// let heal = dok_value!({ Heal: { amount: 7 } });
+/// assert_eq!(heal, Value::Resource {
+///     typ: "Heal".into(),
+///     fields: [("amount".into(), Value::Integer(7))].into(),
+/// });
+/// let dict = dok_value!({ kind: "self", tags: ["a", "b"], ratio: 0.5, hidden: false });
+/// # }
+/// ```
+///
+/// A map with a single key starting with an uppercase letter, holding a map, is a resource
+/// of that type. Other maps are dictionaries. Negative numbers go in parentheses: `(-3)`.
+#[cfg(any(test, feature = "test-utils"))]
+#[macro_export]
+macro_rules! dok_value {
+    ({ $($key:ident : $value:tt),* $(,)? }) => {
+        $crate::macros::map_value(vec![$((stringify!($key), $crate::dok_value!($value))),*])
+    };
+    ([ $($value:tt),* $(,)? ]) => {
+        $crate::Value::Array(vec![$($crate::dok_value!($value)),*])
+    };
+    ($value:expr) => {{
+        // `(-3)`, as `-3` isn't a single token
+        #[allow(unused_parens)]
+        let value = $value;
+        $crate::macros::Scalar::into_value(value)
+    }};
+}
+
+/// Scalars `dok_value!` accepts.
+#[cfg(any(test, feature = "test-utils"))]
+#[doc(hidden)]
+pub trait Scalar {
+    fn into_value(self) -> Value;
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Scalar for i64 {
+    fn into_value(self) -> Value {
+        Value::Integer(self)
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Scalar for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Scalar for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Scalar for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+#[doc(hidden)]
+pub fn map_value(mut entries: Vec<(&str, Value)>) -> Value {
+    let is_resource = matches!(
+        entries.as_slice(),
+        [(typ, Value::Dictionary(_))] if typ.starts_with(|c: char| c.is_ascii_uppercase())
+    );
+    match entries.pop() {
+        Some((typ, Value::Dictionary(fields))) if is_resource => Value::Resource {
+            typ: typ.to_string(),
+            fields,
+        },
+        last => {
+            entries.extend(last);
+            let fields = entries.into_iter().map(|(k, v)| (k.to_string(), v));
+            Value::Dictionary(fields.collect())
+        }
+    }
+}
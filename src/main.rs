@@ -30,7 +30,7 @@ fn main() {
     };
 
     // Get highlight tokens
-    let mut tokens = highlight_tokens(&input, &rules);
+    let mut tokens = highlight_tokens(&input, rules);
 
     // Sort tokens by start position
     tokens.sort_by_key(|t| t.span.start);
@@ -55,6 +55,7 @@ fn main() {
             HighlightKind::FloatLiteral => tok.text.cyan().dimmed(),
             HighlightKind::Identifier => tok.text.white(),
             HighlightKind::ChildName => tok.text.red(),
+            HighlightKind::Comment => tok.text.dimmed().italic(),
         };
 
         print!("{}", colored_text);
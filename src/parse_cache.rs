@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::Value;
+
+/// A least-recently-used cache of parsed values, keyed by `(input, start)`.
+///
+/// It sits behind a `Mutex`, so a `Dokearley` can still be shared between threads
+/// and parse through `&self`. Concurrent parses of uncached inputs compute their
+/// values in parallel, only the cache lookups and inserts are serialized.
+#[derive(Debug, Default)]
+pub(crate) struct ParseCache {
+    capacity: usize,
+    inner: Mutex<Lru>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Lru {
+    /// Each value, with when it was last used
+    values: HashMap<(String, String), (Value, u64)>,
+    /// Keys by when they were last used, the least recently used first
+    order: BTreeMap<u64, (String, String)>,
+    /// Counts the uses, to order them
+    clock: u64,
+    /// How many values were computed and inserted, to check hits skip the parse.
+    misses: usize,
+}
+
+impl ParseCache {
+    /// A cache holding up to `capacity` values, a capacity of 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        // The cache is always left consistent, a panic elsewhere can't corrupt it
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The cached value for `input` parsed as `start`, marked as recently used.
+    pub fn get(&self, input: &str, start: &str) -> Option<Value> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut lru = self.lock();
+        let lru = &mut *lru;
+        let key = (input.to_string(), start.to_string());
+        let (value, used) = lru.values.get_mut(&key)?;
+        lru.order.remove(used);
+        lru.clock += 1;
+        *used = lru.clock;
+        lru.order.insert(lru.clock, key);
+        Some(value.clone())
+    }
+
+    /// Stores a freshly computed value, evicting the least recently used one if full.
+    pub fn insert(&self, input: &str, start: &str, value: &Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lru = self.lock();
+        lru.misses += 1;
+        lru.clock += 1;
+        let used = lru.clock;
+        let key = (input.to_string(), start.to_string());
+        if let Some((_, previous)) = lru.values.insert(key.clone(), (value.clone(), used)) {
+            lru.order.remove(&previous);
+        }
+        lru.order.insert(used, key);
+        while lru.order.len() > self.capacity {
+            if let Some((_, oldest)) = lru.order.pop_first() {
+                lru.values.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets every value, e.g. when the grammar changed.
+    pub fn clear(&self) {
+        let mut lru = self.lock();
+        lru.values.clear();
+        lru.order.clear();
+    }
+
+    #[cfg(test)]
+    pub fn misses(&self) -> usize {
+        self.lock().misses
+    }
+}
+
+impl Clone for ParseCache {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            inner: Mutex::new(self.lock().clone()),
+        }
+    }
+}
@@ -1,5 +1,12 @@
-use crate::recognizer::{is_builtin, Chart, Grammar, Production, Symbol, Token, ValueSpec};
-use std::{collections::HashMap, usize};
+use crate::grammar_parser::{Cast, Constraint, ANONYMOUS, WS_RUN};
+use crate::recognizer::{
+    builtin_len, tokens_text, Chart, Grammar, Production, Symbol, Token, TokenKind, ValueSpec,
+};
+use crate::regex_types::is_regex_type;
+use crate::SubGrammars;
+use indexmap::IndexMap;
+use std::{borrow::Cow, collections::HashMap};
+use thiserror::Error;
 
 /// Represents a completed grammar rule (or terminal edge) in the chart.
 /// `rule = usize::MAX` is a sentinel for a terminal/token edge.
@@ -9,6 +16,21 @@ pub struct Edge {
     pub finish: usize, // end position in the input
 }
 
+/// `rule = ABSENT` is a sentinel for a zero-width edge, over a left out optional placeholder
+/// or the whitespace written before it.
+const ABSENT: usize = usize::MAX - 1;
+
+/// The edges of the symbols `from..to` of a production, left out at `pos`.
+fn absent_edges(from: usize, to: usize, pos: usize) -> impl Iterator<Item = (usize, Edge)> {
+    (from..to).map(move |_| {
+        let edge = Edge {
+            rule: ABSENT,
+            finish: pos,
+        };
+        (pos, edge)
+    })
+}
+
 #[derive(Debug, Clone)]
 pub enum OutSpec<'gr> {
     // A value corresponding to a basic type
@@ -16,24 +38,256 @@ pub enum OutSpec<'gr> {
     // A resource with a type and optionally fixed fields
     Resource {
         typ: &'gr str,
-        fields: HashMap<&'gr str, ValueSpec<'gr>>,
+        fields: FieldSpecs<'gr>,
     },
-    Dict(HashMap<&'gr str, ValueSpec<'gr>>),
+    Dict(FieldSpecs<'gr>),
     // Transparent rules that yield their single nonterminal's value (Disjunction)
     Transparent,
+    // Disjunctions with an output, adding its fixed fields to the alternative's value,
+    // and giving it the output's type if there is one
+    Merged {
+        typ: Option<&'gr str>,
+        fields: FieldSpecs<'gr>,
+    },
+    // Disjunctions marked `@tagged`, yielding `{ variant, value }` for the alternative that matched
+    Tagged,
+    // Rules of only literal text and no output, like `Reply: "yes"`, yielding the text they matched
+    Keyword,
+    // The productions of a `{items:Item}*(,)` repetition, yielding the array of its items,
+    // the items after the first one being a right-recursive chain of the given nonterminal
+    List(&'gr str),
+    // An output followed by a `where` clause, which its value must satisfy
+    Constrained {
+        out: Box<OutSpec<'gr>>,
+        constraints: Vec<Constraint<'gr>>,
+    },
+}
+
+/// Fixed fields of an output, in the order they are declared in the `dokedef`.
+pub type FieldSpecs<'gr> = Vec<(&'gr str, ValueSpec<'gr>)>;
+
+impl<'gr> OutSpec<'gr> {
+    /// This output, checked against the constraints of a `where` clause if there are some.
+    pub fn constrained(self, constraints: &[Constraint<'gr>]) -> Self {
+        if constraints.is_empty() {
+            return self;
+        }
+        OutSpec::Constrained {
+            out: Box::new(self),
+            constraints: constraints.to_vec(),
+        }
+    }
+
+    /// The output without its `where` clause.
+    pub fn unconstrained(&self) -> &OutSpec<'gr> {
+        match self {
+            OutSpec::Constrained { out, .. } => out.unconstrained(),
+            out => out,
+        }
+    }
+
+    /// The placeholder names its fields read, like `amount` in `-> Heal { hp: int(amount) }`.
+    pub fn referenced_names(&self) -> Vec<&'gr str> {
+        let name = |spec: &ValueSpec<'gr>| match spec {
+            ValueSpec::Identifier(n) | ValueSpec::Cast(_, n) | ValueSpec::Index(n, _) => {
+                Some(n.text)
+            }
+            _ => None,
+        };
+        match self.unconstrained() {
+            OutSpec::Value(spec) => name(spec).into_iter().collect(),
+            OutSpec::Resource { fields, .. }
+            | OutSpec::Dict(fields)
+            | OutSpec::Merged { fields, .. } => {
+                fields.iter().filter_map(|(_, spec)| name(spec)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether two output specs build the same values.
+    pub fn same_output(&self, other: &OutSpec<'_>) -> bool {
+        let same_fields = |a: &FieldSpecs, b: &FieldSpecs| {
+            a.len() == b.len()
+                && a.iter().all(|(k, v)| {
+                    b.iter()
+                        .any(|(other_k, w)| k == other_k && v.same_spec(w))
+                })
+        };
+        match (self, other) {
+            (OutSpec::Value(a), OutSpec::Value(b)) => a.same_spec(b),
+            (
+                OutSpec::Resource { typ, fields },
+                OutSpec::Resource {
+                    typ: other_typ,
+                    fields: other_fields,
+                },
+            ) => typ == other_typ && same_fields(fields, other_fields),
+            (OutSpec::Dict(a), OutSpec::Dict(b)) => same_fields(a, b),
+            (OutSpec::Transparent, OutSpec::Transparent) => true,
+            (OutSpec::Keyword, OutSpec::Keyword) => true,
+            (OutSpec::List(a), OutSpec::List(b)) => a == b,
+            (
+                OutSpec::Merged { typ, fields },
+                OutSpec::Merged {
+                    typ: other_typ,
+                    fields: other_fields,
+                },
+            ) => typ == other_typ && same_fields(fields, other_fields),
+            (OutSpec::Tagged, OutSpec::Tagged) => true,
+            (
+                OutSpec::Constrained { out, constraints },
+                OutSpec::Constrained {
+                    out: other_out,
+                    constraints: other_constraints,
+                },
+            ) => {
+                out.same_output(other_out)
+                    && constraints.len() == other_constraints.len()
+                    && constraints.iter().zip(other_constraints).all(|(a, b)| {
+                        a.field.text == b.field.text && a.op == b.op && a.value.same_spec(&b.value)
+                    })
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'gr> Grammar<'gr> {
+    /// The fields of the `typ` resources, in the order the grammar declares them:
+    /// each rule's placeholders and nonterminals as its pattern reads them, then its fixed fields.
+    pub fn field_order(&self, typ: &str) -> Vec<&'gr str> {
+        let mut order = Vec::new();
+        for prod in &self.productions {
+            if matches!(prod.out.unconstrained(), OutSpec::Resource { typ: t, .. } if *t == typ) {
+                self.push_fields(prod, &mut order, &mut Vec::new());
+            }
+        }
+        order
+    }
+
+    fn push_fields(
+        &self,
+        prod: &Production<'gr>,
+        order: &mut Vec<&'gr str>,
+        seen: &mut Vec<&'gr str>,
+    ) {
+        let push = |order: &mut Vec<&'gr str>, name: &'gr str| {
+            if !order.contains(&name) {
+                order.push(name);
+            }
+        };
+        for sym in &prod.rhs {
+            match sym {
+                Symbol::Placeholder { name, .. } if *name != ANONYMOUS => push(order, name),
+                Symbol::NonTerminal(WS_RUN) => {}
+                Symbol::NonTerminal(nt) => {
+                    // `__Propagate__` children merge their own fields in place
+                    let propagated: Vec<_> = self
+                        .productions
+                        .iter()
+                        .filter(|p| p.lhs == *nt)
+                        .filter(|p| {
+                            matches!(
+                                p.out.unconstrained(),
+                                OutSpec::Resource { typ: "__Propagate__", .. }
+                            )
+                        })
+                        .collect();
+                    if propagated.is_empty() {
+                        push(order, nt);
+                    } else if !seen.contains(nt) {
+                        seen.push(nt);
+                        for p in propagated {
+                            self.push_fields(p, order, seen);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let OutSpec::Resource { fields, .. } = prod.out.unconstrained() {
+            for (name, _) in fields {
+                push(order, name);
+            }
+        }
+    }
 }
 
 /// A parse tree node:
 /// - `Token(Token<'inp>)` represents a leaf token in the input.
+/// - `Tokens` is a leaf builtin spanning several tokens.
 /// - `Node` represents a nonterminal with children, production OutSpec, and optional name.
 #[derive(Debug, Clone)]
 pub enum ParseTree<'gr, 'inp> {
     Token(Token<'inp>),
+    /// A builtin matched over several tokens, like a `Duration` (`5`, `m`, `s`).
+    Tokens(Vec<Token<'inp>>),
+    /// The input matched by a `{name:/regex/}` placeholder.
+    RegexMatch(Vec<Token<'inp>>),
+    /// The input of a `{name:@Other}` placeholder, for the `Other` sub-grammar to parse.
+    SubInput {
+        grammar: &'gr str,
+        tokens: Vec<Token<'inp>>,
+    },
+    /// The input read by the scanner of a custom placeholder type, with the value it gave.
+    Scanned {
+        value: crate::Value,
+        tokens: Vec<Token<'inp>>,
+    },
     Node {
         rule: Production<'gr>,
         children: Vec<ParseTree<'gr, 'inp>>,
     },
 }
+/// A traversal of a `ParseTree`, driven by `ParseTree::accept`.
+/// Visitors choose whether to go down a node, by calling `accept` on its children.
+pub trait ParseTreeVisitor<'gr, 'inp> {
+    type Output;
+
+    /// Visits a leaf token
+    fn visit_token(&mut self, token: &Token<'inp>) -> Self::Output;
+
+    /// Visits a builtin spanning several tokens, like a `Duration`
+    fn visit_tokens(&mut self, tokens: &[Token<'inp>]) -> Self::Output;
+
+    /// Visits the input matched by a `{name:/regex/}` placeholder, like any other run of tokens by default
+    fn visit_regex_match(&mut self, tokens: &[Token<'inp>]) -> Self::Output {
+        self.visit_tokens(tokens)
+    }
+
+    /// Visits the input of a `{name:@Other}` placeholder, like any other run of tokens by default
+    fn visit_sub_input(&mut self, _grammar: &'gr str, tokens: &[Token<'inp>]) -> Self::Output {
+        self.visit_tokens(tokens)
+    }
+
+    /// Visits the input read by a scanner, like any other run of tokens by default
+    fn visit_scanned(&mut self, _value: &crate::Value, tokens: &[Token<'inp>]) -> Self::Output {
+        self.visit_tokens(tokens)
+    }
+
+    /// Visits a node built by the production `rule`
+    fn visit_node(
+        &mut self,
+        rule: &Production<'gr>,
+        children: &[ParseTree<'gr, 'inp>],
+    ) -> Self::Output;
+}
+
+impl<'gr, 'inp> ParseTree<'gr, 'inp> {
+    /// Calls the `visitor` method matching this tree's root.
+    pub fn accept<V: ParseTreeVisitor<'gr, 'inp>>(&self, visitor: &mut V) -> V::Output {
+        match self {
+            ParseTree::Token(tok) => visitor.visit_token(tok),
+            ParseTree::Tokens(toks) => visitor.visit_tokens(toks),
+            ParseTree::RegexMatch(tokens) => visitor.visit_regex_match(tokens),
+            ParseTree::SubInput { grammar, tokens } => visitor.visit_sub_input(grammar, tokens),
+            ParseTree::Scanned { value, tokens } => visitor.visit_scanned(value, tokens),
+            ParseTree::Node { rule, children } => visitor.visit_node(rule, children),
+        }
+    }
+}
+
 impl<'gr, 'inp> Chart<'gr, 'inp>
 where
     'gr: 'inp,
@@ -59,14 +313,147 @@ where
         chart
     }
 
+    /// The edges that `sym`, of the right-hand side of `prod`, can span from `cur_start`.
+    fn symbol_edges(
+        &self,
+        chart: &[Vec<Edge>],
+        prod: &Production<'gr>,
+        sym: &Symbol<'gr>,
+        cur_start: usize,
+    ) -> Vec<Edge> {
+        let tokens = &self.tokens[..];
+        match sym {
+            Symbol::Terminal(lit) => {
+                // A collapsed whitespace terminal can span several tokens
+                let mut edges = Vec::new();
+                let mut finish = cur_start;
+                while let Some(repeats) = tokens
+                    .get(finish)
+                    .and_then(|tok| self.scan_terminal(prod, lit, tok.text))
+                {
+                    finish += 1;
+                    edges.push(Edge {
+                        rule: usize::MAX,
+                        finish,
+                    });
+                    if !repeats {
+                        break;
+                    }
+                }
+                edges
+            }
+            Symbol::End => match tokens.get(cur_start) {
+                Some(tok) if tok.kind == TokenKind::Eof => vec![Edge {
+                    rule: usize::MAX,
+                    finish: cur_start,
+                }],
+                _ => Vec::new(),
+            },
+            Symbol::NonTerminal(name) => {
+                if cur_start < chart.len() {
+                    chart[cur_start]
+                        .iter()
+                        .filter(|e| self.grammar.productions[e.rule].lhs == *name)
+                        .cloned()
+                        .collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            Symbol::Placeholder { typ, .. } if typ.starts_with('@') => self
+                .sub_grammar_ends(&typ[1..], cur_start)
+                .into_iter()
+                .map(|finish| Edge {
+                    rule: usize::MAX,
+                    finish,
+                })
+                .collect(),
+            Symbol::Placeholder { typ, .. } => {
+                // built in types act like non-terminals
+                let scanned = self.scan(typ, cur_start).map(|(len, _)| len);
+                if let Some(len) = scanned.or_else(|| builtin_len(typ, tokens, cur_start)) {
+                    vec![Edge {
+                        rule: usize::MAX,
+                        finish: cur_start + len,
+                    }]
+                } else if cur_start < chart.len() {
+                    chart[cur_start]
+                        .iter()
+                        .filter(|e| self.grammar.productions[e.rule].lhs == *typ)
+                        .cloned()
+                        .collect()
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    /// Whether the whole input has more than one derivation as `start`, without building them:
+    /// derivations are counted (up to 2) once per rule, position and span.
+    pub fn is_ambiguous(&self, start: &str) -> bool {
+        let chart = self.chart_of_items();
+        let mut memo = HashMap::new();
+        let derivations: usize = chart[0]
+            .iter()
+            .filter(|e| e.finish == self.end() && self.grammar.productions[e.rule].lhs == start)
+            .map(|e| self.derivations(&chart, e.rule, 0, 0, e.finish, &mut memo))
+            .sum();
+        derivations > 1
+    }
+
+    /// How many ways, up to 2, the symbols of `prod_id` from `depth` can span `pos..finish`.
+    fn derivations(
+        &self,
+        chart: &[Vec<Edge>],
+        prod_id: usize,
+        depth: usize,
+        pos: usize,
+        finish: usize,
+        memo: &mut HashMap<(usize, usize, usize, usize), usize>,
+    ) -> usize {
+        let prod = &self.grammar.productions[prod_id];
+        if depth == prod.rhs.len() {
+            return usize::from(pos == finish);
+        }
+        let key = (prod_id, depth, pos, finish);
+        if let Some(count) = memo.get(&key) {
+            return *count;
+        }
+        // a rule deriving itself over the same span has endless derivations
+        memo.insert(key, 2);
+        let mut count = match prod.skip_absent(depth) {
+            Some(next) => self.derivations(chart, prod_id, next, pos, finish, memo),
+            None => 0,
+        };
+        for edge in self.symbol_edges(chart, prod, &prod.rhs[depth], pos) {
+            if edge.finish > finish {
+                continue;
+            }
+            let child = match edge.rule {
+                usize::MAX => 1,
+                rule => self.derivations(chart, rule, 0, pos, edge.finish, memo),
+            };
+            if child > 0 {
+                let rest = self.derivations(chart, prod_id, depth + 1, edge.finish, finish, memo);
+                count += child * rest;
+            }
+            if count >= 2 {
+                count = 2;
+                break;
+            }
+        }
+        memo.insert(key, count);
+        count
+    }
+
     /// For a completed edge, produce the list of edges corresponding to RHS
-    fn top_list<'a>(
+    fn top_list(
         &self,
-        chart: &'a [Vec<Edge>],
-        tokens: &'a [Token<'inp>],
+        chart: &[Vec<Edge>],
         start: usize,
         completed_edge: &Edge,
-    ) -> Vec<(usize, Edge)> {
+    ) -> Option<Vec<(usize, Edge)>> {
         let prod_id = completed_edge.rule;
         let prod = &self.grammar.productions[prod_id];
         let symbols = &prod.rhs;
@@ -78,164 +465,350 @@ where
         let this = self;
 
         let edges_fn = move |depth: usize, cur_start: usize| -> Vec<Edge> {
-            if depth >= bottom {
-                return Vec::new();
-            }
-            match &symbols[depth] {
-                Symbol::Terminal(lit) => {
-                    if cur_start < tokens.len() && tokens[cur_start].text == *lit {
-                        vec![Edge {
-                            rule: usize::MAX,
-                            finish: cur_start + 1,
-                        }]
-                    } else {
-                        Vec::new()
-                    }
-                }
-                Symbol::NonTerminal(name) => {
-                    if cur_start < chart.len() {
-                        chart[cur_start]
-                            .iter()
-                            .filter(|e| this.grammar.productions[e.rule].lhs == *name)
-                            .cloned()
-                            .collect()
-                    } else {
-                        Vec::new()
-                    }
-                }
-                Symbol::Placeholder { name: _, typ } => {
-                    // built in types act like non-terminals
-                    if is_builtin(typ, &tokens[cur_start]) {
-                        vec![Edge {
-                            rule: usize::MAX,
-                            finish: cur_start + 1,
-                        }]
-                    } else if cur_start < chart.len() {
-                        chart[cur_start]
-                            .iter()
-                            .filter(|e| this.grammar.productions[e.rule].lhs == *typ)
-                            .cloned()
-                            .collect()
-                    } else {
-                        Vec::new()
-                    }
-                }
+            match symbols.get(depth) {
+                Some(sym) => this.symbol_edges(chart, prod, sym, cur_start),
+                None => Vec::new(),
             }
         };
+        let skip = |depth: usize| prod.skip_absent(depth);
 
-        fn dfs<FEdges, FChild, FPred>(
+        fn dfs<FEdges, FChild, FPred, FSkip>(
             depth: usize,
             start: usize,
             edges_fn: &FEdges,
             child_fn: &FChild,
             pred_fn: &FPred,
+            skip_fn: &FSkip,
         ) -> Option<Vec<(usize, Edge)>>
         where
             FEdges: Fn(usize, usize) -> Vec<Edge>,
             FChild: Fn(usize, &Edge) -> usize,
             FPred: Fn(usize, usize) -> bool,
+            FSkip: Fn(usize) -> Option<usize>,
         {
             if pred_fn(depth, start) {
                 return Some(Vec::new());
             }
             for edge in edges_fn(depth, start) {
                 let next_start = child_fn(depth, &edge);
-                if let Some(mut path) = dfs(depth + 1, next_start, edges_fn, child_fn, pred_fn) {
+                if let Some(mut path) =
+                    dfs(depth + 1, next_start, edges_fn, child_fn, pred_fn, skip_fn)
+                {
                     let mut res = Vec::with_capacity(1 + path.len());
                     res.push((start, edge));
                     res.append(&mut path);
                     return Some(res);
                 }
             }
-            None
+            // the optional placeholder at `depth` left out
+            let next = skip_fn(depth)?;
+            let mut path = dfs(next, start, edges_fn, child_fn, pred_fn, skip_fn)?;
+            let mut res: Vec<_> = absent_edges(depth, next, start).collect();
+            res.append(&mut path);
+            Some(res)
         }
 
-        dfs(0, start, &edges_fn, &child, &pred)
-            .expect("recogniser invariants should guarantee a solution")
+        // recogniser invariants should guarantee a solution
+        dfs(0, start, &edges_fn, &child, &pred, &skip)
     }
 
     /// Build parse tree borrowing tokens
-    pub fn build_parse_tree<'s>(&'s self) -> Option<ParseTree<'gr, 'inp>>
-    where
-        's: 'inp,
-    {
+    pub fn build_parse_tree(&self) -> Result<ParseTree<'gr, 'inp>, BuildParseTreeError> {
         let chart = self.chart_of_items();
         let start_pos = 0;
-        let finish_pos = chart.len() - 1;
+        let finish_pos = self.end();
         let start_symbol = self.start;
 
-        let top_edge = chart[start_pos]
-            .iter()
-            .find(|e| {
-                e.finish == finish_pos && self.grammar.productions[e.rule].lhs == start_symbol
-            })?
-            .clone();
+        let top_edge = chart[start_pos].iter().find(|e| {
+            e.finish == finish_pos && self.grammar.productions[e.rule].lhs == start_symbol
+        });
 
         fn build<'gr, 'inp>(
             chart: &[Vec<Edge>],
-            tokens: &'inp [Token<'inp>],
-            grammar: &'gr Grammar<'gr>,
+            this: &Chart<'gr, 'inp>,
             start: usize,
             edge: Edge,
-        ) -> ParseTree<'gr, 'inp> {
+        ) -> Option<ParseTree<'gr, 'inp>>
+        where
+            'gr: 'inp,
+        {
+            let tokens = &this.tokens[..];
+            let grammar = this.grammar;
+            if edge.rule == usize::MAX && edge.finish > start + 1 {
+                return Some(ParseTree::Tokens(tokens[start..edge.finish].to_vec()));
+            }
             if edge.rule == usize::MAX {
-                return ParseTree::Token(tokens[start].clone());
+                return Some(ParseTree::Token(tokens[start].clone()));
             }
 
-            let path = Chart {
-                sets: Vec::new(),
-                tokens: tokens.to_vec(),
-                grammar,
-                start: "",
-            }
-            .top_list(chart, tokens, start, &edge);
+            let path = this.top_list(chart, start, &edge)?;
 
+            let rule = &grammar.productions[edge.rule];
             let children = path
                 .into_iter()
-                .map(|(child_start, child_edge)| {
-                    build(chart, tokens, grammar, child_start, child_edge)
+                .zip(&rule.rhs)
+                .map(|((child_start, child_edge), sym)| {
+                    if child_edge.rule == ABSENT {
+                        return Some(ParseTree::Tokens(Vec::new()));
+                    }
+                    match this.placeholder_tree(sym, child_start, child_edge.finish) {
+                        Some(tree) => tree,
+                        None => build(chart, this, child_start, child_edge),
+                    }
                 })
-                .collect();
+                .collect::<Option<_>>()?;
 
             //ParseTree::Node(grammar.productions[edge.rule].lhs.to_string(), children)
-            ParseTree::Node {
+            Some(ParseTree::Node {
                 rule: grammar.productions[edge.rule].clone(),
                 children,
+            })
+        }
+
+        top_edge
+            .and_then(|edge| {
+                build(&chart, self, start_pos, edge.clone())
+            })
+            .ok_or_else(|| BuildParseTreeError {
+                input: self.input_text(),
+                start: self.start.to_string(),
+                chart: self.render_chart(),
+            })
+    }
+
+    /// The tree of a placeholder read without productions, spanning `start..finish`: a
+    /// sub-grammar input, a regex match, a `SignedInt`, a `Balanced` or a scanned value. `None` for other symbols,
+    /// `Some(None)` if the scanner doesn't read the input anymore.
+    fn placeholder_tree(
+        &self,
+        sym: &Symbol<'gr>,
+        start: usize,
+        finish: usize,
+    ) -> Option<Option<ParseTree<'gr, 'inp>>> {
+        let tokens = &self.tokens[start..finish];
+        let &Symbol::Placeholder { typ, .. } = sym else {
+            return None;
+        };
+        if let Some(grammar) = typ.strip_prefix('@') {
+            return Some(Some(ParseTree::SubInput {
+                grammar,
+                tokens: tokens.to_vec(),
+            }));
+        }
+        if is_regex_type(typ) {
+            return Some(Some(ParseTree::RegexMatch(tokens.to_vec())));
+        }
+        if typ.eq_ignore_ascii_case("SignedInt") {
+            return Some(signed_int(&tokens[0]).map(|value| ParseTree::Scanned {
+                value,
+                tokens: tokens.to_vec(),
+            }));
+        }
+        if typ.eq_ignore_ascii_case("Balanced") {
+            // the text inside the outer parentheses
+            let inner = tokens_text(&tokens[1..tokens.len() - 1]);
+            return Some(Some(ParseTree::Scanned {
+                value: crate::Value::String(inner),
+                tokens: tokens.to_vec(),
+            }));
+        }
+        let scanners = self.scanners.filter(|scanners| scanners.contains(typ))?;
+        Some(scanners.scan(typ, &self.tokens, start).map(|(_, value)| ParseTree::Scanned {
+            value,
+            tokens: tokens.to_vec(),
+        }))
+    }
+
+    /// Every parse tree of the accepted input, up to `limit` of them, for ambiguous grammars.
+    /// A rule deriving itself over the same span is only followed once.
+    pub fn build_parse_trees(&self, limit: usize) -> Vec<ParseTree<'gr, 'inp>> {
+        let chart = self.chart_of_items();
+        let mut in_progress = Vec::new();
+        let mut trees = Vec::new();
+        let top_edges = chart[0].iter().filter(|e| {
+            e.finish == self.end() && self.grammar.productions[e.rule].lhs == self.start
+        });
+        for edge in top_edges {
+            let left = limit - trees.len();
+            trees.extend(self.trees(&chart, 0, edge, left, &mut in_progress));
+            if trees.len() >= limit {
+                break;
+            }
+        }
+        trees
+    }
+
+    /// The trees of `edge` starting at `start`, up to `limit`.
+    fn trees(
+        &self,
+        chart: &[Vec<Edge>],
+        start: usize,
+        edge: &Edge,
+        limit: usize,
+        in_progress: &mut Vec<(usize, usize, usize)>,
+    ) -> Vec<ParseTree<'gr, 'inp>> {
+        if edge.rule == usize::MAX && edge.finish > start + 1 {
+            return vec![ParseTree::Tokens(self.tokens[start..edge.finish].to_vec())];
+        }
+        if edge.rule == usize::MAX {
+            return vec![ParseTree::Token(self.tokens[start].clone())];
+        }
+        let key = (start, edge.rule, edge.finish);
+        if in_progress.contains(&key) {
+            return Vec::new();
+        }
+        in_progress.push(key);
+        let rule = &self.grammar.productions[edge.rule];
+        let mut trees = Vec::new();
+        for path in self.top_lists(chart, start, edge, limit) {
+            // the trees of each child, then every combination of them
+            let mut combinations = vec![Vec::new()];
+            for ((child_start, child_edge), sym) in path.iter().zip(&rule.rhs) {
+                let child_trees = if child_edge.rule == ABSENT {
+                    vec![ParseTree::Tokens(Vec::new())]
+                } else {
+                    match self.placeholder_tree(sym, *child_start, child_edge.finish) {
+                        Some(tree) => tree.into_iter().collect(),
+                        None => self.trees(chart, *child_start, child_edge, limit, in_progress),
+                    }
+                };
+                combinations = combinations
+                    .iter()
+                    .flat_map(|children| {
+                        child_trees.iter().map(move |child| {
+                            let mut children = children.clone();
+                            children.push(child.clone());
+                            children
+                        })
+                    })
+                    .take(limit)
+                    .collect();
+            }
+            trees.extend(combinations.into_iter().map(|children| ParseTree::Node {
+                rule: rule.clone(),
+                children,
+            }));
+            if trees.len() >= limit {
+                trees.truncate(limit);
+                break;
             }
         }
+        in_progress.pop();
+        trees
+    }
 
-        Some(build(
-            &chart,
-            &self.tokens,
-            self.grammar,
-            start_pos,
-            top_edge,
-        ))
+    /// Like `top_list`, every list of edges for the RHS of `completed_edge`, up to `limit`.
+    fn top_lists(
+        &self,
+        chart: &[Vec<Edge>],
+        start: usize,
+        completed_edge: &Edge,
+        limit: usize,
+    ) -> Vec<Vec<(usize, Edge)>> {
+        let prod = &self.grammar.productions[completed_edge.rule];
+        let mut paths = Vec::new();
+        let mut path = Vec::new();
+        self.collect_paths(chart, prod, completed_edge.finish, start, &mut path, &mut paths, limit);
+        paths
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn collect_paths(
+        &self,
+        chart: &[Vec<Edge>],
+        prod: &Production<'gr>,
+        finish: usize,
+        pos: usize,
+        path: &mut Vec<(usize, Edge)>,
+        paths: &mut Vec<Vec<(usize, Edge)>>,
+        limit: usize,
+    ) {
+        if paths.len() >= limit {
+            return;
+        }
+        let Some(sym) = prod.rhs.get(path.len()) else {
+            if pos == finish {
+                paths.push(path.clone());
+            }
+            return;
+        };
+        for edge in self.symbol_edges(chart, prod, sym, pos) {
+            if edge.finish > finish {
+                continue;
+            }
+            let next = edge.finish;
+            path.push((pos, edge));
+            self.collect_paths(chart, prod, finish, next, path, paths, limit);
+            path.pop();
+        }
+        if let Some(next) = prod.skip_absent(path.len()) {
+            let depth = path.len();
+            path.extend(absent_edges(depth, next, pos));
+            self.collect_paths(chart, prod, finish, pos, path, paths, limit);
+            path.truncate(depth);
+        }
     }
 }
 
+/// No derivation could be built for an input the recognizer accepted.
+/// This is a bug in Dokearley, the error carries what is needed to report it.
+#[derive(Debug, Error)]
+#[error("Could not build parse tree for {input:?} from {start}, this is a bug in Dokearley!! Please report it with this chart :\n{chart}")]
+pub struct BuildParseTreeError {
+    /// The input, rebuilt from its tokens
+    pub input: String,
+    /// The start symbol the input was parsed as
+    pub start: String,
+    /// The earley sets, as shown by `Chart::print_chart`
+    pub chart: String,
+}
+
 impl<'gr, 'inp> ParseTree<'gr, 'inp> {
     /// Pretty-print the parse tree with indentation
     #[allow(dead_code)]
     pub fn pretty_print(&self, indent: usize) {
-        let padding = "  ".repeat(indent);
-        match self {
-            ParseTree::Token(tok) => {
-                println!("{}Token({})", padding, tok.text);
-            }
-            ParseTree::Node { rule, children } => {
-                println!("{}Node({:?})", padding, rule);
-                for child in children {
-                    child.pretty_print(indent + 1);
-                }
-            }
+        self.accept(&mut PrettyPrinter { indent });
+    }
+}
+
+/// Prints a tree, one indented line per node or token.
+struct PrettyPrinter {
+    indent: usize,
+}
+
+impl PrettyPrinter {
+    fn padding(&self) -> String {
+        "  ".repeat(self.indent)
+    }
+}
+
+impl<'gr, 'inp> ParseTreeVisitor<'gr, 'inp> for PrettyPrinter {
+    type Output = ();
+
+    fn visit_token(&mut self, tok: &Token<'inp>) {
+        println!("{}Token({})", self.padding(), tok.text);
+    }
+
+    fn visit_tokens(&mut self, toks: &[Token<'inp>]) {
+        let text: String = toks.iter().map(|t| t.text).collect();
+        println!("{}Tokens({})", self.padding(), text);
+    }
+
+    fn visit_node(&mut self, rule: &Production<'gr>, children: &[ParseTree<'gr, 'inp>]) {
+        println!("{}Node({:?})", self.padding(), rule);
+        self.indent += 1;
+        for child in children {
+            child.accept(self);
         }
+        self.indent -= 1;
     }
 }
 
 #[cfg(test)]
 mod parse_tree_pretty_tests {
-    use crate::recognizer::{tokenize, Chart, Grammar, OutSpec, Production, Symbol, ValueSpec};
+    use crate::recognizer::{
+        tokenize, Chart, Grammar, OutSpec, Production, Symbol, ValueSpec, WhitespaceMode,
+    };
 
     fn dummy_outspec<'gr>() -> OutSpec<'gr> {
         OutSpec::Value(ValueSpec::FloatLiteral(0.0))
@@ -249,6 +822,7 @@ mod parse_tree_pretty_tests {
                 lhs: "S",
                 rhs: vec![Symbol::Terminal("a")],
                 out: dummy_outspec(),
+                ws: WhitespaceMode::Exact,
             }],
         };
         let toks = tokenize("a");
@@ -269,16 +843,19 @@ mod parse_tree_pretty_tests {
                     lhs: "S",
                     rhs: vec![Symbol::NonTerminal("A"), Symbol::NonTerminal("B")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "A",
                     rhs: vec![Symbol::Terminal("a")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![Symbol::Terminal("b")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -300,14 +877,17 @@ mod parse_tree_pretty_tests {
                     lhs: "S",
                     rhs: vec![Symbol::NonTerminal("X")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "X",
                     rhs: vec![Symbol::Placeholder {
                         name: "n",
                         typ: "Int",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -329,11 +909,13 @@ mod parse_tree_pretty_tests {
                     lhs: "S",
                     rhs: vec![Symbol::NonTerminal("A"), Symbol::NonTerminal("A")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "A",
                     rhs: vec![Symbol::Terminal("a")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -345,6 +927,88 @@ mod parse_tree_pretty_tests {
         println!("Pretty-print nested nonterminals:");
         tree.pretty_print(0);
     }
+
+    #[test]
+    fn visitor_counts_nodes_by_lhs() {
+        use super::{ParseTree, ParseTreeVisitor};
+        use crate::recognizer::Token;
+        use std::collections::HashMap;
+
+        #[derive(Default)]
+        struct CountByLhs<'gr> {
+            counts: HashMap<&'gr str, usize>,
+        }
+
+        impl<'gr, 'inp> ParseTreeVisitor<'gr, 'inp> for CountByLhs<'gr> {
+            type Output = ();
+
+            fn visit_token(&mut self, _token: &Token<'inp>) {}
+
+            fn visit_tokens(&mut self, _tokens: &[Token<'inp>]) {}
+
+            fn visit_node(&mut self, rule: &Production<'gr>, children: &[ParseTree<'gr, 'inp>]) {
+                *self.counts.entry(rule.lhs).or_default() += 1;
+                for child in children {
+                    child.accept(self);
+                }
+            }
+        }
+
+        // Grammar: S -> A A, A -> "a"
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "S",
+                    rhs: vec![Symbol::NonTerminal("A"), Symbol::NonTerminal("A")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![Symbol::Terminal("a")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+        let toks = tokenize("aa");
+        let mut chart = Chart::new(&grammar, toks, "S");
+        chart.recognize("S");
+
+        let tree = chart.build_parse_tree().expect("should build tree");
+        let mut counter = CountByLhs::default();
+        tree.accept(&mut counter);
+        assert_eq!(counter.counts, HashMap::from([("S", 1), ("A", 2)]));
+    }
+
+    #[test]
+    fn build_error_has_context() {
+        // Grammar: S -> "a" {s:String}, but the chart is never recognized
+        let grammar = Grammar {
+            productions: vec![Production {
+                lhs: "S",
+                rhs: vec![
+                    Symbol::Terminal("a"),
+                    Symbol::Placeholder {
+                        name: "s",
+                        typ: "String",
+                        optional: false,
+                    },
+                ],
+                out: dummy_outspec(),
+                ws: WhitespaceMode::Exact,
+            }],
+        };
+        let toks = tokenize(r#"a"b""#);
+        let chart = Chart::new(&grammar, toks, "S");
+
+        let err = chart.build_parse_tree().expect_err("should not build tree");
+        assert_eq!(err.start, "S");
+        assert_eq!(err.input, r#"a"b""#);
+        let message = err.to_string();
+        assert!(message.contains(r#""a\"b\"""#));
+        assert!(message.contains("from S"));
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -352,179 +1016,584 @@ pub enum Value<'gr, 'inp> {
     Integer(i64),
     Float(f64),
     Bool(bool),
-    String(&'inp str),
+    /// The value of an optional placeholder left out
+    Null,
+    String(Cow<'inp, str>),
     Resource {
         typ: &'gr str,
-        fields: HashMap<&'gr str, Value<'gr, 'inp>>,
+        fields: IndexMap<&'gr str, Value<'gr, 'inp>>,
     },
-    Dictionary(HashMap<&'gr str, Value<'gr, 'inp>>),
+    Dictionary(IndexMap<&'gr str, Value<'gr, 'inp>>),
+    /// The values of a placeholder repeated in a pattern, like `{c:Int}{3}`
+    Array(Vec<Value<'gr, 'inp>>),
     /// A value that will come from the first child matching the given non-terminal.
     Child(&'gr str),
     /// A value that will collect all children matching the given non-terminal into a vec.
     Children(&'gr str),
+    /// A value already parsed by a sub-grammar.
+    Parsed(crate::Value),
+}
+
+/// Errors while computing the value of an accepted input.
+#[derive(Debug, Error)]
+pub enum ValueError {
+    /// A captured value can't be converted with a cast like `int(amount)`
+    #[error("Cannot convert {value} to {cast}")]
+    InvalidCast { value: String, cast: Cast },
+    /// A `{name:@Other}` placeholder names a sub-grammar that wasn't registered
+    #[error("No sub-grammar named {0}")]
+    UnknownSubGrammar(String),
+    /// A value doesn't satisfy the `where` clause of its rule
+    #[error("{0}")]
+    ValidationFailed(String),
+    /// An index like `c[3]` is past the values of its repeated placeholder
+    #[error("No value at index {index} of {name}")]
+    InvalidIndex { name: String, index: usize },
+    /// A sub-grammar failed to parse the input of its placeholder
+    #[error("In sub-grammar {name} : {source}")]
+    SubGrammar {
+        name: String,
+        source: Box<crate::DokearleyError>,
+    },
+}
+
+impl<'gr, 'inp> Value<'gr, 'inp> {
+    /// The field at `path` in nested resources and dictionaries, converting only that field.
+    pub fn into_field(self, path: &[&str]) -> Option<crate::Value> {
+        let Some((name, rest)) = path.split_first() else {
+            return Some(self.into());
+        };
+        match self {
+            Value::Resource { mut fields, .. } | Value::Dictionary(mut fields) => {
+                fields.shift_remove(name)?.into_field(rest)
+            }
+            // a sub-grammar's value is already converted
+            Value::Parsed(value) => path.iter().try_fold(value, |value, name| match value {
+                crate::Value::Resource { mut fields, .. }
+                | crate::Value::Dictionary(mut fields) => fields.shift_remove(*name),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Converts a scalar value for casts like `int(amount)` in output specs.
+    pub fn cast(self, cast: Cast) -> Result<Self, ValueError> {
+        let converted = match (&self, cast) {
+            (Value::Integer(_), Cast::Int)
+            | (Value::Float(_), Cast::Float)
+            | (Value::String(_), Cast::String)
+            | (Value::Bool(_), Cast::Bool) => Some(self.clone()),
+            // `as` would saturate floats out of the range of `i64`
+            (Value::Float(f), Cast::Int)
+                if f.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(f) =>
+            {
+                Some(Value::Integer(*f as i64))
+            }
+            (Value::Bool(b), Cast::Int) => Some(Value::Integer(*b as i64)),
+            (Value::String(s), Cast::Int) => s.trim().parse().ok().map(Value::Integer),
+            (Value::Integer(i), Cast::Float) => Some(Value::Float(*i as f64)),
+            (Value::String(s), Cast::Float) => s.trim().parse().ok().map(Value::Float),
+            (Value::Integer(i), Cast::String) => Some(Value::String(i.to_string().into())),
+            (Value::Float(f), Cast::String) => Some(Value::String(f.to_string().into())),
+            (Value::Bool(b), Cast::String) => Some(Value::String(b.to_string().into())),
+            (Value::Integer(i), Cast::Bool) => Some(Value::Bool(*i != 0)),
+            (Value::String(s), Cast::Bool) => s.trim().parse().ok().map(Value::Bool),
+            _ => None,
+        };
+        converted.ok_or_else(|| ValueError::InvalidCast {
+            value: format!("{:?}", self),
+            cast,
+        })
+    }
 }
 
 impl<'gr, 'inp> ParseTree<'gr, 'inp>
 where
     'gr: 'inp,
 {
-    pub fn compute_value(&self) -> Value<'gr, 'inp> {
+    #[allow(dead_code)]
+    pub fn compute_value(&self) -> Result<Value<'gr, 'inp>, ValueError> {
+        self.accept(&mut ValueBuilder { sub_grammars: None })
+    }
+
+    /// Same as `compute_value`, parsing `{name:@Other}` placeholders with `sub_grammars`.
+    pub fn compute_value_with(
+        &self,
+        sub_grammars: &'gr SubGrammars<'gr>,
+    ) -> Result<Value<'gr, 'inp>, ValueError> {
+        self.accept(&mut ValueBuilder {
+            sub_grammars: Some(sub_grammars),
+        })
+    }
+
+    fn find_placeholder(
+        &self,
+        name: &str,
+        builder: ValueBuilder<'gr>,
+    ) -> Option<Result<Value<'gr, 'inp>, ValueError>> {
         match self {
-            // Tokens can yield a value if needed, but this would not be used currently.
-            ParseTree::Token(tok) => tok.get_value().unwrap_or(Value::String(tok.text)),
-            // For nodes, we check the OutSpec and do what it says
-            ParseTree::Node { rule, children } => match &rule.out {
-                OutSpec::Value(spec) => match spec {
-                    ValueSpec::IntegerLiteral(i) => Value::Integer(*i),
-                    ValueSpec::FloatLiteral(f) => Value::Float(*f),
-                    ValueSpec::StringLiteral(s) => Value::String(s),
-                    ValueSpec::BoolLiteral(b) => Value::Bool(*b),
-                    ValueSpec::Identifier(name) => {
-                                        // find first child matching placeholder name
-                                        children
-                                            .iter()
-                                            .find_map(|c| match c {
-                                                ParseTree::Node {
-                                                    rule: child_rule, ..
-                                                } => child_rule.rhs.iter().zip(c.as_children()).find_map(
-                                                    |(sym, child)| match sym {
-                                                        Symbol::Placeholder { name: n, .. } if *n == **name => {
-                                                            Some(child.compute_value())
-                                                        }
-                                                        _ => None,
-                                                    },
-                                                ),
-                                                ParseTree::Token(_tok) => None,
-                                            })
-                                            .unwrap_or(Value::String("<missing_placeholder>"))
-                                    }
-                    ValueSpec::Child(c) => Value::Child(c),
-                    ValueSpec::Children(c) => Value::Children(c),
-                },
-                // If the outspec says to build a resource, make it
-                OutSpec::Resource { typ, fields } => {
-                    let mut result_fields = HashMap::new();
-
-                    // Collect children placeholders
-                    for (i, sym) in rule.rhs.iter().enumerate() {
-                        match sym {
-                            Symbol::Placeholder { name, .. } => {
-                                let val = children[i].compute_value();
-                                result_fields.insert(*name, val);
-                            }
-                            Symbol::NonTerminal(nt_name) => {
-                                let child_val = children[i].compute_value();
-                                // if child is a __Propagate__ resource, merge fields
-                                match &child_val {
-                                    Value::Resource { typ: t, fields: f }
-                                        if *t == "__Propagate__" =>
-                                    {
-                                        for (k, v) in f {
-                                            result_fields.insert(k, v.clone());
-                                        }
-                                    }
-                                    _ => {
-                                        // otherwise, keep under nonterminal name
-                                        result_fields.insert(*nt_name, child_val);
+            ParseTree::Node { rule, children } => placeholder_value(rule, children, name, builder),
+            _ => None,
+        }
+    }
+}
+
+/// Computes the value of a tree, following the `OutSpec` of each node.
+#[derive(Clone, Copy)]
+struct ValueBuilder<'gr> {
+    sub_grammars: Option<&'gr SubGrammars<'gr>>,
+}
+
+impl<'gr> ValueBuilder<'gr> {
+    fn value_of<'inp>(mut self, tree: &ParseTree<'gr, 'inp>) -> Result<Value<'gr, 'inp>, ValueError>
+    where
+        'gr: 'inp,
+    {
+        tree.accept(&mut self)
+    }
+}
+
+impl<'gr, 'inp> ParseTreeVisitor<'gr, 'inp> for ValueBuilder<'gr>
+where
+    'gr: 'inp,
+{
+    type Output = Result<Value<'gr, 'inp>, ValueError>;
+
+    // Tokens can yield a value if needed, but this would not be used currently.
+    fn visit_token(&mut self, tok: &Token<'inp>) -> Self::Output {
+        Ok(tok.get_value().unwrap_or(Value::String(tok.text.into())))
+    }
+
+    // Durations and percentages span several tokens : the amount, then the unit.
+    fn visit_tokens(&mut self, toks: &[Token<'inp>]) -> Self::Output {
+        let value = toks[0].get_value().unwrap_or(Value::Integer(0));
+        if toks[1].text == "%" {
+            let percent = match value {
+                Value::Integer(i) => i as f64,
+                Value::Float(f) => f,
+                _ => 0.0,
+            };
+            return Ok(Value::Float(percent / 100.0));
+        }
+        let unit: String = toks[1..].iter().map(|t| t.text).collect();
+        Ok(Value::Resource {
+            typ: "Duration",
+            fields: IndexMap::from([("value", value), ("unit", Value::String(unit.into()))]),
+        })
+    }
+
+    // The matched text, as written in the input
+    fn visit_regex_match(&mut self, tokens: &[Token<'inp>]) -> Self::Output {
+        Ok(Value::String(tokens_text(tokens).into()))
+    }
+
+    // The value the scanner gave
+    fn visit_scanned(&mut self, value: &crate::Value, _tokens: &[Token<'inp>]) -> Self::Output {
+        Ok(Value::Parsed(value.clone()))
+    }
+
+    fn visit_sub_input(&mut self, grammar: &'gr str, tokens: &[Token<'inp>]) -> Self::Output {
+        let sub = self
+            .sub_grammars
+            .and_then(|subs| subs.get(grammar))
+            .ok_or_else(|| ValueError::UnknownSubGrammar(grammar.to_string()))?;
+        sub.parse(&tokens_text(tokens), grammar)
+            .map(Value::Parsed)
+            .map_err(|e| ValueError::SubGrammar {
+                name: grammar.to_string(),
+                source: Box::new(e),
+            })
+    }
+
+    // For nodes, we check the OutSpec and do what it says
+    fn visit_node(
+        &mut self,
+        rule: &Production<'gr>,
+        children: &[ParseTree<'gr, 'inp>],
+    ) -> Self::Output {
+        self.compute_out(&rule.out, rule, children)
+    }
+}
+
+impl<'gr> ValueBuilder<'gr> {
+    /// The value of a node of `rule`, built as `out` says.
+    fn compute_out<'inp>(
+        self,
+        out: &OutSpec<'gr>,
+        rule: &Production<'gr>,
+        children: &[ParseTree<'gr, 'inp>],
+    ) -> Result<Value<'gr, 'inp>, ValueError>
+    where
+        'gr: 'inp,
+    {
+        let this = self;
+        Ok(match out {
+            OutSpec::Value(spec) => {
+                // this rule's placeholder with that name, whatever its type, else a child's
+                let lookup = |name: &str| {
+                    placeholder_value(rule, children, name, this)
+                        .or_else(|| children.iter().find_map(|c| c.find_placeholder(name, this)))
+                };
+                spec_value(spec, lookup)?.unwrap_or(Value::Null)
+            }
+            // If the outspec says to build a resource, make it
+            OutSpec::Resource { typ, fields } => {
+                let mut result_fields = IndexMap::new();
+
+                // Collect children placeholders
+                for (i, sym) in rule.rhs.iter().enumerate() {
+                    match sym {
+                        Symbol::Placeholder { .. } if is_absent(&children[i]) => {}
+                        Symbol::Placeholder { name, .. } => {
+                            let val = this.value_of(&children[i])?;
+                            insert_placeholder(&mut result_fields, rule, name, val);
+                        }
+                        // `{~}` whitespace has no value
+                        Symbol::NonTerminal(WS_RUN) => {}
+                        Symbol::NonTerminal(nt_name) => {
+                            let child_val = this.value_of(&children[i])?;
+                            // if child is a __Propagate__ resource, merge fields
+                            match &child_val {
+                                Value::Resource { typ: t, fields: f } if *t == "__Propagate__" => {
+                                    for (k, v) in f {
+                                        result_fields.insert(k, v.clone());
                                     }
                                 }
+                                _ => {
+                                    // otherwise, keep under nonterminal name
+                                    result_fields.insert(*nt_name, child_val);
+                                }
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
+                }
 
-                    // fixed aliases
-                    for (k, v) in fields {
-                        let val = match v {
-                            ValueSpec::Identifier(n) => children
-                                                        .iter()
-                                                        .find_map(|c| c.find_placeholder(n))
-                                                        .unwrap_or(Value::String("<missing_i>")),
-                            ValueSpec::IntegerLiteral(i) => Value::Integer(*i),
-                            ValueSpec::FloatLiteral(f) => Value::Float(*f),
-                            ValueSpec::StringLiteral(s) => Value::String(s),
-                            ValueSpec::BoolLiteral(b) => Value::Bool(*b),
-                            ValueSpec::Child(c) => Value::Child(c),
-                            ValueSpec::Children(c) => Value::Children(c),
-
-                        };
-                        result_fields.insert(*k, val);
+                // fixed aliases, from this rule's placeholders first, then its children's
+                let lookup = |n: &str| {
+                    placeholder_value(rule, children, n, this)
+                        .or_else(|| children.iter().find_map(|c| c.find_placeholder(n, this)))
+                };
+                for (k, v) in fields {
+                    if let Some(value) = this.field_value(v, rule, children, lookup)? {
+                        result_fields.insert(*k, value);
                     }
+                }
 
-                    Value::Resource {
+                Value::Resource {
+                    typ,
+                    fields: result_fields,
+                }
+            }
+            OutSpec::Transparent => this.value_of(&children[0])?,
+            OutSpec::Keyword => {
+                let text: String = children
+                    .iter()
+                    .map(|child| match child {
+                        ParseTree::Token(tok) => tokens_text(std::slice::from_ref(tok)),
+                        ParseTree::Tokens(tokens) => tokens_text(tokens),
+                        _ => String::new(),
+                    })
+                    .collect();
+                Value::String(text.into())
+            }
+            OutSpec::List(rest) => {
+                let mut values = Vec::new();
+                this.list_values(rule, children, rest, &mut values)?;
+                Value::Array(values)
+            }
+            // the alternative's value, with the disjunction's fixed fields added
+            OutSpec::Merged { typ, fields } => {
+                let (alternative_typ, mut result_fields) = match this.value_of(&children[0])? {
+                    Value::Resource { typ, fields } => (Some(typ), fields),
+                    Value::Dictionary(fields) => (None, fields),
+                    other => (None, IndexMap::from([("value", other)])),
+                };
+                let lookup = |n: &str| children.iter().find_map(|c| c.find_placeholder(n, this));
+                for (k, v) in fields {
+                    if let Some(value) = spec_value(v, lookup)? {
+                        result_fields.insert(*k, value);
+                    }
+                }
+                match typ.or(alternative_typ) {
+                    Some(typ) => Value::Resource {
                         typ,
                         fields: result_fields,
-                    }
+                    },
+                    None => Value::Dictionary(result_fields),
                 }
-                OutSpec::Transparent => children[0].compute_value(),
-                // If the outspec says to build a dictionary, make it
-                OutSpec::Dict(fields) => {
-                    let mut result_fields = HashMap::new();
-
-                    // collect children placeholders and non-terminals
-                    for (i, sym) in rule.rhs.iter().enumerate() {
-                        match sym {
-                            Symbol::Placeholder { name, .. } => {
-                                let val = children[i].compute_value();
-                                result_fields.insert(*name, val);
-                            }
-                            Symbol::NonTerminal(nt_name) => {
-                                let child_val = children[i].compute_value();
-                                result_fields.insert(*nt_name, child_val);
-                            }
-                            _ => {}
+            }
+            OutSpec::Tagged => {
+                let variant = match rule.rhs[0] {
+                    // a quoted alternative is named after its source, `"yes"`, which
+                    // can go on with its own output, `"yes" -> { answer: true }`
+                    Symbol::NonTerminal(name) => name.split('"').nth(1).unwrap_or(name),
+                    _ => rule.lhs,
+                };
+                Value::Dictionary(IndexMap::from([
+                    ("variant", Value::String(variant.into())),
+                    ("value", this.value_of(&children[0])?),
+                ]))
+            }
+            // If the outspec says to build a dictionary, make it
+            OutSpec::Dict(fields) => {
+                let mut result_fields = IndexMap::new();
+
+                // collect children placeholders and non-terminals
+                for (i, sym) in rule.rhs.iter().enumerate() {
+                    match sym {
+                        Symbol::Placeholder { .. } if is_absent(&children[i]) => {}
+                        Symbol::Placeholder { name, .. } => {
+                            let val = this.value_of(&children[i])?;
+                            insert_placeholder(&mut result_fields, rule, name, val);
+                        }
+                        Symbol::NonTerminal(WS_RUN) => {}
+                        Symbol::NonTerminal(nt_name) => {
+                            let child_val = this.value_of(&children[i])?;
+                            result_fields.insert(*nt_name, child_val);
                         }
+                        _ => {}
                     }
+                }
 
-                    // fixed fields (aliases) from OutSpec::Dict definition
-                    for (k, v) in fields {
-                        let val = match v {
-                            ValueSpec::Identifier(name) => {
-                                                                                self.find_placeholder(name).unwrap_or(Value::String("<missing related placeholder>"))
-                                                                            },
-                            ValueSpec::IntegerLiteral(i) => Value::Integer(*i),
-                            ValueSpec::FloatLiteral(f) => Value::Float(*f),
-                            ValueSpec::StringLiteral(s) => Value::String(s),
-                            ValueSpec::BoolLiteral(b) => Value::Bool(*b),
-                            ValueSpec::Child(c) => Value::Child(c),
-                            ValueSpec::Children(c) => Value::Children(c),
-                        };
-                        result_fields.insert(*k, val);
+                // fixed fields (aliases) from OutSpec::Dict definition
+                let lookup = |name: &str| placeholder_value(rule, children, name, this);
+                for (k, v) in fields {
+                    if let Some(value) = this.field_value(v, rule, children, lookup)? {
+                        result_fields.insert(*k, value);
                     }
+                }
 
-                    Value::Dictionary(result_fields)
+                Value::Dictionary(result_fields)
+            }
+            OutSpec::Constrained { out, constraints } => {
+                let value = this.compute_out(out, rule, children)?;
+                for constraint in constraints {
+                    check_constraint(constraint, &value)?;
                 }
-            },
-        }
+                value
+            }
+        })
     }
+}
 
-    fn as_children(&self) -> Vec<ParseTree<'gr, 'inp>> {
-        match self {
-            ParseTree::Node { rule: _, children } => children.clone(),
-            _ => vec![],
+impl<'gr> ValueBuilder<'gr> {
+    /// Value of a fixed field of a node of `rule`, see `spec_value`. `<* Items` is the flat
+    /// array of the `Items` chain when the rule matched one, the children marker otherwise.
+    fn field_value<'inp>(
+        self,
+        spec: &ValueSpec<'gr>,
+        rule: &Production<'gr>,
+        children: &[ParseTree<'gr, 'inp>],
+        lookup: impl Fn(&str) -> Option<Result<Value<'gr, 'inp>, ValueError>>,
+    ) -> Result<Option<Value<'gr, 'inp>>, ValueError>
+    where
+        'gr: 'inp,
+    {
+        let ValueSpec::Children(nt) = spec else {
+            return spec_value(spec, lookup);
+        };
+        let chain = rule.rhs.iter().zip(children).find(|(sym, _)| is_of_type(sym, nt));
+        let Some((_, chain)) = chain else {
+            return spec_value(spec, lookup);
+        };
+        let mut values = Vec::new();
+        self.chain_values(chain, nt, &mut values)?;
+        Ok(Some(Value::Array(values)))
+    }
+
+    /// Pushes the values along a right-recursive chain of `nt`, like
+    /// `Items: "{item:Item} {rest:Items}" | ""`: each link adds the values of its other symbols.
+    fn chain_values<'inp>(
+        self,
+        tree: &ParseTree<'gr, 'inp>,
+        nt: &str,
+        values: &mut Vec<Value<'gr, 'inp>>,
+    ) -> Result<(), ValueError>
+    where
+        'gr: 'inp,
+    {
+        let ParseTree::Node { rule, children } = tree else {
+            values.push(self.value_of(tree)?);
+            return Ok(());
+        };
+        for (sym, child) in rule.rhs.iter().zip(children) {
+            match sym {
+                _ if is_of_type(sym, nt) => self.chain_values(child, nt, values)?,
+                Symbol::Placeholder { name, .. } if *name != ANONYMOUS => {
+                    values.push(self.value_of(child)?)
+                }
+                Symbol::NonTerminal(name) if *name != WS_RUN => values.push(self.value_of(child)?),
+                _ => {}
+            }
         }
+        Ok(())
     }
+}
 
-    fn find_placeholder(&self, name: &str) -> Option<Value<'gr, 'inp>> {
-        match self {
-            ParseTree::Node { rule, children } => {
-                for (sym, child) in rule.rhs.iter().zip(children) {
-                    if let Symbol::Placeholder { name: n, .. } = sym {
-                        if **n == *name {
-                            return Some(child.compute_value());
-                        }
-                    }
+impl<'gr> ValueBuilder<'gr> {
+    /// Pushes the items of a repetition, following its chain of `rest` nonterminals.
+    /// Separators have no value.
+    fn list_values<'inp>(
+        self,
+        rule: &Production<'gr>,
+        children: &[ParseTree<'gr, 'inp>],
+        rest: &str,
+        values: &mut Vec<Value<'gr, 'inp>>,
+    ) -> Result<(), ValueError>
+    where
+        'gr: 'inp,
+    {
+        for (sym, child) in rule.rhs.iter().zip(children) {
+            match (sym, child) {
+                (Symbol::NonTerminal(nt), ParseTree::Node { rule, children }) if *nt == rest => {
+                    self.list_values(rule, children, rest, values)?
                 }
-                None
+                (Symbol::Placeholder { .. }, _) => values.push(self.value_of(child)?),
+                _ => {}
             }
-            _ => None,
         }
+        Ok(())
+    }
+}
+
+/// Whether `sym` matches the nonterminal `nt`, as `{name:nt}` or a bare `nt`.
+fn is_of_type(sym: &Symbol<'_>, nt: &str) -> bool {
+    match sym {
+        Symbol::Placeholder { typ, .. } => *typ == nt,
+        Symbol::NonTerminal(name) => *name == nt,
+        _ => false,
+    }
+}
+
+/// Checks a `where` clause constraint on the value its rule built.
+fn check_constraint(constraint: &Constraint<'_>, value: &Value<'_, '_>) -> Result<(), ValueError> {
+    let field = match value {
+        Value::Resource { fields, .. } | Value::Dictionary(fields) => {
+            fields.get(constraint.field.text)
+        }
+        _ => None,
+    };
+    let ordering = match (field, constraint.value) {
+        (Some(Value::Integer(a)), ValueSpec::IntegerLiteral(b)) => a.partial_cmp(&b),
+        (Some(Value::Integer(a)), ValueSpec::FloatLiteral(b)) => (*a as f64).partial_cmp(&b),
+        (Some(Value::Float(a)), ValueSpec::IntegerLiteral(b)) => a.partial_cmp(&(b as f64)),
+        (Some(Value::Float(a)), ValueSpec::FloatLiteral(b)) => a.partial_cmp(&b),
+        (Some(Value::String(a)), ValueSpec::StringLiteral(b)) => {
+            a.as_ref().partial_cmp(b.unescaped().as_ref())
+        }
+        _ => None,
+    };
+    if ordering.is_some_and(|ordering| constraint.op.holds(ordering)) {
+        return Ok(());
+    }
+    let found = match field {
+        Some(Value::Integer(i)) => i.to_string(),
+        Some(Value::Float(f)) => f.to_string(),
+        Some(Value::String(s)) => format!("{:?}", s),
+        Some(other) => format!("{:?}", other),
+        None => "missing".to_string(),
+    };
+    Err(ValueError::ValidationFailed(format!(
+        "{}, but {} is {}",
+        constraint, constraint.field, found
+    )))
+}
+
+/// Value of a fixed field, with `lookup` giving the placeholders it names.
+/// `None` when it reads a placeholder the input left out, like an optional one.
+fn spec_value<'gr, 'inp>(
+    spec: &ValueSpec<'gr>,
+    lookup: impl Fn(&str) -> Option<Result<Value<'gr, 'inp>, ValueError>>,
+) -> Result<Option<Value<'gr, 'inp>>, ValueError>
+where
+    'gr: 'inp,
+{
+    let read = |name: &str| lookup(name).transpose();
+    Ok(Some(match spec {
+        ValueSpec::IntegerLiteral(i) => Value::Integer(*i),
+        ValueSpec::FloatLiteral(f) => Value::Float(*f),
+        ValueSpec::StringLiteral(s) => Value::String(s.unescaped()),
+        ValueSpec::BoolLiteral(b) => Value::Bool(*b),
+        ValueSpec::Identifier(name) => return read(name),
+        ValueSpec::Cast(cast, name) => return read(name)?.map(|v| v.cast(*cast)).transpose(),
+        ValueSpec::Index(name, i) => match read(name)? {
+            None => return Ok(None),
+            Some(Value::Array(mut values)) if *i < values.len() => values.swap_remove(*i),
+            Some(_) => Err(ValueError::InvalidIndex {
+                name: name.to_string(),
+                index: *i,
+            })?,
+        },
+        ValueSpec::Child(c) => Value::Child(c),
+        ValueSpec::Children(c) => Value::Children(c),
+    }))
+}
+
+/// Value of the placeholder `name` among a node's direct children.
+fn placeholder_value<'gr, 'inp>(
+    rule: &Production<'gr>,
+    children: &[ParseTree<'gr, 'inp>],
+    name: &str,
+    builder: ValueBuilder<'gr>,
+) -> Option<Result<Value<'gr, 'inp>, ValueError>>
+where
+    'gr: 'inp,
+{
+    let mut values: Vec<_> = rule
+        .rhs
+        .iter()
+        .zip(children)
+        .filter(|(sym, _)| matches!(sym, Symbol::Placeholder { name: n, .. } if **n == *name))
+        .filter(|(_, child)| !is_absent(child))
+        .map(|(_, child)| builder.value_of(child))
+        .collect();
+    match values.len() {
+        0 | 1 => values.pop(),
+        // a repeated placeholder, like `{c:Int}{3}`
+        _ => Some(values.into_iter().collect::<Result<_, _>>().map(Value::Array)),
+    }
+}
+
+/// The value of a `SignedInt` read as `tok`: its magnitude, and whether it was negative.
+/// `None` when the magnitude doesn't fit an `Int`.
+fn signed_int(tok: &Token) -> Option<crate::Value> {
+    let negative = tok.text.starts_with('-');
+    let magnitude = tok.text.trim_start_matches(['-', '+']).parse::<i64>().ok()?;
+    Some(crate::Value::Dictionary(IndexMap::from([
+        ("magnitude".to_string(), crate::Value::Integer(magnitude)),
+        ("negative".to_string(), crate::Value::Bool(negative)),
+    ])))
+}
+
+/// Whether `tree` is an optional placeholder like `{t:Target?}` that the input left out.
+pub(crate) fn is_absent(tree: &ParseTree) -> bool {
+    matches!(tree, ParseTree::Tokens(tokens) if tokens.is_empty())
+}
+
+/// Adds the value of the placeholder `name` to `fields`, in an array when `rule`
+/// repeats it, like `{c:Int}{3}`. Anonymous placeholders add nothing.
+fn insert_placeholder<'gr, 'inp>(
+    fields: &mut IndexMap<&'gr str, Value<'gr, 'inp>>,
+    rule: &Production<'gr>,
+    name: &'gr str,
+    value: Value<'gr, 'inp>,
+) {
+    if name == ANONYMOUS {
+        return;
+    }
+    let is_name = |sym: &Symbol| matches!(sym, Symbol::Placeholder { name: n, .. } if *n == name);
+    if rule.rhs.iter().filter(|sym| is_name(sym)).count() < 2 {
+        fields.insert(name, value);
+    } else if let Value::Array(values) = fields.entry(name).or_insert(Value::Array(Vec::new())) {
+        values.push(value);
     }
 }
 #[cfg(test)]
 mod parse_tree_value_tests {
     use super::*;
-    use crate::{recognizer::tokenize};
+    use crate::recognizer::{tokenize, WhitespaceMode};
 
     #[test]
     fn compute_value_simple_effect() {
@@ -542,6 +1611,7 @@ mod parse_tree_value_tests {
                         Symbol::Placeholder {
                             name: "damage",
                             typ: "Int",
+                            optional: false,
                         },
                         Symbol::Terminal(" "),
                         Symbol::Terminal("d"),
@@ -558,8 +1628,9 @@ mod parse_tree_value_tests {
                     ],
                     out: OutSpec::Resource {
                         typ: "DamageEffect",
-                        fields: HashMap::new(), // implicit fields come from placeholders + children
+                        fields: Vec::new(), // implicit fields come from placeholders + children
                     },
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Target",
@@ -573,6 +1644,7 @@ mod parse_tree_value_tests {
                         Symbol::Terminal("s"),
                     ],
                     out: OutSpec::Value(ValueSpec::IntegerLiteral(1)),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -584,7 +1656,7 @@ mod parse_tree_value_tests {
         let tree = chart.build_parse_tree().expect("tree should build");
         tree.pretty_print(0);
 
-        let val = tree.compute_value();
+        let val = tree.compute_value().unwrap();
         println!("Computed value: {:?}", val);
 
         match val {
@@ -615,6 +1687,7 @@ mod parse_tree_value_tests {
                         Symbol::Placeholder {
                             name: "damage",
                             typ: "Int",
+                            optional: false,
                         },
                         Symbol::Terminal(" "),
                         Symbol::Terminal("d"),
@@ -631,8 +1704,9 @@ mod parse_tree_value_tests {
                     ],
                     out: OutSpec::Resource {
                         typ: "DamageEffect",
-                        fields: HashMap::new(),
+                        fields: Vec::new(),
                     },
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Position",
@@ -641,15 +1715,18 @@ mod parse_tree_value_tests {
                         Symbol::Placeholder {
                             name: "x",
                             typ: "Int",
+                            optional: false,
                         },
                         Symbol::Terminal(","),
                         Symbol::Placeholder {
                             name: "y",
                             typ: "Int",
+                            optional: false,
                         },
                         Symbol::Terminal(")"),
                     ],
-                    out: OutSpec::Dict(HashMap::new()),
+                    out: OutSpec::Dict(Vec::new()),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -661,7 +1738,7 @@ mod parse_tree_value_tests {
         let tree = chart.build_parse_tree().expect("tree should build");
         tree.pretty_print(0);
 
-        let val = tree.compute_value();
+        let val = tree.compute_value().unwrap();
         println!("Computed value: {:?}", val);
 
         match val {
@@ -680,4 +1757,57 @@ mod parse_tree_value_tests {
             _ => panic!("expected Resource"),
         }
     }
+
+    #[test]
+    fn value_aliases_nonterminal_placeholder() {
+        // Effect : "use {e:Target}" -> e
+        // Target : "self" -> Target
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "Effect",
+                    rhs: vec![
+                        Symbol::Terminal("u"),
+                        Symbol::Terminal("s"),
+                        Symbol::Terminal("e"),
+                        Symbol::Terminal(" "),
+                        Symbol::Placeholder {
+                            name: "e",
+                            typ: "Target",
+                            optional: false,
+                        },
+                    ],
+                    out: OutSpec::Value(ValueSpec::mock_identifier("e")),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "Target",
+                    rhs: vec![
+                        Symbol::Terminal("s"),
+                        Symbol::Terminal("e"),
+                        Symbol::Terminal("l"),
+                        Symbol::Terminal("f"),
+                    ],
+                    out: OutSpec::Resource {
+                        typ: "Target",
+                        fields: Vec::new(),
+                    },
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+
+        let toks = tokenize("use self");
+        let mut chart = Chart::new(&grammar, toks, "Effect");
+        chart.recognize("Effect");
+
+        let tree = chart.build_parse_tree().expect("tree should build");
+        match tree.compute_value().unwrap() {
+            Value::Resource { typ, fields } => {
+                assert_eq!(typ, "Target");
+                assert!(fields.is_empty());
+            }
+            other => panic!("expected the Target resource, got {:?}", other),
+        }
+    }
 }
@@ -1,7 +1,12 @@
 pub use crate::grammar_parser::ValueSpec;
+use crate::grammar_parser::{is_internal, unescape, Cast, Constraint, WS_RUN};
 pub use crate::parser::OutSpec;
-use crate::parser::Value;
+use crate::parser::{FieldSpecs, Value};
+use crate::regex_types::{is_regex_type, regex_len};
+use crate::scanners::Scanners;
+use crate::SubGrammars;
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
@@ -24,16 +29,20 @@ impl std::fmt::Display for Span {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Symbol<'gr> {
     Terminal(&'gr str),
-    Placeholder { name: &'gr str, typ: &'gr str },
+    Placeholder {
+        name: &'gr str,
+        typ: &'gr str,
+        /// Written `{name:Type?}`, the input may leave it out
+        optional: bool,
+    },
     NonTerminal(&'gr str),
+    /// The `$` anchor ending a pattern, matching only at the end of the input.
+    End,
 }
 
 impl<'gr> Symbol<'gr> {
     pub fn is_terminal(&self) -> bool {
-        match self {
-            Symbol::Terminal(_) => true,
-            _ => false,
-        }
+        matches!(self, Symbol::Terminal(_))
     }
 }
 
@@ -43,17 +52,207 @@ impl<'gr> fmt::Display for Symbol<'gr> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Symbol::Terminal(s) => write!(f, "{}", s),
-            Symbol::Placeholder { name, typ } => write!(f, "<{}:{}>", name, typ),
+            // a repetition, named as written, like `{items:Item*}`
+            Symbol::Placeholder { typ, .. } if is_internal(typ) => write!(f, "{}", typ),
+            Symbol::Placeholder {
+                name,
+                typ,
+                optional: false,
+            } => write!(f, "<{}:{}>", name, typ),
+            Symbol::Placeholder { name, typ, .. } => write!(f, "<{}:{}?>", name, typ),
             Symbol::NonTerminal(s) => write!(f, "{}", s),
+            Symbol::End => write!(f, "$"),
         }
     }
 }
 
+/// How a production's whitespace terminals match the input, set with `@ws:<mode>` on a rule.
+///
+/// Only the production's own terminals are affected: sub-productions it refers to
+/// keep their own mode, and whitespace around the production isn't touched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "compile", derive(serde::Serialize, serde::Deserialize))]
+pub enum WhitespaceMode {
+    /// Each whitespace character of the pattern matches exactly one in the input.
+    #[default]
+    Exact,
+    /// A run of whitespace in the pattern matches a run of one or more whitespace characters.
+    Collapse,
+}
+
 #[derive(Debug, Clone)]
 pub struct Production<'gr> {
     pub lhs: &'gr str,
     pub rhs: Vec<Symbol<'gr>>,
     pub out: OutSpec<'gr>,
+    pub ws: WhitespaceMode,
+}
+
+impl<'gr> Production<'gr> {
+    /// The production as written in a rule, like `Heal : "heal for <amount:Int>"`.
+    pub fn pattern_text(&self) -> String {
+        format!("{} : \"{}\"", self.lhs, self.rhs_text())
+    }
+
+    /// The right hand side, like `heal for <amount:Int>`.
+    pub fn rhs_text(&self) -> String {
+        self.rhs.iter().map(|sym| sym.to_string()).collect()
+    }
+
+    /// A readable signature, like `ItemEffect := "heal for " <amount:Int> -> Heal`,
+    /// with consecutive terminals joined in quoted strings.
+    pub fn signature(&self) -> String {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        for sym in &self.rhs {
+            if let Symbol::Terminal(t) = sym {
+                literal.push_str(t);
+                continue;
+            }
+            if !literal.is_empty() {
+                parts.push(format!("{:?}", std::mem::take(&mut literal)));
+            }
+            parts.push(sym.to_string());
+        }
+        if !literal.is_empty() {
+            parts.push(format!("{:?}", literal));
+        }
+
+        let pattern = parts.join(" ");
+        format!("{} := {}{}", self.lhs, pattern, output_text(&self.out))
+    }
+
+    /// Where the dot moves when the optional placeholder at `dot` is left out, with the
+    /// whitespace terminals written before it: `attack {t:Target?}` reads `attack` too.
+    pub fn skip_absent(&self, dot: usize) -> Option<usize> {
+        let is_ws = |sym: Option<&Symbol<'_>>| match sym {
+            Some(Symbol::Terminal(t)) => t.chars().all(char::is_whitespace),
+            _ => false,
+        };
+        // the whitespace goes with the placeholder from its start only
+        if dot > 0 && is_ws(self.rhs.get(dot - 1)) {
+            return None;
+        }
+        let mut end = dot;
+        while is_ws(self.rhs.get(end)) {
+            end += 1;
+        }
+        match self.rhs.get(end) {
+            Some(Symbol::Placeholder { optional: true, .. }) => Some(end + 1),
+            _ => None,
+        }
+    }
+
+    /// Whether the right-hand side can read no token at all, the nonterminals and placeholder
+    /// types of `nullable` reading none and optional placeholders being left out.
+    pub fn is_nullable(&self, nullable: &HashSet<&'gr str>) -> bool {
+        let mut dot = 0;
+        while dot < self.rhs.len() {
+            if let Some(next) = self.skip_absent(dot) {
+                dot = next;
+                continue;
+            }
+            let empty = match &self.rhs[dot] {
+                Symbol::NonTerminal(nt) => nullable.contains(nt),
+                Symbol::Placeholder { typ, .. } => nullable.contains(typ),
+                Symbol::Terminal(_) | Symbol::End => false, // Terminals are never nullable
+            };
+            if !empty {
+                return false;
+            }
+            dot += 1;
+        }
+        true
+    }
+
+    /// The type of the placeholder named `name`, if the pattern has one.
+    fn placeholder_type(&self, name: &str) -> Option<&'gr str> {
+        self.rhs.iter().find_map(|sym| match sym {
+            Symbol::Placeholder { name: n, typ, .. } if *n == name => Some(*typ),
+            _ => None,
+        })
+    }
+
+    /// Whether the value of the placeholder `name` is a `number`, a `string` or a `bool`,
+    /// when its type tells. A name used twice gives an array.
+    fn placeholder_kind(&self, name: &str) -> Option<&'static str> {
+        let is_name =
+            |sym: &&Symbol| matches!(sym, Symbol::Placeholder { name: n, .. } if *n == name);
+        if self.rhs.iter().filter(is_name).count() > 1 {
+            return None;
+        }
+        match self.placeholder_type(name)?.to_ascii_lowercase().as_str() {
+            "int" | "uint" | "signedint" | "float" | "ufloat" | "number" => Some("number"),
+            "string" | "str" => Some("string"),
+            "bool" => Some("bool"),
+            _ => None,
+        }
+    }
+
+    /// Whether the terminal `lit` of this production matches the token text `tok`,
+    /// and if it does, whether it may also match the tokens after it (collapsed whitespace).
+    pub fn scan_terminal(&self, lit: &str, tok: &str) -> Option<bool> {
+        let is_ws = |s: &str| !s.is_empty() && s.chars().all(char::is_whitespace);
+        if self.ws == WhitespaceMode::Collapse && is_ws(lit) && is_ws(tok) {
+            Some(true)
+        } else if tok == lit && !lit.is_empty() {
+            // an empty terminal would match the empty text of the end of input
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a fixed field of `prod` is a `number`, a `string` or a `bool`, when it can be told.
+fn spec_kind(prod: &Production<'_>, spec: &ValueSpec<'_>) -> Option<&'static str> {
+    match spec {
+        ValueSpec::IntegerLiteral(_) | ValueSpec::FloatLiteral(_) => Some("number"),
+        ValueSpec::StringLiteral(_) => Some("string"),
+        ValueSpec::BoolLiteral(_) => Some("bool"),
+        ValueSpec::Cast(Cast::Int | Cast::Float, _) => Some("number"),
+        ValueSpec::Cast(Cast::String, _) => Some("string"),
+        ValueSpec::Cast(Cast::Bool, _) => Some("bool"),
+        ValueSpec::Identifier(name) => prod.placeholder_kind(name.text),
+        _ => None,
+    }
+}
+
+/// An output spec as written after a pattern, like ` -> Target { kind: "self" }`.
+fn output_text(out: &OutSpec<'_>) -> String {
+    let fields_text = |fields: &FieldSpecs| {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(name, spec)| match spec {
+                ValueSpec::Child(_) | ValueSpec::Children(_) => format!("{} {}", name, spec),
+                _ => format!("{}: {}", name, spec),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    };
+    match out {
+        OutSpec::Value(spec) => format!(" -> {}", spec),
+        OutSpec::Resource { typ, fields }
+        | OutSpec::Merged {
+            typ: Some(typ),
+            fields,
+        } => {
+            if fields.is_empty() {
+                format!(" -> {}", typ)
+            } else {
+                format!(" -> {} {}", typ, fields_text(fields))
+            }
+        }
+        OutSpec::Dict(fields) | OutSpec::Merged { typ: None, fields } => {
+            format!(" -> {}", fields_text(fields))
+        }
+        OutSpec::Transparent | OutSpec::Keyword | OutSpec::List(_) => String::new(),
+        OutSpec::Tagged => " @tagged".to_string(),
+        OutSpec::Constrained { out, constraints } => {
+            let constraints: Vec<String> = constraints.iter().map(|c| c.to_string()).collect();
+            format!("{} where {}", output_text(out), constraints.join(" and "))
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -63,7 +262,12 @@ pub struct Grammar<'gr> {
 
 impl<'gr> Grammar<'gr> {
     pub fn compute_nullable(&self) -> HashSet<&'gr str> {
-        let mut nullable = HashSet::new();
+        self.compute_nullable_from(HashSet::new())
+    }
+
+    /// Same as `compute_nullable`, starting from nonterminals already known to be nullable.
+    pub fn compute_nullable_from(&self, known: HashSet<&'gr str>) -> HashSet<&'gr str> {
+        let mut nullable = known;
         let mut changed = true;
 
         while changed {
@@ -75,14 +279,7 @@ impl<'gr> Grammar<'gr> {
                     continue;
                 }
 
-                // Check if all RHS symbols are nullable
-                let all_nullable = prod.rhs.iter().all(|sym| match sym {
-                    Symbol::NonTerminal(nt) => nullable.contains(nt),
-                    Symbol::Placeholder { name: _, typ } => nullable.contains(typ),
-                    Symbol::Terminal(_) => false, // Terminals are never nullable
-                });
-
-                if all_nullable {
+                if prod.is_nullable(&nullable) {
                     nullable.insert(prod.lhs);
                     changed = true;
                 }
@@ -91,6 +288,59 @@ impl<'gr> Grammar<'gr> {
 
         nullable
     }
+
+    /// Indices of the productions whose whole right-hand side can derive the empty input.
+    pub fn nullable_productions(&self) -> Vec<usize> {
+        let nullable = self.compute_nullable();
+        self.productions
+            .iter()
+            .enumerate()
+            .filter(|(_, prod)| prod.is_nullable(&nullable))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+impl<'gr> Grammar<'gr> {
+    /// Whether two grammars have the same productions, ignoring their output specs.
+    /// Anything derived from the shape of the grammar only (nullable set, FIRST sets)
+    /// can be reused between two structurally equal grammars.
+    pub fn structural_eq(&self, other: &Grammar<'_>) -> bool {
+        self.productions.len() == other.productions.len()
+            && self
+                .productions
+                .iter()
+                .zip(&other.productions)
+                .all(|(a, b)| a.lhs == b.lhs && a.rhs == b.rhs)
+    }
+
+    /// The nonterminals whose nullability or FIRST set may differ from `old`: those whose
+    /// productions changed, and those deriving them. The analyses of the others can be kept.
+    pub fn changed_nonterminals(&self, old: &Grammar<'gr>) -> HashSet<&'gr str> {
+        // the right-hand sides of each nonterminal, in the new grammar then in the old one
+        let mut patterns: HashMap<&'gr str, (Vec<_>, Vec<_>)> = HashMap::new();
+        for p in &self.productions {
+            patterns.entry(p.lhs).or_default().0.push(&p.rhs);
+        }
+        for p in &old.productions {
+            patterns.entry(p.lhs).or_default().1.push(&p.rhs);
+        }
+        let mut changed: HashSet<&'gr str> = patterns
+            .iter()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(nt, _)| *nt)
+            .collect();
+        let graph = self.dependency_graph();
+        let mut pending: Vec<&'gr str> = changed.iter().copied().collect();
+        while let Some(nt) = pending.pop() {
+            for (user, references) in &graph {
+                if references.contains(nt) && changed.insert(user) {
+                    pending.push(user);
+                }
+            }
+        }
+        changed
+    }
 }
 
 impl<'gr> Grammar<'gr> {
@@ -99,15 +349,56 @@ impl<'gr> Grammar<'gr> {
             .iter()
             .enumerate()
             .filter(|(_, p)| p.lhs == name)
-            .map(|(i, p)| (i, p))
             .collect()
     }
 }
 
+/// The productions of a grammar by their first terminal, so that recognition only starts
+/// the ones matching the first token, see `Options::keyword_dispatch`.
+#[derive(Debug, Clone, Default)]
+pub struct KeywordIndex<'gr> {
+    by_terminal: HashMap<&'gr str, Vec<usize>>,
+    /// Productions not starting with a terminal, that any first token may start
+    others: Vec<usize>,
+}
+
+impl<'gr> KeywordIndex<'gr> {
+    pub fn new(grammar: &Grammar<'gr>) -> Self {
+        let mut index = KeywordIndex::default();
+        for (pid, prod) in grammar.productions.iter().enumerate() {
+            match prod.rhs.first() {
+                // collapsed whitespace matches other whitespace than its own, and the
+                // whitespace before an optional placeholder may be left out
+                Some(Symbol::Terminal(lit))
+                    if (prod.ws != WhitespaceMode::Collapse || !lit.trim().is_empty())
+                        && prod.skip_absent(0).is_none() =>
+                {
+                    index.by_terminal.entry(*lit).or_default().push(pid)
+                }
+                _ => index.others.push(pid),
+            }
+        }
+        index
+    }
+
+    /// The productions of `lhs` that may match tokens starting with `first`, in order.
+    pub fn seeds(&self, grammar: &Grammar<'gr>, lhs: &str, first: &str) -> Vec<usize> {
+        let keyed = self.by_terminal.get(first).into_iter().flatten();
+        let mut seeds: Vec<usize> = keyed
+            .chain(&self.others)
+            .copied()
+            .filter(|pid| grammar.productions[*pid].lhs == lhs)
+            .collect();
+        seeds.sort_unstable();
+        seeds
+    }
+}
+
 impl<'gr> Grammar<'gr> {
-    /// Detect whether the grammar contains an infinite nullable cycle (a cycle
-    /// entirely through nullable nonterminals / placeholder types).
-    pub fn has_infinite_loop(&self) -> bool {
+    /// Find an infinite nullable cycle (a cycle entirely through nullable
+    /// nonterminals / placeholder types), as its nonterminals in order,
+    /// starting and ending with the same name (`A -> B -> A`).
+    pub fn find_nullable_cycle(&self) -> Option<Vec<&'gr str>> {
         use std::collections::{HashMap, HashSet};
 
         // 1 compute nullable set (nonterminal names that can produce epsilon)
@@ -115,7 +406,7 @@ impl<'gr> Grammar<'gr> {
 
         // quick exit: nothing nullable -> no nullable cycles
         if null_set.is_empty() {
-            return false;
+            return None;
         }
 
         // 2 build adjacency map for nullable symbols:
@@ -129,33 +420,31 @@ impl<'gr> Grammar<'gr> {
 
             for (_pid, prod) in self.prods_for(sym) {
                 // check if whole rhs is nullable
-                let rhs_all_nullable = prod.rhs.iter().all(|s| match s {
-                    Symbol::NonTerminal(nt) => null_set.contains(nt),
-                    Symbol::Placeholder { name: _, typ } => null_set.contains(typ),
-                    Symbol::Terminal(_) => false,
-                });
-
-                if rhs_all_nullable {
+                if prod.is_nullable(&null_set) {
                     // gather nonterminals / placeholder types from rhs
                     for s in &prod.rhs {
                         match s {
                             Symbol::NonTerminal(nt) => {
                                 children.insert(nt);
                             }
-                            Symbol::Placeholder { name: _, typ } => {
+                            Symbol::Placeholder { typ, .. } => {
                                 children.insert(typ);
                             }
-                            Symbol::Terminal(_) => { /* terminals shouldn't appear here */ }
+                            Symbol::Terminal(_) | Symbol::End => {
+                                /* terminals shouldn't appear here */
+                            }
                         }
                     }
                 }
             }
 
             // keep only children that are in the nullable set (we only care about cycles among nullable symbols)
-            let filtered: Vec<&'gr str> = children
+            let mut filtered: Vec<&'gr str> = children
                 .into_iter()
                 .filter(|c| null_set.contains(c))
                 .collect();
+            // sorted, so the same grammar always reports the same cycle
+            filtered.sort();
             adj.insert(sym, filtered);
         }
 
@@ -166,42 +455,663 @@ impl<'gr> Grammar<'gr> {
             color.insert(s, 0);
         }
 
+        // `path` holds the symbols being visited, from the DFS root to `v`
         fn dfs<'a>(
             v: &'a str,
             adj: &HashMap<&'a str, Vec<&'a str>>,
             color: &mut HashMap<&'a str, u8>,
-        ) -> bool {
+            path: &mut Vec<&'a str>,
+        ) -> Option<Vec<&'a str>> {
             color.insert(v, 1); // visiting
+            path.push(v);
             if let Some(neighs) = adj.get(v) {
                 for &w in neighs {
                     match color.get(w).copied().unwrap_or(0) {
                         0 => {
-                            if dfs(w, adj, color) {
-                                return true;
+                            if let Some(cycle) = dfs(w, adj, color, path) {
+                                return Some(cycle);
                             }
                         }
                         1 => {
-                            // found back-edge -> cycle
-                            return true;
+                            // found back-edge -> cycle from w back to itself
+                            let from = path.iter().position(|&p| p == w).unwrap_or(0);
+                            let mut cycle = path[from..].to_vec();
+                            cycle.push(w);
+                            return Some(cycle);
                         }
                         _ => {}
                     }
                 }
             }
+            path.pop();
             color.insert(v, 2); // done
-            false
+            None
         }
 
-        for &s in &null_set {
+        let mut roots: Vec<&'gr str> = null_set.iter().copied().collect();
+        roots.sort();
+        for s in roots {
             if color.get(s).copied().unwrap_or(0) == 0 {
-                if dfs(s, &adj, &mut color) {
-                    return true;
+                if let Some(cycle) = dfs(s, &adj, &mut color, &mut Vec::new()) {
+                    return Some(cycle);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Two number placeholders with nothing in between, like `{a:Int}{b:Int}`: adjacent digits
+    /// are a single number token, so they could never both match. Gives the rule and the two symbols.
+    pub fn find_adjacent_numbers(&self) -> Option<(&Production<'gr>, &Symbol<'gr>, &Symbol<'gr>)> {
+        let is_number = |sym: &Symbol, types: &[&str]| match sym {
+            Symbol::Placeholder { typ, .. } => types.iter().any(|t| t.eq_ignore_ascii_case(typ)),
+            _ => false,
+        };
+        const NUMBERS: [&str; 6] = ["Int", "UInt", "SignedInt", "Float", "UFloat", "Number"];
+        // A `Duration` starts with its number
+        const STARTING_WITH_NUMBER: [&str; 8] = [
+            "Int",
+            "UInt",
+            "SignedInt",
+            "Float",
+            "UFloat",
+            "Number",
+            "Duration",
+            "Percent",
+        ];
+        self.productions.iter().find_map(|p| {
+            p.rhs
+                .windows(2)
+                .find(|pair| {
+                    is_number(&pair[0], &NUMBERS) && is_number(&pair[1], &STARTING_WITH_NUMBER)
+                })
+                .map(|pair| (p, &pair[0], &pair[1]))
+        })
+    }
+
+    /// Literal text in patterns that the tokenizer reads as one number, like the `12` of `"level 12"`:
+    /// terminals match single characters, so it could never match. Single digits, like the `3`
+    /// of `"level 3"`, match their `Int` token. Gives each rule with the number text.
+    pub fn find_shadowed_numbers(&self) -> Vec<(&Production<'gr>, String)> {
+        let mut shadowed = Vec::new();
+        for p in &self.productions {
+            let runs = p.rhs.split(|sym| !sym.is_terminal());
+            for (i, run) in runs.enumerate() {
+                // after another symbol, a `-` is an operator rather than a sign
+                let mut text = String::from(if i == 0 { "" } else { "x" });
+                text.extend(run.iter().filter_map(|sym| match sym {
+                    Symbol::Terminal(t) => Some(*t),
+                    _ => None,
+                }));
+                shadowed.extend(
+                    tokenize(&text)
+                        .iter()
+                        .filter(|t| matches!(t.kind, TokenKind::Int | TokenKind::Float))
+                        .filter(|t| t.text.chars().count() > 1)
+                        .map(|t| (p, t.text.to_string())),
+                );
+            }
+        }
+        shadowed
+    }
+
+    /// Each nonterminal, with the nonterminals and placeholder types its productions reference,
+    /// e.g. to draw the grammar or look for cycles.
+    pub fn dependency_graph(&self) -> HashMap<&'gr str, HashSet<&'gr str>> {
+        let mut graph: HashMap<&'gr str, HashSet<&'gr str>> = HashMap::new();
+        for p in &self.productions {
+            let references = graph.entry(p.lhs).or_default();
+            for sym in &p.rhs {
+                match sym {
+                    Symbol::NonTerminal(WS_RUN) => {}
+                    Symbol::NonTerminal(name) | Symbol::Placeholder { typ: name, .. } => {
+                        references.insert(name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // the nonterminals of repetitions are replaced by what they reference
+        let internal: HashMap<_, _> = graph
+            .iter()
+            .filter(|(name, _)| is_internal(name))
+            .map(|(name, references)| (*name, references.clone()))
+            .collect();
+        graph.retain(|name, _| !is_internal(name));
+        for references in graph.values_mut() {
+            let mut pending: Vec<&'gr str> = references
+                .iter()
+                .copied()
+                .filter(|name| is_internal(name))
+                .collect();
+            let mut seen = HashSet::new();
+            while let Some(name) = pending.pop() {
+                references.remove(name);
+                if !seen.insert(name) {
+                    continue;
+                }
+                for next in internal.get(name).into_iter().flatten() {
+                    match is_internal(next) {
+                        true => pending.push(next),
+                        false => {
+                            references.insert(next);
+                        }
+                    }
+                }
+            }
+        }
+        graph
+    }
+
+    /// The resource types that parsing `start` could produce, from the outputs of the rules
+    /// it reaches. `Duration` placeholders produce `Duration` resources too.
+    pub fn output_types_from(&self, start: &str) -> HashSet<&'gr str> {
+        let mut reached: HashSet<&str> = HashSet::from([start]);
+        let mut pending = vec![start];
+        let mut types = HashSet::new();
+        while let Some(name) = pending.pop() {
+            for (_, p) in self.prods_for(name) {
+                match p.out.unconstrained() {
+                    // merged into the resource of the rule using it
+                    OutSpec::Resource { typ, .. } if *typ == "__Propagate__" => {}
+                    OutSpec::Resource { typ, .. } | OutSpec::Merged { typ: Some(typ), .. } => {
+                        types.insert(*typ);
+                    }
+                    _ => {}
+                }
+                for sym in &p.rhs {
+                    let next = match sym {
+                        Symbol::NonTerminal(nt) => *nt,
+                        Symbol::Placeholder { typ, .. } if typ.eq_ignore_ascii_case("Duration") => {
+                            types.insert("Duration");
+                            continue;
+                        }
+                        Symbol::Placeholder { typ, .. } => *typ,
+                        _ => continue,
+                    };
+                    if reached.insert(next) {
+                        pending.push(next);
+                    }
+                }
+            }
+        }
+        types
+    }
+
+    /// A production with an empty terminal, which never matches a token. `dokedef` patterns
+    /// can't write one, but a compiled or hand-built grammar could.
+    pub fn find_empty_terminal(&self) -> Option<&Production<'gr>> {
+        self.productions
+            .iter()
+            .find(|p| p.rhs.contains(&Symbol::Terminal("")))
+    }
+
+    /// A field alias of an output that names no placeholder, like `nope` in `-> X { y: nope }`.
+    /// Outputs read the placeholders of their rule, then those of the rules it directly contains.
+    pub fn find_undefined_alias(&self) -> Option<(&Production<'gr>, &'gr str)> {
+        self.undefined_aliases().into_iter().next()
+    }
+
+    /// Every field alias naming no placeholder, see `find_undefined_alias`.
+    pub fn undefined_aliases(&self) -> Vec<(&Production<'gr>, &'gr str)> {
+        let placeholders = |p: &Production<'gr>| -> Vec<&'gr str> {
+            p.rhs
+                .iter()
+                .filter_map(|sym| match sym {
+                    Symbol::Placeholder { name, .. } => Some(*name),
+                    _ => None,
+                })
+                .collect()
+        };
+        self.productions
+            .iter()
+            .flat_map(|p| {
+                let mut known = placeholders(p);
+                for sym in &p.rhs {
+                    let (Symbol::NonTerminal(nt) | Symbol::Placeholder { typ: nt, .. }) = sym
+                    else {
+                        continue;
+                    };
+                    for child in self.productions.iter().filter(|c| c.lhs == *nt) {
+                        known.extend(placeholders(child));
+                    }
+                }
+                let mut referenced = p.out.referenced_names();
+                referenced.retain(|n| !known.contains(n));
+                referenced.dedup();
+                referenced.into_iter().map(move |n| (p, n))
+            })
+            .collect()
+    }
+
+    /// `where` clauses that can never hold, with why: comparing a field the output doesn't
+    /// have, like `z` in `-> X { y: n } where z > 1`, or a field to a literal of another
+    /// type, like `n > "s"` when `n` is an `Int`. Only resource and dictionary outputs are
+    /// checked, the fields of other outputs depend on the input.
+    pub fn invalid_constraints(&self) -> Vec<(&Production<'gr>, &Constraint<'gr>, String)> {
+        let mut invalid = Vec::new();
+        for p in &self.productions {
+            let OutSpec::Constrained { out, constraints } = &p.out else {
+                continue;
+            };
+            let (fields, resource) = match out.unconstrained() {
+                OutSpec::Resource { fields, .. } => (fields, true),
+                OutSpec::Dict(fields) => (fields, false),
+                _ => continue,
+            };
+            let nonterminals: Vec<&'gr str> = p
+                .rhs
+                .iter()
+                .filter_map(|sym| match sym {
+                    Symbol::NonTerminal(nt) if *nt != WS_RUN => Some(*nt),
+                    _ => None,
+                })
+                .collect();
+            for constraint in constraints {
+                let field = constraint.field.text;
+                let kind = match fields.iter().rev().find(|(name, _)| *name == field) {
+                    Some((_, spec)) => spec_kind(p, spec),
+                    None if p.placeholder_type(field).is_some() => p.placeholder_kind(field),
+                    None if nonterminals.contains(&field) => None,
+                    // a resource takes the fields of the rules it contains
+                    None if resource && !nonterminals.is_empty() => None,
+                    None => {
+                        invalid.push((p, constraint, format!("the output has no field {field}")));
+                        continue;
+                    }
+                };
+                let literal = spec_kind(p, &constraint.value);
+                if let (Some(kind), Some(literal)) = (kind, literal) {
+                    if kind != literal {
+                        let reason = format!("{field} is a {kind}, not a {literal}");
+                        invalid.push((p, constraint, reason));
+                    }
                 }
             }
         }
+        invalid
+    }
 
-        false
+    /// Every distinct placeholder type written in a pattern, builtins and nonterminals alike.
+    pub fn placeholder_types(&self) -> HashSet<&'gr str> {
+        self.productions
+            .iter()
+            .flat_map(|p| &p.rhs)
+            .filter_map(|sym| match sym {
+                Symbol::Placeholder { typ, .. } if !is_internal(typ) => Some(*typ),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The first nonterminal of a pattern or disjunction that has no productions, like
+    /// `Ice` in `Element: Fire | Ice` without an `Ice` rule, with the rules referencing it.
+    /// Placeholder types are checked by `find_unknown_type`.
+    pub fn find_undefined_nonterminal(&self) -> Option<(&'gr str, Vec<&Production<'gr>>)> {
+        self.undefined_nonterminals().into_iter().next()
+    }
+
+    /// Every nonterminal without productions, see `find_undefined_nonterminal`.
+    pub fn undefined_nonterminals(&self) -> Vec<(&'gr str, Vec<&Production<'gr>>)> {
+        let defined: HashSet<&'gr str> = self.productions.iter().map(|p| p.lhs).collect();
+        let mut undefined: Vec<&'gr str> = Vec::new();
+        for sym in self.productions.iter().flat_map(|p| &p.rhs) {
+            match sym {
+                Symbol::NonTerminal(nt) if !defined.contains(nt) && !undefined.contains(nt) => {
+                    undefined.push(nt)
+                }
+                _ => {}
+            }
+        }
+        undefined
+            .into_iter()
+            .map(|name| {
+                let referencing = self
+                    .productions
+                    .iter()
+                    .filter(|p| p.rhs.contains(&Symbol::NonTerminal(name)))
+                    .collect();
+                (name, referencing)
+            })
+            .collect()
     }
+
+    /// The first placeholder type that is neither a builtin, a sub-grammar (`@Name`),
+    /// one of the `custom` types nor a nonterminal with productions, like a misspelled `{x:Integer}`.
+    pub fn find_unknown_type(&self, custom: &[String]) -> Option<&'gr str> {
+        self.unknown_types(custom).into_iter().next()
+    }
+
+    /// Every distinct unknown placeholder type, see `find_unknown_type`.
+    pub fn unknown_types(&self, custom: &[String]) -> Vec<&'gr str> {
+        let defined: HashSet<&'gr str> = self.productions.iter().map(|p| p.lhs).collect();
+        let mut unknown: Vec<&'gr str> = self
+            .productions
+            .iter()
+            .flat_map(|p| &p.rhs)
+            .filter_map(|sym| match sym {
+                Symbol::Placeholder { typ, .. }
+                    if !is_builtin_type(typ)
+                        && !typ.starts_with('@')
+                        && !is_regex_type(typ)
+                        && !is_exclusion_type(typ)
+                        && !defined.contains(typ)
+                        && !custom.iter().any(|c| c == typ) =>
+                {
+                    Some(*typ)
+                }
+                _ => None,
+            })
+            .collect();
+        let mut seen = HashSet::new();
+        unknown.retain(|typ| seen.insert(*typ));
+        unknown
+    }
+
+    /// The nonterminals that `start` never derives, directly or through other rules,
+    /// in the order of their first production.
+    pub fn unreachable_nonterminals(&self, start: &str) -> Vec<&'gr str> {
+        let graph = self.dependency_graph();
+        let mut reached: HashSet<&str> = HashSet::from([start]);
+        let mut pending = vec![start];
+        while let Some(nt) = pending.pop() {
+            for next in graph.get(nt).into_iter().flatten() {
+                if reached.insert(next) {
+                    pending.push(next);
+                }
+            }
+        }
+        let mut unreachable: Vec<&'gr str> = Vec::new();
+        for p in self.productions.iter().filter(|p| !is_internal(p.lhs)) {
+            if !reached.contains(p.lhs) && !unreachable.contains(&p.lhs) {
+                unreachable.push(p.lhs);
+            }
+        }
+        unreachable
+    }
+
+    /// Nonterminals named like a builtin type, like an `Int` rule: `{x:Int}` placeholders
+    /// read the builtin, so the rule is only used by patterns naming it without a placeholder.
+    pub fn builtin_shadowing_rules(&self) -> Vec<&'gr str> {
+        let mut shadowing: Vec<&'gr str> = Vec::new();
+        for p in &self.productions {
+            if is_builtin_type(p.lhs) && !shadowing.contains(&p.lhs) {
+                shadowing.push(p.lhs);
+            }
+        }
+        shadowing
+    }
+
+    /// Output fields written twice in the same output, like `-> X { a: 1, a: 2 }`,
+    /// where only the last one is kept.
+    pub fn duplicate_fields(&self) -> Vec<(&Production<'gr>, &'gr str)> {
+        let mut duplicates = Vec::new();
+        for p in &self.productions {
+            let mut out = &p.out;
+            while let OutSpec::Constrained { out: inner, .. } = out {
+                out = inner;
+            }
+            let (OutSpec::Resource { fields, .. }
+            | OutSpec::Dict(fields)
+            | OutSpec::Merged { fields, .. }) = out
+            else {
+                continue;
+            };
+            let mut repeated: Vec<&'gr str> = Vec::new();
+            for (i, (name, _)) in fields.iter().enumerate() {
+                if fields[..i].iter().any(|(n, _)| n == name) && !repeated.contains(name) {
+                    repeated.push(name);
+                }
+            }
+            duplicates.extend(repeated.into_iter().map(|name| (p, name)));
+        }
+        duplicates
+    }
+
+    /// The builtin or nonterminal closest to an unknown type, if one is close enough
+    /// to be a likely typo, or a shortened / lengthened name like `Integer` for `Int`.
+    pub fn suggest_type(&self, typ: &str) -> Option<&'gr str> {
+        let typ_lower = typ.to_ascii_lowercase();
+        let mut nonterminals: Vec<&'gr str> = self
+            .productions
+            .iter()
+            .map(|p| p.lhs)
+            .filter(|lhs| !is_internal(lhs))
+            .collect();
+        nonterminals.sort();
+        nonterminals.dedup();
+        BUILTIN_TYPES
+            .iter()
+            .copied()
+            .chain(nonterminals)
+            .filter_map(|candidate| {
+                let candidate_lower = candidate.to_ascii_lowercase();
+                let distance = edit_distance(&typ_lower, &candidate_lower);
+                let close = distance <= (typ.len() / 3).max(2)
+                    || typ_lower.starts_with(&candidate_lower)
+                    || candidate_lower.starts_with(&typ_lower);
+                close.then_some((distance, candidate))
+            })
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate)
+    }
+}
+
+/// What can start a derivation, for `Grammar::is_deterministic_hint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum FirstAtom<'gr> {
+    Terminal(&'gr str),
+    /// A whitespace terminal of a collapsed production, matching any whitespace
+    Whitespace,
+    /// A builtin type, by the token kind it reads: `Int`, `Float` or `String`
+    Builtin(&'static str),
+    /// A sub-grammar or regex, which could start with anything
+    Any,
+    End,
+}
+
+impl<'gr> FirstAtom<'gr> {
+    /// Whether both could match the same token.
+    fn conflicts(&self, other: &FirstAtom<'gr>) -> bool {
+        use FirstAtom::*;
+        let is_ws = |t: &str| t.chars().all(char::is_whitespace);
+        let is_number =
+            |t: &str| t.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+');
+        match (self, other) {
+            (Any, _) | (_, Any) => true,
+            (Whitespace, Whitespace) => true,
+            (Whitespace, Terminal(t)) | (Terminal(t), Whitespace) => is_ws(t),
+            // Terminals are compared to the token text, which for a string literal can be anything
+            (Builtin("String"), Terminal(_)) | (Terminal(_), Builtin("String")) => true,
+            (Builtin(_), Terminal(t)) | (Terminal(t), Builtin(_)) => is_number(t),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl<'gr> Grammar<'gr> {
+    /// A conservative LL(1)-like check, as a hint that a faster deterministic parse is possible.
+    /// For each nonterminal, the first tokens of its productions are pairwise disjoint,
+    /// at most one production is nullable, and then nothing that can follow the nonterminal
+    /// can also start one of its productions. `false` doesn't mean the grammar is ambiguous.
+    pub fn is_deterministic_hint(&self) -> bool {
+        let nullable = self.compute_nullable();
+        let firsts = self.first_atoms(&nullable);
+        let follows = self.follow_atoms(&nullable, &firsts);
+        let disjoint = |a: &HashSet<FirstAtom<'gr>>, b: &HashSet<FirstAtom<'gr>>| {
+            a.iter().all(|x| b.iter().all(|y| !x.conflicts(y)))
+        };
+
+        let mut lhss: Vec<&'gr str> = self.productions.iter().map(|p| p.lhs).collect();
+        lhss.sort();
+        lhss.dedup();
+        lhss.into_iter().all(|lhs| {
+            let starts: Vec<(HashSet<FirstAtom<'gr>>, bool)> = self
+                .productions
+                .iter()
+                .filter(|p| p.lhs == lhs)
+                .map(|p| Self::sequence_first(p, 0, &nullable, &firsts))
+                .collect();
+            let nullable_count = starts.iter().filter(|(_, n)| *n).count();
+            let pairwise = starts
+                .iter()
+                .enumerate()
+                .all(|(i, (a, _))| starts[i + 1..].iter().all(|(b, _)| disjoint(a, b)));
+            let follow_ok = nullable_count == 0
+                || follows
+                    .get(lhs)
+                    .is_none_or(|follow| starts.iter().all(|(first, _)| disjoint(first, follow)));
+            nullable_count <= 1 && pairwise && follow_ok
+        })
+    }
+
+    /// The atoms that can start the symbols of `prod` from `from`, and whether they can all
+    /// be empty.
+    fn sequence_first(
+        prod: &Production<'gr>,
+        from: usize,
+        nullable: &HashSet<&'gr str>,
+        firsts: &HashMap<&'gr str, HashSet<FirstAtom<'gr>>>,
+    ) -> (HashSet<FirstAtom<'gr>>, bool) {
+        let mut atoms = HashSet::new();
+        // whether they can be empty by leaving an optional placeholder out
+        let mut skipped = false;
+        for (i, sym) in prod.rhs.iter().enumerate().skip(from) {
+            if let Some(next) = prod.skip_absent(i) {
+                let (after, empty) = Self::sequence_first(prod, next, nullable, firsts);
+                atoms.extend(after);
+                skipped |= empty;
+            }
+            let nonterminal = match sym {
+                Symbol::Terminal(t) => {
+                    let ws =
+                        prod.ws == WhitespaceMode::Collapse && t.chars().all(char::is_whitespace);
+                    atoms.insert(if ws {
+                        FirstAtom::Whitespace
+                    } else {
+                        FirstAtom::Terminal(t)
+                    });
+                    return (atoms, skipped);
+                }
+                Symbol::End => {
+                    atoms.insert(FirstAtom::End);
+                    return (atoms, skipped);
+                }
+                Symbol::Placeholder { typ, .. }
+                    if typ.starts_with('@')
+                        || is_regex_type(typ)
+                        || is_exclusion_type(typ)
+                        // an `Int` or a `Float`
+                        || typ.eq_ignore_ascii_case("Percent")
+                        || typ.eq_ignore_ascii_case("Number") =>
+                {
+                    atoms.insert(FirstAtom::Any);
+                    return (atoms, skipped);
+                }
+                Symbol::Placeholder { typ, .. } if typ.eq_ignore_ascii_case("Bool") => {
+                    // the terminals spelling them read the same token
+                    atoms.extend([FirstAtom::Terminal("true"), FirstAtom::Terminal("false")]);
+                    return (atoms, skipped);
+                }
+                Symbol::Placeholder { typ, .. } if typ.eq_ignore_ascii_case("Balanced") => {
+                    atoms.insert(FirstAtom::Terminal("("));
+                    return (atoms, skipped);
+                }
+                Symbol::Placeholder { typ, .. } if is_builtin_type(typ) => {
+                    let kind = match typ.to_ascii_lowercase().as_str() {
+                        "float" | "ufloat" => "Float",
+                        "string" | "str" => "String",
+                        _ => "Int",
+                    };
+                    atoms.insert(FirstAtom::Builtin(kind));
+                    return (atoms, skipped);
+                }
+                Symbol::Placeholder { typ: nt, .. } | Symbol::NonTerminal(nt) => *nt,
+            };
+            if let Some(first) = firsts.get(nonterminal) {
+                atoms.extend(first.iter().copied());
+            }
+            if !nullable.contains(nonterminal) {
+                return (atoms, skipped);
+            }
+        }
+        (atoms, true)
+    }
+
+    /// FIRST sets of the nonterminals, with builtins as their own atoms.
+    fn first_atoms(
+        &self,
+        nullable: &HashSet<&'gr str>,
+    ) -> HashMap<&'gr str, HashSet<FirstAtom<'gr>>> {
+        let mut firsts: HashMap<&'gr str, HashSet<FirstAtom<'gr>>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for prod in &self.productions {
+                let (atoms, _) = Self::sequence_first(prod, 0, nullable, &firsts);
+                let set = firsts.entry(prod.lhs).or_default();
+                let old_len = set.len();
+                set.extend(atoms);
+                changed |= set.len() > old_len;
+            }
+        }
+        firsts
+    }
+
+    /// FOLLOW sets of the nonterminals, what can come right after them in a production.
+    fn follow_atoms(
+        &self,
+        nullable: &HashSet<&'gr str>,
+        firsts: &HashMap<&'gr str, HashSet<FirstAtom<'gr>>>,
+    ) -> HashMap<&'gr str, HashSet<FirstAtom<'gr>>> {
+        let mut follows: HashMap<&'gr str, HashSet<FirstAtom<'gr>>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for prod in &self.productions {
+                for (i, sym) in prod.rhs.iter().enumerate() {
+                    let nonterminal = match sym {
+                        Symbol::NonTerminal(nt) => *nt,
+                        Symbol::Placeholder { typ, .. }
+                            if !typ.starts_with('@')
+                                && !is_builtin_type(typ)
+                                && !is_regex_type(typ)
+                                && !is_exclusion_type(typ) =>
+                        {
+                            *typ
+                        }
+                        _ => continue,
+                    };
+                    let (mut atoms, rest_nullable) =
+                        Self::sequence_first(prod, i + 1, nullable, firsts);
+                    if rest_nullable {
+                        atoms.extend(follows.get(prod.lhs).into_iter().flatten().copied());
+                    }
+                    let set = follows.entry(nonterminal).or_default();
+                    let old_len = set.len();
+                    set.extend(atoms);
+                    changed |= set.len() > old_len;
+                }
+            }
+        }
+        follows
+    }
+}
+
+/// Levenshtein distance between two strings, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            row.push(substitution.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -228,12 +1138,17 @@ impl Item {
     }
 }
 
+/// What a token of the input is: a single character, a number or a string literal.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenKind {
     Char,
     Int,
     Float,
     StringLit,
+    /// The word `true` or `false`, not part of a longer word.
+    Bool,
+    /// Synthetic token ending every input, for `$` anchors.
+    Eof,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -250,12 +1165,26 @@ impl<'inp> Token<'inp> {
         match self.kind {
             TokenKind::Int => Some(Value::Integer(self.text.parse::<i64>().ok()?)),
             TokenKind::Float => Some(Value::Float(self.text.parse::<f64>().ok()?)),
-            TokenKind::StringLit => Some(Value::String(self.text)),
-            TokenKind::Char => None, // structural only
+            TokenKind::StringLit => Some(Value::String(unescape(self.text))),
+            TokenKind::Bool => Some(Value::Bool(self.text == "true")),
+            TokenKind::Char | TokenKind::Eof => None, // structural only
         }
     }
 }
 
+/// The length of the `true` or `false` at `byte_pos`, if it isn't part of a longer word.
+fn bool_len(input: &str, byte_pos: usize) -> Option<usize> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    if input[..byte_pos].ends_with(is_word) {
+        return None;
+    }
+    let rest = &input[byte_pos..];
+    ["true", "false"]
+        .iter()
+        .find(|word| rest.starts_with(*word) && !rest[word.len()..].starts_with(is_word))
+        .map(|word| word.len())
+}
+
 pub fn tokenize(input: &str) -> Vec<Token<'_>> {
     let mut tokens = vec![];
     let mut byte_pos = 0;
@@ -266,31 +1195,40 @@ pub fn tokenize(input: &str) -> Vec<Token<'_>> {
         let char_len = c.len_utf8();
         let start = byte_pos;
 
-        // String literal
+        // String literal, up to the first quote that isn't escaped by a `\`.
+        // Without one, the quote is read as a plain character.
         if c == '"' {
-            byte_pos += char_len;
-            let str_start = byte_pos;
-            while byte_pos < input_len {
-                let ch = input[byte_pos..].chars().next().unwrap();
-                if ch == '"' {
-                    break;
+            let str_start = byte_pos + char_len;
+            let mut escaped = false;
+            let closing = input[str_start..].char_indices().find_map(|(i, ch)| {
+                match ch {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => return Some(str_start + i),
+                    _ => {}
                 }
-                byte_pos += ch.len_utf8();
-            }
-            let str_end = byte_pos;
-            let text = &input[str_start..str_end];
-            tokens.push(Token {
-                kind: TokenKind::StringLit,
-                text,
-                span: Span::new(start, str_end + 1),
+                None
             });
-            byte_pos += 1; // skip closing quote
-            continue;
+            if let Some(str_end) = closing {
+                tokens.push(Token {
+                    kind: TokenKind::StringLit,
+                    text: &input[str_start..str_end],
+                    span: Span::new(start, str_end + 1),
+                });
+                byte_pos = str_end + 1; // skip closing quote
+                continue;
+            }
         }
 
-        // Number parsing (int or float)
-        if c.is_ascii_digit() {
-            let mut end_pos = byte_pos;
+        // Number parsing (int or float), signed when a '-' or '+' can't be an operator
+        let signed = (c == '-' || c == '+')
+            && input[byte_pos + char_len..].starts_with(|ch: char| ch.is_ascii_digit())
+            && input[..byte_pos]
+                .chars()
+                .next_back()
+                .is_none_or(|prev| prev.is_whitespace() || "([{,:;=".contains(prev));
+        if c.is_ascii_digit() || signed {
+            let mut end_pos = byte_pos + if signed { char_len } else { 0 };
             while end_pos < input_len {
                 let ch = input[end_pos..].chars().next().unwrap();
                 if !ch.is_ascii_digit() && ch != '.' {
@@ -327,6 +1265,16 @@ pub fn tokenize(input: &str) -> Vec<Token<'_>> {
             continue;
         }
 
+        if let Some(len) = bool_len(input, byte_pos) {
+            tokens.push(Token {
+                kind: TokenKind::Bool,
+                text: &input[byte_pos..byte_pos + len],
+                span: Span::new(byte_pos, byte_pos + len),
+            });
+            byte_pos += len;
+            continue;
+        }
+
         // Default: single char token
         tokens.push(Token {
             kind: TokenKind::Char,
@@ -336,23 +1284,217 @@ pub fn tokenize(input: &str) -> Vec<Token<'_>> {
         byte_pos += char_len;
     }
 
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        text: "",
+        span: Span::new(input_len, input_len),
+    });
     tokens
 }
 
+/// The builtin placeholder types, matched case-insensitively.
+pub const BUILTIN_TYPES: [&str; 12] = [
+    "Int",
+    "Float",
+    "UInt",
+    "UFloat",
+    "Number",
+    "String",
+    "Str",
+    "Duration",
+    "Percent",
+    "Bool",
+    "SignedInt",
+    "Balanced",
+];
+
+/// Whether `typ` names a builtin type rather than a nonterminal.
+pub fn is_builtin_type(typ: &str) -> bool {
+    BUILTIN_TYPES.iter().any(|b| b.eq_ignore_ascii_case(typ))
+}
+
+/// Whether `typ` is an exclusion like `!then`, matching one token where the input doesn't read `then`.
+pub fn is_exclusion_type(typ: &str) -> bool {
+    typ.len() > 1 && typ.starts_with('!')
+}
+
+/// Whether the tokens from `pos` read `word`, the terminal excluded by a `!word` type.
+fn reads_word(word: &str, tokens: &[Token<'_>], pos: usize) -> bool {
+    let mut read = String::new();
+    for tok in tokens.iter().skip(pos) {
+        if read.len() >= word.len() || tok.kind == TokenKind::Eof {
+            break;
+        }
+        read.push_str(&tokens_text(std::slice::from_ref(tok)));
+    }
+    read.starts_with(word)
+}
+
 pub fn is_builtin(typ: &str, tok: &Token<'_>) -> bool {
     match typ.to_ascii_lowercase().as_str() {
-        "int" => tok.kind == TokenKind::Int,
+        "int" | "signedint" => tok.kind == TokenKind::Int,
         "float" => tok.kind == TokenKind::Float,
+        "uint" => tok.kind == TokenKind::Int && !tok.text.starts_with('-'),
+        "ufloat" => tok.kind == TokenKind::Float && !tok.text.starts_with('-'),
+        // keeps the kind it was written with, `3` or `3.0`
+        "number" => matches!(tok.kind, TokenKind::Int | TokenKind::Float),
         "string" | "str" => tok.kind == TokenKind::StringLit,
+        "bool" => tok.kind == TokenKind::Bool,
         _ => false,
     }
 }
 
+/// Units a `Duration` can have, longest first so `ms` isn't read as `m`.
+pub const DURATION_UNITS: [&str; 3] = ["ms", "s", "m"];
+
+/// How many tokens from `pos` spell `word` one character at a time, if the word ends there.
+fn spelled_len(word: &str, tokens: &[Token<'_>], pos: usize) -> Option<usize> {
+    let rest = tokens.get(pos..)?;
+    let len = word.chars().count();
+    let spelled = rest.len() >= len
+        && rest[..len]
+            .iter()
+            .zip(word.char_indices())
+            .all(|(t, (i, c))| t.kind == TokenKind::Char && t.text == &word[i..i + c.len_utf8()]);
+    // `5min` is not `5m` followed by `in`
+    let ends_word = rest
+        .get(len)
+        .is_none_or(|t| !t.text.starts_with(char::is_alphanumeric));
+    (spelled && ends_word).then_some(len)
+}
+
+/// How many tokens from `pos` a `Balanced` reads: a `(` up to the `)` closing it, with the
+/// parentheses in between balanced too. Those of string literals don't count.
+fn balanced_len(tokens: &[Token<'_>], pos: usize) -> Option<usize> {
+    let is_char = |tok: &Token, c: &str| tok.kind == TokenKind::Char && tok.text == c;
+    if !is_char(tokens.get(pos)?, "(") {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, tok) in tokens[pos..].iter().enumerate() {
+        if is_char(tok, "(") {
+            depth += 1;
+        } else if is_char(tok, ")") {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i + 1);
+            }
+        }
+    }
+    None
+}
+
+/// How many tokens a builtin type matches at `pos`, if it matches.
+/// `Duration` and `Percent` span several tokens : an integer then its unit, or a number then `%`.
+/// So does `Balanced`, from a `(` to the `)` closing it.
+/// `/regex/` types are matched here too, over as many tokens as the match covers,
+/// and `!word` exclusions, over one token.
+pub fn builtin_len(typ: &str, tokens: &[Token<'_>], pos: usize) -> Option<usize> {
+    if is_regex_type(typ) {
+        return regex_len(typ, tokens, pos);
+    }
+    let tok = tokens.get(pos)?;
+    if let Some(word) = typ.strip_prefix('!').filter(|_| is_exclusion_type(typ)) {
+        return (tok.kind != TokenKind::Eof && !reads_word(word, tokens, pos)).then_some(1);
+    }
+    if typ.eq_ignore_ascii_case("percent") {
+        // `50%`, as `50 %` has a whitespace token in between
+        let number = matches!(tok.kind, TokenKind::Int | TokenKind::Float);
+        let sign = tokens.get(pos + 1).is_some_and(|t| t.text == "%");
+        return (number && sign).then_some(2);
+    }
+    if typ.eq_ignore_ascii_case("balanced") {
+        return balanced_len(tokens, pos);
+    }
+    if !typ.eq_ignore_ascii_case("duration") {
+        return is_builtin(typ, tok).then_some(1);
+    }
+    if tok.kind != TokenKind::Int {
+        return None;
+    }
+    DURATION_UNITS
+        .iter()
+        .find_map(|unit| spelled_len(unit, tokens, pos + 1).map(|len| 1 + len))
+}
+
+/// The end of the tokens `builtin_len` may read to match `typ` at `pos`.
+fn lookahead(typ: &str, pos: usize) -> usize {
+    // up to the closing parenthesis, wherever it is
+    if is_regex_type(typ) || typ.eq_ignore_ascii_case("balanced") {
+        return usize::MAX;
+    }
+    match typ.strip_prefix('!').filter(|_| is_exclusion_type(typ)) {
+        Some(word) => pos + word.len().max(1),
+        None if typ.eq_ignore_ascii_case("percent") => pos + 2,
+        // The integer, the longest unit, and the token telling the unit ends there
+        None if typ.eq_ignore_ascii_case("duration") => pos + 4,
+        None => pos + 1,
+    }
+}
+
 pub struct Chart<'gr, 'inp> {
     pub sets: Vec<HashMap<ItemKey, Item>>,
     pub tokens: Vec<Token<'inp>>,
     pub grammar: &'gr Grammar<'gr>,
     pub start: &'inp str,
+    /// Grammars that `{name:@Other}` placeholders delegate to
+    pub sub_grammars: Option<&'gr SubGrammars<'gr>>,
+    /// For each set, the end of the tokens read while completing it, see `Chart::edit`
+    pub reach: Vec<usize>,
+    /// Readers of custom placeholder types, see `Dokearley::register_scanner`
+    pub scanners: Option<&'gr Scanners>,
+    /// How many items were added, to give up past `max_steps`
+    pub steps: usize,
+    /// Recognition stops once this many items were added, see `Dokearley::parse_bounded`
+    pub max_steps: Option<usize>,
+    /// Only start the productions whose first terminal matches the first token
+    pub keyword_index: Option<&'gr KeywordIndex<'gr>>,
+    /// Terminals match ignoring case and accents, see `Options::fold_terminals`
+    #[cfg(feature = "unicode-normalization")]
+    pub fold_terminals: bool,
+}
+
+/// `text` in ASCII lowercase without its diacritics, `É` reading as `e`.
+#[cfg(feature = "unicode-normalization")]
+fn fold_text(text: &str) -> String {
+    use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// How many tokens from the start of `tokens` have `len` bytes of text,
+/// if that is more than nothing and ends on a token boundary.
+pub fn token_count(tokens: &[Token<'_>], len: usize) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    let mut read = 0;
+    for (count, tok) in tokens.iter().enumerate() {
+        if tok.kind == TokenKind::Eof {
+            break;
+        }
+        read += tokens_text(std::slice::from_ref(tok)).len();
+        if read == len {
+            return Some(count + 1);
+        }
+        if read > len {
+            break;
+        }
+    }
+    None
+}
+
+/// The text of some tokens, with string literals quoted again.
+pub fn tokens_text(tokens: &[Token<'_>]) -> String {
+    tokens
+        .iter()
+        .map(|tok| match tok.kind {
+            TokenKind::StringLit => format!("\"{}\"", tok.text),
+            _ => tok.text.to_string(),
+        })
+        .collect()
 }
 
 impl<'gr, 'inp> Chart<'gr, 'inp> {
@@ -365,8 +1507,8 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
             let sym = &prod.rhs[dot];
             let is_nullable = match sym {
                 Symbol::NonTerminal(nt) => nullable.contains(nt),
-                Symbol::Placeholder { name: _, typ } => nullable.contains(typ),
-                Symbol::Terminal(_) => false,
+                Symbol::Placeholder { typ, .. } => nullable.contains(typ),
+                Symbol::Terminal(_) | Symbol::End => false,
             };
 
             if !is_nullable {
@@ -399,129 +1541,309 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
             tokens,
             grammar,
             start,
+            sub_grammars: None,
+            reach: vec![0; n + 1],
+            scanners: None,
+            steps: 0,
+            max_steps: None,
+            keyword_index: None,
+            #[cfg(feature = "unicode-normalization")]
+            fold_terminals: false,
+        }
+    }
+
+    /// Whether the terminal `lit` of `prod` matches the token text `tok`, see
+    /// `Production::scan_terminal`, folding both first with `fold_terminals`.
+    pub fn scan_terminal(&self, prod: &Production<'_>, lit: &str, tok: &str) -> Option<bool> {
+        #[cfg(feature = "unicode-normalization")]
+        if self.fold_terminals {
+            return prod.scan_terminal(&fold_text(lit), &fold_text(tok));
         }
+        prod.scan_terminal(lit, tok)
     }
 
     pub fn add_item(&mut self, pos: usize, item: Item) -> bool {
-        let key = item.key.clone();
-        if self.sets[pos].contains_key(&key) {
-            false
-        } else {
-            self.sets[pos].insert(key, item);
-            true
+        match self.sets[pos].entry(item.key.clone()) {
+            std::collections::hash_map::Entry::Occupied(_) => false,
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(item);
+                self.steps += 1;
+                true
+            }
         }
     }
 
+    /// Recognizes `start`, computing the nullable set on the fly: the parser keeps
+    /// a cached one for `recognize_with_nullable`, so only tests use this.
+    #[cfg(test)]
     pub fn recognize(&mut self, start: &str) {
         // Precompute nullable nonterminals
         let nullable = self.grammar.compute_nullable();
+        self.recognize_with_nullable(start, &nullable);
+    }
 
+    /// Same as `recognize`, with a nullable set computed beforehand.
+    pub fn recognize_with_nullable(&mut self, start: &str, nullable: &HashSet<&'gr str>) {
         // Initialize chart with start productions
-        for (pid, _) in self.grammar.prods_for(start) {
+        let seeds = match (self.keyword_index, self.tokens.first()) {
+            (Some(index), Some(first)) => index.seeds(self.grammar, start, first.text),
+            _ => self
+                .grammar
+                .prods_for(start)
+                .into_iter()
+                .map(|(pid, _)| pid)
+                .collect(),
+        };
+        for pid in seeds {
             let it = Item::new(pid, 0, 0);
             self.add_item(0, it.clone());
             // Advance dot for nullable prefixes
-            self.add_nullable_items(it, 0, &nullable);
+            self.add_nullable_items(it, 0, nullable);
         }
 
-        let n = self.tokens.len();
-        for pos in 0..=n {
-            let mut changed = true;
-            while changed {
-                changed = false;
-                let keys: Vec<ItemKey> = self.sets[pos].keys().cloned().collect();
-
-                for key in keys {
-                    let item = match self.sets[pos].get(&key) {
-                        Some(it) => it.clone(),
-                        None => continue,
-                    };
+        for pos in 0..=self.tokens.len() {
+            if self.exceeded_steps() {
+                return;
+            }
+            self.complete_set(pos, nullable);
+        }
+    }
 
-                    let prod = &self.grammar.productions[item.key.prod_id];
+    /// Whether recognition gave up, having added more than `max_steps` items.
+    pub fn exceeded_steps(&self) -> bool {
+        self.max_steps.is_some_and(|max| self.steps > max)
+    }
 
-                    if item.key.dot < prod.rhs.len() {
-                        let next = &prod.rhs[item.key.dot];
-                        match next {
-                            Symbol::NonTerminal(nt) => {
-                                for (pid, _) in self.grammar.prods_for(nt) {
-                                    let new_it = Item::new(pid, 0, pos);
-                                    if self.add_item(pos, new_it.clone()) {
-                                        changed = true;
-                                        self.add_nullable_items(new_it, pos, &nullable);
-                                    }
+    /// Adds the items of the set at `pos`, and the items they scan into later sets.
+    fn complete_set(&mut self, pos: usize, nullable: &HashSet<&'gr str>) {
+        self.reach[pos] = self.reach[pos].max(pos + 1);
+        let mut changed = true;
+        while changed && !self.exceeded_steps() {
+            changed = false;
+            let keys: Vec<ItemKey> = self.sets[pos].keys().cloned().collect();
+
+            for key in keys {
+                let item = match self.sets[pos].get(&key) {
+                    Some(it) => it.clone(),
+                    None => continue,
+                };
+
+                let prod = &self.grammar.productions[item.key.prod_id];
+
+                if item.key.dot < prod.rhs.len() {
+                    // an optional placeholder may read nothing
+                    if let Some(dot) = prod.skip_absent(item.key.dot) {
+                        let new_it = Item::new(item.key.prod_id, dot, item.key.start);
+                        if self.add_item(pos, new_it) {
+                            changed = true;
+                        }
+                    }
+                    let next = &prod.rhs[item.key.dot];
+                    match next {
+                        Symbol::NonTerminal(nt) => {
+                            for (pid, _) in self.grammar.prods_for(nt) {
+                                let new_it = Item::new(pid, 0, pos);
+                                if self.add_item(pos, new_it.clone()) {
+                                    changed = true;
+                                    self.add_nullable_items(new_it, pos, nullable);
                                 }
                             }
-                            Symbol::Terminal(lit) => {
-                                if pos < self.tokens.len() && self.tokens[pos].text == *lit {
-                                    let new_it = Item::new(
-                                        item.key.prod_id,
-                                        item.key.dot + 1,
-                                        item.key.start,
-                                    );
-                                    if self.add_item(pos + 1, new_it) {
-                                        changed = true;
-                                    }
+                        }
+                        Symbol::Terminal(lit) => {
+                            let scanned = self
+                                .tokens
+                                .get(pos)
+                                .and_then(|tok| self.scan_terminal(prod, lit, tok.text));
+                            if let Some(repeats) = scanned {
+                                let new_it =
+                                    Item::new(item.key.prod_id, item.key.dot + 1, item.key.start);
+                                if self.add_item(pos + 1, new_it) {
+                                    changed = true;
+                                }
+                                // Collapsed whitespace: stay before the terminal to eat more of it
+                                if repeats && self.add_item(pos + 1, item.clone()) {
+                                    changed = true;
                                 }
                             }
-                            Symbol::Placeholder { name: _, typ } => {
-                                if pos < self.tokens.len() && is_builtin(typ, &self.tokens[pos]) {
-                                    let new_it = Item::new(
-                                        item.key.prod_id,
-                                        item.key.dot + 1,
-                                        item.key.start,
-                                    );
-                                    if self.add_item(pos + 1, new_it) {
+                        }
+                        Symbol::End => {
+                            // Zero-width: the dot moves on but stays in this set
+                            let at_end = self
+                                .tokens
+                                .get(pos)
+                                .is_some_and(|tok| tok.kind == TokenKind::Eof);
+                            let new_it =
+                                Item::new(item.key.prod_id, item.key.dot + 1, item.key.start);
+                            if at_end && self.add_item(pos, new_it) {
+                                changed = true;
+                            }
+                        }
+                        Symbol::Placeholder { typ, .. } if typ.starts_with('@') => {
+                            self.reach[pos] = self.tokens.len();
+                            for end in self.sub_grammar_ends(&typ[1..], pos) {
+                                let new_it =
+                                    Item::new(item.key.prod_id, item.key.dot + 1, item.key.start);
+                                if self.add_item(end, new_it) {
+                                    changed = true;
+                                }
+                            }
+                        }
+                        Symbol::Placeholder { typ, .. } => {
+                            let scanned = self.scan(typ, pos).map(|(len, _)| len);
+                            let lookahead = match self.scanners {
+                                Some(scanners) if scanners.contains(typ) => self.tokens.len(),
+                                _ => lookahead(typ, pos),
+                            };
+                            self.reach[pos] = self.reach[pos].max(lookahead);
+                            let len = scanned.or_else(|| builtin_len(typ, &self.tokens, pos));
+                            if let Some(len) = len {
+                                let new_it =
+                                    Item::new(item.key.prod_id, item.key.dot + 1, item.key.start);
+                                if self.add_item(pos + len, new_it) {
+                                    changed = true;
+                                }
+                            } else {
+                                for (pid, _) in self.grammar.prods_for(typ) {
+                                    let new_it = Item::new(pid, 0, pos);
+                                    if self.add_item(pos, new_it.clone()) {
                                         changed = true;
-                                    }
-                                } else {
-                                    for (pid, _) in self.grammar.prods_for(typ) {
-                                        let new_it = Item::new(pid, 0, pos);
-                                        if self.add_item(pos, new_it.clone()) {
-                                            changed = true;
-                                            self.add_nullable_items(new_it, pos, &nullable);
-                                        }
+                                        self.add_nullable_items(new_it, pos, nullable);
                                     }
                                 }
                             }
                         }
-                    } else {
-                        // Completion
-                        let lhs = prod.lhs;
-                        let waiting_keys: Vec<ItemKey> = self.sets[item.key.start]
-                            .keys()
-                            .filter(|k| {
-                                let p = &self.grammar.productions[k.prod_id];
-                                if k.dot < p.rhs.len() {
-                                    match &p.rhs[k.dot] {
-                                        Symbol::NonTerminal(name) => name == &lhs,
-                                        Symbol::Placeholder { name: _, typ } => **typ == *lhs,
-                                        _ => false,
-                                    }
-                                } else {
-                                    false
+                    }
+                } else {
+                    // Completion
+                    let lhs = prod.lhs;
+                    let waiting_keys: Vec<ItemKey> = self.sets[item.key.start]
+                        .keys()
+                        .filter(|k| {
+                            let p = &self.grammar.productions[k.prod_id];
+                            if k.dot < p.rhs.len() {
+                                match &p.rhs[k.dot] {
+                                    Symbol::NonTerminal(name) => name == &lhs,
+                                    Symbol::Placeholder { typ, .. } => **typ == *lhs,
+                                    _ => false,
                                 }
-                            })
-                            .cloned()
-                            .collect();
-
-                        for wk in waiting_keys {
-                            let new_it = Item::new(wk.prod_id, wk.dot + 1, wk.start);
-                            if self.add_item(pos, new_it) {
-                                changed = true;
+                            } else {
+                                false
                             }
+                        })
+                        .cloned()
+                        .collect();
+
+                    for wk in waiting_keys {
+                        let new_it = Item::new(wk.prod_id, wk.dot + 1, wk.start);
+                        if self.add_item(pos, new_it) {
+                            changed = true;
                         }
                     }
                 }
             }
         }
     }
+
+    /// Recognizes `edited` again, the input after replacing `byte_range` of the previous one.
+    /// The sets that only read tokens before the edit are kept, the others are recomputed.
+    ///
+    /// `edited` is the whole new input rather than the text replacing `byte_range`: the
+    /// tokens borrow their input, which the chart doesn't own. `byte_range` only bounds the
+    /// tokens kept, they must also be the same in `edited`. `nullable` is the nullable set
+    /// of the grammar, like for `recognize_with_nullable`.
+    pub fn edit(
+        &mut self,
+        byte_range: Range<usize>,
+        edited: &'inp str,
+        nullable: &HashSet<&'gr str>,
+    ) {
+        let tokens = tokenize(edited);
+        // Tokens before the edit can still be read differently, like an opening quote
+        let kept = self
+            .tokens
+            .iter()
+            .zip(&tokens)
+            .take_while(|(old, new)| old.span.end < byte_range.start && old == new)
+            .count();
+        let restart = self
+            .reach
+            .iter()
+            .position(|&reach| reach > kept)
+            .unwrap_or(kept);
+        let n = tokens.len();
+        self.tokens = tokens;
+        self.sets.truncate(restart);
+        self.sets.resize_with(n + 1, HashMap::new);
+        self.reach.truncate(restart);
+        self.reach.resize(n + 1, 0);
+        if restart == 0 {
+            return self.recognize_with_nullable(self.start, nullable);
+        }
+        // Kept sets can scan into the recomputed ones
+        for pos in 0..restart {
+            if self.reach[pos] >= restart {
+                self.complete_set(pos, nullable);
+            }
+        }
+        for pos in restart..=n {
+            self.complete_set(pos, nullable);
+        }
+    }
+
+    /// What the scanner registered for `typ` reads at `pos`: how many tokens, and their value.
+    pub fn scan(&self, typ: &str, pos: usize) -> Option<(usize, crate::Value)> {
+        self.scanners?.scan(typ, &self.tokens, pos)
+    }
+
+    /// Position of the end of the input, before the `Eof` token if there is one.
+    pub fn end(&self) -> usize {
+        match self.tokens.last() {
+            Some(tok) if tok.kind == TokenKind::Eof => self.tokens.len() - 1,
+            _ => self.tokens.len(),
+        }
+    }
+
     /// After recognizing, checks wether the start symbol accepts the input.
     pub fn accepted(&self, start: &str) -> bool {
-        let n = self.tokens.len();
-        self.sets[n].values().any(|it| {
-            it.key.start == 0
-                && it.key.dot == self.grammar.productions[it.key.prod_id].rhs.len()
-                && self.grammar.productions[it.key.prod_id].lhs == start
+        !self.accepting_items(start).is_empty()
+    }
+
+    /// The `start` productions that matched the whole input, by production id,
+    /// to see why an ambiguous input is accepted by several rules.
+    pub fn accepting_items(&self, start: &str) -> Vec<(usize, &'gr Production<'gr>)> {
+        let mut items: Vec<_> = self.sets[self.end()]
+            .values()
+            .filter_map(|it| {
+                let prod = &self.grammar.productions[it.key.prod_id];
+                let complete = it.key.start == 0 && it.key.dot == prod.rhs.len();
+                (complete && prod.lhs == start).then_some((it.key.prod_id, prod))
+            })
+            .collect();
+        items.sort_by_key(|(prod_id, _)| *prod_id);
+        items
+    }
+
+    /// The earliest `(pos, start, lhs)` where two productions of `lhs` both matched
+    /// the tokens from `start` to `pos`, to find where a grammar is ambiguous without
+    /// enumerating the parse trees. Ambiguities within a single production aren't seen.
+    pub fn first_ambiguity(&self) -> Option<(usize, usize, &'gr str)> {
+        self.sets.iter().enumerate().find_map(|(pos, set)| {
+            let mut completed: Vec<(usize, &'gr str, usize)> = set
+                .values()
+                .filter_map(|it| {
+                    let prod = &self.grammar.productions[it.key.prod_id];
+                    (it.key.dot == prod.rhs.len()).then_some((
+                        it.key.start,
+                        prod.lhs,
+                        it.key.prod_id,
+                    ))
+                })
+                .collect();
+            completed.sort();
+            completed
+                .windows(2)
+                .find(|pair| (pair[0].0, pair[0].1) == (pair[1].0, pair[1].1))
+                .map(|pair| (pos, pair[0].0, pair[0].1))
         })
     }
 }
@@ -530,9 +1852,32 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
     #[allow(dead_code)]
     /// Pretty printing for debugging earley items.
     pub fn print_chart(&self) {
+        print!("{}", self.render_chart());
+    }
+
+    /// The input, as rebuilt from the tokens (string literals are quoted again).
+    pub fn input_text(&self) -> String {
+        tokens_text(&self.tokens)
+    }
+
+    /// Where a `{name:@Other}` placeholder starting at `pos` can end :
+    /// the positions up to which the `Other` sub-grammar accepts the tokens.
+    pub fn sub_grammar_ends(&self, name: &str, pos: usize) -> Vec<usize> {
+        let Some(sub) = self.sub_grammars.and_then(|subs| subs.get(name)) else {
+            return Vec::new();
+        };
+        (pos + 1..=self.end())
+            .filter(|&end| sub.accepts(&tokens_text(&self.tokens[pos..end]), name))
+            .collect()
+    }
+
+    /// Renders the earley items of each set, for debugging.
+    pub fn render_chart(&self) -> String {
+        use std::fmt::Write;
+        let mut out = String::new();
         // For each Earley set
         for (i, set) in self.sets.iter().enumerate() {
-            println!("\n=== {} ===", i);
+            let _ = writeln!(out, "\n=== {} ===", i);
 
             // If the set is empty, skip
             if set.is_empty() {
@@ -543,7 +1888,7 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
             let mut lines = Vec::new();
             let mut lhs_width = 0;
 
-            for (key, _item) in set {
+            for key in set.keys() {
                 let prod = &self.grammar.productions[key.prod_id];
                 let lhs = prod.lhs;
                 lhs_width = lhs_width.max(lhs.len());
@@ -572,9 +1917,10 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
             }
 
             for l in lines {
-                println!("{}", l);
+                let _ = writeln!(out, "{}", l);
             }
         }
+        out
     }
 }
 
@@ -599,35 +1945,43 @@ mod recognizer_tests {
                         Symbol::NonTerminal("Expr"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Expr",
                     rhs: vec![Symbol::NonTerminal("Term")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Term",
                     rhs: vec![Symbol::Placeholder {
                         name: "n",
                         typ: "Int",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Term",
                     rhs: vec![Symbol::Placeholder {
                         name: "x",
                         typ: "Float",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Term",
                     rhs: vec![Symbol::Placeholder {
                         name: "s",
                         typ: "String",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         }
@@ -683,6 +2037,72 @@ mod recognizer_tests {
         assert!(!chart.accepted("Expr"));
     }
 
+    #[test]
+    fn tokenize_negative_numbers() {
+        let kinds = |input| {
+            tokenize(input)
+                .into_iter()
+                .map(|t| (t.kind, t.text))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            kinds("-3 (-2.5"),
+            vec![
+                (TokenKind::Int, "-3"),
+                (TokenKind::Char, " "),
+                (TokenKind::Char, "("),
+                (TokenKind::Float, "-2.5"),
+                (TokenKind::Eof, ""),
+            ]
+        );
+        // A '-' right after a number or word is an operator
+        assert_eq!(
+            kinds("4-5"),
+            vec![
+                (TokenKind::Int, "4"),
+                (TokenKind::Char, "-"),
+                (TokenKind::Int, "5"),
+                (TokenKind::Eof, ""),
+            ]
+        );
+        assert_eq!(kinds("a-1")[1], (TokenKind::Char, "-"));
+    }
+
+    #[test]
+    fn tokenize_positive_signed_numbers() {
+        let kinds = |input| {
+            tokenize(input)
+                .into_iter()
+                .map(|t| (t.kind, t.text))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds("+5")[0], (TokenKind::Int, "+5"));
+        assert_eq!(kinds("(+2.5")[1], (TokenKind::Float, "+2.5"));
+        // A '+' right after a number or word is an operator
+        assert_eq!(kinds("4+5")[1], (TokenKind::Char, "+"));
+        assert_eq!(kinds("a+1")[1], (TokenKind::Char, "+"));
+    }
+
+    #[test]
+    fn tokenize_bool_words() {
+        let kinds = |input| {
+            tokenize(input)
+                .into_iter()
+                .map(|t| (t.kind, t.text))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds("(true)")[1], (TokenKind::Bool, "true"));
+        assert_eq!(kinds("false")[0], (TokenKind::Bool, "false"));
+        assert!(matches!(
+            tokenize("false")[0].get_value(),
+            Some(Value::Bool(false))
+        ));
+        // Within a longer word, they are plain characters
+        assert_eq!(kinds("truest")[0], (TokenKind::Char, "t"));
+        assert_eq!(kinds("untrue")[2], (TokenKind::Char, "t"));
+        assert_eq!(kinds("true_")[0], (TokenKind::Char, "t"));
+    }
+
     #[test]
     fn placeholder_bound_to_nonterminal() {
         let grammar = Grammar {
@@ -691,19 +2111,23 @@ mod recognizer_tests {
                     lhs: "S",
                     rhs: vec![Symbol::NonTerminal("A")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "A",
                     rhs: vec![Symbol::Placeholder {
                         name: "x",
                         typ: "B",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![Symbol::Terminal("x")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -723,16 +2147,19 @@ mod recognizer_tests {
                     lhs: "Start",
                     rhs: vec![Symbol::NonTerminal("A")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "A",
                     rhs: vec![Symbol::Terminal("a"), Symbol::NonTerminal("B")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![Symbol::Terminal("b")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -752,11 +2179,13 @@ mod recognizer_tests {
                     lhs: "X",
                     rhs: vec![Symbol::Terminal("x")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "X",
                     rhs: vec![Symbol::Terminal("y")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -773,6 +2202,91 @@ mod recognizer_tests {
         chart_y.print_chart();
         assert!(chart_y.accepted("X"));
     }
+
+    #[test]
+    fn ambiguous_input_has_two_accepting_items() {
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "S",
+                    rhs: vec![Symbol::Terminal("x")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "S",
+                    rhs: vec![Symbol::Terminal("y")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "S",
+                    rhs: vec![Symbol::NonTerminal("A")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![Symbol::Terminal("x")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+
+        let mut chart = Chart::new(&grammar, tokenize("x"), "S");
+        chart.recognize("S");
+        let ids: Vec<usize> = chart
+            .accepting_items("S")
+            .iter()
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+
+    #[test]
+    fn first_ambiguity_is_the_earliest_span_with_two_productions() {
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "S",
+                    rhs: vec![
+                        Symbol::Terminal("a"),
+                        Symbol::NonTerminal("T"),
+                        Symbol::Terminal("b"),
+                    ],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "T",
+                    rhs: vec![Symbol::Terminal("x")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "T",
+                    rhs: vec![Symbol::NonTerminal("A")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![Symbol::Terminal("x")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+
+        let mut chart = Chart::new(&grammar, tokenize("axb"), "S");
+        chart.recognize("S");
+        assert_eq!(chart.first_ambiguity(), Some((2, 1, "T")));
+
+        let mut chart = Chart::new(&grammar, tokenize("ab"), "S");
+        chart.recognize("S");
+        assert_eq!(chart.first_ambiguity(), None);
+    }
 }
 
 #[cfg(test)]
@@ -790,6 +2304,7 @@ mod nullable_tests {
                 lhs: "S",
                 rhs: vec![],
                 out: dummy_outspec(),
+                ws: WhitespaceMode::Exact,
             }],
         };
 
@@ -800,6 +2315,28 @@ mod nullable_tests {
         assert!(chart.accepted("S"));
     }
 
+    #[test]
+    fn only_empty_production_is_nullable() {
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "A",
+                    rhs: vec![],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![Symbol::Terminal("x")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+
+        assert_eq!(grammar.nullable_productions(), vec![0]);
+    }
+
     #[test]
     fn nullable_nonterminal_in_sequence() {
         let grammar = Grammar {
@@ -808,16 +2345,19 @@ mod nullable_tests {
                     lhs: "S",
                     rhs: vec![Symbol::NonTerminal("A"), Symbol::NonTerminal("B")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "A",
                     rhs: vec![],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![Symbol::Terminal("x")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -841,21 +2381,25 @@ mod nullable_tests {
                         Symbol::NonTerminal("C"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "A",
                     rhs: vec![],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "C",
                     rhs: vec![Symbol::Terminal("y")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -877,15 +2421,18 @@ mod nullable_tests {
                         Symbol::Placeholder {
                             name: "x",
                             typ: "X",
+                            optional: false,
                         },
                         Symbol::Terminal("b"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "X",
                     rhs: vec![],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -909,16 +2456,19 @@ mod nullable_tests {
                         Symbol::Terminal("c"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "B",
                     rhs: vec![Symbol::Terminal("b")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         };
@@ -936,6 +2486,62 @@ mod nullable_tests {
         chart2.print_chart();
         assert!(chart2.accepted("S"));
     }
+
+    #[test]
+    fn nullable_completed_at_several_starts() {
+        // `A` completes at the same position from several starts: on `a`, the first `A` can be
+        // empty and the second read `a`, or the other way around.
+        let grammar = Grammar {
+            productions: vec![
+                Production {
+                    lhs: "S",
+                    rhs: vec![Symbol::NonTerminal("A"), Symbol::NonTerminal("A")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![Symbol::Terminal("a")],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+                Production {
+                    lhs: "A",
+                    rhs: vec![],
+                    out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
+                },
+            ],
+        };
+
+        for (input, accepted) in [("a", true), ("", true), ("aa", true), ("aaa", false)] {
+            let mut chart = Chart::new(&grammar, tokenize(input), "S");
+            chart.recognize("S");
+            assert_eq!(chart.accepted("S"), accepted, "{input:?}");
+            if accepted {
+                assert!(chart.build_parse_tree().is_ok(), "{input:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_terminal_never_matches() {
+        let grammar = Grammar {
+            productions: vec![Production {
+                lhs: "S",
+                rhs: vec![Symbol::Terminal("a"), Symbol::Terminal("")],
+                out: dummy_outspec(),
+                ws: WhitespaceMode::Exact,
+            }],
+        };
+        assert!(grammar.find_empty_terminal().is_some());
+        // Not even the end of the input, whose token text is empty too
+        for input in ["a", "", "a "] {
+            let mut chart = Chart::new(&grammar, tokenize(input), "S");
+            chart.recognize("S");
+            assert!(!chart.accepted("S"), "{input:?}");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -969,6 +2575,7 @@ mod complex_expr_tests {
                         Symbol::NonTerminal("Term"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Expr",
@@ -978,11 +2585,13 @@ mod complex_expr_tests {
                         Symbol::NonTerminal("Term"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Expr",
                     rhs: vec![Symbol::NonTerminal("Term")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 // Term
                 Production {
@@ -993,6 +2602,7 @@ mod complex_expr_tests {
                         Symbol::NonTerminal("Factor"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Term",
@@ -1002,17 +2612,20 @@ mod complex_expr_tests {
                         Symbol::NonTerminal("Factor"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Term",
                     rhs: vec![Symbol::NonTerminal("Factor")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 // Factor
                 Production {
                     lhs: "Factor",
                     rhs: vec![Symbol::NonTerminal("Number")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Factor",
@@ -1022,6 +2635,7 @@ mod complex_expr_tests {
                         Symbol::Terminal(")"),
                     ],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 // Number
                 Production {
@@ -1029,16 +2643,20 @@ mod complex_expr_tests {
                     rhs: vec![Symbol::Placeholder {
                         name: "n",
                         typ: "Int",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Number",
                     rhs: vec![Symbol::Placeholder {
                         name: "x",
                         typ: "Float",
+                        optional: false,
                     }],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         }
@@ -1074,3 +2692,63 @@ mod complex_expr_tests {
         assert!(chart.accepted("Expr"));
     }
 }
+
+#[cfg(test)]
+mod edit_tests {
+    use super::*;
+    use crate::{Dokearley, Options};
+
+    fn items(chart: &Chart<'_, '_>) -> Vec<HashSet<ItemKey>> {
+        chart
+            .sets
+            .iter()
+            .map(|set| set.keys().cloned().collect())
+            .collect()
+    }
+
+    #[test]
+    fn edit_matches_full_reparse() {
+        let dokedef = r#"
+Effect: "deal {x:Int} damage" -> Damage
+Effect: "wait {d:Duration}" -> Wait
+Effect: "say {s:String}" -> Say
+Effect: "{a:Effect} then {b:Effect}" -> Then
+"#;
+        let grammar = Dokearley::parse_dokedef(dokedef, &Options::default()).unwrap();
+        // (before, byte range replaced, after)
+        let edits = [
+            (
+                "deal 1 damage then wait 5s",
+                5..6,
+                "deal 12 damage then wait 5s",
+            ),
+            (
+                "deal 1 damage then wait 5s",
+                25..25,
+                "deal 1 damage then wait 5ms",
+            ),
+            (
+                "deal 1 damage then wait 5s",
+                19..26,
+                "deal 1 damage then say \"hi\"",
+            ),
+            ("deal 1 damage", 0..0, "wait 3m then deal 1 damage"),
+            (
+                "say hi then deal 1 damage",
+                4..4,
+                "say \"hi then deal 1 damage",
+            ),
+        ];
+        for (before, range, after) in edits {
+            let mut chart = Chart::new(&grammar, tokenize(before), "Effect");
+            chart.recognize("Effect");
+            chart.edit(range, after, &grammar.compute_nullable());
+
+            let mut full = Chart::new(&grammar, tokenize(after), "Effect");
+            full.recognize("Effect");
+            assert_eq!(chart.tokens, full.tokens, "{after}");
+            assert_eq!(items(&chart), items(&full), "{after}");
+            assert_eq!(chart.accepted("Effect"), full.accepted("Effect"), "{after}");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! `{name:/regex/}` placeholders, matching a run of the input with a regular expression.
+//!
+//! A regex placeholder reads the input text from its position, not tokens: the match
+//! is a contiguous byte range. It must still end where a token ends, so `/[A-Z]+-\d/`
+//! won't match the start of `ABC-42`, since `42` is a single number token.
+use crate::recognizer::Token;
+
+/// Whether a placeholder type is a `/regex/`.
+pub fn is_regex_type(typ: &str) -> bool {
+    typ.len() >= 2 && typ.starts_with('/') && typ.ends_with('/')
+}
+
+/// The regular expression of a `/regex/` type, with `\/` unescaped.
+#[cfg(feature = "regex")]
+fn pattern(typ: &str) -> String {
+    typ[1..typ.len() - 1].replace("\\/", "/")
+}
+
+/// Checks that a `/regex/` type can be used, when building the grammar.
+#[cfg(feature = "regex")]
+pub fn check_regex_type(typ: &str) -> Result<(), String> {
+    regex::Regex::new(&pattern(typ))
+        .map(|_| ())
+        .map_err(|e| format!("invalid regex placeholder {} : {}", typ, e))
+}
+
+#[cfg(not(feature = "regex"))]
+pub fn check_regex_type(typ: &str) -> Result<(), String> {
+    Err(format!(
+        "the regex placeholder {} needs the `regex` feature",
+        typ
+    ))
+}
+
+/// How many tokens the `/regex/` type matches at `pos`, if it matches.
+#[cfg(feature = "regex")]
+pub fn regex_len(typ: &str, tokens: &[Token<'_>], pos: usize) -> Option<usize> {
+    use crate::recognizer::{token_count, tokens_text};
+    use regex::Regex;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, OnceLock};
+
+    // Patterns are compiled once, the recognizer tries them at many positions
+    static REGEXES: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let rest = tokens.get(pos..)?;
+    let text = tokens_text(rest);
+    let len = {
+        let mut regexes = REGEXES
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if !regexes.contains_key(typ) {
+            let regex = Regex::new(&format!("^(?:{})", pattern(typ))).ok()?;
+            regexes.insert(typ.to_string(), regex);
+        }
+        regexes[typ].find(&text)?.end()
+    };
+    token_count(rest, len)
+}
+
+#[cfg(not(feature = "regex"))]
+pub fn regex_len(_typ: &str, _tokens: &[Token<'_>], _pos: usize) -> Option<usize> {
+    None
+}
@@ -0,0 +1,39 @@
+//! Custom placeholder types, read by callbacks registered with `Dokearley::register_scanner`.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::recognizer::{token_count, tokens_text, Token};
+use crate::Value;
+
+/// Reads a custom type at the start of its text: how many bytes it matched, with their value.
+pub(crate) type Scanner = Arc<dyn Fn(&str) -> Option<(usize, Value)> + Send + Sync>;
+
+/// Scanners registered with `Dokearley::register_scanner`, by type name.
+#[derive(Clone, Default)]
+pub(crate) struct Scanners(HashMap<String, Scanner>);
+
+impl fmt::Debug for Scanners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl Scanners {
+    pub fn insert(&mut self, typ: &str, scanner: Scanner) {
+        self.0.insert(typ.to_string(), scanner);
+    }
+
+    pub fn contains(&self, typ: &str) -> bool {
+        self.0.contains_key(typ)
+    }
+
+    /// How many tokens from `pos` the scanner of `typ` reads, with their value.
+    /// It must read something, and stop on a token boundary.
+    pub fn scan(&self, typ: &str, tokens: &[Token<'_>], pos: usize) -> Option<(usize, Value)> {
+        let scanner = self.0.get(typ)?;
+        let rest = tokens.get(pos..)?;
+        let (len, value) = scanner(&tokens_text(rest))?;
+        Some((token_count(rest, len)?, value))
+    }
+}
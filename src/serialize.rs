@@ -0,0 +1,162 @@
+//! `serde` serialization of parsed values, with a choice of shape for resources.
+use indexmap::IndexMap;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::Value;
+
+/// The key holding the type of a resource.
+const TYPE_TAG: &str = "$type";
+
+/// How a `Value::Resource` is serialized, to match the tagged enums of the consumer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResourceTagging {
+    /// `{"$type": "Heal", "amount": 7}`, like `#[serde(tag = "$type")]`.
+    /// The `$` keeps the tag apart from a field named `type`, as in `Value::to_json_string`.
+    #[default]
+    Internal,
+    /// `{"$type": "Heal", "fields": {"amount": 7}}`, like `#[serde(tag = "$type", content = "fields")]`.
+    Adjacent,
+}
+
+/// A value serialized with a given `ResourceTagging`, see `Value::tagged`.
+#[derive(Debug, Clone, Copy)]
+pub struct TaggedValue<'v> {
+    value: &'v Value,
+    tagging: ResourceTagging,
+}
+
+impl Value {
+    /// This value, serialized with its resources (nested ones too) shaped by `tagging`.
+    pub fn tagged(&self, tagging: ResourceTagging) -> TaggedValue<'_> {
+        TaggedValue {
+            value: self,
+            tagging,
+        }
+    }
+}
+
+impl Serialize for TaggedValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Integer(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Null => serializer.serialize_unit(),
+            Value::String(s) | Value::Child(s) | Value::Children(s) => serializer.serialize_str(s),
+            Value::Array(values) => {
+                serializer.collect_seq(values.iter().map(|v| v.tagged(self.tagging)))
+            }
+            Value::Dictionary(fields) => Fields(fields, self.tagging).serialize(serializer),
+            Value::Resource { typ, fields } => match self.tagging {
+                ResourceTagging::Internal => {
+                    let mut map = serializer.serialize_map(Some(fields.len() + 1))?;
+                    map.serialize_entry(TYPE_TAG, typ)?;
+                    for (k, v) in fields {
+                        map.serialize_entry(k, &v.tagged(self.tagging))?;
+                    }
+                    map.end()
+                }
+                ResourceTagging::Adjacent => {
+                    let mut map = serializer.serialize_map(Some(2))?;
+                    map.serialize_entry(TYPE_TAG, typ)?;
+                    map.serialize_entry("fields", &Fields(fields, self.tagging))?;
+                    map.end()
+                }
+            },
+        }
+    }
+}
+
+/// The fields of a dictionary or adjacently tagged resource, as a map.
+struct Fields<'v>(&'v IndexMap<String, Value>, ResourceTagging);
+
+impl Serialize for Fields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Fields(fields, tagging) = *self;
+        serializer.collect_map(fields.iter().map(|(k, v)| (k, v.tagged(tagging))))
+    }
+}
+
+/// Values serialize with `ResourceTagging::Internal`, use `Value::tagged` for another shape.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.tagged(ResourceTagging::default())
+            .serialize(serializer)
+    }
+}
+
+/// Reads values in the shape they serialize to by default, so that they round-trip:
+/// an object with a string `$type` is a resource, other objects are dictionaries.
+/// Integers and floats stay distinct, as long as the format keeps `7.0` apart from `7` like JSON.
+/// `Child` and `Children` serialize as their name, and read back as strings.
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number, string, boolean, null, array or object")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Integer(i))
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<Value, E> {
+        i64::try_from(u)
+            .map(Value::Integer)
+            .map_err(|_| E::custom(format!("integer {u} is too large")))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::Float(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut fields: IndexMap<String, Value> = IndexMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            fields.insert(key, value);
+        }
+        Ok(match fields.shift_remove(TYPE_TAG) {
+            Some(Value::String(typ)) => Value::Resource { typ, fields },
+            Some(other) => {
+                fields.insert(TYPE_TAG.to_string(), other);
+                Value::Dictionary(fields)
+            }
+            None => Value::Dictionary(fields),
+        })
+    }
+}
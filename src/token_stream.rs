@@ -0,0 +1,107 @@
+//! Tokenizing from a byte stream, for inputs too large to load as one `&str`.
+use std::io::Read;
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::recognizer::{tokenize, Span, Token, TokenKind};
+
+/// How many bytes are read from the stream at a time.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A token that owns its text, made by `tokenize_reader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedToken {
+    pub kind: TokenKind,
+    /// The token text, without the quotes of string literals
+    pub text: String,
+    /// Byte range of the token in the stream
+    pub span: Range<usize>,
+}
+
+impl OwnedToken {
+    /// A borrowed view, as the recognizer reads tokens.
+    pub(crate) fn as_token(&self) -> Token<'_> {
+        Token {
+            kind: self.kind.clone(),
+            text: &self.text,
+            span: Span::new(self.span.start, self.span.end),
+        }
+    }
+}
+
+/// Errors while reading tokens from a stream
+#[derive(Debug, Error)]
+pub enum TokenizeError {
+    /// Reading the stream failed
+    #[error("Error while reading the input : {0}")]
+    Io(#[from] std::io::Error),
+    /// The stream isn't valid UTF-8, at this byte offset
+    #[error("Invalid UTF-8 in the input at byte {0}")]
+    InvalidUtf8(usize),
+}
+
+/// Tokenizes a stream chunk by chunk, like `tokenize` would tokenize the whole text.
+///
+/// Only the text after the last whitespace of a chunk is kept for the next one,
+/// so tokens are never cut, and a UTF-8 character split between two reads is decoded whole.
+pub fn tokenize_reader(mut reader: impl Read) -> Result<Vec<OwnedToken>, TokenizeError> {
+    let mut tokens = Vec::new();
+    // Bytes read but not tokenized yet, and their offset in the stream
+    let mut pending: Vec<u8> = Vec::new();
+    let mut offset = 0;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let at_end = read == 0;
+        pending.extend_from_slice(&chunk[..read]);
+
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(text) => text,
+            // An incomplete character at the end will be completed by the next read
+            Err(e) if e.error_len().is_none() && !at_end => {
+                std::str::from_utf8(&pending[..e.valid_up_to()]).unwrap_or_default()
+            }
+            Err(e) => return Err(TokenizeError::InvalidUtf8(offset + e.valid_up_to())),
+        };
+
+        let chunk_tokens = tokenize(valid);
+        let done = if at_end {
+            // Everything but the `Eof` token, added once below
+            chunk_tokens.len() - 1
+        } else {
+            // Up to the last whitespace outside of a string literal, later text may continue a token.
+            // A quote without its closing one yet starts a string literal the next reads may end.
+            let open_quote = chunk_tokens
+                .iter()
+                .position(|t| t.kind == TokenKind::Char && t.text == "\"")
+                .unwrap_or(chunk_tokens.len());
+            chunk_tokens[..open_quote]
+                .iter()
+                .rposition(|t| t.kind == TokenKind::Char && t.text.chars().all(char::is_whitespace))
+                .map_or(0, |last| last + 1)
+        };
+        let consumed = chunk_tokens[..done].last().map_or(0, |t| t.span.end);
+        tokens.extend(chunk_tokens[..done].iter().map(|t| OwnedToken {
+            kind: t.kind.clone(),
+            text: t.text.to_string(),
+            span: offset + t.span.start..offset + t.span.end,
+        }));
+
+        if at_end {
+            let end = offset + pending.len();
+            tokens.push(OwnedToken {
+                kind: TokenKind::Eof,
+                text: String::new(),
+                span: end..end,
+            });
+            return Ok(tokens);
+        }
+        pending.drain(..consumed);
+        offset += consumed;
+    }
+}
@@ -1,13 +1,23 @@
 use thiserror::Error;
 
+use crate::grammar_parser::is_internal;
 use crate::recognizer::Chart;
-use crate::recognizer::{Grammar, Symbol};
+use crate::recognizer::{Grammar, Symbol, TokenKind};
 use std::collections::{HashMap, HashSet};
 
 /// A parse error with both user-friendly and developer-friendly details
 #[derive(Debug, Error)]
 pub struct ParseError {
+    /// Token index of the failure, or grapheme index with `Options::grapheme_positions`
     pub pos: usize,
+    /// Byte offset of the failure in the input, for slicing
+    pub byte_pos: usize,
+    /// Line of the failure in the input, from 1
+    pub line: usize,
+    /// Column of the failure in its line, in characters from 1
+    pub column: usize,
+    /// The input line of the failure, shown under the message
+    pub source_line: Box<str>,
     pub found: Option<String>,
     pub expected: Vec<String>, // user-facing terminals
     pub items: Vec<String>,    // developer-facing Earley items
@@ -16,10 +26,13 @@ impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(
             f,
-            "Parse error at pos {}: around {:?}",
-            self.pos,
+            "Parse error at {}:{}: around {:?}",
+            self.line,
+            self.column,
             self.found.clone().unwrap_or("<EOF>".to_string())
         )?;
+        writeln!(f, "{}", self.source_line)?;
+        writeln!(f, "{}^", " ".repeat(self.column - 1))?;
 
         if !self.expected.is_empty() {
             writeln!(f, "Expected one of: {}", self.expected.join(", "))?;
@@ -54,7 +67,15 @@ fn format_item(lhs: &str, rhs: &[Symbol], dot: usize) -> String {
 impl<'gr> Grammar<'gr> {
     /// Compute FIRST sets for all nonterminals and placeholders.
     pub fn compute_first_sets(&self) -> HashMap<&'gr str, HashSet<Symbol<'gr>>> {
-        let mut first: HashMap<&'gr str, HashSet<Symbol<'gr>>> = HashMap::new();
+        self.compute_first_sets_from(HashMap::new())
+    }
+
+    /// Same as `compute_first_sets`, starting from FIRST sets already known.
+    pub fn compute_first_sets_from(
+        &self,
+        known: HashMap<&'gr str, HashSet<Symbol<'gr>>>,
+    ) -> HashMap<&'gr str, HashSet<Symbol<'gr>>> {
+        let mut first = known;
 
         // Initialize nonterminals and placeholders with empty sets
         for prod in &self.productions {
@@ -82,7 +103,7 @@ impl<'gr> Grammar<'gr> {
 
                 if let Some(sym) = prod.rhs.first() {
                     match sym {
-                        Symbol::Terminal(_) => {
+                        Symbol::Terminal(_) | Symbol::End => {
                             new_syms.insert(sym.clone());
                         }
                         Symbol::NonTerminal(nt) => {
@@ -116,7 +137,6 @@ impl<'gr> Grammar<'gr> {
     }
 }
 
-/// Expand a symbol into expected tokens (terminal names)
 /// Expand a symbol into expected tokens (terminal names)
 fn expected_tokens<'a>(
     sym: &Symbol<'a>,
@@ -124,26 +144,22 @@ fn expected_tokens<'a>(
 ) -> Vec<String> {
     match sym {
         Symbol::Terminal(s) => vec![s.to_string()],
+        Symbol::End => vec!["<EOF>".to_string()],
         Symbol::NonTerminal(nt) => first_sets
             .get(nt)
             .map(|set| set.iter().map(|s| format!("{}", s)).collect())
             .unwrap_or_default(),
-        Symbol::Placeholder { .. } => vec![], // placeholders don't expand to terminals
+        // placeholders of a nonterminal type expand to its terminals, builtins are shown as is
+        Symbol::Placeholder { typ, .. } => match first_sets.get(typ) {
+            Some(set) if !set.is_empty() => set.iter().map(|s| format!("{}", s)).collect(),
+            _ => vec![format!("{}", sym)],
+        },
     }
 }
 impl<'gr, 'inp> Chart<'gr, 'inp> {
-    pub fn try_accept(&self, start: &str) -> Result<(), ParseError> {
-        if self.accepted(start) {
-            return Ok(());
-        }
-
-        let first_sets = self.grammar.compute_first_sets();
-
-        // 1️⃣ Find furthest index with some in-progress items (dot < rhs.len())
+    /// Furthest position with some in-progress items (dot < rhs.len()).
+    pub fn furthest_pos(&self) -> usize {
         let mut furthest_pos = 0;
-        let mut expected = Vec::new();
-        let mut items = Vec::new();
-
         for (i, set) in self.sets.iter().enumerate() {
             for item in set.values() {
                 let prod = &self.grammar.productions[item.key.prod_id];
@@ -152,9 +168,84 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
                 }
             }
         }
+        furthest_pos
+    }
+
+    /// In-progress `(production id, dot)` pairs at the furthest position,
+    /// sorted and without duplicates.
+    pub fn continuations(&self) -> Vec<(usize, usize)> {
+        let mut found: Vec<(usize, usize)> = self.sets[self.furthest_pos()]
+            .values()
+            .filter(|item| item.key.dot < self.grammar.productions[item.key.prod_id].rhs.len())
+            .map(|item| (item.key.prod_id, item.key.dot))
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Token ranges `(start, end)` read by some completed item, merged and sorted.
+    pub fn completed_ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .sets
+            .iter()
+            .enumerate()
+            .flat_map(|(end, set)| {
+                set.values()
+                    .filter(move |item| {
+                        item.key.dot == self.grammar.productions[item.key.prod_id].rhs.len()
+                            && item.key.start < end
+                    })
+                    .map(move |item| (item.key.start, end))
+            })
+            .collect();
+        ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    /// Checks that `start` was recognized, computing the FIRST sets on the fly: the parser
+    /// keeps cached ones for `try_accept_with_first_sets`, so only tests use this.
+    #[cfg(test)]
+    pub fn try_accept(&self, start: &str) -> Result<(), ParseError> {
+        if self.accepted(start) {
+            return Ok(());
+        }
+        self.try_accept_with_first_sets(start, &self.grammar.compute_first_sets())
+    }
+
+    /// Same as `try_accept`, with FIRST sets computed beforehand.
+    pub fn try_accept_with_first_sets(
+        &self,
+        start: &str,
+        first_sets: &HashMap<&'gr str, HashSet<Symbol<'gr>>>,
+    ) -> Result<(), ParseError> {
+        if self.accepted(start) {
+            return Ok(());
+        }
+
+        // 1️⃣ Find furthest index with some in-progress items (dot < rhs.len())
+        let furthest_pos = self.furthest_pos();
+        let mut expected = Vec::new();
+        let mut items = Vec::new();
 
         // 2️⃣ Offending token is the one *at* furthest_pos
-        let found = self.tokens.get(furthest_pos).map(|t| t.text.to_string());
+        let byte_pos = self
+            .tokens
+            .get(furthest_pos)
+            .or(self.tokens.last())
+            .map_or(0, |t| t.span.start);
+        let found = self
+            .tokens
+            .get(furthest_pos)
+            .filter(|t| t.kind != TokenKind::Eof)
+            .map(|t| t.text.to_string());
 
         // 3️⃣ Collect expectations/items from that point
         if let Some(set) = self.sets.get(furthest_pos) {
@@ -162,8 +253,11 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
                 let prod = &self.grammar.productions[item.key.prod_id];
                 if item.key.dot < prod.rhs.len() {
                     let next_sym = &prod.rhs[item.key.dot];
-                    expected.extend(expected_tokens(next_sym, &first_sets));
-                    items.push(format_item(prod.lhs, &prod.rhs, item.key.dot));
+                    expected.extend(expected_tokens(next_sym, first_sets));
+                    // the items of a repetition show in the item of the rule using it
+                    if !is_internal(prod.lhs) {
+                        items.push(format_item(prod.lhs, &prod.rhs, item.key.dot));
+                    }
                 }
             }
         }
@@ -171,8 +265,21 @@ impl<'gr, 'inp> Chart<'gr, 'inp> {
         expected.sort();
         expected.dedup();
 
+        // The input as rebuilt from the tokens, whose spans are offsets into it
+        let input = self.input_text();
+        let mut at = byte_pos.min(input.len());
+        while !input.is_char_boundary(at) {
+            at -= 1;
+        }
+        let line_start = input[..at].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[at..].find('\n').map_or(input.len(), |i| at + i);
+
         Err(ParseError {
             pos: furthest_pos,
+            byte_pos,
+            line: input[..at].matches('\n').count() + 1,
+            column: input[line_start..at].chars().count() + 1,
+            source_line: input[line_start..line_end].trim_end_matches('\r').into(),
             found,
             expected,
             items,
@@ -185,7 +292,7 @@ mod try_accept_file_tests {
     use super::*;
     use crate::grammar_parser::ValueSpec;
     use crate::parser::OutSpec;
-    use crate::recognizer::{tokenize, Production};
+    use crate::recognizer::{tokenize, Production, WhitespaceMode};
     use std::fs;
     use std::path::Path;
 
@@ -202,7 +309,7 @@ mod try_accept_file_tests {
     }
 
     /// Write input and ParseError Display directly
-    fn write_parse_error<'inp>(test_name: &str, input: &str, err: &ParseError) {
+    fn write_parse_error(test_name: &str, input: &str, err: &ParseError) {
         let dir = Path::new("./target/test_user_errors");
         fs::create_dir_all(dir).unwrap();
         let path = dir.join(format!("{}.txt", test_name));
@@ -230,6 +337,7 @@ mod try_accept_file_tests {
                         rhs.push(Symbol::Placeholder {
                             name: "name",
                             typ: "String",
+                            optional: false,
                         });
                         rhs.extend(chars(" "));
                         rhs.push(Symbol::Terminal("{"));
@@ -238,17 +346,20 @@ mod try_accept_file_tests {
                         rhs
                     },
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 // Items ::= Item Items | ε
                 Production {
                     lhs: "Items",
                     rhs: vec![Symbol::NonTerminal("Item"), Symbol::NonTerminal("Items")],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Items",
                     rhs: vec![],
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 // Item ::= "enemy" String | "treasure" String
                 Production {
@@ -258,10 +369,12 @@ mod try_accept_file_tests {
                         rhs.push(Symbol::Placeholder {
                             name: "id",
                             typ: "String",
+                            optional: false,
                         });
                         rhs
                     },
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
                 Production {
                     lhs: "Item",
@@ -270,10 +383,12 @@ mod try_accept_file_tests {
                         rhs.push(Symbol::Placeholder {
                             name: "id",
                             typ: "String",
+                            optional: false,
                         });
                         rhs
                     },
                     out: dummy_outspec(),
+                    ws: WhitespaceMode::Exact,
                 },
             ],
         }